@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+
+/**
+ * Minimal command-line parsing. Arguments are split into positional values,
+ * `--option value` pairs and boolean `--switch` flags. Options may be
+ * repeated; the last occurrence wins for `value()`.
+ */
+pub struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, Vec<String>>,
+    switches: HashSet<String>,
+}
+
+impl Args {
+    /**
+     * Parses `args`, treating the names in `switches` as flags that take no
+     * value. Every other `--name` consumes the following argument.
+     */
+    pub fn parse(args: &[String], switches: &[&str]) -> Result<Args, String> {
+        let mut positional = Vec::new();
+        let mut options: HashMap<String, Vec<String>> = HashMap::new();
+        let mut flags = HashSet::new();
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if switches.contains(&name) => {
+                    flags.insert(name.to_string());
+                }
+                Some(name) => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| format!("Missing value for --{}", name))?;
+                    options
+                        .entry(name.to_string())
+                        .or_default()
+                        .push(value.clone());
+                }
+                None => positional.push(arg.clone()),
+            }
+        }
+
+        Ok(Args {
+            positional,
+            options,
+            switches: flags,
+        })
+    }
+
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.options
+            .get(name)
+            .and_then(|values| values.last())
+            .map(String::as_str)
+    }
+
+    /**
+     * Every value given for a repeated `--name`, in order.
+     */
+    pub fn values(&self, name: &str) -> &[String] {
+        self.options.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.switches.contains(name)
+    }
+
+    /**
+     * Parses the value of `--name` into `T`, reporting which option was
+     * malformed on failure.
+     */
+    pub fn parsed<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        match self.value(name) {
+            Some(raw) => raw
+                .parse::<T>()
+                .map(Some)
+                .map_err(|_| format!("Invalid value for --{}: {}", name, raw)),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,5 @@
+/**
+ * Records the radio stations of a country from Radio Garden. `radafi` is
+ * also usable as a library: see `midhyae::Listener`.
+ */
+pub mod midhyae;
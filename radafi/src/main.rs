@@ -1,41 +1,2145 @@
-mod midhyae;
+mod cli;
+mod picker;
 
-use midhyae::Listener;
+use chrono::TimeZone;
+use cli::Args;
+use log::{error, info, warn};
+use radafi::midhyae::{
+    self, ArchiveCodec, Artwork, Bitrate, ChannelId, Classify, Config, Discovery, Encryption,
+    ErrorCategory, ErrorCode, Favorite, FeedGrouping, FilterScript, IcecastTarget, InstanceLock,
+    IpVersion, Length, Limits, Listener, LiveConfig, PlaceId, PlaceSelection, Query,
+    RecordingError, Registry, Report, Run, S3Target, Size, Status, Store, Transcriber,
+    UsageGrouping, DEFAULT_CONFIRM_ABOVE,
+};
+use std::collections::BTreeMap;
 use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 use tokio::runtime;
-use log::{error, info};
+use tracing_subscriber::EnvFilter;
+use url::Url;
 
 fn main() {
-    env_logger::init();
-    
+    // Log lines of recordings carry their station, channel, worker and session
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error")),
+        )
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .init();
+
     // Parse command-line arguments
     let args: Vec<String> = env::args().collect();
-    if args.len() < 4 {
-        error!("Usage: {} <country> <directory> <duration>", args[0]);
-        return;
+    let program = &args[0];
+
+    match args.get(1).map(String::as_str) {
+        Some("record") => {
+            if let Err(code) = record(program, &args[2..]) {
+                std::process::exit(code.exit_status());
+            }
+        }
+        Some("favorites") => favorites(program, &args[2..]),
+        Some("bench") => bench(program, &args[2..]),
+        Some("stats") => stats(program, &args[2..]),
+        Some("export-places") => export_places(program, &args[2..]),
+        Some("countries") => countries(program, &args[2..]),
+        Some("places") => places(program, &args[2..]),
+        Some("feed") => feed(program, &args[2..]),
+        Some("find") => find(program, &args[2..]),
+        Some("recover") => recover(program, &args[2..]),
+        Some("doctor") => doctor(program, &args[2..]),
+        Some("bot") => bot(program, &args[2..]),
+        Some("serve") => serve(program, &args[2..]),
+        Some("daemon") => daemon(program, &args[2..]),
+        Some("serve-archive") => serve_archive(program, &args[2..]),
+        Some("coordinate") => coordinate(program, &args[2..]),
+        Some("work") => work(program, &args[2..]),
+        Some("consume") => consume(program, &args[2..]),
+        _ => {
+            if let Err(code) = record(program, &args[1..]) {
+                std::process::exit(code.exit_status());
+            }
+        }
+    }
+}
+
+/**
+ * Longest a recording runs when only its size is limited, so a stream that
+ * stalls still ends.
+ */
+const SIZE_LIMITED_DURATION: u64 = 24 * 60 * 60;
+
+/**
+ * Discovers the streams of a country and records them. Fails with the code
+ * of what kept the run from recording anything.
+ */
+fn record(program: &str, raw: &[String]) -> Result<(), ErrorCode> {
+    let switches = [
+        "interactive",
+        "session-dir",
+        "stdout",
+        "compress",
+        "classify",
+        "check-levels",
+        "geoip",
+        "notify",
+        "latest-links",
+        "trim",
+        "cue",
+        "artwork",
+        "embed-artwork",
+        "opus-copy",
+        "yes",
+        "speech-only",
+        "lock",
+        "force",
+        "purge-corrupt",
+        "allow-insecure",
+        "include-dead",
+        "audio-duration",
+    ];
+    let mut args = match Args::parse(raw, &switches) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return Err(ErrorCode::Usage);
+        }
+    };
+    // A profile's arguments go first, so those given here take precedence
+    if let Some(name) = args.value("profile") {
+        let config_path = args
+            .value("config")
+            .map(PathBuf::from)
+            .unwrap_or_else(Config::default_path);
+        let config = match Config::load(&config_path) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("{}", e);
+                return Err(e.code());
+            }
+        };
+        let Some(profile) = config.profile.get(name) else {
+            error!("No profile {} in {}", name, config_path.display());
+            return Err(ErrorCode::Usage);
+        };
+        let positional = args.positional().is_empty();
+        let target = positional && args.values("place").is_empty() && !args.flag("geoip");
+        let mut expanded = profile.args(target, positional);
+        expanded.extend(raw.iter().cloned());
+        args = match Args::parse(&expanded, &switches) {
+            Ok(args) => args,
+            Err(e) => {
+                error!("Profile {}: {}", name, e);
+                return Err(ErrorCode::Usage);
+            }
+        };
+    }
+    // With --stdout or --relay a single station is streamed out and no
+    // directory is used
+    let to_stdout = args.flag("stdout");
+    let relay = match args.value("relay").map(IcecastTarget::parse).transpose() {
+        Ok(relay) => relay,
+        Err(e) => {
+            error!("{}", e);
+            return Err(ErrorCode::Usage);
+        }
+    };
+    let single = to_stdout || relay.is_some();
+    let progress_json = match args.value("progress") {
+        None => false,
+        Some("json") => true,
+        Some(other) => {
+            error!("Unknown progress format {}; expected json", other);
+            return Err(ErrorCode::Usage);
+        }
+    };
+    if progress_json && to_stdout {
+        error!("--progress json writes to standard output and cannot be combined with --stdout");
+        return Err(ErrorCode::Usage);
+    }
+    // `--place -` reads place IDs from standard input, as listed by `places`
+    let mut places = Vec::new();
+    for place in args.values("place") {
+        if place != "-" {
+            places.extend(place.split(',').map(str::trim).map(str::to_string));
+            continue;
+        }
+        if args.flag("interactive") {
+            error!("--place - reads standard input and cannot be combined with --interactive");
+            return Err(ErrorCode::Usage);
+        }
+        for line in io::stdin().lines() {
+            match line {
+                Ok(line) => {
+                    places.extend(line.split('\t').next().map(str::trim).map(str::to_string))
+                }
+                Err(e) => {
+                    error!("Failed to read place IDs: {}", e);
+                    return Err(ErrorCode::Usage);
+                }
+            }
+        }
+    }
+    places.retain(|place| !place.is_empty());
+    if places.is_empty() && !args.values("place").is_empty() {
+        error!("--place was given no place IDs");
+        return Err(ErrorCode::Usage);
+    }
+    let places: Vec<PlaceId> = match places.iter().map(|place| place.parse()).collect() {
+        Ok(places) => places,
+        Err(e) => {
+            error!("--place: {}", e);
+            return Err(ErrorCode::Usage);
+        }
+    };
+    // With --geoip the country is left out and looked up instead, and with
+    // --place it is not needed
+    let geoip = args.flag("geoip");
+    if geoip && !places.is_empty() {
+        error!("--geoip and --place are mutually exclusive");
+        return Err(ErrorCode::Usage);
+    }
+    let positional = args.positional();
+    let (country, positional) = match positional.split_first() {
+        Some((country, rest)) if !geoip && places.is_empty() => (Some(country.clone()), rest),
+        _ => (None, positional),
+    };
+    let max_bytes = match args.parsed::<Size>("max-bytes") {
+        Ok(max_bytes) => max_bytes,
+        Err(e) => {
+            error!("{}", e);
+            return Err(ErrorCode::Usage);
+        }
+    };
+    // Recordings limited in size may leave out the duration
+    let needed = match (single, max_bytes) {
+        (true, _) => 1,
+        (false, Some(_)) => 1,
+        (false, None) => 2,
+    };
+    if positional.len() < needed {
+        error!(
+            "Usage: {} <country | --geoip | --place <id|->...> <directory> <duration | --max-bytes <size>> [--interactive] [--station <id|title>] [--genre <genre,...>] [--include-dead] [--max-per-place <n> [--place-selection first|random|favorites]] [--concurrency <n>] [--session-dir] [--latest-links] [--staging-dir <dir>] [--write-buffer <size>] [--segment <seconds>] [--max-bytes <size>] [--speech-only] [--time-shift <minutes>] [--trim] [--audio-duration] [--cue] [--tag <name>=<value>]... [--opus-copy | --archive-codec mp3|opus] [--bitrate <rate>] [--filename <template>] [--upload s3://<bucket>/<prefix>] [--encrypt-to <age_recipient> | --encrypt-passphrase-file <file>] [--compress] [--classify] [--artwork | --embed-artwork] [--check-levels] [--purge-corrupt] [--transcribe-command <cmd> | --transcribe-url <url>] [--ip-version <4|6|auto>] [--allow-insecure] [--debug-dump <dir> | --replay <dir>] [--filter-script <file>] [--notify] [--notify-failures <n>] [--progress json] [--yes] [--confirm-above <size>] [--lock | --pid-file <file>] [--force] [--profile <name>] [--config <file>]",
+            program
+        );
+        error!(
+            "       {} <country | --geoip> <duration> <--stdout | --relay <icecast_url>> [--interactive] [--station <id|title>] [--ip-version <4|6|auto>] [--pid-file <file> [--force]]",
+            program
+        );
+        return Err(ErrorCode::Usage);
+    }
+    let concurrency = match args.parsed::<usize>("concurrency") {
+        Ok(concurrency) => concurrency,
+        Err(e) => {
+            error!("{}", e);
+            return Err(ErrorCode::Usage);
+        }
+    };
+    let (max_per_place, place_selection) = match (
+        args.parsed::<usize>("max-per-place"),
+        args.parsed::<PlaceSelection>("place-selection"),
+    ) {
+        (Ok(max), Ok(selection)) => (max, selection.unwrap_or(PlaceSelection::First)),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("{}", e);
+            return Err(ErrorCode::Usage);
+        }
+    };
+
+    // Packing removes the directory packed, which must hold this run alone
+    if args.flag("compress") && !args.flag("session-dir") {
+        error!("--compress needs --session-dir, as it removes the directory it packs");
+        return Err(ErrorCode::Usage);
+    }
+    let (mut directory, duration) = if single {
+        (String::new(), positional.first())
+    } else {
+        (positional[0].clone(), positional.get(1))
+    };
+    let duration = match duration.map(|d| d.parse::<u64>()) {
+        Some(Ok(duration)) => duration,
+        None => SIZE_LIMITED_DURATION,
+        Some(Err(_)) => {
+            error!("Invalid duration: {}", duration.unwrap());
+            return Err(ErrorCode::Usage);
+        }
+    };
+    // Held until the run ends, so overlapping runs cannot share the directory
+    let locked = (!single).then(|| midhyae::expand_directory(&directory, &chrono::Local::now()));
+    let instance = lock_instance(&args, locked.as_deref())?;
+
+    let store_path = Store::default_path();
+    let store = match Store::open(&store_path) {
+        Ok(store) => store,
+        Err(e) => {
+            error!("Failed to open store: {}", e);
+            return Err(RecordingError::from(e).code());
+        }
+    };
+
+    let config_path = args
+        .value("config")
+        .map(PathBuf::from)
+        .unwrap_or_else(Config::default_path);
+    let config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            return Err(e.code());
+        }
+    };
+
+    let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
+    let mut listener = Listener::new("http://radio.garden/api/ara/content/");
+    match listener.pending_journals() {
+        Ok(journals) if !journals.is_empty() => warn!(
+            "{} interrupted runs found; run `{} recover` to finish them.",
+            journals.len(),
+            program
+        ),
+        Ok(_) => {}
+        Err(e) => warn!("Failed to look for interrupted runs: {}", e),
+    }
+    listener.add_country_aliases(&config.country_aliases);
+    if let Some(mqtt) = &config.mqtt {
+        listener.set_mqtt(mqtt);
+    }
+    if progress_json {
+        listener.set_progress_json();
+    }
+    listener.set_drop_when_paused(config.drop_connection_when_paused);
+    // With --lock the directory is locked for the whole run already
+    let locked_output = args.flag("lock")
+        && args.value("pid-file").is_none()
+        && !args.flag("session-dir")
+        && instance.is_some();
+    listener.set_shared_directory(args.flag("force") || locked_output);
+    let mut http = config.http.clone();
+    match args.parsed::<IpVersion>("ip-version") {
+        Ok(ip_version) => http.ip_version = ip_version.unwrap_or_default(),
+        Err(e) => {
+            error!("{}", e);
+            return Err(ErrorCode::Usage);
+        }
+    }
+    http.allow_insecure |= args.flag("allow-insecure");
+    if let Err(e) = listener.set_http_options(&http) {
+        error!("{}", e);
+        return Err(e.code());
+    }
+    if let Some(dir) = args.value("replay") {
+        listener.set_replay(Path::new(dir));
+    }
+    if let Some(dir) = args.value("debug-dump") {
+        if let Err(e) = listener.set_debug_dump(Path::new(dir)) {
+            error!("Failed to create dump directory {}: {}", dir, e);
+            return Err(e.code());
+        }
+    }
+    if let Some(concurrency) = concurrency {
+        listener.set_concurrency(concurrency);
+    }
+    if let Some(filename) = args.value("filename") {
+        if let Err(e) = listener.set_filename_template(filename) {
+            error!("{}", e);
+            return Err(e.code());
+        }
+    }
+    match args.parsed::<Size>("write-buffer") {
+        Ok(size) => {
+            if let Some(Size(bytes)) = size.or(config.write_buffer) {
+                listener.set_write_buffer(bytes);
+            }
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(ErrorCode::Usage);
+        }
+    }
+    let confirm_above = match args.parsed::<Size>("confirm-above") {
+        Ok(size) => size
+            .or(config.confirm_above)
+            .unwrap_or(DEFAULT_CONFIRM_ABOVE),
+        Err(e) => {
+            error!("{}", e);
+            return Err(ErrorCode::Usage);
+        }
+    };
+    let notify = args.flag("notify");
+    let notify_failures = match args.parsed::<usize>("notify-failures") {
+        Ok(max) => max,
+        Err(e) => {
+            error!("{}", e);
+            return Err(ErrorCode::Usage);
+        }
+    };
+    if (notify || notify_failures.is_some()) && !cfg!(feature = "desktop-notifications") {
+        error!("Desktop notifications need a build with --features desktop-notifications");
+        return Err(ErrorCode::Usage);
+    }
+    let filter_script = match args
+        .value("filter-script")
+        .map(Path::new)
+        .map(FilterScript::load)
+    {
+        Some(Ok(script)) => Some(script),
+        Some(Err(e)) => {
+            error!("{}", e);
+            return Err(e.code());
+        }
+        None => None,
+    };
+    listener.set_level_check(args.flag("check-levels"));
+    listener.set_latest_links(args.flag("latest-links"));
+    listener.set_trim(args.flag("trim"));
+    listener.set_audio_duration(args.flag("audio-duration"));
+    listener.set_staging_dir(
+        args.value("staging-dir")
+            .map(PathBuf::from)
+            .or_else(|| config.staging_dir.clone()),
+    );
+    listener.set_purge_corrupt(args.flag("purge-corrupt"));
+    listener.set_cue_sheets(args.flag("cue"));
+    // Tags from the command line add to and replace those configured
+    let mut tags = config.tags.clone();
+    for tag in args.values("tag") {
+        match tag.split_once('=') {
+            Some((name, value)) if !name.is_empty() => {
+                tags.insert(name.to_string(), value.to_string());
+            }
+            _ => {
+                error!("Invalid tag {}, expected <name>=<value>", tag);
+                return Err(ErrorCode::Usage);
+            }
+        }
+    }
+    listener.set_tags(tags);
+    let plain_local = ["upload", "encrypt-to", "encrypt-passphrase-file"]
+        .iter()
+        .all(|option| args.value(option).is_none());
+    let bitrate = match args.parsed::<Bitrate>("bitrate") {
+        Ok(bitrate) => bitrate,
+        Err(e) => {
+            error!("{}", e);
+            return Err(ErrorCode::Usage);
+        }
+    };
+    let codec = match args
+        .value("archive-codec")
+        .map(|name| ArchiveCodec::new(name, bitrate))
+    {
+        Some(Ok(codec)) => codec,
+        Some(Err(e)) => {
+            error!("{}", e);
+            return Err(ErrorCode::Usage);
+        }
+        None => ArchiveCodec::Mp3,
+    };
+    if (args.flag("opus-copy") || codec != ArchiveCodec::Mp3) && !plain_local {
+        error!("Opus applies to plain local recordings and cannot be combined with --upload or encryption");
+        return Err(ErrorCode::Usage);
+    }
+    if args.flag("opus-copy") && codec != ArchiveCodec::Mp3 {
+        error!("--opus-copy needs MP3 recordings to copy");
+        return Err(ErrorCode::Usage);
+    }
+    if args.flag("opus-copy") {
+        listener.set_opus_copy(Some(bitrate.unwrap_or_default()));
+    }
+    listener.set_archive_codec(codec);
+    listener.set_max_bytes(max_bytes);
+    listener.set_speech_only(args.flag("speech-only"));
+    match args.parsed::<u64>("segment") {
+        Ok(segment) => listener.set_segment_length(segment),
+        Err(e) => {
+            error!("{}", e);
+            return Err(ErrorCode::Usage);
+        }
+    }
+    let time_shift = match args.parsed::<u64>("time-shift") {
+        Ok(minutes) => minutes.filter(|&minutes| minutes > 0),
+        Err(e) => {
+            error!("{}", e);
+            return Err(ErrorCode::Usage);
+        }
+    };
+    listener.set_time_shift(time_shift);
+    if args.flag("classify") {
+        listener.add_post_processor(Box::new(Classify));
+    }
+    if args.flag("artwork") || args.flag("embed-artwork") {
+        listener.add_post_processor(Box::new(Artwork::new(args.flag("embed-artwork"))));
+    }
+    match (
+        args.value("transcribe-command"),
+        args.value("transcribe-url"),
+    ) {
+        (Some(_), Some(_)) => {
+            error!("--transcribe-command and --transcribe-url are mutually exclusive");
+            return Err(ErrorCode::Usage);
+        }
+        (Some(command), None) => {
+            listener.add_post_processor(Box::new(Transcriber::Command(command.to_string())))
+        }
+        (None, Some(url)) => match Transcriber::http(url) {
+            Ok(transcriber) => listener.add_post_processor(Box::new(transcriber)),
+            Err(e) => {
+                error!("{}", e);
+                return Err(e.code());
+            }
+        },
+        (None, None) => {}
+    }
+    // Stages from the configuration run after those from the command line
+    let registry = Registry::default();
+    for table in config.post_process.iter().cloned() {
+        match registry.build(table) {
+            Ok(stage) => listener.add_post_processor(stage),
+            Err(e) => {
+                error!("{}", e);
+                return Err(e.code());
+            }
+        }
+    }
+    let encryption = match (
+        args.value("encrypt-to"),
+        args.value("encrypt-passphrase-file"),
+    ) {
+        (Some(_), Some(_)) => {
+            error!("--encrypt-to and --encrypt-passphrase-file are mutually exclusive");
+            return Err(ErrorCode::Usage);
+        }
+        (Some(recipients), None) => {
+            let recipients: Vec<&str> = recipients.split(',').map(str::trim).collect();
+            Some(Encryption::with_recipients(&recipients))
+        }
+        (None, Some(file)) => Some(Encryption::with_passphrase_file(Path::new(file))),
+        (None, None) => None,
+    };
+    match encryption.transpose() {
+        Ok(Some(_)) if args.value("upload").is_some() => {
+            error!("Encryption applies to local recordings and cannot be combined with --upload");
+            return Err(ErrorCode::Usage);
+        }
+        Ok(Some(encryption)) => listener.set_encryption(encryption),
+        Ok(None) => {}
+        Err(e) => {
+            error!("{}", e);
+            return Err(e.code());
+        }
+    }
+    if let Some(upload) = args.value("upload") {
+        match S3Target::from_url(upload) {
+            Ok(target) => listener.set_upload_target(target),
+            Err(e) => {
+                error!("{}", e);
+                return Err(ErrorCode::Usage);
+            }
+        }
+    }
+
+    rt.block_on(async {
+        let country = match country {
+            Some(country) => Some(country),
+            None if !places.is_empty() => None,
+            None => match listener.local_country().await {
+                Ok(country) => {
+                    eprintln!("Using local country from IP geolocation: {}", country);
+                    Some(country)
+                }
+                Err(e) => {
+                    error!("Failed to determine the local country: {} ({})", e, e.code());
+                    return Err(e.code());
+                }
+            },
+        };
+
+        // Store streams for the given country or places
+        let discovery = match &country {
+            Some(country) => listener.store_streams(country).await,
+            None => listener.store_place_streams(&places).await,
+        };
+        // Runs of places are counted under their countries
+        let country = match discovery {
+            Ok(discovery) => {
+                log_discovery(&discovery);
+                country.unwrap_or_else(|| discovery.countries.join(", "))
+            }
+            Err(e) => {
+                error!("Failed to store streams: {} ({})", e, e.code());
+                return Err(e.code());
+            }
+        };
+
+        if let Some(station) = args.value("station") {
+            let count = listener.filter_stations(&[station]);
+            info!("{} streams match station {}.", count, station);
+        }
+        if !args.flag("include-dead") {
+            let dead = store.dead_stations();
+            let before = listener.streams().len();
+            listener.retain(|stream| !dead.iter().any(|d| d.id == stream.id));
+            let skipped = before - listener.streams().len();
+            if skipped > 0 {
+                warn!(
+                    "Skipping {} stations that failed hard in their last runs; pass --include-dead to try them.",
+                    skipped
+                );
+            }
+        }
+        if let Some(max) = max_per_place {
+            let removed = listener.cap_per_place(max, place_selection, store.favorites());
+            if removed > 0 {
+                info!("Left out {} streams beyond {} per place.", removed, max);
+            }
+        }
+
+        let favorites = listener.apply_favorites(store.favorites());
+        if favorites > 0 {
+            info!("Recording {} favorite streams first.", favorites);
+        }
+        let overridden = listener.apply_overrides(&config);
+        if overridden > 0 {
+            info!("Applied configured overrides to {} streams.", overridden);
+        }
+        if let Some(script) = &filter_script {
+            match listener.apply_filter_script(script) {
+                Ok(skipped) => info!("Filter script skipped {} streams.", skipped),
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(e.code());
+                }
+            }
+        }
+
+        if let Some(genres) = args.value("genre") {
+            let genres: Vec<String> = genres
+                .split(',')
+                .map(str::trim)
+                .filter(|genre| !genre.is_empty())
+                .map(str::to_string)
+                .collect();
+            let count = listener.filter_genres(&genres).await;
+            info!("{} streams report genre {}.", count, genres.join(" or "));
+        }
+
+        // Let the user choose stations before any stream is recorded
+        if args.flag("interactive") {
+            let titles = listener.stream_titles();
+            match picker::pick(&titles.iter().map(String::as_str).collect::<Vec<_>>()) {
+                Ok(Some(selected)) => listener.select_streams(&selected),
+                Ok(None) => {
+                    info!("Selection aborted; nothing to record.");
+                    return Ok(());
+                }
+                Err(e) => {
+                    error!("Failed to read selection: {}", e);
+                    return Err(ErrorCode::Other);
+                }
+            }
+            info!("Selected {} streams.", listener.stream_titles().len());
+        }
+
+        if to_stdout {
+            let mut stdout = tokio::io::stdout();
+            return match listener.pipe_stream(duration, &mut stdout).await {
+                Ok(bytes) => {
+                    info!("Wrote {} bytes to stdout.", bytes);
+                    Ok(())
+                }
+                Err(e) => {
+                    error!("Failed to pipe stream: {} ({})", e, e.code());
+                    Err(e.code())
+                }
+            };
+        }
+        if let Some(relay) = &relay {
+            return match listener.relay_stream(duration, relay).await {
+                Ok(bytes) => {
+                    info!("Relayed {} bytes.", bytes);
+                    Ok(())
+                }
+                Err(e) => {
+                    error!("Failed to relay stream: {} ({})", e, e.code());
+                    Err(e.code())
+                }
+            };
+        }
+
+        // Big sessions are only started when confirmed
+        let estimate = listener.estimate(duration, &store.byte_rates());
+        eprintln!(
+            "Estimated volume for {} stations: {} to download, {} on disk (bitrates of {} known from earlier runs)",
+            estimate.stations, estimate.download, estimate.disk, estimate.known
+        );
+        if estimate.exceeds(confirm_above) && !args.flag("yes") {
+            error!("The session would exceed {}; pass --yes to record anyway", confirm_above);
+            return Err(ErrorCode::NotConfirmed);
+        }
+
+        // Give each invocation its own timestamped directory if requested,
+        // inside the directory its date tokens name
+        if args.flag("session-dir") {
+            let parent = midhyae::expand_directory(&directory, &chrono::Local::now());
+            match midhyae::create_session_dir(&parent) {
+                Ok(session) => directory = session.to_string_lossy().into_owned(),
+                Err(e) => {
+                    error!("Failed to create session directory: {}", e);
+                    return Err(RecordingError::from(e).code());
+                }
+            }
+        }
+
+        // SIGUSR1 prints the status, SIGUSR2 rotates files, SIGTSTP pauses
+        // every recording when not run from a terminal and SIGCONT resumes
+        // them
+        #[cfg(unix)]
+        {
+            let progress = listener.progress();
+            tokio::spawn(async move {
+                if let Err(e) = progress.follow_signals().await {
+                    warn!("Following signals is unavailable: {}", e);
+                }
+            });
+        }
+
+        // SIGQUIT saves the time-shift into <output>/timeshift
+        let output = midhyae::expand_directory(&directory, &chrono::Local::now());
+        #[cfg(unix)]
+        if time_shift.is_some() {
+            let progress = listener.progress();
+            let clips = output.join("timeshift");
+            eprintln!(
+                "Keeping the last {} min of each station; press Ctrl+\\ or send SIGQUIT to save them",
+                time_shift.unwrap_or_default()
+            );
+            tokio::spawn(async move {
+                if let Err(e) = progress.time_shift().save_on_signal(&clips).await {
+                    warn!("Saving the time-shift on signals is unavailable: {}", e);
+                }
+            });
+        }
+
+        // Record streams
+        let mut run = Run::new(&country, duration, SystemTime::now());
+        let mut failure = None;
+        match listener.record_streams(&listener.streams(), duration, &directory).await {
+            Ok(outcomes) => {
+                let failed = outcomes.iter().filter(|o| !o.ok()).count();
+                info!(
+                    "Recorded {} streams, {} failed.",
+                    outcomes.len() - failed,
+                    failed
+                );
+                run.outcomes = outcomes;
+                if failed > 0 {
+                    let failures = run.failures_by_category();
+                    info!("Failures by cause: {}", describe_failures(&failures));
+                }
+            }
+            Err(e @ RecordingError::DirectoryInUse { .. }) => {
+                error!("{}; wait for it to finish, or pass --force to record alongside it", e);
+                failure = Some(e.code());
+            }
+            Err(e) => {
+                error!("Failed to record streams: {} ({})", e, e.code());
+                failure = Some(e.code());
+            }
+        }
+        // A run in which no station could be recorded fails with their code
+        failure = failure.or(run.failure_code());
+
+        if args.value("upload").is_none() && output.is_dir() {
+            if let Err(e) = midhyae::write_summary(&output, &run) {
+                error!("Failed to write summary: {}", e);
+            }
+            // Pack the finished session, keeping the originals on any error
+            if args.flag("compress") {
+                match midhyae::compress_dir(&output) {
+                    Ok(archive) => info!("Archived session to {}", archive.display()),
+                    Err(e) => error!("Failed to compress {}: {}", output.display(), e),
+                }
+            }
+        }
+
+        let report = Report::new(&run, SystemTime::now());
+        if let Some(email) = &config.email {
+            if let Err(e) = email.send(&report).await {
+                error!("Failed to mail run report: {}", e);
+            }
+        }
+        listener.notify_chats(&config, &report).await;
+        let alert = notify_failures.is_some_and(|max| report.failed > max);
+        if notify || alert {
+            show_notification(&report, alert);
+        }
+
+        // Keep the per-station outcomes for later reliability queries
+        if let Err(e) = Store::append_run(&store_path, run) {
+            error!("Failed to save store {}: {}", store_path.display(), e);
+        }
+        match failure {
+            Some(code) => Err(code),
+            None => Ok(()),
+        }
+    })
+}
+
+/**
+ * Takes the lock asked for with `--pid-file <file>`, or with `--lock` on
+ * `directory`, if any. With `--force`, a lock held by another process is
+ * only warned about and the run goes ahead without one.
+ */
+fn lock_instance(args: &Args, directory: Option<&Path>) -> Result<Option<InstanceLock>, ErrorCode> {
+    let result = match (args.value("pid-file"), directory) {
+        (Some(path), _) => InstanceLock::acquire(Path::new(path)),
+        (None, Some(directory)) if args.flag("lock") => InstanceLock::for_directory(directory),
+        _ => return Ok(None),
+    };
+    match result {
+        Ok(lock) => Ok(Some(lock)),
+        Err(e @ RecordingError::Locked { .. }) if args.flag("force") => {
+            warn!("{}; going ahead anyway (--force)", e);
+            Ok(None)
+        }
+        Err(e @ RecordingError::Locked { .. }) => {
+            error!("{}; pass --force to run anyway", e);
+            Err(e.code())
+        }
+        Err(e) => {
+            error!("Failed to take the lock: {}", e);
+            Err(e.code())
+        }
+    }
+}
+
+/**
+ * Records the shows scheduled in the configuration as they air, until
+ * stopped.
+ */
+fn daemon(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &["lock", "force"]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let root = match args.positional() {
+        [root] => PathBuf::from(root),
+        _ => {
+            error!(
+                "Usage: {} daemon <directory> [--max-recordings <n>] [--max-bandwidth <KB/s>] [--lock | --pid-file <file>] [--force] [--config <file>]",
+                program
+            );
+            return;
+        }
+    };
+    // Shared by overlapping captures; the command line takes precedence over [limits]
+    let limits = match (
+        args.parsed::<usize>("max-recordings"),
+        args.parsed::<u64>("max-bandwidth"),
+    ) {
+        (Ok(max_recordings), Ok(max_bandwidth)) => Limits {
+            max_recordings,
+            max_bandwidth,
+        },
+        (Err(e), _) | (_, Err(e)) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let config_path = args
+        .value("config")
+        .map(PathBuf::from)
+        .unwrap_or_else(Config::default_path);
+    let config = match LiveConfig::load(&config_path, limits) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let _lock = match lock_instance(&args, Some(&root)) {
+        Ok(lock) => lock,
+        Err(code) => std::process::exit(code.exit_status()),
+    };
+
+    let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
+    if let Err(e) = rt.block_on(midhyae::record_schedule(&root, config)) {
+        error!("Scheduler stopped: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/**
+ * Runs the REST API for starting and managing recording sessions.
+ */
+fn serve(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &["lock", "force"]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let root = match args.positional() {
+        [root] => PathBuf::from(root),
+        _ => {
+            error!(
+                "Usage: {} serve <directory> [--listen <address:port>] [--max-recordings <n>] [--max-bandwidth <KB/s>] [--lock | --pid-file <file>] [--force] [--config <file>]",
+                program
+            );
+            return;
+        }
+    };
+    let address = match args.value("listen").unwrap_or("127.0.0.1:8080").parse() {
+        Ok(address) => address,
+        Err(e) => {
+            error!("Invalid listen address: {}", e);
+            return;
+        }
+    };
+    // Shared by every session, on top of each session's own concurrency; the command line takes precedence over [limits]
+    let limits = match (
+        args.parsed::<usize>("max-recordings"),
+        args.parsed::<u64>("max-bandwidth"),
+    ) {
+        (Ok(max_recordings), Ok(max_bandwidth)) => Limits {
+            max_recordings,
+            max_bandwidth,
+        },
+        (Err(e), _) | (_, Err(e)) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let config_path = args
+        .value("config")
+        .map(PathBuf::from)
+        .unwrap_or_else(Config::default_path);
+    let config = match LiveConfig::load(&config_path, limits) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let _lock = match lock_instance(&args, Some(&root)) {
+        Ok(lock) => lock,
+        Err(code) => std::process::exit(code.exit_status()),
+    };
+
+    let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
+    if let Err(e) = rt.block_on(midhyae::serve(address, &root, config)) {
+        error!("Server stopped: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/**
+ * Serves a recordings directory read-only over HTTP.
+ */
+fn serve_archive(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &[]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let root = match args.positional() {
+        [root] => PathBuf::from(root),
+        _ => {
+            error!(
+                "Usage: {} serve-archive <directory> [--listen <address:port>] [--config <file>]",
+                program
+            );
+            return;
+        }
+    };
+    let address = match args.value("listen").unwrap_or("127.0.0.1:8081").parse() {
+        Ok(address) => address,
+        Err(e) => {
+            error!("Invalid listen address: {}", e);
+            return;
+        }
+    };
+    let config_path = args
+        .value("config")
+        .map(PathBuf::from)
+        .unwrap_or_else(Config::default_path);
+    let config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
+    if let Err(e) = rt.block_on(midhyae::serve_archive(address, &root, &config)) {
+        error!("Server stopped: {}", e);
+        std::process::exit(1);
     }
+}
+
+/**
+ * Discovers the streams of a country and hands them out to `work`ers,
+ * collecting their outcomes into one run.
+ */
+fn coordinate(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &[]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let (country, directory, duration) = match args.positional() {
+        [country, directory, duration] => (country, PathBuf::from(directory), duration),
+        _ => {
+            error!(
+                "Usage: {} coordinate <country> <directory> <duration> [--listen <address:port>] [--station <id|title>] [--replay <dir>] [--config <file>]",
+                program
+            );
+            return;
+        }
+    };
+    let duration = match duration.parse::<u64>() {
+        Ok(duration) => duration,
+        Err(_) => {
+            error!("Invalid duration: {}", duration);
+            return;
+        }
+    };
+    let address = match args.value("listen").unwrap_or("127.0.0.1:8090").parse() {
+        Ok(address) => address,
+        Err(e) => {
+            error!("Invalid listen address: {}", e);
+            return;
+        }
+    };
+    let config_path = args
+        .value("config")
+        .map(PathBuf::from)
+        .unwrap_or_else(Config::default_path);
+    let config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let store_path = Store::default_path();
+    let store = match Store::open(&store_path) {
+        Ok(store) => store,
+        Err(e) => {
+            error!("Failed to open store: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let country = &args[1];
-    let directory = &args[2];
-    let duration = args[3].parse::<u64>().unwrap_or_else(|_| {
-        error!("Invalid duration: {}", args[3]);
+    let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
+    let mut listener = Listener::new("http://radio.garden/api/ara/content/");
+    listener.add_country_aliases(&config.country_aliases);
+    if let Err(e) = listener.set_http_options(&config.http) {
+        error!("{}", e);
         std::process::exit(1);
+    }
+    if let Some(dir) = args.value("replay") {
+        listener.set_replay(Path::new(dir));
+    }
+    rt.block_on(async {
+        match listener.store_streams(country).await {
+            Ok(discovery) => log_discovery(&discovery),
+            Err(e) => {
+                error!("Failed to store streams: {}", e);
+                return;
+            }
+        }
+        if let Some(station) = args.value("station") {
+            let count = listener.filter_stations(&[station]);
+            info!("{} streams match station {}.", count, station);
+        }
+        listener.apply_favorites(store.favorites());
+        listener.apply_overrides(&config);
+
+        let mut run = Run::new(country, duration, SystemTime::now());
+        match listener
+            .coordinate(&listener.streams(), address, duration, &config.server)
+            .await
+        {
+            Ok(outcomes) => {
+                let failed = outcomes.iter().filter(|o| !o.ok()).count();
+                info!(
+                    "Workers recorded {} streams, {} failed.",
+                    outcomes.len() - failed,
+                    failed
+                );
+                run.outcomes = outcomes;
+                if failed > 0 {
+                    let failures = run.failures_by_category();
+                    info!("Failures by cause: {}", describe_failures(&failures));
+                }
+            }
+            Err(e) => error!("Coordination failed: {}", e),
+        }
+
+        // The recordings stay with the workers; only the summary is kept here
+        if let Err(e) = std::fs::create_dir_all(&directory)
+            .and_then(|_| midhyae::write_summary(&directory, &run))
+        {
+            error!("Failed to write summary: {}", e);
+        }
+        if let Err(e) = Store::append_run(&store_path, run) {
+            error!("Failed to save store {}: {}", store_path.display(), e);
+        }
     });
+}
+
+/**
+ * Records stations assigned by a `coordinate` process until it is done.
+ */
+fn work(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &[]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let (coordinator, directory) = match args.positional() {
+        [coordinator, directory] => (coordinator, directory),
+        _ => {
+            error!(
+                "Usage: {} work <coordinator_url> <directory> [--name <worker>] [--concurrency <n>] [--filename <template>] [--config <file>]",
+                program
+            );
+            return;
+        }
+    };
+    let concurrency = match args.parsed::<usize>("concurrency") {
+        Ok(concurrency) => concurrency,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let name = match args.value("name") {
+        Some(name) => name.to_string(),
+        None => format!("{}-{}", hostname(), std::process::id()),
+    };
+    let config_path = args
+        .value("config")
+        .map(PathBuf::from)
+        .unwrap_or_else(Config::default_path);
+    let config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
+    let mut listener = Listener::new("http://radio.garden/api/ara/content/");
+    if let Err(e) = listener.set_http_options(&config.http) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    if let Some(concurrency) = concurrency {
+        listener.set_concurrency(concurrency);
+    }
+    if let Some(filename) = args.value("filename") {
+        if let Err(e) = listener.set_filename_template(filename) {
+            error!("{}", e);
+            return;
+        }
+    }
+    let registry = Registry::default();
+    for table in config.post_process.iter().cloned() {
+        match registry.build(table) {
+            Ok(stage) => listener.add_post_processor(stage),
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+    }
+    info!("Working for {} as {}", coordinator, name);
+    match rt.block_on(listener.work(coordinator, directory, &name, &config.server)) {
+        Ok(outcomes) => {
+            let failed = outcomes.iter().filter(|o| !o.ok()).count();
+            info!(
+                "Coordinator done; recorded {} streams here, {} failed.",
+                outcomes.len() - failed,
+                failed
+            );
+        }
+        Err(e) => {
+            error!("Worker stopped: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/**
+ * Records jobs taken from the Redis queue in the configuration.
+ */
+fn consume(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &[]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    if !args.positional().is_empty() {
+        error!(
+            "Usage: {} consume [--name <consumer>] [--concurrency <n>] [--filename <template>] [--config <file>]",
+            program
+        );
+        return;
+    }
+    let concurrency = match args.parsed::<usize>("concurrency") {
+        Ok(concurrency) => concurrency,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let config_path = args
+        .value("config")
+        .map(PathBuf::from)
+        .unwrap_or_else(Config::default_path);
+    let config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let redis = match &config.redis {
+        Some(redis) => redis,
+        None => {
+            error!("No [redis] table in {}", config_path.display());
+            std::process::exit(1);
+        }
+    };
+    // Pending jobs are found again by name, so it must survive restarts
+    let name = args
+        .value("name")
+        .map(str::to_string)
+        .unwrap_or_else(hostname);
+
+    let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
+    let mut listener = Listener::new("http://radio.garden/api/ara/content/");
+    if let Err(e) = listener.set_http_options(&config.http) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    if let Some(concurrency) = concurrency {
+        listener.set_concurrency(concurrency);
+    }
+    if let Some(filename) = args.value("filename") {
+        if let Err(e) = listener.set_filename_template(filename) {
+            error!("{}", e);
+            return;
+        }
+    }
+    let registry = Registry::default();
+    for table in config.post_process.iter().cloned() {
+        match registry.build(table) {
+            Ok(stage) => listener.add_post_processor(stage),
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+    }
+    if let Err(e) = rt.block_on(listener.consume(redis, &name)) {
+        error!("Consumer stopped: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/**
+ * Name of this machine, used to tell workers and consumers apart.
+ */
+fn hostname() -> String {
+    env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "worker".to_string())
+}
+
+/**
+ * Runs the Telegram bot, which starts recordings on request from chat.
+ */
+fn bot(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &[]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    if !args.positional().is_empty() {
+        error!("Usage: {} bot [--config <file>]", program);
+        return;
+    }
+    let config_path = args
+        .value("config")
+        .map(PathBuf::from)
+        .unwrap_or_else(Config::default_path);
+    let config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let telegram = match &config.telegram {
+        Some(telegram) => telegram,
+        None => {
+            error!("No [telegram] table in {}", config_path.display());
+            std::process::exit(1);
+        }
+    };
+
+    let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
+    let mut listener = Listener::new("http://radio.garden/api/ara/content/");
+    if let Err(e) = listener.set_http_options(&config.http) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = rt.block_on(listener.run_bot(telegram, &config_path)) {
+        error!("Bot stopped: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/**
+ * Checks connectivity, the output directory and the clock, printing what is
+ * wrong and how to fix it.
+ */
+fn doctor(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &[]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let directory = match args.positional() {
+        [] => Path::new("."),
+        [directory] => Path::new(directory),
+        _ => {
+            error!("Usage: {} doctor [<directory>] [--config <file>]", program);
+            return;
+        }
+    };
+    let config_path = args
+        .value("config")
+        .map(PathBuf::from)
+        .unwrap_or_else(Config::default_path);
+    let config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
+    let mut listener = Listener::new("http://radio.garden/api/ara/content/");
+    if let Err(e) = listener.set_http_options(&config.http) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    let checks = rt.block_on(listener.diagnose(directory));
+    for check in &checks {
+        let status = match check.status {
+            Status::Ok => " ok ",
+            Status::Warning => "warn",
+            Status::Failed => "FAIL",
+        };
+        println!("[{}] {:<6} {}", status, check.name, check.detail);
+        if let Some(advice) = check.advice {
+            println!("              {}", advice);
+        }
+    }
+    if checks.iter().any(|c| c.status == Status::Failed) {
+        std::process::exit(1);
+    }
+}
+
+/**
+ * Finishes the runs that were interrupted, e.g. by a crash or power loss:
+ * partial recordings are marked in their sidecars and open uploads are
+ * completed. Must not be run while another recording is in progress.
+ */
+fn recover(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &[]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    if !args.positional().is_empty() {
+        error!("Usage: {} recover", program);
+        return;
+    }
+
+    let listener = Listener::new("http://radio.garden/api/ara/content/");
+    let journals = match listener.pending_journals() {
+        Ok(journals) => journals,
+        Err(e) => {
+            error!("Failed to look for interrupted runs: {}", e);
+            return;
+        }
+    };
+    if journals.is_empty() {
+        println!("No interrupted runs.");
+        return;
+    }
+
+    let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
+    for journal in journals {
+        match rt.block_on(listener.recover_journal(&journal)) {
+            Ok(recovery) => println!(
+                "{}: marked {} partial recordings, finished {} uploads",
+                journal.display(),
+                recovery.partial_recordings,
+                recovery.uploads
+            ),
+            Err(e) => error!("Failed to recover {}: {}", journal.display(), e),
+        }
+    }
+}
+
+/**
+ * Writes podcast feeds over the recordings in an archive directory.
+ */
+fn feed(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &[]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let (directory, base_url) = match (args.positional(), args.value("base-url")) {
+        ([directory], Some(base_url)) => (Path::new(directory), base_url),
+        _ => {
+            error!(
+                "Usage: {} feed <directory> --base-url <url> [--group all|station|country] [--title <title>]",
+                program
+            );
+            return;
+        }
+    };
+    // A base URL without a trailing slash would lose its last segment
+    let base_url = match Url::parse(&format!("{}/", base_url.trim_end_matches('/'))) {
+        Ok(url) => url,
+        Err(e) => {
+            error!("Invalid base URL {}: {}", base_url, e);
+            return;
+        }
+    };
+    let grouping = match args.parsed::<FeedGrouping>("group") {
+        Ok(grouping) => grouping.unwrap_or(FeedGrouping::All),
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    // Feeds are named after the archive directory by default
+    let title = match args.value("title") {
+        Some(title) => title.to_string(),
+        None => std::path::absolute(directory)
+            .ok()
+            .and_then(|d| d.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "radafi".to_string()),
+    };
+
+    let episodes = match midhyae::scan_archive(directory) {
+        Ok(episodes) => episodes,
+        Err(e) => {
+            error!("Failed to read {}: {}", directory.display(), e);
+            return;
+        }
+    };
+    match midhyae::write_feeds(directory, &episodes, &base_url, grouping, &title) {
+        Ok(feeds) => {
+            for feed in feeds {
+                info!("Wrote {}", feed.display());
+            }
+            info!("{} recordings in the feeds.", episodes.len());
+        }
+        Err(e) => error!("Failed to write feeds: {}", e),
+    }
+}
+
+/**
+ * Lists the recordings in an archive that match the given criteria.
+ */
+fn find(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &["paths"]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let directory = match args.positional() {
+        [directory] => Path::new(directory),
+        _ => {
+            error!(
+                "Usage: {} find <directory> [--station <id|name>] [--country <country>] [--since <YYYY-MM-DD>] [--until <YYYY-MM-DD>] [--min-duration <length>] [--max-duration <length>] [--tag <name>[=<value>]]... [--min-quality <score>] [--sort <started|quality>] [--paths]",
+                program
+            );
+            return;
+        }
+    };
+    let query = match catalog_query(&args) {
+        Ok(query) => query,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    let episodes = match midhyae::scan_archive(directory) {
+        Ok(episodes) => episodes,
+        Err(e) => {
+            error!("Failed to read {}: {}", directory.display(), e);
+            return;
+        }
+    };
+    let mut matches: Vec<_> = episodes.iter().filter(|e| query.matches(e)).collect();
+    match args.value("sort") {
+        None | Some("started") => {}
+        // Most usable first, unscored recordings last
+        Some("quality") => matches.sort_by_key(|e| std::cmp::Reverse(e.quality)),
+        Some(other) => {
+            error!("Invalid sort order {}, expected started or quality", other);
+            return;
+        }
+    }
+    // Bare paths are meant for piping into other tools
+    if args.flag("paths") {
+        for episode in &matches {
+            println!("{}", directory.join(&episode.path).display());
+        }
+        return;
+    }
+    println!(
+        "{:<16} {:>8} {:>7}  {:<30} path",
+        "started", "length", "quality", "station"
+    );
+    for episode in &matches {
+        println!(
+            "{:<16} {:>8} {:>7}  {:<30} {}",
+            episode.started.format("%Y-%m-%d %H:%M"),
+            format_length(episode.duration),
+            episode
+                .quality
+                .map_or_else(|| "-".to_string(), |quality| quality.to_string()),
+            episode.station,
+            directory.join(&episode.path).display()
+        );
+    }
+    let total: u64 = matches.iter().map(|e| e.duration).sum();
+    println!();
+    println!(
+        "{} of {} recordings, {} in total",
+        matches.len(),
+        episodes.len(),
+        format_length(total)
+    );
+}
+
+fn catalog_query(args: &Args) -> Result<Query, String> {
+    Ok(Query {
+        station: args.value("station").map(str::to_string),
+        country: args.value("country").map(str::to_string),
+        since: args.parsed("since")?,
+        until: args.parsed("until")?,
+        min_duration: args.parsed::<Length>("min-duration")?,
+        max_duration: args.parsed::<Length>("max-duration")?,
+        min_quality: args.parsed("min-quality")?,
+        tags: args
+            .values("tag")
+            .iter()
+            .map(|tag| match tag.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (tag.to_string(), None),
+            })
+            .collect(),
+    })
+}
+
+fn format_length(seconds: u64) -> String {
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/**
+ * Dumps Radio Garden places with coordinates and station counts as CSV or
+ * GeoJSON, to stdout or a file.
+ */
+fn export_places(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &[]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let format = args.value("format").unwrap_or("csv");
+    if !args.positional().is_empty() || !matches!(format, "csv" | "geojson") {
+        error!(
+            "Usage: {} export-places [--format csv|geojson] [--country <country>] [--output <file>] [--replay <dir>]",
+            program
+        );
+        return;
+    }
+
+    let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
+    let mut listener = Listener::new("http://radio.garden/api/ara/content/");
+    if let Some(dir) = args.value("replay") {
+        listener.set_replay(Path::new(dir));
+    }
+    let places = match args.value("country") {
+        Some(country) => rt.block_on(listener.fetch_places(country)),
+        None => rt.block_on(listener.fetch_all_places()),
+    };
+    let places = match places {
+        Ok(places) => places,
+        Err(e) => {
+            error!("Failed to fetch places: {}", e);
+            return;
+        }
+    };
+
+    let mut out: Box<dyn Write> = match args.value("output") {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(e) => {
+                error!("Failed to create {}: {}", path, e);
+                return;
+            }
+        },
+        None => Box::new(io::stdout().lock()),
+    };
+    let result = match format {
+        "geojson" => midhyae::write_places_geojson(&places, &mut out),
+        _ => midhyae::write_places_csv(&places, &mut out),
+    };
+    if let Err(e) = result.and_then(|()| out.flush()) {
+        error!("Failed to write places: {}", e);
+        return;
+    }
+    info!("Exported {} places.", places.len());
+}
+
+/**
+ * Lists the countries Radio Garden has stations in, with how many places
+ * and stations each has, as valid values of the country argument.
+ */
+fn countries(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &[]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let sort = args.value("sort").unwrap_or("name");
+    if !args.positional().is_empty() || !matches!(sort, "name" | "places" | "stations") {
+        error!(
+            "Usage: {} countries [--sort name|places|stations] [--replay <dir>]",
+            program
+        );
+        return;
+    }
+
+    let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
+    let mut listener = Listener::new("http://radio.garden/api/ara/content/");
+    if let Some(dir) = args.value("replay") {
+        listener.set_replay(Path::new(dir));
+    }
+    let places = match rt.block_on(listener.fetch_all_places()) {
+        Ok(places) => places,
+        Err(e) => {
+            error!("Failed to fetch places: {}", e);
+            return;
+        }
+    };
+    let mut counts = midhyae::count_countries(&places);
+    match sort {
+        "places" => counts.sort_by_key(|c| std::cmp::Reverse(c.places)),
+        "stations" => counts.sort_by_key(|c| std::cmp::Reverse(c.stations)),
+        _ => {}
+    }
+    let width = counts
+        .iter()
+        .map(|c| c.country.chars().count())
+        .fold("country".len(), usize::max);
+    println!(
+        "{:<width$}  {:>6}  {:>8}",
+        "country",
+        "places",
+        "stations",
+        width = width
+    );
+    for count in &counts {
+        println!(
+            "{:<width$}  {:>6}  {:>8}",
+            count.country,
+            count.places,
+            count.stations,
+            width = width
+        );
+    }
+    info!("Listed {} countries.", counts.len());
+}
+
+/**
+ * Lists the places of a country with their IDs, coordinates and number of
+ * stations, one per line and tab-separated so the IDs can be cut out and
+ * passed to `--place`.
+ */
+fn places(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &[]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let min_stations = match args.parsed::<u32>("min-stations") {
+        Ok(min) => min.unwrap_or(0),
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let country = match args.positional() {
+        [country] => country,
+        _ => {
+            error!(
+                "Usage: {} places <country> [--title <text>] [--min-stations <n>] [--replay <dir>]",
+                program
+            );
+            return;
+        }
+    };
+
+    let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
+    let mut listener = Listener::new("http://radio.garden/api/ara/content/");
+    if let Some(dir) = args.value("replay") {
+        listener.set_replay(Path::new(dir));
+    }
+    let places = match rt.block_on(listener.fetch_places(country)) {
+        Ok(places) => places,
+        Err(e) => {
+            error!("Failed to fetch places: {}", e);
+            return;
+        }
+    };
+    let title = args.value("title").map(str::to_lowercase);
+    let mut listed = 0;
+    for place in places {
+        let matches = title
+            .as_ref()
+            .is_none_or(|title| place.title.to_lowercase().contains(title.as_str()));
+        if !matches || place.size < min_stations {
+            continue;
+        }
+        // Radio Garden gives coordinates as [longitude, latitude]
+        let (latitude, longitude) = match place.geo.as_slice() {
+            [longitude, latitude, ..] => (latitude.to_string(), longitude.to_string()),
+            _ => (String::new(), String::new()),
+        };
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            place.id, place.title, latitude, longitude, place.size
+        );
+        listed += 1;
+    }
+    info!("Listed {} places.", listed);
+}
+
+/**
+ * Reports stations that failed in at least a given share of recent runs.
+ */
+fn stats(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &["dead"]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    if !args.positional().is_empty() {
+        error!(
+            "Usage: {} stats [--country <country>] [--runs <n>] [--min-failure <percent> | --by country|place | --dead]",
+            program
+        );
+        return;
+    }
+    let (runs, min_failure, by) = match (
+        args.parsed::<usize>("runs"),
+        args.parsed::<f64>("min-failure"),
+        args.parsed::<UsageGrouping>("by"),
+    ) {
+        // Totals cover every run unless limited
+        (Ok(runs), Ok(min_failure), Ok(by)) => (
+            runs.unwrap_or(if by.is_some() { usize::MAX } else { 30 }),
+            min_failure.unwrap_or(0.0),
+            by,
+        ),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    let store = match Store::open(&Store::default_path()) {
+        Ok(store) => store,
+        Err(e) => {
+            error!("Failed to open store: {}", e);
+            return;
+        }
+    };
+
+    if args.flag("dead") {
+        println!(
+            "{:<20} {:<40} {:<20} {:<10}",
+            "id", "station", "since", "cause"
+        );
+        for dead in store.dead_stations() {
+            let since = chrono::Local
+                .timestamp_opt(dead.since as i64, 0)
+                .single()
+                .map(|since| since.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            println!(
+                "{:<20} {:<40} {:<20} {:<10}",
+                dead.id,
+                dead.name,
+                since,
+                dead.category.name()
+            );
+        }
+        return;
+    }
+
+    if let Some(by) = by {
+        println!(
+            "{:<40} {:>10} {:>10} {:>10}",
+            "name", "recordings", "hours", "MB"
+        );
+        for usage in store.usage(by, args.value("country"), runs) {
+            println!(
+                "{:<40} {:>10} {:>10.1} {:>10.1}",
+                usage.name,
+                usage.recordings,
+                usage.seconds as f64 / 3600.0,
+                usage.bytes as f64 / 1_000_000.0
+            );
+        }
+        return;
+    }
+
+    println!(
+        "{:<20} {:<40} {:>8} {:>8} {:>8}",
+        "id", "station", "runs", "failed", "rate"
+    );
+    for station in store.reliability(args.value("country"), runs) {
+        let rate = station.failure_rate() * 100.0;
+        if rate < min_failure {
+            continue;
+        }
+        println!(
+            "{:<20} {:<40} {:>8} {:>8} {:>7.0}%",
+            station.id, station.name, station.attempts, station.failures, rate
+        );
+    }
+
+    let failures = store.failures_by_category(args.value("country"), runs);
+    if !failures.is_empty() {
+        println!();
+        println!("Failures by cause: {}", describe_failures(&failures));
+    }
+}
+
+/**
+ * Reports the streams found and any places that had to be skipped.
+ */
+fn log_discovery(discovery: &Discovery) {
+    for failure in &discovery.failed_places {
+        warn!(
+            "Skipped place {} ({}): {}",
+            failure.place.title, failure.place.id, failure.error
+        );
+    }
+    for url in &discovery.malformed_urls {
+        warn!("Skipped channel with malformed page URL: {}", url);
+    }
+    if discovery.duplicates > 0 {
+        info!(
+            "Merged {} listings of channels found under several places.",
+            discovery.duplicates
+        );
+    }
+    if discovery.failed_places.is_empty() {
+        info!("Stored {} streams.", discovery.streams.len());
+    } else {
+        info!(
+            "Stored {} streams; {} places failed.",
+            discovery.streams.len(),
+            discovery.failed_places.len()
+        );
+    }
+}
+
+/**
+ * Shows the run report on the desktop; `alert` marks it as critical.
+ */
+#[cfg(feature = "desktop-notifications")]
+fn show_notification(report: &Report, alert: bool) {
+    if let Err(e) = midhyae::notify_desktop(report, alert) {
+        warn!("Failed to show desktop notification: {}", e);
+    }
+}
+
+// Unreachable: the notification options are rejected without the feature
+#[cfg(not(feature = "desktop-notifications"))]
+fn show_notification(_report: &Report, _alert: bool) {}
+
+/**
+ * Formats failure counts as e.g. "dns 2, timeout 1".
+ */
+fn describe_failures(failures: &BTreeMap<ErrorCategory, usize>) -> String {
+    failures
+        .iter()
+        .map(|(category, count)| format!("{} {}", category.name(), count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/**
+ * Connects briefly to every stream of a country and reports time to first
+ * byte, throughput and error rate, to help choose `--concurrency`.
+ */
+fn bench(program: &str, raw: &[String]) {
+    let args = match Args::parse(raw, &[]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let country = match args.positional().first() {
+        Some(country) => country,
+        None => {
+            error!(
+                "Usage: {} bench <country> [--seconds <window>] [--concurrency <n>]",
+                program
+            );
+            return;
+        }
+    };
+    let (window, concurrency) = match (
+        args.parsed::<u64>("seconds"),
+        args.parsed::<usize>("concurrency"),
+    ) {
+        (Ok(window), Ok(concurrency)) => (window.unwrap_or(10), concurrency),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("{}", e);
+            return;
+        }
+    };
 
     let rt: runtime::Runtime = runtime::Runtime::new().expect("Failed to create a runtime");
     let mut listener = Listener::new("http://radio.garden/api/ara/content/");
+    if let Some(concurrency) = concurrency {
+        listener.set_concurrency(concurrency);
+    }
 
     rt.block_on(async {
-        // Store streams for the given country
         match listener.store_streams(country).await {
-            Ok(count) => info!("Stored {} streams.", count),
-            Err(e) => error!("Failed to store streams: {}", e),
+            Ok(discovery) => log_discovery(&discovery),
+            Err(e) => {
+                error!("Failed to store streams: {}", e);
+                return;
+            }
         }
 
-        // Record streams
-        match listener.record_streams(duration, directory).await {
-            Ok(()) => info!("Successfully recorded streams."),
-            Err(e) => error!("Failed to record streams: {}", e),
+        let started = std::time::Instant::now();
+        let results = listener.bench_streams(Duration::from_secs(window)).await;
+        let wall = started.elapsed().as_secs_f64();
+
+        println!(
+            "{:<40} {:>10} {:>12}  error",
+            "station", "ttfb (ms)", "kbit/s"
+        );
+        for result in &results {
+            let ttfb = result
+                .first_byte
+                .map(|d| d.as_millis().to_string())
+                .unwrap_or_else(|| "-".to_string());
+            println!(
+                "{:<40} {:>10} {:>12.1}  {}",
+                result.name,
+                ttfb,
+                result.kbps(),
+                result.error.as_deref().unwrap_or("")
+            );
+        }
+
+        let failed = results.iter().filter(|r| r.error.is_some()).count();
+        let ok: Vec<_> = results.iter().filter(|r| r.error.is_none()).collect();
+        let total_bytes: u64 = results.iter().map(|r| r.bytes).sum();
+        println!();
+        println!(
+            "{} streams, {} failed ({:.0}% error rate)",
+            results.len(),
+            failed,
+            if results.is_empty() {
+                0.0
+            } else {
+                failed as f64 * 100.0 / results.len() as f64
+            }
+        );
+        if !ok.is_empty() {
+            let mut ttfbs: Vec<u128> = ok
+                .iter()
+                .filter_map(|r| r.first_byte.map(|d| d.as_millis()))
+                .collect();
+            ttfbs.sort_unstable();
+            let per_stream = ok.iter().map(|r| r.kbps()).sum::<f64>() / ok.len() as f64;
+            println!("median time to first byte: {} ms", ttfbs[ttfbs.len() / 2]);
+            println!("mean per-stream throughput: {:.1} kbit/s", per_stream);
+            println!(
+                "aggregate throughput: {:.1} kbit/s",
+                total_bytes as f64 * 8.0 / 1000.0 / wall
+            );
+            println!(
+                "each additional concurrent recording needs roughly {:.0} kbit/s of downlink",
+                per_stream
+            );
         }
     });
 }
+
+/**
+ * Adds, removes or lists favorite stations in the persistent store.
+ */
+fn favorites(program: &str, raw: &[String]) {
+    let usage = || {
+        error!(
+            "Usage: {} favorites <add <channel_id> [--name <name>] [--duration <seconds>] | remove <channel_id> | list>",
+            program
+        );
+    };
+    let args = match Args::parse(raw, &[]) {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    let path = Store::default_path();
+    let mut store = match Store::open(&path) {
+        Ok(store) => store,
+        Err(e) => {
+            error!("Failed to open store {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    let positional = args.positional();
+    match (positional.first().map(String::as_str), positional.get(1)) {
+        (Some("add"), Some(id)) => {
+            let id: ChannelId = match id.parse() {
+                Ok(id) => id,
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            };
+            let duration = match args.parsed::<u64>("duration") {
+                Ok(duration) => duration,
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            };
+            let favorite = Favorite {
+                id: id.clone(),
+                name: args.value("name").map(str::to_string),
+                duration,
+            };
+            if store.add_favorite(favorite) {
+                info!("Updated favorite {}.", id);
+            } else {
+                info!("Added favorite {}.", id);
+            }
+        }
+        (Some("remove"), Some(id)) => {
+            let id: ChannelId = match id.parse() {
+                Ok(id) => id,
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            };
+            if !store.remove_favorite(&id) {
+                error!("{} is not a favorite.", id);
+                return;
+            }
+            info!("Removed favorite {}.", id);
+        }
+        (Some("list"), None) => {
+            for favorite in store.favorites() {
+                let duration = favorite
+                    .duration
+                    .map(|d| format!("{}s", d))
+                    .unwrap_or_else(|| "default".to_string());
+                println!(
+                    "{}\t{}\t{}",
+                    favorite.id,
+                    favorite.name.as_deref().unwrap_or("-"),
+                    duration
+                );
+            }
+            return;
+        }
+        _ => {
+            usage();
+            return;
+        }
+    }
+
+    if let Err(e) = store.save() {
+        error!("Failed to save store {}: {}", path.display(), e);
+    }
+}
@@ -0,0 +1,235 @@
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use log::{info, warn};
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::RecordingError;
+
+/**
+ * Access to the HTTP servers (`serve`, `serve-archive` and `coordinate`),
+ * read from a `[server]` table:
+ *
+ *   [server]
+ *   token = "long random string"    # Authorization: Bearer <token>
+ *   username = "radafi"             # HTTP basic authentication
+ *   password = "secret"
+ *   tls_cert = "/etc/radafi/cert.pem"
+ *   tls_key = "/etc/radafi/key.pem"
+ *
+ * Requests must carry the token or the credentials if either is set. With a
+ * certificate chain and private key (PEM), the servers speak HTTPS only.
+ */
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ServerOptions {
+    pub token: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+}
+
+impl ServerOptions {
+    pub fn validate(&self) -> Result<(), RecordingError> {
+        let invalid = |reason: &str| RecordingError::Config(format!("server: {}", reason));
+        if self.username.is_some() != self.password.is_some() {
+            return Err(invalid("username and password go together"));
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err(invalid("tls_cert and tls_key go together"));
+        }
+        if self.token.as_deref() == Some("") {
+            return Err(invalid("token is empty"));
+        }
+        Ok(())
+    }
+
+    fn requires_auth(&self) -> bool {
+        self.token.is_some() || self.username.is_some()
+    }
+
+    /**
+     * Whether the `Authorization` header in `headers` carries the token or
+     * the credentials.
+     */
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        let authorization = match headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(authorization) => authorization,
+            None => return false,
+        };
+        let (scheme, value) = authorization.split_once(' ').unwrap_or((authorization, ""));
+        if scheme.eq_ignore_ascii_case("bearer") {
+            if let Some(token) = &self.token {
+                return constant_time_eq(value.trim().as_bytes(), token.as_bytes());
+            }
+        }
+        if scheme.eq_ignore_ascii_case("basic") {
+            if let (Some(username), Some(password)) = (&self.username, &self.password) {
+                let expected = format!("{}:{}", username, password);
+                return STANDARD
+                    .decode(value.trim())
+                    .is_ok_and(|given| constant_time_eq(&given, expected.as_bytes()));
+            }
+        }
+        false
+    }
+
+    /**
+     * Adds the configured token or credentials to a request to a server
+     * with the same `[server]` settings.
+     */
+    pub fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match (&self.token, &self.username) {
+            (Some(token), _) => request.bearer_auth(token),
+            (None, Some(username)) => request.basic_auth(username, self.password.as_ref()),
+            (None, None) => request,
+        }
+    }
+
+    /**
+     * Serves `app` on `address` behind the configured authentication, over
+     * TLS if a certificate is configured. `name` describes the server in the
+     * log.
+     */
+    pub async fn serve(
+        &self,
+        address: SocketAddr,
+        app: Router,
+        name: &str,
+    ) -> Result<(), RecordingError> {
+        self.serve_until(address, app, name, std::future::pending())
+            .await
+    }
+
+    /**
+     * Like `serve`, but stops accepting connections once `shutdown`
+     * completes.
+     */
+    pub async fn serve_until<F>(
+        &self,
+        address: SocketAddr,
+        app: Router,
+        name: &str,
+        shutdown: F,
+    ) -> Result<(), RecordingError>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let app = match self.requires_auth() {
+            true => app.layer(middleware::from_fn_with_state(
+                Arc::new(self.clone()),
+                authenticate,
+            )),
+            false => app,
+        };
+        let tls = match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Some(tls_acceptor(cert, key)?),
+            _ => None,
+        };
+
+        let listener = TcpListener::bind(address).await?;
+        let tls = match tls {
+            Some(tls) => tls,
+            None => {
+                info!("Serving {} on http://{}", name, listener.local_addr()?);
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown)
+                    .await?;
+                return Ok(());
+            }
+        };
+        info!("Serving {} on https://{}", name, listener.local_addr()?);
+        tokio::pin!(shutdown);
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = &mut shutdown => return Ok(()),
+            };
+            let tls = tls.clone();
+            let service = TowerToHyperService::new(app.clone());
+            tokio::spawn(async move {
+                let stream = match tls.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("TLS handshake with {} failed: {}", peer, e);
+                        return;
+                    }
+                };
+                if let Err(e) = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .with_upgrades()
+                    .await
+                {
+                    warn!("Connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+async fn authenticate(
+    State(options): State<Arc<ServerOptions>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if options.authorized(request.headers()) {
+        return next.run(request).await;
+    }
+    // Make browsers ask for the credentials
+    match options.username.is_some() {
+        true => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"radafi\"")],
+        )
+            .into_response(),
+        false => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/**
+ * Compares secrets in time independent of where they differ.
+ */
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn tls_acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor, RecordingError> {
+    let invalid = |path: &Path, reason: String| {
+        RecordingError::Config(format!("server: {}: {}", path.display(), reason))
+    };
+    let chain = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid(cert, e.to_string()))?;
+    if chain.is_empty() {
+        return Err(invalid(cert, "no certificates".to_string()));
+    }
+    let key_der = rustls_pemfile::private_key(&mut BufReader::new(File::open(key)?))
+        .map_err(|e| invalid(key, e.to_string()))?
+        .ok_or_else(|| invalid(key, "no private key".to_string()))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(chain, key_der)
+        .map_err(|e| invalid(cert, e.to_string()))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
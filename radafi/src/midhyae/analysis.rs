@@ -0,0 +1,309 @@
+use minimp3::{Decoder, Error as Mp3Error, Frame};
+use serde::Serialize;
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use super::RecordingError;
+
+/**
+ * Decodes MP3 audio from `reader`, mixing every frame down to mono samples
+ * in [-1, 1] and passing them to `sink` along with the sample rate. Returns
+ * the number of frames decoded.
+ */
+pub fn decode_mono<R, F>(reader: R, mut sink: F) -> Result<u64, RecordingError>
+where
+    R: Read,
+    F: FnMut(&[f32], u32),
+{
+    let mut decoder = Decoder::new(reader);
+    let mut mono = Vec::new();
+    let mut frames = 0;
+    loop {
+        let frame = match decoder.next_frame() {
+            Ok(frame) => frame,
+            Err(Mp3Error::Eof) | Err(Mp3Error::InsufficientData) => break,
+            Err(Mp3Error::SkippedData) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        mix_down(&frame, &mut mono);
+        sink(&mono, frame.sample_rate.max(1) as u32);
+        frames += 1;
+    }
+    Ok(frames)
+}
+
+/**
+ * Replaces the contents of `mono` with the samples of `frame` mixed down to
+ * mono, in [-1, 1].
+ */
+pub fn mix_down(frame: &Frame, mono: &mut Vec<f32>) {
+    let channels = frame.channels.max(1);
+    mono.clear();
+    mono.extend(frame.data.chunks(channels).map(|samples| {
+        samples.iter().map(|&s| s as f32).sum::<f32>() / (channels as f32 * 32768.0)
+    }));
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Speech/music/silence classification of a recording, based on short-time
+ * energy and zero-crossing statistics over one-second segments.
+ */
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentKind {
+    Speech,
+    Music,
+    Silence,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Segment {
+    pub start: u64, // Seconds from the beginning of the recording
+    pub end: u64,
+    pub kind: SegmentKind,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Classification {
+    pub speech_seconds: u64,
+    pub music_seconds: u64,
+    pub silence_seconds: u64,
+    pub segments: Vec<Segment>, // Consecutive seconds of the same kind merged
+}
+
+const FRAME_MS: u32 = 20; // Analysis frame length
+pub const SILENCE_RMS: f32 = 0.01; // Below roughly -40 dBFS counts as silence
+const SPEECH_LOW_ENERGY_RATIO: f32 = 0.15; // Pauses between syllables
+const SPEECH_ZCR_VARIATION: f32 = 0.6; // Voiced/unvoiced alternation
+
+/**
+ * Accumulates 20 ms frame features and classifies each full second.
+ */
+#[derive(Default)]
+pub struct Classifier {
+    frame_len: usize,
+    frame_energy: f32,
+    frame_crossings: u32,
+    frame_samples: usize,
+    last_sample: f32,
+    energies: Vec<f32>, // Mean square energy of each frame in the segment
+    zcrs: Vec<f32>,     // Zero-crossing rate of each frame in the segment
+    result: Classification,
+}
+
+impl Classifier {
+    pub fn push(&mut self, samples: &[f32], sample_rate: u32) {
+        self.frame_len = (sample_rate * FRAME_MS / 1000) as usize;
+        // Too low a rate for a single sample per frame
+        if self.frame_len == 0 {
+            return;
+        }
+        for &sample in samples {
+            self.frame_energy += sample * sample;
+            if (sample >= 0.0) != (self.last_sample >= 0.0) {
+                self.frame_crossings += 1;
+            }
+            self.last_sample = sample;
+            self.frame_samples += 1;
+
+            // A frame may have grown past a length shortened by a rate change
+            if self.frame_samples >= self.frame_len {
+                let samples = self.frame_samples as f32;
+                self.energies.push(self.frame_energy / samples);
+                self.zcrs.push(self.frame_crossings as f32 / samples);
+                self.frame_energy = 0.0;
+                self.frame_crossings = 0;
+                self.frame_samples = 0;
+
+                if self.energies.len() as u32 * FRAME_MS >= 1000 {
+                    self.finish_segment();
+                }
+            }
+        }
+    }
+
+    /**
+     * Number of seconds classified so far.
+     */
+    pub fn seconds(&self) -> u64 {
+        self.result.speech_seconds + self.result.music_seconds + self.result.silence_seconds
+    }
+
+    /**
+     * Kind of the last second classified.
+     */
+    pub fn last_kind(&self) -> Option<SegmentKind> {
+        self.result.segments.last().map(|segment| segment.kind)
+    }
+
+    fn finish_segment(&mut self) {
+        let kind = classify(&self.energies, &self.zcrs);
+        self.energies.clear();
+        self.zcrs.clear();
+
+        let second = self.seconds();
+        match kind {
+            SegmentKind::Speech => self.result.speech_seconds += 1,
+            SegmentKind::Music => self.result.music_seconds += 1,
+            SegmentKind::Silence => self.result.silence_seconds += 1,
+        }
+        match self.result.segments.last_mut() {
+            Some(last) if last.kind == kind => last.end = second + 1,
+            _ => self.result.segments.push(Segment {
+                start: second,
+                end: second + 1,
+                kind,
+            }),
+        }
+    }
+}
+
+/**
+ * Classifies one segment from its per-frame energies and zero-crossing
+ * rates. Speech alternates between voiced sounds, unvoiced sounds and short
+ * pauses, so it shows many low-energy frames and a strongly varying
+ * zero-crossing rate; music is comparatively steady.
+ */
+fn classify(energies: &[f32], zcrs: &[f32]) -> SegmentKind {
+    let n = energies.len().max(1) as f32;
+    let mean_energy = energies.iter().sum::<f32>() / n;
+    if mean_energy.sqrt() < SILENCE_RMS {
+        return SegmentKind::Silence;
+    }
+
+    let low_energy = energies.iter().filter(|&&e| e < 0.5 * mean_energy).count() as f32 / n;
+    let mean_zcr = zcrs.iter().sum::<f32>() / n;
+    let zcr_std = (zcrs.iter().map(|z| (z - mean_zcr).powi(2)).sum::<f32>() / n).sqrt();
+    let zcr_variation = if mean_zcr > 0.0 {
+        zcr_std / mean_zcr
+    } else {
+        0.0
+    };
+
+    if low_energy > SPEECH_LOW_ENERGY_RATIO && zcr_variation > SPEECH_ZCR_VARIATION {
+        SegmentKind::Speech
+    } else {
+        SegmentKind::Music
+    }
+}
+
+/**
+ * Decodes the MP3 file at `path` and classifies each second of it as
+ * speech, music or silence.
+ */
+pub fn classify_file(path: &Path) -> Result<Classification, RecordingError> {
+    let mut classifier = Classifier::default();
+    decode_mono(BufReader::new(File::open(path)?), |samples, rate| {
+        classifier.push(samples, rate)
+    })?;
+    Ok(classifier.result)
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Loudness of a recording, per minute and overall, in dBFS.
+ */
+#[derive(Serialize, Debug, Clone)]
+pub struct MinuteLevel {
+    pub minute: u64,
+    pub rms_dbfs: f32,
+    pub peak_dbfs: f32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Levels {
+    pub rms_dbfs: f32,
+    pub peak_dbfs: f32,
+    pub minutes: Vec<MinuteLevel>,
+    pub dead_air: bool, // Effectively silent throughout
+}
+
+const DEAD_AIR_DBFS: f32 = -50.0; // Seconds quieter than this are dead air
+const DEAD_AIR_RATIO: f32 = 0.9; // Share of dead seconds that flags a recording
+
+fn dbfs(amplitude: f32) -> f32 {
+    (20.0 * amplitude.max(1e-6).log10()).max(-120.0)
+}
+
+/**
+ * Accumulates the energy and peak of a span of samples.
+ */
+#[derive(Default, Clone, Copy)]
+struct Span {
+    energy: f64,
+    peak: f32,
+    samples: u64,
+}
+
+impl Span {
+    fn add(&mut self, sample: f32) {
+        self.energy += (sample * sample) as f64;
+        self.peak = self.peak.max(sample.abs());
+        self.samples += 1;
+    }
+
+    fn rms(&self) -> f32 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        (self.energy / self.samples as f64).sqrt() as f32
+    }
+}
+
+/**
+ * Decodes the MP3 file at `path` and measures its RMS and peak levels per
+ * minute, flagging recordings where nearly every second is below
+ * `DEAD_AIR_DBFS`.
+ */
+pub fn measure_levels(path: &Path) -> Result<Levels, RecordingError> {
+    let mut total = Span::default();
+    let mut minute = Span::default();
+    let mut second = Span::default();
+    let mut minutes = Vec::new();
+    let (mut seconds, mut dead_seconds) = (0u64, 0u64);
+
+    decode_mono(BufReader::new(File::open(path)?), |samples, rate| {
+        let rate = rate as u64;
+        for &sample in samples {
+            total.add(sample);
+            minute.add(sample);
+            second.add(sample);
+            if second.samples == rate {
+                seconds += 1;
+                if dbfs(second.rms()) < DEAD_AIR_DBFS {
+                    dead_seconds += 1;
+                }
+                second = Span::default();
+            }
+            if minute.samples == rate * 60 {
+                minutes.push(MinuteLevel {
+                    minute: minutes.len() as u64,
+                    rms_dbfs: dbfs(minute.rms()),
+                    peak_dbfs: dbfs(minute.peak),
+                });
+                minute = Span::default();
+            }
+        }
+    })?;
+    if minute.samples > 0 {
+        minutes.push(MinuteLevel {
+            minute: minutes.len() as u64,
+            rms_dbfs: dbfs(minute.rms()),
+            peak_dbfs: dbfs(minute.peak),
+        });
+    }
+
+    Ok(Levels {
+        rms_dbfs: dbfs(total.rms()),
+        peak_dbfs: dbfs(total.peak),
+        minutes,
+        dead_air: if seconds > 0 {
+            dead_seconds as f32 >= seconds as f32 * DEAD_AIR_RATIO
+        } else {
+            dbfs(total.rms()) < DEAD_AIR_DBFS
+        },
+    })
+}
@@ -0,0 +1,94 @@
+use log::info;
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/**
+ * Packs `dir` into `<dir>.tar.zst`, verifies that every file in the archive
+ * matches the original byte for byte, and only then removes `dir`.
+ * Returns the path of the archive.
+ */
+pub fn compress_dir(dir: &Path) -> io::Result<PathBuf> {
+    let name = dir
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "directory has no name"))?;
+    let mut archive_name = name.to_owned();
+    archive_name.push(".tar.zst");
+    let archive = dir.with_file_name(archive_name);
+
+    let encoder = zstd::Encoder::new(File::create(&archive)?, 0)?;
+    let mut builder = tar::Builder::new(encoder);
+    builder.follow_symlinks(false);
+    builder.append_dir_all(name, dir)?;
+    builder.into_inner()?.finish()?.sync_all()?;
+
+    verify(dir, &archive)?;
+    fs::remove_dir_all(dir)?;
+    info!("Compressed {} into {}", dir.display(), archive.display());
+    Ok(archive)
+}
+
+/**
+ * Checks that `archive` contains exactly the regular files of `dir` with
+ * identical contents.
+ */
+fn verify(dir: &Path, archive: &Path) -> io::Result<()> {
+    let mut expected = HashMap::new();
+    collect_hashes(dir, Path::new(""), &mut expected)?;
+
+    let mut reader = tar::Archive::new(zstd::Decoder::new(File::open(archive)?)?);
+    for entry in reader.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        // Strip the leading directory name added when packing
+        let path: PathBuf = entry.path()?.components().skip(1).collect();
+        let hash = hash_reader(&mut entry)?;
+        match expected.remove(&path) {
+            Some(original) if original == hash => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("archive entry {} does not match", path.display()),
+                ))
+            }
+        }
+    }
+
+    if let Some(missing) = expected.keys().next() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is missing from the archive", missing.display()),
+        ));
+    }
+    Ok(())
+}
+
+fn collect_hashes(
+    root: &Path,
+    relative: &Path,
+    hashes: &mut HashMap<PathBuf, Vec<u8>>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(root.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_hashes(root, &path, hashes)?;
+        } else if file_type.is_file() {
+            let hash = hash_reader(&mut File::open(entry.path())?)?;
+            hashes.insert(path, hash);
+        }
+    }
+    Ok(())
+}
+
+fn hash_reader(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    io::copy(reader, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
@@ -0,0 +1,223 @@
+use log::{info, warn};
+use reqwest::Client;
+use serde::Deserialize;
+use url::Url;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::id3::{self, Frame};
+use super::RecordingError;
+
+const RADIO_GARDEN_API: &str = "http://radio.garden/api/ara/content/";
+
+#[derive(Deserialize)]
+struct ChannelInfo {
+    data: ChannelDetails,
+}
+
+#[derive(Deserialize)]
+struct ChannelDetails {
+    website: Option<String>,
+}
+
+/**
+ * An image downloaded for a station.
+ */
+struct Image {
+    bytes: Vec<u8>,
+    mime: &'static str,
+    extension: &'static str,
+}
+
+impl Image {
+    /**
+     * Recognizes the image formats that icons come in.
+     */
+    fn sniff(bytes: Vec<u8>) -> Option<Image> {
+        let (mime, extension) = match bytes.as_slice() {
+            [0x89, b'P', b'N', b'G', ..] => ("image/png", "png"),
+            [0xFF, 0xD8, 0xFF, ..] => ("image/jpeg", "jpg"),
+            [b'G', b'I', b'F', b'8', ..] => ("image/gif", "gif"),
+            [0, 0, 1, 0, ..] => ("image/x-icon", "ico"),
+            _ => return None,
+        };
+        Some(Image {
+            bytes,
+            mime,
+            extension,
+        })
+    }
+}
+
+/**
+ * Downloads each station's artwork once, from the website listed on its
+ * Radio Garden page: the icon the site links to (preferring its Apple touch
+ * icon, which is usually the largest), or else its `/favicon.ico`. The image
+ * is saved as `<recording>.<png|jpg|gif|ico>`. With `embed`, PNG and JPEG
+ * images are also added to the recording as ID3 front cover art.
+ */
+pub struct Artwork {
+    api: Url,
+    embed: bool,
+    images: Mutex<HashMap<String, Option<Arc<Image>>>>, // By channel ID; None if there is none
+}
+
+impl Artwork {
+    pub fn new(embed: bool) -> Self {
+        Artwork {
+            api: Url::parse(RADIO_GARDEN_API).expect("Failed to parse API URL"),
+            embed,
+            images: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /**
+     * Saves (and embeds) the artwork of channel `id` for `recording`,
+     * returning the path of the saved image, or None if the station has no
+     * artwork.
+     */
+    pub async fn apply(
+        &self,
+        client: &Client,
+        id: &str,
+        recording: &Path,
+    ) -> Result<Option<PathBuf>, RecordingError> {
+        let image = match self.image(client, id).await? {
+            Some(image) => image,
+            None => return Ok(None),
+        };
+        let mut path = recording.as_os_str().to_owned();
+        path.push(".");
+        path.push(image.extension);
+        let path = PathBuf::from(path);
+        fs::write(&path, &image.bytes)?;
+        if self.embed {
+            match image.mime {
+                "image/png" | "image/jpeg" => embed_cover(recording, &image)?,
+                _ => info!(
+                    "Not embedding {} artwork in {}",
+                    image.extension,
+                    recording.display()
+                ),
+            }
+        }
+        Ok(Some(path))
+    }
+
+    async fn image(&self, client: &Client, id: &str) -> Result<Option<Arc<Image>>, RecordingError> {
+        if let Some(image) = self.images.lock().unwrap().get(id) {
+            return Ok(image.clone());
+        }
+        let image = self.download(client, id).await?.map(Arc::new);
+        self.images
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), image.clone());
+        Ok(image)
+    }
+
+    async fn download(&self, client: &Client, id: &str) -> Result<Option<Image>, RecordingError> {
+        let url = self
+            .api
+            .join(&format!("channel/{}", id))
+            .expect("Failed to construct API URL");
+        let channel: ChannelInfo = client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let website = match channel.data.website.and_then(|w| Url::parse(&w).ok()) {
+            Some(website) => website,
+            None => return Ok(None),
+        };
+
+        // The page may not load; the favicon is still worth a try
+        let mut candidates = match client.get(website.clone()).send().await {
+            Ok(response) => icon_links(&response.text().await.unwrap_or_default(), &website),
+            Err(e) => {
+                warn!("Fetching {} failed: {}", website, e);
+                Vec::new()
+            }
+        };
+        candidates.extend(website.join("/favicon.ico").ok());
+        for candidate in candidates {
+            let response = match client.get(candidate.clone()).send().await {
+                Ok(response) if response.status().is_success() => response,
+                _ => continue,
+            };
+            if let Some(image) = Image::sniff(response.bytes().await?.to_vec()) {
+                info!("Found artwork for {} at {}", id, candidate);
+                return Ok(Some(image));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/**
+ * URLs of the icons linked from an HTML page, Apple touch icons first.
+ */
+fn icon_links(html: &str, base: &Url) -> Vec<Url> {
+    let lower = html.to_ascii_lowercase();
+    let mut touch = Vec::new();
+    let mut other = Vec::new();
+    let mut rest = lower.as_str();
+    while let Some(start) = rest.find("<link") {
+        let offset = lower.len() - rest.len() + start;
+        let end = lower[offset..]
+            .find('>')
+            .map_or(lower.len(), |end| offset + end);
+        let tag = &lower[offset..end];
+        rest = &lower[end..];
+
+        let rel = match tag.find("rel=") {
+            Some(at) => value(&tag[at + 4..]),
+            None => continue,
+        };
+        if !rel.contains("icon") {
+            continue;
+        }
+        // Take the href from the original text, where case matters
+        let href = match tag.find("href=") {
+            Some(at) => value(&html[offset + at + 5..end]),
+            None => continue,
+        };
+        if let Ok(url) = base.join(href) {
+            match rel.contains("apple-touch-icon") {
+                true => touch.push(url),
+                false => other.push(url),
+            }
+        }
+    }
+    touch.extend(other);
+    touch
+}
+
+/**
+ * An attribute value at the start of `text`, quoted or not.
+ */
+fn value(text: &str) -> &str {
+    match text.chars().next() {
+        Some(quote @ ('"' | '\'')) => {
+            let text = &text[1..];
+            &text[..text.find(quote).unwrap_or(text.len())]
+        }
+        _ => {
+            let end = text.find(char::is_whitespace).unwrap_or(text.len());
+            &text[..end]
+        }
+    }
+}
+
+/**
+ * Adds `image` to the MP3 file at `recording` as its front cover.
+ */
+fn embed_cover(recording: &Path, image: &Image) -> io::Result<()> {
+    id3::add_frames(recording, &[Frame::picture(image.mime, 3, &image.bytes)])
+}
@@ -0,0 +1,101 @@
+use futures::stream::{self, StreamExt};
+use log::{info, warn};
+use tokio::time::{timeout_at, Instant};
+
+use std::time::Duration;
+
+use super::Listener;
+
+/**
+ * Outcome of connecting to one stream for a short measurement window.
+ */
+pub struct BenchResult {
+    pub name: String,
+    pub first_byte: Option<Duration>, // Time from request to the first chunk
+    pub bytes: u64,                   // Bytes received within the window
+    pub elapsed: Duration,            // Time spent reading after the first chunk
+    pub error: Option<String>,
+}
+
+impl BenchResult {
+    /**
+     * Observed throughput in kilobits per second.
+     */
+    pub fn kbps(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.bytes as f64 * 8.0 / 1000.0 / secs
+    }
+}
+
+impl Listener {
+    /**
+     * Connects to every stored stream for `window`, running up to the
+     * configured concurrency at once, and measures time to first byte and
+     * throughput. Nothing is written to disk.
+     */
+    pub async fn bench_streams(&self, window: Duration) -> Vec<BenchResult> {
+        let concurrency = self.concurrency.max(1);
+        let streams = self.streams();
+        stream::iter(streams.iter())
+            .map(|s| self.bench_stream(&s.name, &s.url, window))
+            .buffer_unordered(concurrency)
+            .collect()
+            .await
+    }
+
+    async fn bench_stream(&self, name: &str, url: &str, window: Duration) -> BenchResult {
+        let mut result = BenchResult {
+            name: name.to_string(),
+            first_byte: None,
+            bytes: 0,
+            elapsed: Duration::ZERO,
+            error: None,
+        };
+
+        let start = Instant::now();
+        // A stream that stalls must not hold the benchmark past its window
+        let deadline = start + window;
+        let mut response = match timeout_at(deadline, self.client.get(url).send()).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) => {
+                warn!("Benchmark of {} failed to connect: {}", name, e);
+                result.error = Some(e.to_string());
+                return result;
+            }
+            Err(_) => {
+                warn!("Benchmark of {} timed out connecting", name);
+                result.error = Some("timed out connecting".to_string());
+                return result;
+            }
+        };
+
+        let mut read_start = None;
+        while let Ok(chunk) = timeout_at(deadline, response.chunk()).await {
+            match chunk {
+                Ok(Some(chunk)) => {
+                    if read_start.is_none() {
+                        result.first_byte = Some(start.elapsed());
+                        read_start = Some(Instant::now());
+                    }
+                    result.bytes += chunk.len() as u64;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Benchmark of {} failed while reading: {}", name, e);
+                    result.error = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+        if result.error.is_none() && read_start.is_none() {
+            result.error = Some("no data received".to_string());
+        }
+        result.elapsed = read_start.map(|t| t.elapsed()).unwrap_or_default();
+
+        info!("Benchmarked {}: {:.1} kbit/s", name, result.kbps());
+        result
+    }
+}
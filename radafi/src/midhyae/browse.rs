@@ -0,0 +1,382 @@
+use axum::body::Body;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::get;
+use axum::Router;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use url::Url;
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, SeekFrom};
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use super::feed::{self, escape, scan_archive, Episode};
+use super::{Config, RecordingError};
+
+/**
+ * Where files are served, relative to the server. Feeds written with
+ * `feed` should use it as their base URL.
+ */
+const FILES: &str = "http://archive/files/";
+
+/**
+ * Serves the archive under `root` read-only on `address`:
+ *
+ *   GET /                  stations and days with recordings
+ *   GET /stations/<id>     recordings of a station, by day
+ *   GET /days/<YYYY-MM-DD> recordings started on a day
+ *   GET /files/<path>      a file, with range requests for seeking, or a
+ *                          listing of a directory
+ *
+ * Pages are built from the sidecars on every request, so recordings show up
+ * as soon as they are finished. Access is limited by the `[server]` table
+ * of `config`, see `ServerOptions`.
+ */
+pub async fn serve_archive(
+    address: SocketAddr,
+    root: &Path,
+    config: &Config,
+) -> Result<(), RecordingError> {
+    let root = Arc::new(fs::canonicalize(root)?);
+    let app = Router::new()
+        .route("/", get(index))
+        .route("/stations/:id", get(station))
+        .route("/days/:day", get(day))
+        .route("/files", get(|| async { Redirect::permanent("/files/") }))
+        .route("/files/", get(top_level))
+        .route("/files/*path", get(file))
+        .with_state(root);
+    config.server.serve(address, app, "the archive").await
+}
+
+type Shared = Arc<PathBuf>;
+
+/**
+ * MIME type of the files radafi writes, by extension.
+ */
+pub fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("mp3") => "audio/mpeg",
+        Some("opus") => "audio/ogg",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("cue") => "application/x-cue",
+        Some("xml") => "application/rss+xml",
+        Some("png") => "image/png",
+        Some("jpg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+fn error_page(status: StatusCode, message: impl ToString) -> Response {
+    (status, page("Error", &escape(&message.to_string()))).into_response()
+}
+
+fn page(title: &str, body: &str) -> Html<String> {
+    Html(format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{}</title>\n</head>\n<body>\n<p><a href=\"/\">Archive</a></p>\n\
+         <h1>{}</h1>\n{}</body>\n</html>\n",
+        escape(title),
+        escape(title),
+        body
+    ))
+}
+
+/**
+ * Link to a path relative to the archive.
+ */
+fn file_link(path: &Path) -> String {
+    let base = Url::parse(FILES).expect("Failed to parse files URL");
+    feed::url(&base, path).path().to_string()
+}
+
+fn day_of(episode: &Episode) -> String {
+    episode.started.format("%Y-%m-%d").to_string()
+}
+
+async fn episodes(root: Shared) -> Result<Vec<Episode>, Response> {
+    tokio::task::spawn_blocking(move || scan_archive(&root))
+        .await
+        .map_err(|e| error_page(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .map_err(|e| error_page(StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+async fn index(State(root): State<Shared>) -> Response {
+    let episodes = match episodes(root).await {
+        Ok(episodes) => episodes,
+        Err(response) => return response,
+    };
+    let mut stations: BTreeMap<&str, (&str, usize)> = BTreeMap::new();
+    let mut days: BTreeMap<String, usize> = BTreeMap::new();
+    for episode in &episodes {
+        stations
+            .entry(&episode.id)
+            .or_insert((&episode.station, 0))
+            .1 += 1;
+        *days.entry(day_of(episode)).or_default() += 1;
+    }
+    // Stations by name, days newest first
+    let mut stations: Vec<_> = stations.into_iter().collect();
+    stations.sort_by_key(|(_, (name, _))| name.to_lowercase());
+
+    let mut body = String::from("<h2>Stations</h2>\n<ul>\n");
+    for (id, (name, count)) in stations {
+        let _ = writeln!(
+            body,
+            "<li><a href=\"/stations/{}\">{}</a> ({})</li>",
+            escape(id),
+            escape(name),
+            count
+        );
+    }
+    body.push_str("</ul>\n<h2>Days</h2>\n<ul>\n");
+    for (day, count) in days.iter().rev() {
+        let _ = writeln!(
+            body,
+            "<li><a href=\"/days/{}\">{}</a> ({})</li>",
+            day, day, count
+        );
+    }
+    body.push_str("</ul>\n<p><a href=\"/files/\">All files</a></p>\n");
+    page("Archive", &body).into_response()
+}
+
+async fn station(State(root): State<Shared>, UrlPath(id): UrlPath<String>) -> Response {
+    let episodes = match episodes(root).await {
+        Ok(episodes) => episodes,
+        Err(response) => return response,
+    };
+    let episodes: Vec<&Episode> = episodes.iter().filter(|e| e.id == id).collect();
+    let name = match episodes.last() {
+        Some(episode) => &episode.station,
+        None => return error_page(StatusCode::NOT_FOUND, "no recordings of this station"),
+    };
+    let mut body = String::new();
+    let mut current = None;
+    for episode in episodes.iter().rev() {
+        let day = day_of(episode);
+        if current.as_ref() != Some(&day) {
+            if current.is_some() {
+                body.push_str("</ul>\n");
+            }
+            let _ = writeln!(body, "<h2><a href=\"/days/{}\">{}</a></h2>\n<ul>", day, day);
+            current = Some(day);
+        }
+        body.push_str(&recording_item(
+            episode,
+            &episode.started.format("%H:%M").to_string(),
+        ));
+    }
+    body.push_str("</ul>\n");
+    page(name, &body).into_response()
+}
+
+async fn day(State(root): State<Shared>, UrlPath(day): UrlPath<String>) -> Response {
+    let episodes = match episodes(root).await {
+        Ok(episodes) => episodes,
+        Err(response) => return response,
+    };
+    let mut body = String::from("<ul>\n");
+    let mut found = false;
+    for episode in episodes.iter().filter(|e| day_of(e) == day) {
+        let label = format!(
+            "{} <a href=\"/stations/{}\">{}</a>",
+            episode.started.format("%H:%M"),
+            escape(&episode.id),
+            escape(&episode.station)
+        );
+        body.push_str(&recording_item(episode, &label));
+        found = true;
+    }
+    if !found {
+        return error_page(StatusCode::NOT_FOUND, "no recordings on this day");
+    }
+    body.push_str("</ul>\n");
+    page(&day, &body).into_response()
+}
+
+/**
+ * A list item with a player for `episode`; `label` is HTML.
+ */
+fn recording_item(episode: &Episode, label: &str) -> String {
+    let link = file_link(&episode.path);
+    format!(
+        "<li>{} ({}:{:02}, {} KB) <a href=\"{}\">download</a><br>\
+         <audio controls preload=\"none\" src=\"{}\"></audio></li>\n",
+        label,
+        episode.duration / 60,
+        episode.duration % 60,
+        episode.length / 1024,
+        escape(&link),
+        escape(&link)
+    )
+}
+
+async fn file(
+    State(root): State<Shared>,
+    UrlPath(path): UrlPath<String>,
+    headers: HeaderMap,
+) -> Response {
+    // Only plain components, so paths cannot escape the archive
+    let relative = Path::new(&path);
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return error_page(StatusCode::NOT_FOUND, "no such file");
+    }
+    // Symlinks such as `latest` may only point within the archive
+    let full = match fs::canonicalize(root.join(relative)) {
+        Ok(full) if full.starts_with(root.as_path()) => full,
+        _ => return error_page(StatusCode::NOT_FOUND, "no such file"),
+    };
+    if full.is_dir() {
+        return match listing(relative, &full) {
+            Ok(body) => page(&format!("/{}", path), &body).into_response(),
+            Err(e) => error_page(StatusCode::INTERNAL_SERVER_ERROR, e),
+        };
+    }
+    match send_file(
+        &full,
+        headers.get(header::RANGE).and_then(|r| r.to_str().ok()),
+    )
+    .await
+    {
+        Ok(response) => response,
+        Err(e) => error_page(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+async fn top_level(state: State<Shared>, headers: HeaderMap) -> Response {
+    file(state, UrlPath(String::new()), headers).await
+}
+
+/**
+ * Links to the entries of the directory `full`, at `relative` in the
+ * archive; directories first.
+ */
+fn listing(relative: &Path, full: &Path) -> io::Result<String> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(full)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        entries.push((!entry.path().is_dir(), name));
+    }
+    entries.sort();
+
+    let mut body = String::from("<ul>\n");
+    if let Some(parent) = relative.parent() {
+        let _ = writeln!(
+            body,
+            "<li><a href=\"{}/\">..</a></li>",
+            escape(&file_link(parent))
+        );
+    }
+    for (is_file, name) in entries {
+        let mut link = file_link(&relative.join(&name));
+        if !is_file {
+            link.push('/');
+        }
+        let _ = writeln!(
+            body,
+            "<li><a href=\"{}\">{}{}</a></li>",
+            escape(&link),
+            escape(&name),
+            if is_file { "" } else { "/" }
+        );
+    }
+    body.push_str("</ul>\n");
+    Ok(body)
+}
+
+/**
+ * Responds with the file at `path`, or the part of it asked for by a
+ * `Range` header.
+ */
+async fn send_file(path: &Path, range: Option<&str>) -> io::Result<Response> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let content_type = content_type(path);
+
+    let (start, end) = match range.map(|range| parse_range(range, size)) {
+        Some(Some(Ok(range))) => range,
+        Some(Some(Err(()))) => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+            )
+                .into_response())
+        }
+        // No range, or one we do not support: the whole file
+        Some(None) | None => {
+            return Ok((
+                [
+                    (header::CONTENT_TYPE, content_type.to_string()),
+                    (header::CONTENT_LENGTH, size.to_string()),
+                    (header::ACCEPT_RANGES, "bytes".to_string()),
+                ],
+                Body::from_stream(ReaderStream::new(file)),
+            )
+                .into_response())
+        }
+    };
+    file.seek(SeekFrom::Start(start)).await?;
+    Ok((
+        StatusCode::PARTIAL_CONTENT,
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_LENGTH, (end - start + 1).to_string()),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, size),
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(file.take(end - start + 1))),
+    )
+        .into_response())
+}
+
+/**
+ * The inclusive byte range of a `Range` header such as `bytes=0-499`,
+ * `bytes=500-` or `bytes=-500` within a file of `size` bytes. None if the
+ * header is not a single byte range, in which case the whole file is sent;
+ * an error if the range lies outside the file.
+ */
+fn parse_range(range: &str, size: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    let range = match (first.is_empty(), last.is_empty()) {
+        // The last `last` bytes
+        (true, false) => match last.parse::<u64>().ok()? {
+            0 => return Some(Err(())),
+            suffix => (size.saturating_sub(suffix), size.saturating_sub(1)),
+        },
+        (false, true) => (first.parse().ok()?, size.saturating_sub(1)),
+        (false, false) => {
+            let (first, last): (u64, u64) = (first.parse().ok()?, last.parse().ok()?);
+            if last < first {
+                return None;
+            }
+            (first, last.min(size.saturating_sub(1)))
+        }
+        (true, true) => return None,
+    };
+    if range.0 >= size {
+        return Some(Err(()));
+    }
+    Some(Ok(range))
+}
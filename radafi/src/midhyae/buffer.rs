@@ -0,0 +1,152 @@
+use bytes::Bytes;
+use log::warn;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinHandle;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::estimate::Size;
+use super::sink::Sink;
+use super::RecordingError;
+
+/**
+ * Audio a recording may hold in memory, received but not yet written, unless
+ * configured otherwise.
+ */
+pub const DEFAULT_WRITE_BUFFER: u64 = 4 * 1024 * 1024;
+
+/**
+ * ----------------------------------------------------------------------------
+ * A sink written by a task of its own, so the stream keeps being read while
+ * the disk or an upload is momentarily slow. At most `capacity` bytes wait
+ * in memory; once they do, the reader waits for the writer, with a warning,
+ * rather than letting memory grow. The bytes waiting are counted in `level`
+ * for progress reports.
+ *
+ * The writer stops at its first error, which is returned by the next write
+ * or by `finish`.
+ */
+pub struct WriteBuffer {
+    chunks: Option<mpsc::UnboundedSender<Bytes>>,
+    space: Arc<Semaphore>, // A permit per free byte
+    capacity: usize,
+    level: Arc<AtomicU64>,
+    writer: Option<JoinHandle<Result<Sink, RecordingError>>>,
+    warned: bool, // The buffer filled up before
+}
+
+impl WriteBuffer {
+    pub fn spawn(sink: Sink, capacity: u64, level: Arc<AtomicU64>) -> Self {
+        let capacity = capacity.min(Semaphore::MAX_PERMITS as u64) as usize;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let space = Arc::new(Semaphore::new(capacity));
+        let writer = tokio::spawn(drain(
+            sink,
+            receiver,
+            Arc::clone(&space),
+            capacity,
+            Arc::clone(&level),
+        ));
+        WriteBuffer {
+            chunks: Some(sender),
+            space,
+            capacity,
+            level,
+            writer: Some(writer),
+            warned: false,
+        }
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), RecordingError> {
+        // Chunks larger than the whole buffer wait for it to empty
+        let needed = chunk.len().min(self.capacity) as u32;
+        let permits = match self.space.try_acquire_many(needed) {
+            Ok(permits) => Ok(permits),
+            Err(_) => {
+                if !self.warned && !self.space.is_closed() {
+                    warn!(
+                        "Write buffer of {} is full; waiting for the output",
+                        Size(self.capacity as u64)
+                    );
+                    self.warned = true;
+                }
+                self.space.acquire_many(needed).await
+            }
+        };
+        let sent = match (permits, &self.chunks) {
+            (Ok(permits), Some(chunks)) => {
+                permits.forget();
+                self.level.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                let sent = chunks.send(Bytes::copy_from_slice(chunk)).is_ok();
+                if !sent {
+                    self.level.fetch_sub(chunk.len() as u64, Ordering::Relaxed);
+                }
+                sent
+            }
+            _ => false,
+        };
+        if sent {
+            return Ok(());
+        }
+        // The writer failed
+        self.chunks = None;
+        match self.writer.take() {
+            Some(writer) => Err(joined(writer.await).err().unwrap_or_else(stopped)),
+            None => Err(stopped()),
+        }
+    }
+
+    /**
+     * Writes what is still buffered and finishes the sink.
+     */
+    pub async fn finish(mut self) -> Result<(), RecordingError> {
+        self.chunks = None;
+        match self.writer.take() {
+            Some(writer) => joined(writer.await)?.finish().await,
+            // Its error was returned by a write
+            None => Ok(()),
+        }
+    }
+}
+
+/**
+ * Writes the chunks received to `sink` until the sender is dropped, giving
+ * back their space as they are written. Returns the sink to be finished.
+ */
+async fn drain(
+    mut sink: Sink,
+    mut chunks: mpsc::UnboundedReceiver<Bytes>,
+    space: Arc<Semaphore>,
+    capacity: usize,
+    level: Arc<AtomicU64>,
+) -> Result<Sink, RecordingError> {
+    while let Some(chunk) = chunks.recv().await {
+        let written = sink.write(&chunk).await;
+        level.fetch_sub(chunk.len() as u64, Ordering::Relaxed);
+        space.add_permits(chunk.len().min(capacity));
+        if let Err(e) = written {
+            // Wakes a reader waiting for space
+            space.close();
+            chunks.close();
+            while let Ok(chunk) = chunks.try_recv() {
+                level.fetch_sub(chunk.len() as u64, Ordering::Relaxed);
+            }
+            return Err(e);
+        }
+    }
+    Ok(sink)
+}
+
+fn joined(
+    result: Result<Result<Sink, RecordingError>, tokio::task::JoinError>,
+) -> Result<Sink, RecordingError> {
+    result.unwrap_or_else(|e| Err(RecordingError::Io(std::io::Error::other(e))))
+}
+
+/**
+ * Writes after the writer stopped, whose error was returned before.
+ */
+fn stopped() -> RecordingError {
+    RecordingError::Io(std::io::Error::other("the writer stopped"))
+}
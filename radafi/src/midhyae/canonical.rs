@@ -0,0 +1,48 @@
+use url::Url;
+
+/**
+ * `url` in a canonical form, so that URLs reaching the same stream compare
+ * equal: the scheme and host in lower case, without a trailing dot on the
+ * host, without the scheme's default port, with runs of slashes in the path
+ * collapsed, and without a fragment or an empty query. A URL that cannot be
+ * parsed is returned as it is.
+ */
+pub fn canonical_url(url: &str) -> String {
+    // Parsing lowercases the scheme and host and drops default ports
+    let mut parsed = match Url::parse(url.trim()) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.to_string(),
+    };
+    if let Some(host) = parsed.host_str().filter(|host| host.ends_with('.')) {
+        let host = host.trim_end_matches('.').to_string();
+        if !host.is_empty() {
+            let _ = parsed.set_host(Some(&host));
+        }
+    }
+    if !parsed.cannot_be_a_base() {
+        let mut path = String::with_capacity(parsed.path().len());
+        for c in parsed.path().chars() {
+            if c != '/' || !path.ends_with('/') {
+                path.push(c);
+            }
+        }
+        parsed.set_path(&path);
+    }
+    parsed.set_fragment(None);
+    if parsed.query() == Some("") {
+        parsed.set_query(None);
+    }
+    parsed.into()
+}
+
+/**
+ * What identifies the stream at `url`: its canonical form without the
+ * scheme, as streams are tried over HTTPS and plain HTTP alike.
+ */
+pub fn stream_key(url: &str) -> String {
+    let canonical = canonical_url(url);
+    match canonical.split_once("://") {
+        Some((_, rest)) => rest.to_string(),
+        None => canonical,
+    }
+}
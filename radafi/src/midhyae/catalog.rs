@@ -0,0 +1,95 @@
+use chrono::NaiveDate;
+
+use std::str::FromStr;
+
+use super::country::normalize;
+use super::feed::Episode;
+
+/**
+ * A length of time such as `90`, `90s`, `10m`, `2h` or `1h30m`, in seconds.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Length(pub u64);
+
+impl FromStr for Length {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid length {}, expected e.g. 90, 10m or 1h30m", s);
+        if let Ok(seconds) = s.parse() {
+            return Ok(Length(seconds));
+        }
+        let mut seconds = 0u64;
+        let mut digits = String::new();
+        for c in s.chars() {
+            if c.is_ascii_digit() {
+                digits.push(c);
+                continue;
+            }
+            let unit = match c {
+                'h' => 3600,
+                'm' => 60,
+                's' => 1,
+                _ => return Err(invalid()),
+            };
+            let value: u64 = digits.parse().map_err(|_| invalid())?;
+            seconds += value * unit;
+            digits.clear();
+        }
+        if !digits.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Length(seconds))
+    }
+}
+
+/**
+ * Filters on the recordings of an archive, see `scan_archive`. Every
+ * criterion that is set must hold; dates are those the recordings started
+ * on, in their own time zone.
+ */
+#[derive(Debug, Default)]
+pub struct Query {
+    pub station: Option<String>, // Channel ID, or part of the station name
+    pub country: Option<String>,
+    pub since: Option<NaiveDate>, // Inclusive
+    pub until: Option<NaiveDate>, // Inclusive
+    pub min_duration: Option<Length>,
+    pub max_duration: Option<Length>,
+    pub tags: Vec<(String, Option<String>)>, // Names, and values if they matter
+    pub min_quality: Option<u8>,             // Recordings without a score are left out
+}
+
+impl Query {
+    pub fn matches(&self, episode: &Episode) -> bool {
+        // Names are compared without case, accents or punctuation
+        let station = self.station.as_deref().is_none_or(|station| {
+            episode.id == station || normalize(&episode.station).contains(&normalize(station))
+        });
+        let country = self.country.as_deref().is_none_or(|country| {
+            episode
+                .country
+                .as_deref()
+                .is_some_and(|c| normalize(c) == normalize(country))
+        });
+        let tags = self.tags.iter().all(|(name, value)| {
+            let tag = episode.tags.get(name);
+            tag.is_some() && (value.is_none() || value.as_ref() == tag)
+        });
+        let day = episode.started.date_naive();
+        station
+            && country
+            && tags
+            && self.since.is_none_or(|since| day >= since)
+            && self.until.is_none_or(|until| day <= until)
+            && self
+                .min_duration
+                .is_none_or(|min| episode.duration >= min.0)
+            && self
+                .max_duration
+                .is_none_or(|max| episode.duration <= max.0)
+            && self
+                .min_quality
+                .is_none_or(|min| episode.quality.is_some_and(|quality| quality >= min))
+    }
+}
@@ -0,0 +1,254 @@
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use std::path::Path;
+use std::time::Duration;
+
+use super::notify::Report;
+use super::{Config, Listener, RecordingError};
+
+const TELEGRAM_API: &str = "https://api.telegram.org";
+const TELEGRAM_MAX_LEN: usize = 4096; // Characters per message
+const DISCORD_MAX_LEN: usize = 2000;
+const POLL_TIMEOUT_SECS: u64 = 50; // Long-polling timeout for getUpdates
+
+/**
+ * Telegram bot to report runs to, read from a `[telegram]` table. The bot
+ * must have been added to the chat. `radafi bot` also accepts commands from
+ * that chat, recording into `directory`.
+ */
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TelegramOptions {
+    pub bot_token: String,
+    pub chat_id: i64,
+    #[serde(default)]
+    pub only_failures: bool, // Report only runs with failed stations
+    #[serde(default = "default_directory")]
+    pub directory: String, // Output directory for recordings started from chat
+}
+
+fn default_directory() -> String {
+    "archive".to_string()
+}
+
+/**
+ * Discord channel webhook to report runs to, read from a `[discord]` table.
+ */
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DiscordOptions {
+    pub webhook_url: String,
+    #[serde(default)]
+    pub only_failures: bool,
+}
+
+#[derive(Deserialize)]
+struct Updates {
+    ok: bool,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    result: Vec<Update>,
+}
+
+#[derive(Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<Message>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    chat: Chat,
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Serialize)]
+struct SendMessage<'a> {
+    chat_id: i64,
+    text: &'a str,
+}
+
+/**
+ * A command sent to the bot.
+ */
+enum BotCommand {
+    Record { country: String, duration: u64 },
+    Help,
+}
+
+impl BotCommand {
+    /**
+     * Parses `/record <country> <duration>` or `/help`. Country names may
+     * contain spaces; the duration is the last word.
+     */
+    fn parse(text: &str) -> Option<Result<Self, String>> {
+        let mut words = text.split_whitespace();
+        // Commands may be addressed to the bot as /record@radafi_bot
+        let command = words.next()?.split('@').next()?;
+        let args: Vec<&str> = words.collect();
+        match command {
+            "/record" => Some(match args.split_last() {
+                Some((duration, country)) if !country.is_empty() => duration
+                    .parse()
+                    .map(|duration| BotCommand::Record {
+                        country: country.join(" "),
+                        duration,
+                    })
+                    .map_err(|_| format!("Invalid duration: {}", duration)),
+                _ => Err("Usage: /record <country> <duration>".to_string()),
+            }),
+            "/help" | "/start" => Some(Ok(BotCommand::Help)),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Cuts `text` to at most `max` characters.
+ */
+fn truncate(text: &str, max: usize) -> &str {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+impl Listener {
+    /**
+     * Posts `report` to the Telegram chat and Discord channel configured in
+     * `config`, if any. Failures are logged.
+     */
+    pub async fn notify_chats(&self, config: &Config, report: &Report) {
+        let text = format!("{}\n\n{}", report.subject(), report.body());
+        if let Some(telegram) = &config.telegram {
+            if report.failed > 0 || !telegram.only_failures {
+                if let Err(e) = self.send_telegram(telegram, &text).await {
+                    error!("Failed to send Telegram message: {}", e);
+                }
+            }
+        }
+        if let Some(discord) = &config.discord {
+            if report.failed > 0 || !discord.only_failures {
+                if let Err(e) = self.send_discord(discord, &text).await {
+                    error!("Failed to send Discord message: {}", e);
+                }
+            }
+        }
+    }
+
+    async fn send_telegram(
+        &self,
+        telegram: &TelegramOptions,
+        text: &str,
+    ) -> Result<(), RecordingError> {
+        let url = format!("{}/bot{}/sendMessage", TELEGRAM_API, telegram.bot_token);
+        let message = SendMessage {
+            chat_id: telegram.chat_id,
+            text: truncate(text, TELEGRAM_MAX_LEN),
+        };
+        self.client
+            .post(url)
+            .json(&message)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn send_discord(
+        &self,
+        discord: &DiscordOptions,
+        text: &str,
+    ) -> Result<(), RecordingError> {
+        let body = json!({ "content": truncate(text, DISCORD_MAX_LEN) });
+        self.client
+            .post(&discord.webhook_url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /**
+     * Runs the Telegram bot until an error occurs: `/record <country>
+     * <duration>` from the configured chat starts `radafi` with the same
+     * configuration file in a child process, which reports back when done.
+     * Messages from other chats are ignored.
+     */
+    pub async fn run_bot(
+        &self,
+        telegram: &TelegramOptions,
+        config_path: &Path,
+    ) -> Result<(), RecordingError> {
+        let program = std::env::current_exe()?;
+        let url = format!("{}/bot{}/getUpdates", TELEGRAM_API, telegram.bot_token);
+        let mut offset = 0;
+        info!("Waiting for commands from chat {}", telegram.chat_id);
+        loop {
+            let updates: Updates = self
+                .client
+                .get(&url)
+                .query(&[("offset", offset), ("timeout", POLL_TIMEOUT_SECS as i64)])
+                .timeout(Duration::from_secs(POLL_TIMEOUT_SECS + 10))
+                .send()
+                .await?
+                .json()
+                .await?;
+            if !updates.ok {
+                return Err(RecordingError::Chat(updates.description));
+            }
+
+            for update in updates.result {
+                offset = offset.max(update.update_id + 1);
+                let message = match update.message {
+                    Some(message) if message.chat.id == telegram.chat_id => message,
+                    Some(message) => {
+                        warn!("Ignoring message from chat {}", message.chat.id);
+                        continue;
+                    }
+                    None => continue,
+                };
+                let reply = match BotCommand::parse(&message.text) {
+                    Some(Ok(BotCommand::Record { country, duration })) => {
+                        let mut command = tokio::process::Command::new(&program);
+                        command
+                            .arg(&country)
+                            .arg(&telegram.directory)
+                            .arg(duration.to_string())
+                            .arg("--config")
+                            .arg(config_path);
+                        match command.spawn() {
+                            Ok(mut child) => {
+                                tokio::spawn(async move {
+                                    if let Err(e) = child.wait().await {
+                                        error!("Failed to wait for recording: {}", e);
+                                    }
+                                });
+                                format!("Recording {} for {} seconds.", country, duration)
+                            }
+                            Err(e) => format!("Failed to start recording: {}", e),
+                        }
+                    }
+                    Some(Ok(BotCommand::Help)) => {
+                        "Commands:\n/record <country> <duration> - record every station of a country for <duration> seconds".to_string()
+                    }
+                    Some(Err(e)) => e,
+                    None => continue,
+                };
+                if let Err(e) = self.send_telegram(telegram, &reply).await {
+                    error!("Failed to reply: {}", e);
+                }
+            }
+        }
+    }
+}
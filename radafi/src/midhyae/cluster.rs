@@ -0,0 +1,311 @@
+use axum::extract::{Path as UrlPath, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::resources;
+use super::{Listener, RecordingError, ServerOptions, Stream, StreamOutcome};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5); // Workers ask again after a 204
+const LEASE_GRACE: Duration = Duration::from_secs(120); // On top of the longest recording
+
+/**
+ * Body of `POST /assignments`.
+ */
+#[derive(Serialize, Deserialize)]
+struct WorkRequest {
+    worker: String,
+    slots: usize, // Stations the worker records at once
+}
+
+/**
+ * Stations handed to one worker.
+ */
+#[derive(Serialize, Deserialize)]
+struct Assignment {
+    id: u64,
+    duration: u64,
+    stations: Vec<Stream>,
+}
+
+/**
+ * Body of `POST /assignments/<id>`.
+ */
+#[derive(Serialize, Deserialize)]
+struct WorkReport {
+    worker: String,
+    outcomes: Vec<StreamOutcome>,
+}
+
+/**
+ * An assignment a worker has yet to report on. It goes back to the queue
+ * after `deadline`, in case the worker died.
+ */
+struct Lease {
+    worker: String,
+    stations: Vec<Stream>,
+    deadline: Instant,
+}
+
+struct Queue {
+    waiting: VecDeque<Stream>,
+    leases: HashMap<u64, Lease>,
+    next_id: u64,
+    outcomes: Vec<StreamOutcome>,
+}
+
+impl Queue {
+    fn finished(&self) -> bool {
+        self.waiting.is_empty() && self.leases.is_empty()
+    }
+
+    /**
+     * Requeues the stations of workers that did not report in time.
+     */
+    fn reclaim_expired(&mut self, now: Instant) {
+        let expired: Vec<u64> = self
+            .leases
+            .iter()
+            .filter(|(_, lease)| lease.deadline <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            if let Some(lease) = self.leases.remove(&id) {
+                warn!(
+                    "Worker {} did not report on assignment {}; requeueing {} stations",
+                    lease.worker,
+                    id,
+                    lease.stations.len()
+                );
+                for station in lease.stations.into_iter().rev() {
+                    self.waiting.push_front(station);
+                }
+            }
+        }
+    }
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * State shared by the coordinator's request handlers.
+ */
+struct Coordinator {
+    duration: u64,
+    total: usize,
+    queue: Mutex<Queue>,
+    done: tokio::sync::Notify,
+}
+
+type Shared = Arc<Coordinator>;
+
+async fn assign(State(coordinator): State<Shared>, Json(request): Json<WorkRequest>) -> Response {
+    let mut queue = coordinator.queue.lock().unwrap();
+    if queue.finished() {
+        return StatusCode::GONE.into_response();
+    }
+    queue.reclaim_expired(Instant::now());
+    if queue.waiting.is_empty() {
+        // Other workers may still fail to report; ask again later
+        return StatusCode::NO_CONTENT.into_response();
+    }
+
+    let count = request.slots.max(1).min(queue.waiting.len());
+    let stations: Vec<Stream> = queue.waiting.drain(..count).collect();
+    let longest = stations
+        .iter()
+        .map(|s| s.duration.unwrap_or(coordinator.duration))
+        .max()
+        .unwrap_or(coordinator.duration);
+    let id = queue.next_id;
+    queue.next_id += 1;
+    info!(
+        "Assignment {}: {} stations to worker {} ({} waiting)",
+        id,
+        stations.len(),
+        request.worker,
+        queue.waiting.len()
+    );
+    queue.leases.insert(
+        id,
+        Lease {
+            worker: request.worker,
+            stations: stations.clone(),
+            deadline: Instant::now() + Duration::from_secs(longest) + LEASE_GRACE,
+        },
+    );
+    Json(Assignment {
+        id,
+        duration: coordinator.duration,
+        stations,
+    })
+    .into_response()
+}
+
+async fn report(
+    State(coordinator): State<Shared>,
+    UrlPath(id): UrlPath<u64>,
+    Json(report): Json<WorkReport>,
+) -> Response {
+    let mut queue = coordinator.queue.lock().unwrap();
+    if queue.leases.remove(&id).is_none() {
+        // The stations were requeued after the deadline
+        warn!(
+            "Ignoring late report from worker {} on assignment {}",
+            report.worker, id
+        );
+        return StatusCode::CONFLICT.into_response();
+    }
+    let failed = report.outcomes.iter().filter(|o| !o.ok()).count();
+    queue.outcomes.extend(report.outcomes);
+    info!(
+        "Assignment {}: worker {} reported, {} failed ({} of {} stations done)",
+        id,
+        report.worker,
+        failed,
+        queue.outcomes.len(),
+        coordinator.total
+    );
+    if queue.finished() {
+        coordinator.done.notify_one();
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+impl Listener {
+    /**
+     * Hands `stations` out to workers connecting to `address` (see
+     * `work`) and collects their outcomes:
+     *
+     *   POST /assignments       {"worker": "<name>", "slots": <n>}, answered
+     *                           with up to `slots` stations, 204 to ask again
+     *                           later or 410 once every station is done
+     *   POST /assignments/<id>  {"worker": "<name>", "outcomes": [...]}
+     *
+     * Stations of a worker that does not report within the longest recording
+     * plus two minutes are handed to another worker. Workers ask for no more
+     * stations than they record at once, so this covers every assignment.
+     * Requests must authenticate as `server` asks. Returns once every
+     * station has been reported on.
+     */
+    pub async fn coordinate(
+        &self,
+        stations: &[Stream],
+        address: SocketAddr,
+        duration_seconds: u64,
+        server: &ServerOptions,
+    ) -> Result<Vec<StreamOutcome>, RecordingError> {
+        let stations = stations.to_vec();
+        let coordinator = Arc::new(Coordinator {
+            duration: duration_seconds,
+            total: stations.len(),
+            queue: Mutex::new(Queue {
+                waiting: stations.into(),
+                leases: HashMap::new(),
+                next_id: 1,
+                outcomes: Vec::new(),
+            }),
+            done: tokio::sync::Notify::new(),
+        });
+        if coordinator.total == 0 {
+            return Ok(Vec::new());
+        }
+
+        let app = Router::new()
+            .route("/assignments", post(assign))
+            .route("/assignments/:id", post(report))
+            .with_state(Arc::clone(&coordinator));
+        info!("Coordinating {} stations", coordinator.total);
+        let shared = Arc::clone(&coordinator);
+        let done = async move {
+            shared.done.notified().await;
+            // Keep answering 410 so polling workers learn the run is over
+            tokio::time::sleep(POLL_INTERVAL * 2).await;
+        };
+        server
+            .serve_until(address, app, "the coordinator", done)
+            .await?;
+
+        let outcomes = std::mem::take(&mut coordinator.queue.lock().unwrap().outcomes);
+        Ok(outcomes)
+    }
+
+    /**
+     * Records stations assigned by the coordinator at `coordinator` (see
+     * `coordinate`) into `directory` until it has no more work, with the
+     * token or credentials of `server`. Asks for as many stations as it can
+     * record at once, so each assignment is recorded in one go and within
+     * its lease. Returns the outcomes of every station this worker recorded.
+     */
+    pub async fn work(
+        &self,
+        coordinator: &str,
+        directory: &str,
+        worker: &str,
+        server: &ServerOptions,
+    ) -> Result<Vec<StreamOutcome>, RecordingError> {
+        let base = coordinator.trim_end_matches('/');
+        let mut slots = resources::fit_recordings(self.concurrency.max(1))?;
+        if let Some(max) = self.budget.max_recordings() {
+            slots = slots.min(max);
+        }
+        let mut recorded = Vec::new();
+        loop {
+            let request = WorkRequest {
+                worker: worker.to_string(),
+                slots,
+            };
+            let response = server
+                .authorize(self.client.post(format!("{}/assignments", base)))
+                .json(&request)
+                .send()
+                .await?;
+            match response.status() {
+                reqwest::StatusCode::GONE => return Ok(recorded),
+                reqwest::StatusCode::NO_CONTENT => {
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let assignment: Assignment = response.error_for_status()?.json().await?;
+            info!(
+                "Recording {} stations of assignment {}",
+                assignment.stations.len(),
+                assignment.id
+            );
+            let outcomes = self
+                .record_streams(&assignment.stations, assignment.duration, directory)
+                .await?;
+            let report = WorkReport {
+                worker: worker.to_string(),
+                outcomes: outcomes.clone(),
+            };
+            let response = server
+                .authorize(
+                    self.client
+                        .post(format!("{}/assignments/{}", base, assignment.id)),
+                )
+                .json(&report)
+                .send()
+                .await?;
+            if response.status() == reqwest::StatusCode::CONFLICT {
+                warn!(
+                    "Assignment {} was handed to another worker meanwhile",
+                    assignment.id
+                );
+            } else {
+                response.error_for_status()?;
+            }
+            recorded.extend(outcomes);
+        }
+    }
+}
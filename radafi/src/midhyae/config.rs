@@ -0,0 +1,388 @@
+use serde::Deserialize;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+use super::access::ServerOptions;
+use super::chat::{DiscordOptions, TelegramOptions};
+use super::email::EmailOptions;
+use super::estimate::Size;
+use super::http::HttpOptions;
+use super::manager::Budget;
+use super::mqtt::MqttOptions;
+use super::opus::ArchiveCodec;
+use super::queue::RedisOptions;
+use super::schedule::Show;
+use super::store::Store;
+use super::{template, ChannelId, PlaceId, RecordingError};
+
+/**
+ * Settings for an individual channel that take precedence over the
+ * session-wide ones.
+ */
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct StationOverride {
+    pub duration: Option<u64>,    // Seconds to record
+    pub format: Option<String>,   // Output format, "mp3" passthrough or "opus"
+    pub bitrate: Option<String>,  // Of Opus output, e.g. "32k"
+    pub filename: Option<String>, // Filename template, see `template`
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>, // Added to the session's tags
+    #[serde(default)]
+    pub schedule: Vec<Show>, // Shows that `radafi daemon` records
+}
+
+impl StationOverride {
+    /**
+     * The codec to record the station with, if overridden.
+     */
+    pub fn codec(&self) -> Result<Option<ArchiveCodec>, String> {
+        let bitrate = self.bitrate.as_deref().map(str::parse).transpose()?;
+        self.format
+            .as_deref()
+            .map(|format| ArchiveCodec::new(format, bitrate))
+            .transpose()
+    }
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * User configuration, read from a TOML file:
+ *
+ *   [stations.<channel_id>]
+ *   duration = 3600
+ *   filename = "priority/{name}_%Y%m%d.mp3"
+ *   schedule = ["Mon-Fri 07:00-08:00"]
+ *
+ * HTTP client tuning goes in an `[http]` table, see `HttpOptions`, and
+ * extra country names in a `[country_aliases]` table mapping each alias to a
+ * country:
+ *
+ *   [country_aliases]
+ *   "Elfenbeinküste" = "Côte d'Ivoire"
+ *
+ * Tags in a `[tags]` table, and a `tags` table per station, are attached to
+ * recordings:
+ *
+ *   [tags]
+ *   project = "elections-2024"
+ *
+ * Post-processing stages are listed as `[[post_process]]` tables, see
+ * `postprocess::Registry`. Run reports are mailed if there is an `[email]`
+ * table, see `EmailOptions`, and posted to chat with `[telegram]` and
+ * `[discord]` tables. Progress is published to MQTT with an `[mqtt]` table,
+ * see `MqttOptions`. `radafi consume` takes jobs from the queue in a
+ * `[redis]` table, see `RedisOptions`. Paused recordings keep reading
+ * their streams unless `drop_connection_when_paused` is set. Access to the
+ * HTTP servers is set in a `[server]` table, see `ServerOptions`. Named
+ * sets of recording options go in `[profile.<name>]` tables, see `Profile`.
+ * Recording sessions estimated to download or write more than
+ * `confirm_above` (e.g. "20G", 10 GB if unset) need `--yes`. Recordings in
+ * progress are written below `staging_dir`, if set, and moved to the
+ * output directory when finished. Each recording holds up to `write_buffer`
+ * (4 MB if unset) of audio in memory while its output is slow. A `[limits]`
+ * table caps the recordings of `serve` and `daemon`, see `Limits`.
+ */
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub stations: HashMap<ChannelId, StationOverride>,
+    #[serde(default)]
+    pub http: HttpOptions,
+    #[serde(default)]
+    pub country_aliases: HashMap<String, String>,
+    #[serde(default)]
+    pub post_process: Vec<toml::Table>,
+    pub email: Option<EmailOptions>,
+    pub telegram: Option<TelegramOptions>,
+    pub discord: Option<DiscordOptions>,
+    pub mqtt: Option<MqttOptions>,
+    pub redis: Option<RedisOptions>,
+    #[serde(default)]
+    pub drop_connection_when_paused: bool, // Instead of discarding the stream
+    #[serde(default)]
+    pub server: ServerOptions,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>, // Attached to every recording
+    pub confirm_above: Option<Size>, // Estimated volume that needs --yes
+    pub staging_dir: Option<PathBuf>, // For files in progress, e.g. on a local disk
+    pub write_buffer: Option<Size>,  // Per recording, "0" for none
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>, // By name
+}
+
+impl Config {
+    /**
+     * Location of the configuration when none is given explicitly, next to
+     * the store.
+     */
+    pub fn default_path() -> PathBuf {
+        Store::default_path().with_file_name("config.toml")
+    }
+
+    /**
+     * Loads and validates the configuration at `path`. A missing file yields
+     * the default configuration.
+     */
+    pub fn load(path: &Path) -> Result<Self, RecordingError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let config: Config = toml::from_str(&contents)
+            .map_err(|e| RecordingError::Config(format!("{}: {}", path.display(), e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), RecordingError> {
+        if let Some(email) = &self.email {
+            email.validate()?;
+        }
+        self.server.validate()?;
+        for (id, station) in &self.stations {
+            let invalid =
+                |reason: String| RecordingError::Config(format!("station {}: {}", id, reason));
+            station.codec().map_err(invalid)?;
+            if let Some(filename) = &station.filename {
+                template::validate(filename).map_err(invalid)?;
+            }
+        }
+        for (name, profile) in &self.profile {
+            let invalid =
+                |reason: String| RecordingError::Config(format!("profile {}: {}", name, reason));
+            if profile.country.is_some() && !profile.places.is_empty() {
+                return Err(invalid(
+                    "country and places are mutually exclusive".to_string(),
+                ));
+            }
+            if let Some(filename) = &profile.filename {
+                template::validate(filename).map_err(invalid)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * A named set of recording options, recorded with `radafi record --profile
+ * <name>`:
+ *
+ *   [profile.nightly-fr]
+ *   country = "France"
+ *   output = "/archive/fr/%Y-%m-%d"
+ *   duration = 3600
+ *   max_per_place = 2
+ *   genre = ["news", "talk"]
+ *   upload = "s3://archive/fr"
+ *   options = ["--trim", "--cue"]
+ *
+ * A profile stands for the command-line arguments it sets, and those given
+ * on the command line as well take precedence. A country, `--place` or
+ * `--geoip` on the command line replaces the profile's country and places,
+ * and positional arguments replace its output and duration.
+ */
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub country: Option<String>,
+    #[serde(default)]
+    pub places: Vec<PlaceId>, // To record instead of a country
+    pub output: Option<String>, // Directory to record into
+    pub duration: Option<u64>,  // Seconds to record
+    pub station: Option<String>,
+    #[serde(default)]
+    pub genre: Vec<String>,
+    pub max_per_place: Option<usize>,
+    pub filter_script: Option<PathBuf>,
+    pub filename: Option<String>, // Filename template, see `template`
+    pub upload: Option<String>,   // s3://<bucket>/<prefix>
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>, // Added to the session's tags
+    #[serde(default)]
+    pub options: Vec<String>, // Any other command-line arguments
+}
+
+impl Profile {
+    /**
+     * The command-line arguments the profile stands for. Without `target`,
+     * its country and places are left out, and without `positional` its
+     * output and duration, for the command line to give instead.
+     */
+    pub fn args(&self, target: bool, positional: bool) -> Vec<String> {
+        let mut args = Vec::new();
+        if target {
+            args.extend(self.country.clone());
+        }
+        if positional {
+            args.extend(self.output.clone());
+            args.extend(self.duration.map(|duration| duration.to_string()));
+        }
+        let mut option = |name: &str, value: String| {
+            args.push(format!("--{}", name));
+            args.push(value);
+        };
+        if target {
+            for place in &self.places {
+                option("place", place.to_string());
+            }
+        }
+        if let Some(station) = &self.station {
+            option("station", station.clone());
+        }
+        if !self.genre.is_empty() {
+            option("genre", self.genre.join(","));
+        }
+        if let Some(max) = self.max_per_place {
+            option("max-per-place", max.to_string());
+        }
+        if let Some(script) = &self.filter_script {
+            option("filter-script", script.to_string_lossy().into_owned());
+        }
+        if let Some(filename) = &self.filename {
+            option("filename", filename.clone());
+        }
+        if let Some(upload) = &self.upload {
+            option("upload", upload.clone());
+        }
+        for (name, value) in &self.tags {
+            option("tag", format!("{}={}", name, value));
+        }
+        args.extend(self.options.iter().cloned());
+        args
+    }
+}
+
+/**
+ * Caps on the recordings of `serve` and `daemon` across their sessions, set
+ * on the command line or in a `[limits]` table:
+ *
+ *   [limits]
+ *   max_recordings = 20
+ *   max_bandwidth = 2048   # KB/s
+ */
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    pub max_recordings: Option<usize>, // Streams recorded at once
+    pub max_bandwidth: Option<u64>,    // Combined download rate in KB/s
+}
+
+impl Limits {
+    /**
+     * These limits, with those that are not set taken from `other`.
+     */
+    pub fn or(self, other: Limits) -> Limits {
+        Limits {
+            max_recordings: self.max_recordings.or(other.max_recordings),
+            max_bandwidth: self.max_bandwidth.or(other.max_bandwidth),
+        }
+    }
+
+    /**
+     * A budget enforcing these limits.
+     */
+    pub fn budget(self) -> Budget {
+        Budget::new(self.max_recordings, self.bytes_per_second())
+    }
+
+    /**
+     * Changes the limits of `budget` to these, for the recordings started
+     * from now on.
+     */
+    pub fn apply(self, budget: &Budget) {
+        budget.set_limits(self.max_recordings, self.bytes_per_second());
+    }
+
+    fn bytes_per_second(self) -> Option<u64> {
+        self.max_bandwidth.map(|kb| kb * 1024)
+    }
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * The configuration of a long-running command, which may be reloaded from
+ * its file while the command runs. Sessions take the configuration that is
+ * current when they start, so reloading leaves recordings in progress
+ * alone. Limits given on the command line take precedence over those of the
+ * file.
+ */
+pub struct LiveConfig {
+    path: PathBuf,
+    flags: Limits, // From the command line
+    current: watch::Sender<Arc<Config>>,
+}
+
+impl LiveConfig {
+    pub fn load(path: &Path, flags: Limits) -> Result<Self, RecordingError> {
+        let config = Config::load(path)?;
+        Ok(LiveConfig {
+            path: path.to_path_buf(),
+            flags,
+            current: watch::Sender::new(Arc::new(config)),
+        })
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        Arc::clone(&self.current.borrow())
+    }
+
+    /**
+     * The limits currently in effect.
+     */
+    pub fn limits(&self) -> Limits {
+        self.flags.or(self.current.borrow().limits)
+    }
+
+    /**
+     * A receiver that is notified of each reload.
+     */
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Config>> {
+        self.current.subscribe()
+    }
+
+    /**
+     * Loads the file again. If it is invalid, the current configuration is
+     * kept.
+     */
+    pub fn reload(&self) -> Result<(), RecordingError> {
+        let config = Config::load(&self.path)?;
+        self.current.send_replace(Arc::new(config));
+        Ok(())
+    }
+
+    /**
+     * Reloads the file on each SIGHUP, applying the new limits to `budget`,
+     * until the runtime shuts down.
+     */
+    #[cfg(unix)]
+    pub async fn reload_on_signal(&self, budget: &Budget) -> io::Result<()> {
+        use log::{info, warn};
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        while hangup.recv().await.is_some() {
+            match self.reload() {
+                Ok(()) => {
+                    self.limits().apply(budget);
+                    info!(
+                        "Reloaded {}; [http] and [server] changes apply after a restart",
+                        self.path.display()
+                    );
+                }
+                Err(e) => warn!("Keeping the current configuration: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
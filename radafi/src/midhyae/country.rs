@@ -0,0 +1,253 @@
+use deunicode::deunicode;
+
+use std::collections::HashMap;
+
+/**
+ * Built-in names for the same country: common English variants, ISO-style
+ * official names and names in a few widely used languages.
+ */
+const ALIASES: &[&[&str]] = &[
+    &[
+        "Côte d'Ivoire",
+        "Ivory Coast",
+        "Elfenbeinküste",
+        "Costa de Marfil",
+    ],
+    &[
+        "South Korea",
+        "Korea, Republic of",
+        "Republic of Korea",
+        "Südkorea",
+        "Corée du Sud",
+        "Corea del Sur",
+    ],
+    &[
+        "North Korea",
+        "Korea, Democratic People's Republic of",
+        "DPRK",
+        "Nordkorea",
+    ],
+    &[
+        "United States",
+        "United States of America",
+        "USA",
+        "US",
+        "Vereinigte Staaten",
+        "États-Unis",
+        "Estados Unidos",
+    ],
+    &[
+        "United Kingdom",
+        "UK",
+        "Great Britain",
+        "Britain",
+        "Vereinigtes Königreich",
+        "Royaume-Uni",
+        "Reino Unido",
+    ],
+    &[
+        "Germany",
+        "Deutschland",
+        "Allemagne",
+        "Alemania",
+        "Germania",
+    ],
+    &["France", "Frankreich", "Francia"],
+    &["Spain", "España", "Spanien", "Espagne", "Spagna"],
+    &["Italy", "Italia", "Italien", "Italie"],
+    &["Brazil", "Brasil", "Brasilien", "Brésil"],
+    &["Mexico", "México", "Mexiko", "Mexique"],
+    &[
+        "Netherlands",
+        "The Netherlands",
+        "Holland",
+        "Nederland",
+        "Niederlande",
+        "Pays-Bas",
+        "Países Bajos",
+    ],
+    &["Belgium", "België", "Belgique", "Belgien"],
+    &["Switzerland", "Schweiz", "Suisse", "Svizzera", "Suiza"],
+    &["Austria", "Österreich", "Autriche"],
+    &["Sweden", "Sverige", "Schweden", "Suède"],
+    &["Norway", "Norge", "Norwegen", "Norvège"],
+    &["Denmark", "Danmark", "Dänemark", "Danemark"],
+    &["Finland", "Suomi", "Finnland", "Finlande"],
+    &["Poland", "Polska", "Polen", "Pologne"],
+    &["Hungary", "Magyarország", "Ungarn", "Hongrie"],
+    &["Ireland", "Éire", "Irland", "Irlande"],
+    &["Croatia", "Hrvatska", "Kroatien", "Croatie"],
+    &[
+        "Greece",
+        "Hellas",
+        "Ελλάδα",
+        "Griechenland",
+        "Grèce",
+        "Grecia",
+    ],
+    &["Turkey", "Türkiye", "Türkei", "Turquie", "Turquía"],
+    &["Czechia", "Czech Republic", "Česko", "Tschechien"],
+    &[
+        "Russia",
+        "Russian Federation",
+        "Россия",
+        "Russland",
+        "Russie",
+        "Rusia",
+    ],
+    &["Japan", "日本", "Japon", "Japón"],
+    &["China", "People's Republic of China", "中国", "Chine"],
+    &["Taiwan", "Republic of China", "台灣"],
+    &["Vietnam", "Viet Nam", "Việt Nam"],
+    &["Myanmar", "Burma"],
+    &["Laos", "Lao People's Democratic Republic"],
+    &["Iran", "Iran, Islamic Republic of"],
+    &["Syria", "Syrian Arab Republic"],
+    &["Palestine", "State of Palestine", "Palestinian Territories"],
+    &["Timor-Leste", "East Timor"],
+    &[
+        "Democratic Republic of the Congo",
+        "DR Congo",
+        "DRC",
+        "Congo-Kinshasa",
+        "Congo, Democratic Republic of the",
+    ],
+    &[
+        "Republic of the Congo",
+        "Congo-Brazzaville",
+        "Congo, Republic of the",
+    ],
+    &["Cape Verde", "Cabo Verde"],
+    &["Eswatini", "Swaziland"],
+    &["North Macedonia", "Macedonia"],
+    &["Moldova", "Moldova, Republic of"],
+    &["Tanzania", "Tanzania, United Republic of"],
+    &["Bolivia", "Bolivia, Plurinational State of"],
+    &["Venezuela", "Venezuela, Bolivarian Republic of"],
+];
+
+/**
+ * ----------------------------------------------------------------------------
+ * Matches country names given by the user against the names Radio Garden
+ * uses, ignoring case, accents and punctuation and accepting any alias of
+ * the same country.
+ */
+pub struct Countries {
+    groups: Vec<Vec<String>>, // Normalized names of the same country
+}
+
+impl Default for Countries {
+    fn default() -> Self {
+        Countries {
+            groups: ALIASES
+                .iter()
+                .map(|names| names.iter().map(|n| normalize(n)).collect())
+                .collect(),
+        }
+    }
+}
+
+impl Countries {
+    /**
+     * Adds user-defined aliases, mapping each alias to a country name (or
+     * any of its known aliases).
+     */
+    pub fn add_aliases(&mut self, aliases: &HashMap<String, String>) {
+        for (alias, country) in aliases {
+            let (alias, country) = (normalize(alias), normalize(country));
+            match self.groups.iter_mut().find(|g| g.contains(&country)) {
+                Some(group) => group.push(alias),
+                None => self.groups.push(vec![country, alias]),
+            }
+        }
+    }
+
+    /**
+     * Whether `place_country`, as listed by Radio Garden, is the country the
+     * user asked for.
+     */
+    pub fn matches(&self, requested: &str, place_country: &str) -> bool {
+        let (requested, place_country) = (normalize(requested), normalize(place_country));
+        requested == place_country
+            || self
+                .groups
+                .iter()
+                .any(|g| g.contains(&requested) && g.contains(&place_country))
+    }
+
+    /**
+     * Up to three of `countries` (names as listed by Radio Garden) that
+     * `requested` may have been meant as, closest first. A country is as
+     * close as the closest of its aliases.
+     */
+    pub fn suggest<'a>(
+        &self,
+        requested: &str,
+        countries: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<String> {
+        let requested = normalize(requested);
+        if requested.is_empty() {
+            return Vec::new();
+        }
+        // Allow roughly one typo per three letters
+        let max_distance = (requested.chars().count() / 3).max(1);
+        let mut candidates: Vec<(usize, &str)> = Vec::new();
+        for country in countries {
+            if candidates.iter().any(|&(_, c)| c == country) {
+                continue;
+            }
+            let name = normalize(country);
+            let group = self.groups.iter().find(|g| g.contains(&name));
+            let distance = group
+                .into_iter()
+                .flatten()
+                .chain(std::iter::once(&name))
+                .map(|alias| {
+                    if alias.starts_with(&requested) {
+                        0
+                    } else {
+                        edit_distance(&requested, alias)
+                    }
+                })
+                .min()
+                .unwrap_or(usize::MAX);
+            if distance <= max_distance {
+                candidates.push((distance, country));
+            }
+        }
+        candidates.sort();
+        candidates
+            .into_iter()
+            .take(3)
+            .map(|(_, country)| country.to_string())
+            .collect()
+    }
+}
+
+/**
+ * Levenshtein distance between `a` and `b`.
+ */
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/**
+ * Reduces a name to lowercase ASCII letters and digits.
+ */
+pub fn normalize(name: &str) -> String {
+    deunicode(name)
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
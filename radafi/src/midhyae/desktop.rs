@@ -0,0 +1,27 @@
+use notify_rust::Notification;
+
+use super::notify::Report;
+use super::RecordingError;
+
+/**
+ * Shows `report` as a desktop notification, marked critical if `alert` is
+ * set (e.g. because too many stations failed).
+ */
+pub fn notify_desktop(report: &Report, alert: bool) -> Result<(), RecordingError> {
+    let mut notification = Notification::new();
+    notification
+        .appname("radafi")
+        .summary(&report.subject())
+        .body(&report.body());
+    // Urgency is only understood by freedesktop notification servers
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if alert {
+        notification.urgency(notify_rust::Urgency::Critical);
+    }
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    let _ = alert;
+    notification
+        .show()
+        .map(|_| ())
+        .map_err(|e| RecordingError::Notification(e.to_string()))
+}
@@ -0,0 +1,186 @@
+use futures::stream::{self, StreamExt};
+use hyper_0_14::client::connect::dns::Name;
+use log::{info, warn};
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::header::LOCATION;
+use reqwest::Client;
+use tokio::net::lookup_host;
+use tokio::sync::OnceCell;
+use url::Url;
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::canonical::canonical_url;
+use super::hosts::Hosts;
+
+/**
+ * How long a lookup is reused. Failed lookups are retried sooner.
+ */
+const DNS_TTL: Duration = Duration::from_secs(10 * 60);
+const FAILED_TTL: Duration = Duration::from_secs(30);
+
+/**
+ * Result of looking up one host name.
+ */
+struct Lookup {
+    done: Instant,
+    result: Result<Vec<SocketAddr>, (io::ErrorKind, String)>,
+}
+
+impl Lookup {
+    fn expired(&self) -> bool {
+        let ttl = match self.result {
+            Ok(_) => DNS_TTL,
+            Err(_) => FAILED_TTL,
+        };
+        self.done.elapsed() > ttl
+    }
+}
+
+/**
+ * Resolver of the HTTP client that caches lookups, so recordings starting
+ * together share one lookup per host instead of each asking the system
+ * resolver, and hosts looked up ahead by `pre_resolve` are found at once.
+ * Concurrent lookups of the same host wait for the first.
+ */
+#[derive(Clone, Default)]
+pub struct DnsCache {
+    lookups: Arc<Mutex<HashMap<String, Arc<OnceCell<Lookup>>>>>, // By host name
+}
+
+impl DnsCache {
+    pub async fn lookup(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        let cell = {
+            let mut lookups = self.lookups.lock().unwrap();
+            let cell = lookups.entry(host.to_string()).or_default();
+            if cell.get().is_some_and(Lookup::expired) {
+                *cell = Arc::default();
+            }
+            Arc::clone(cell)
+        };
+        let lookup = cell
+            .get_or_init(|| async {
+                // The port is replaced by the connector
+                let result = match lookup_host((host, 0)).await {
+                    Ok(addresses) => Ok(addresses.collect()),
+                    Err(e) => Err((e.kind(), e.to_string())),
+                };
+                Lookup {
+                    done: Instant::now(),
+                    result,
+                }
+            })
+            .await;
+        lookup
+            .result
+            .clone()
+            .map_err(|(kind, message)| io::Error::new(kind, message))
+    }
+}
+
+impl Resolve for DnsCache {
+    fn resolve(&self, name: Name) -> Resolving {
+        let cache = self.clone();
+        Box::pin(async move {
+            let addresses = cache.lookup(name.as_str()).await?;
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/**
+ * What `pre_resolve` found out about the streams it was given.
+ */
+pub struct PreResolved {
+    pub unresolved: usize,                // Stations on hosts that do not resolve
+    pub stream_urls: Vec<Option<String>>, // Canonical URL of each stream, in order
+}
+
+/**
+ * Looks up the hosts of `streams` (station names and URLs) all at once,
+ * `concurrency` at a time, into the cache of `hosts`. For Radio Garden
+ * listen URLs, `single_hop` (a client not following redirects) asks which
+ * URL the stream is served from first. Stations whose host does not
+ * resolve are reported.
+ */
+pub async fn pre_resolve(
+    single_hop: &Client,
+    hosts: &Hosts,
+    streams: &[(&str, &str)],
+    concurrency: usize,
+) -> PreResolved {
+    let started = Instant::now();
+    // Owned, so the future stays `Send` for callers spawning it
+    let urls: Vec<String> = streams.iter().map(|(_, url)| url.to_string()).collect();
+    let stream_urls: Vec<Option<Url>> = stream::iter(urls)
+        .map(|url| async move { stream_url(single_hop, hosts, &url).await })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut stations: HashMap<String, Vec<&str>> = HashMap::new();
+    for (&(station, _), url) in streams.iter().zip(&stream_urls) {
+        if let Some(host) = url.as_ref().and_then(|url| url.host_str()) {
+            stations.entry(host.to_string()).or_default().push(station);
+        }
+    }
+    let lookups: Vec<(String, io::Result<Vec<SocketAddr>>)> =
+        stream::iter(stations.keys().cloned())
+            .map(|host| async move {
+                let result = hosts.dns().lookup(&host).await;
+                (host, result)
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await;
+    info!(
+        "Looked up {} stream hosts in {} ms",
+        lookups.len(),
+        started.elapsed().as_millis()
+    );
+
+    let mut unresolved = 0;
+    for (host, result) in lookups {
+        if let Err(e) = result {
+            for station in &stations[&host] {
+                warn!("{}: {} does not resolve ({})", station, host, e);
+                unresolved += 1;
+            }
+        }
+    }
+    PreResolved {
+        unresolved,
+        stream_urls: stream_urls
+            .into_iter()
+            .map(|url| url.map(|url| canonical_url(url.as_str())))
+            .collect(),
+    }
+}
+
+/**
+ * The URL the stream at `url` is served from: where a Radio Garden listen
+ * URL redirects to, or `url` itself.
+ */
+async fn stream_url(single_hop: &Client, hosts: &Hosts, url: &str) -> Option<Url> {
+    let url = Url::parse(url).ok()?;
+    if !url.path().contains("/listen/") {
+        return Some(url);
+    }
+    let location = match hosts.send(single_hop.get(url.clone())).await {
+        // Dropping the response closes the connection before any audio
+        Ok(response) => response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| url.join(location).ok()),
+        Err(e) => {
+            warn!("Could not ask where {} redirects to: {}", url, e);
+            None
+        }
+    };
+    Some(location.unwrap_or(url))
+}
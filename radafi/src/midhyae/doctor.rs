@@ -0,0 +1,220 @@
+use chrono::{DateTime, Utc};
+use tokio::net::lookup_host;
+
+use std::path::Path;
+use std::time::Instant;
+
+use super::session;
+use super::{ErrorCategory, Listener, RecordingError};
+
+const API_HOST: &str = "radio.garden";
+const TLS_URL: &str = "https://radio.garden/";
+const MIN_FREE_BYTES: u64 = 1024 * 1024 * 1024; // Roughly a day of one 96 kbps stream
+const MAX_CLOCK_SKEW_SECS: i64 = 300; // S3 rejects requests signed further off than this
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warning,
+    Failed,
+}
+
+/**
+ * Result of one self-test, with advice on how to fix a problem.
+ */
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    pub advice: Option<&'static str>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: String) -> Self {
+        Check {
+            name,
+            status: Status::Ok,
+            detail,
+            advice: None,
+        }
+    }
+
+    fn problem(name: &'static str, status: Status, detail: String, advice: &'static str) -> Self {
+        Check {
+            name,
+            status,
+            detail,
+            advice: Some(advice),
+        }
+    }
+}
+
+impl Listener {
+    /**
+     * Checks everything a recording into `directory` depends on: name
+     * resolution, TLS, the Radio Garden API, the output directory and the
+     * system clock.
+     */
+    pub async fn diagnose(&self, directory: &Path) -> Vec<Check> {
+        let mut checks = vec![check_dns().await];
+        let (tls, server_time) = self.check_tls().await;
+        checks.push(tls);
+        checks.push(self.check_api().await);
+        checks.push(check_writable(directory));
+        checks.push(check_space(directory));
+        checks.push(check_clock(server_time));
+        checks
+    }
+
+    async fn check_tls(&self) -> (Check, Option<DateTime<Utc>>) {
+        const NAME: &str = "TLS";
+        match self.client.get(TLS_URL).send().await {
+            Ok(response) => {
+                let server_time = response
+                    .headers()
+                    .get("date")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+                    .map(|t| t.with_timezone(&Utc));
+                let check = Check::ok(NAME, format!("handshake with {} succeeded", API_HOST));
+                (check, server_time)
+            }
+            Err(e) => {
+                let detail = format!("{} failed: {}", TLS_URL, e);
+                let advice = match RecordingError::from(e).category() {
+                    ErrorCategory::Tls => {
+                        "Certificates could not be verified. Check the system CA bundle, a \
+                         TLS-intercepting proxy, or set `root_ca` or `tls` in the [http] config."
+                    }
+                    _ => "HTTPS connections fail. Check firewalls and proxy settings.",
+                };
+                let check = Check::problem(NAME, Status::Failed, detail, advice);
+                (check, None)
+            }
+        }
+    }
+
+    async fn check_api(&self) -> Check {
+        const NAME: &str = "API";
+        let url = self
+            .url
+            .join("places")
+            .expect("Failed to construct places URL");
+        let start = Instant::now();
+        let response = self
+            .client
+            .get(url.clone())
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        match response {
+            Ok(_) => Check::ok(
+                NAME,
+                format!("{} answered in {} ms", url, start.elapsed().as_millis()),
+            ),
+            Err(e) => {
+                let category = RecordingError::from(e).category();
+                let advice = match category {
+                    ErrorCategory::Http4xx | ErrorCategory::Http5xx => {
+                        "Radio Garden is reachable but refused the request; it may be down or \
+                         may have changed its API. Try again later or report it with --debug-dump."
+                    }
+                    ErrorCategory::Throttled => {
+                        "Radio Garden is limiting how many requests it accepts. Wait a while, \
+                         and record fewer countries at once."
+                    }
+                    ErrorCategory::Timeout => "The API did not answer in time. Check the network.",
+                    _ => "The API is unreachable. Check the network connection and proxies.",
+                };
+                Check::problem(
+                    NAME,
+                    Status::Failed,
+                    format!("{} failed ({})", url, category.name()),
+                    advice,
+                )
+            }
+        }
+    }
+}
+
+async fn check_dns() -> Check {
+    const NAME: &str = "DNS";
+    match lookup_host((API_HOST, 443)).await {
+        Ok(addresses) => {
+            let addresses: Vec<String> = addresses.map(|a| a.ip().to_string()).collect();
+            Check::ok(
+                NAME,
+                format!("{} resolves to {}", API_HOST, addresses.join(", ")),
+            )
+        }
+        Err(e) => Check::problem(
+            NAME,
+            Status::Failed,
+            format!("{} does not resolve: {}", API_HOST, e),
+            "Check /etc/resolv.conf or the network's DNS server.",
+        ),
+    }
+}
+
+fn check_writable(directory: &Path) -> Check {
+    const NAME: &str = "Disk";
+    match session::ensure_writable(directory) {
+        Ok(()) => Check::ok(NAME, format!("{} is writable", directory.display())),
+        Err(e) => Check::problem(
+            NAME,
+            Status::Failed,
+            format!("cannot write to {}: {}", directory.display(), e),
+            "Choose another directory or fix its permissions.",
+        ),
+    }
+}
+
+fn check_space(directory: &Path) -> Check {
+    const NAME: &str = "Space";
+    // The directory exists if the write check passed
+    match fs2::available_space(directory) {
+        Ok(free) if free >= MIN_FREE_BYTES => Check::ok(NAME, format!("{} free", megabytes(free))),
+        Ok(free) => Check::problem(
+            NAME,
+            Status::Warning,
+            format!("only {} free", megabytes(free)),
+            "Long sessions may fill the disk; free space or use --compress or --upload.",
+        ),
+        Err(e) => Check::problem(
+            NAME,
+            Status::Warning,
+            format!("free space unknown: {}", e),
+            "Make sure the directory exists and is accessible.",
+        ),
+    }
+}
+
+fn check_clock(server_time: Option<DateTime<Utc>>) -> Check {
+    const NAME: &str = "Clock";
+    let server_time = match server_time {
+        Some(server_time) => server_time,
+        None => {
+            return Check::problem(
+                NAME,
+                Status::Warning,
+                "no server time to compare with".to_string(),
+                "Fix the TLS check first.",
+            )
+        }
+    };
+    let skew = (Utc::now() - server_time).num_seconds();
+    if skew.abs() <= MAX_CLOCK_SKEW_SECS {
+        Check::ok(NAME, format!("within {} s of server time", skew.abs()))
+    } else {
+        Check::problem(
+            NAME,
+            Status::Warning,
+            format!("{} s off server time", skew),
+            "Enable NTP; a wrong clock breaks TLS validation, S3 signing and file names.",
+        )
+    }
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{} MB", bytes / 1_000_000)
+}
@@ -0,0 +1,73 @@
+use log::warn;
+use reqwest::Response;
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/**
+ * Saves raw API responses and stream response headers for reporting and
+ * reproducing API-format issues. Files mirror the API paths:
+ *
+ *   <dir>/places.json
+ *   <dir>/page/<place_id>/channels.json
+ *   <dir>/streams/<channel_id>.headers
+ *
+ * Failing to save is logged but never interrupts a run.
+ */
+pub struct DebugDump {
+    dir: PathBuf,
+}
+
+impl DebugDump {
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(DebugDump {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /**
+     * File holding the body of the API call to `api_path`, e.g. "places".
+     */
+    pub fn body_path(dir: &Path, api_path: &str) -> PathBuf {
+        dir.join(format!("{}.json", api_path))
+    }
+
+    pub fn save_body(&self, api_path: &str, body: &[u8]) {
+        let path = DebugDump::body_path(&self.dir, api_path);
+        if let Err(e) = write(&path, body) {
+            warn!("Failed to dump {}: {}", path.display(), e);
+        }
+    }
+
+    /**
+     * Saves the status line and headers of a stream response.
+     */
+    pub fn save_headers(&self, channel_id: &str, response: &Response) {
+        let mut contents = format!("{:?} {}\n", response.version(), response.status());
+        for (name, value) in response.headers() {
+            let _ = writeln!(
+                contents,
+                "{}: {}",
+                name,
+                String::from_utf8_lossy(value.as_bytes())
+            );
+        }
+        let path = self
+            .dir
+            .join("streams")
+            .join(format!("{}.headers", channel_id));
+        if let Err(e) = write(&path, contents.as_bytes()) {
+            warn!("Failed to dump {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)
+}
@@ -0,0 +1,114 @@
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::info;
+use serde::Deserialize;
+
+use super::notify::Report;
+use super::RecordingError;
+
+/**
+ * How to secure the connection to the SMTP server.
+ */
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    #[default]
+    Starttls, // Upgrade a plain connection, usually on port 587
+    Tls,  // TLS from the start, usually on port 465
+    None, // Unencrypted, for a relay on localhost
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * SMTP settings for run reports, read from an `[email]` table:
+ *
+ *   [email]
+ *   server = "smtp.example.com"
+ *   username = "radafi@example.com"
+ *   password = "..."
+ *   from = "radafi <radafi@example.com>"
+ *   to = ["me@example.com"]
+ */
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EmailOptions {
+    pub server: String,
+    pub port: Option<u16>, // Defaults to the usual port for `security`
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+impl EmailOptions {
+    /**
+     * Checks the addresses, so mistakes surface before a long run instead
+     * of after it.
+     */
+    pub fn validate(&self) -> Result<(), RecordingError> {
+        self.mailboxes().map(|_| ())
+    }
+
+    /**
+     * The sender and the recipients.
+     */
+    fn mailboxes(&self) -> Result<(Mailbox, Vec<Mailbox>), RecordingError> {
+        let parse = |address: &str| {
+            address
+                .parse::<Mailbox>()
+                .map_err(|e| RecordingError::Email(format!("{}: {}", address, e)))
+        };
+        if self.to.is_empty() {
+            return Err(RecordingError::Email("no recipients in `to`".to_string()));
+        }
+        let to = self
+            .to
+            .iter()
+            .map(|to| parse(to))
+            .collect::<Result<_, _>>()?;
+        Ok((parse(&self.from)?, to))
+    }
+
+    fn message(&self, report: &Report) -> Result<Message, RecordingError> {
+        let (from, to) = self.mailboxes()?;
+        let mut builder = Message::builder().from(from).subject(report.subject());
+        for to in to {
+            builder = builder.to(to);
+        }
+        builder
+            .body(report.body())
+            .map_err(|e| RecordingError::Email(e.to_string()))
+    }
+
+    /**
+     * Mails `report` to every recipient.
+     */
+    pub async fn send(&self, report: &Report) -> Result<(), RecordingError> {
+        let message = self.message(report)?;
+        let email_error = |e: lettre::transport::smtp::Error| RecordingError::Email(e.to_string());
+        let mut transport = match self.security {
+            SmtpSecurity::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.server)
+                    .map_err(email_error)?
+            }
+            SmtpSecurity::Tls => {
+                AsyncSmtpTransport::<Tokio1Executor>::relay(&self.server).map_err(email_error)?
+            }
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.server)
+            }
+        };
+        if let Some(port) = self.port {
+            transport = transport.port(port);
+        }
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        transport.build().send(message).await.map_err(email_error)?;
+        info!("Mailed run report to {}", self.to.join(", "));
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use age::secrecy::SecretString;
+use age::stream::StreamWriter;
+use age::{x25519, Encryptor, Recipient};
+use serde::Serialize;
+
+use std::fs::{self, File};
+use std::path::Path;
+
+use super::RecordingError;
+
+/**
+ * At-rest encryption of recordings in the age format. Files are written as
+ * `<name>.age` and can be decrypted with the standard `age` tool.
+ */
+pub enum Encryption {
+    Recipients(Vec<x25519::Recipient>), // age1... public keys
+    Passphrase(SecretString),           // Key derived with scrypt
+}
+
+/**
+ * Description of how a recording was encrypted, stored in its sidecar.
+ */
+#[derive(Serialize, Debug, Clone)]
+pub struct EncryptionInfo {
+    pub format: &'static str,
+    pub method: &'static str,
+    pub recipients: Vec<String>,
+}
+
+impl Encryption {
+    /**
+     * Encrypts to one or more age X25519 recipients (`age1...`).
+     */
+    pub fn with_recipients(recipients: &[&str]) -> Result<Self, RecordingError> {
+        let recipients = recipients
+            .iter()
+            .map(|r| {
+                r.parse::<x25519::Recipient>()
+                    .map_err(|e| RecordingError::Encryption(format!("{}: {}", r, e)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if recipients.is_empty() {
+            return Err(RecordingError::Encryption(
+                "no recipients given".to_string(),
+            ));
+        }
+        Ok(Encryption::Recipients(recipients))
+    }
+
+    /**
+     * Encrypts with a passphrase read from the first line of `path`.
+     */
+    pub fn with_passphrase_file(path: &Path) -> Result<Self, RecordingError> {
+        let contents = fs::read_to_string(path)?;
+        let passphrase = contents.lines().next().unwrap_or("").to_string();
+        if passphrase.is_empty() {
+            return Err(RecordingError::Encryption(format!(
+                "empty passphrase in {}",
+                path.display()
+            )));
+        }
+        Ok(Encryption::Passphrase(SecretString::from(passphrase)))
+    }
+
+    /**
+     * Wraps `file` so that everything written to it is encrypted. The
+     * returned writer must be finished to write the final chunk.
+     */
+    pub fn wrap(&self, file: File) -> Result<StreamWriter<File>, RecordingError> {
+        let encryptor = match self {
+            Encryption::Recipients(recipients) => {
+                Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn Recipient))
+                    .map_err(|e| RecordingError::Encryption(e.to_string()))?
+            }
+            Encryption::Passphrase(passphrase) => {
+                Encryptor::with_user_passphrase(passphrase.clone())
+            }
+        };
+        Ok(encryptor.wrap_output(file)?)
+    }
+
+    pub fn info(&self) -> EncryptionInfo {
+        match self {
+            Encryption::Recipients(recipients) => EncryptionInfo {
+                format: "age",
+                method: "x25519",
+                recipients: recipients.iter().map(|r| r.to_string()).collect(),
+            },
+            Encryption::Passphrase(_) => EncryptionInfo {
+                format: "age",
+                method: "scrypt",
+                recipients: Vec::new(),
+            },
+        }
+    }
+}
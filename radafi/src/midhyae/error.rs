@@ -0,0 +1,504 @@
+use serde::{Deserialize, Serialize, Serializer};
+use thiserror::Error;
+
+use std::error::Error as _;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use super::PlaceId;
+
+/**
+ * Defines the categories of errors that may occur when recording radio streams
+ * from Radio Garden.
+ */
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    #[error("network error: {0}")]
+    Network(#[from] reqwest::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("MP3 decoding error: {0}")]
+    Decode(#[from] minimp3::Error),
+
+    #[error("invalid output template: {0}")]
+    Template(String),
+
+    #[error("output directory {} is not writable: {source}", path.display())]
+    OutputDirectory { path: PathBuf, source: io::Error },
+
+    #[error("expected exactly one stream, found {0}")]
+    NotSingleStream(usize),
+
+    #[error("relay error: {0}")]
+    Relay(String),
+
+    #[error("upload error: {0}")]
+    Upload(String),
+
+    #[error("encryption error: {0}")]
+    Encryption(String),
+
+    #[error("configuration error: {0}")]
+    Config(String),
+
+    #[error("encoding error: {0}")]
+    Encoding(String),
+
+    #[error("transcription error: {0}")]
+    Transcription(String),
+
+    #[error("IP geolocation failed: {0}")]
+    GeoIp(String),
+
+    #[error("post-processing error: {0}")]
+    PostProcess(String),
+
+    #[error("filter script error: {0}")]
+    Script(String),
+
+    #[error("email error: {0}")]
+    Email(String),
+
+    #[error("chat error: {0}")]
+    Chat(String),
+
+    #[error("job queue error: {0}")]
+    Queue(#[from] redis::RedisError),
+
+    #[error("not enough resources: {0}")]
+    Resources(String),
+
+    #[error("{} is locked by {owner}, which is still running", path.display())]
+    Locked { path: PathBuf, owner: String },
+
+    #[error("{owner} is already recording into {}", path.display())]
+    DirectoryInUse { path: PathBuf, owner: String },
+
+    #[error(transparent)]
+    Stream(Box<StreamError>), // Of a stream read outside `record_streams`
+
+    #[cfg(feature = "desktop-notifications")]
+    #[error("notification error: {0}")]
+    Notification(String),
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Errors while discovering streams, each naming the request that failed.
+ */
+#[derive(Debug, Error)]
+pub enum DiscoveryError {
+    #[error("fetching places failed: {0}")]
+    Places(#[source] reqwest::Error),
+
+    #[error("fetching channels of place {place} failed: {source}")]
+    Channels {
+        place: String, // Radio Garden place ID
+        source: reqwest::Error,
+    },
+
+    #[error("the API did not send {path} within {seconds} s; raise {setting} in [http] if it is only slow")]
+    Timeout {
+        path: String,          // API path of the request
+        seconds: u64,          // The limit it ran into
+        setting: &'static str, // The `[http]` setting for the limit
+    },
+
+    #[error("parsing {path} failed: {source}")]
+    Parse {
+        path: String, // API path of the response
+        source: serde_json::Error,
+    },
+
+    #[error("reading {} failed: {source}", path.display())]
+    Replay { path: PathBuf, source: io::Error },
+
+    #[error("no stations found in {country}{}", did_you_mean(suggestions))]
+    NoStationsFound {
+        country: String,          // As requested
+        suggestions: Vec<String>, // Country names close to the requested one
+    },
+
+    #[error("no place has any of the IDs {}", listed(places))]
+    NoSuchPlaces {
+        places: Vec<PlaceId>, // As requested
+    },
+}
+
+fn did_you_mean(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        String::new()
+    } else {
+        format!("; did you mean {}?", suggestions.join(", "))
+    }
+}
+
+fn listed(places: &[PlaceId]) -> String {
+    let ids: Vec<&str> = places.iter().map(PlaceId::as_str).collect();
+    ids.join(", ")
+}
+
+/**
+ * Errors while recording a single station, each naming the station.
+ */
+#[derive(Debug, Error)]
+pub enum StreamError {
+    #[error("connecting to {station} failed: {source}")]
+    Connect {
+        station: String,
+        source: reqwest::Error,
+    },
+
+    #[error("reading {station} failed: {source}")]
+    Stream {
+        station: String,
+        source: reqwest::Error,
+    },
+
+    #[error("writing {station} failed: {source}")]
+    Write {
+        station: String,
+        source: RecordingError, // Creating, writing or finishing the output
+    },
+
+    #[error("not connecting to {station}: {host} is down")]
+    HostDown {
+        station: String,
+        host: String, // Of the stream or a relay it redirects to
+    },
+
+    #[error("recording {station} was cancelled")]
+    Cancelled { station: String },
+
+    #[error("{station} could not be recorded over HTTPS and plain HTTP ({url}) is not allowed")]
+    Insecure { station: String, url: String },
+
+    #[error("recording of {station} is corrupt: {reason}")]
+    Corrupt { station: String, reason: String },
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Broad cause of a failed recording, used to tell problems with the local
+ * network or disk apart from problems on the station's or Radio Garden's
+ * side.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    Dns,       // Host name could not be resolved
+    Tls,       // Handshake or certificate failure
+    Connect,   // Connection refused, reset or unreachable
+    Timeout,   // No response in time
+    Throttled, // The server answered 429 Too Many Requests
+    Http4xx,   // The server rejected the request
+    Http5xx,   // The server failed to handle the request
+    HostDown,  // Skipped since the host failed repeatedly
+    Network,   // Any other transport error
+    Decode,    // Audio could not be decoded
+    Disk,      // Local file system error
+    DiskFull,  // No space left on the device
+    Upload,    // Object storage rejected the upload
+    DeadAir,   // Recording was effectively silent
+    Cancelled, // Stopped on request
+    Insecure,  // Only plain HTTP would have worked, which is not allowed
+    Other,
+}
+
+impl ErrorCategory {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCategory::Dns => "dns",
+            ErrorCategory::Tls => "tls",
+            ErrorCategory::Connect => "connect",
+            ErrorCategory::Timeout => "timeout",
+            ErrorCategory::Throttled => "throttled",
+            ErrorCategory::Http4xx => "http_4xx",
+            ErrorCategory::Http5xx => "http_5xx",
+            ErrorCategory::HostDown => "host_down",
+            ErrorCategory::Network => "network",
+            ErrorCategory::Decode => "decode",
+            ErrorCategory::Disk => "disk",
+            ErrorCategory::DiskFull => "disk_full",
+            ErrorCategory::Upload => "upload",
+            ErrorCategory::DeadAir => "dead_air",
+            ErrorCategory::Cancelled => "cancelled",
+            ErrorCategory::Insecure => "insecure",
+            ErrorCategory::Other => "other",
+        }
+    }
+
+    /**
+     * Whether the failure lies with the station and is unlikely to go away
+     * by itself: its host does not resolve, refuses connections or TLS, or
+     * rejects the request. A station refused by radafi itself, such as one
+     * that would need plain HTTP, is not.
+     */
+    pub fn is_hard(&self) -> bool {
+        matches!(
+            self,
+            ErrorCategory::Dns
+                | ErrorCategory::Tls
+                | ErrorCategory::Connect
+                | ErrorCategory::Http4xx
+        )
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ErrorCategory::Dns => ErrorCode::NetDns,
+            ErrorCategory::Tls => ErrorCode::NetTls,
+            ErrorCategory::Connect => ErrorCode::NetConnect,
+            ErrorCategory::Timeout => ErrorCode::NetTimeout,
+            ErrorCategory::Throttled => ErrorCode::NetThrottled,
+            ErrorCategory::Http4xx => ErrorCode::Http4xx,
+            ErrorCategory::Http5xx => ErrorCode::Http5xx,
+            ErrorCategory::HostDown => ErrorCode::NetHostDown,
+            ErrorCategory::Network => ErrorCode::NetOther,
+            ErrorCategory::Decode => ErrorCode::Decode,
+            ErrorCategory::Disk => ErrorCode::Disk,
+            ErrorCategory::DiskFull => ErrorCode::DiskFull,
+            ErrorCategory::Upload => ErrorCode::Upload,
+            ErrorCategory::DeadAir => ErrorCode::DeadAir,
+            ErrorCategory::Cancelled => ErrorCode::Cancelled,
+            ErrorCategory::Insecure => ErrorCode::Insecure,
+            ErrorCategory::Other => ErrorCode::Other,
+        }
+    }
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Stable code of a failure, for scripts to branch on instead of parsing
+ * messages: a name such as `E_NET_TIMEOUT` and the exit status radafi ends
+ * with when it is why a run failed. Codes are never renumbered; new ones
+ * only get new numbers.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorCode {
+    Other,        // E_OTHER, 1
+    Usage,        // E_USAGE, 2: invalid command-line arguments
+    Config,       // E_CONFIG, 3: invalid configuration, template or script
+    NotConfirmed, // E_NOT_CONFIRMED, 4: a big session was not confirmed
+    Locked,       // E_LOCKED, 5: another instance holds the lock
+    ApiRequest,   // E_API_REQUEST, 10: Radio Garden answered with an error
+    ApiSchema,    // E_API_SCHEMA, 11: a Radio Garden response did not parse
+    NoStations,   // E_NO_STATIONS, 12
+    GeoIp,        // E_GEOIP, 13: the local country could not be determined
+    NetDns,       // E_NET_DNS, 20
+    NetTls,       // E_NET_TLS, 21
+    NetConnect,   // E_NET_CONNECT, 22
+    NetTimeout,   // E_NET_TIMEOUT, 23
+    NetThrottled, // E_NET_THROTTLED, 24
+    Http4xx,      // E_HTTP_4XX, 25
+    Http5xx,      // E_HTTP_5XX, 26
+    NetHostDown,  // E_NET_HOST_DOWN, 27
+    NetOther,     // E_NET_OTHER, 28
+    Insecure,     // E_INSECURE, 29: only plain HTTP would have worked
+    Decode,       // E_DECODE, 30
+    Disk,         // E_DISK, 40
+    DiskFull,     // E_DISK_FULL, 41
+    Upload,       // E_UPLOAD, 42
+    DeadAir,      // E_DEAD_AIR, 50
+    Cancelled,    // E_CANCELLED, 51
+}
+
+impl ErrorCode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCode::Other => "E_OTHER",
+            ErrorCode::Usage => "E_USAGE",
+            ErrorCode::Config => "E_CONFIG",
+            ErrorCode::NotConfirmed => "E_NOT_CONFIRMED",
+            ErrorCode::Locked => "E_LOCKED",
+            ErrorCode::ApiRequest => "E_API_REQUEST",
+            ErrorCode::ApiSchema => "E_API_SCHEMA",
+            ErrorCode::NoStations => "E_NO_STATIONS",
+            ErrorCode::GeoIp => "E_GEOIP",
+            ErrorCode::NetDns => "E_NET_DNS",
+            ErrorCode::NetTls => "E_NET_TLS",
+            ErrorCode::NetConnect => "E_NET_CONNECT",
+            ErrorCode::NetTimeout => "E_NET_TIMEOUT",
+            ErrorCode::NetThrottled => "E_NET_THROTTLED",
+            ErrorCode::Http4xx => "E_HTTP_4XX",
+            ErrorCode::Http5xx => "E_HTTP_5XX",
+            ErrorCode::NetHostDown => "E_NET_HOST_DOWN",
+            ErrorCode::NetOther => "E_NET_OTHER",
+            ErrorCode::Insecure => "E_INSECURE",
+            ErrorCode::Decode => "E_DECODE",
+            ErrorCode::Disk => "E_DISK",
+            ErrorCode::DiskFull => "E_DISK_FULL",
+            ErrorCode::Upload => "E_UPLOAD",
+            ErrorCode::DeadAir => "E_DEAD_AIR",
+            ErrorCode::Cancelled => "E_CANCELLED",
+        }
+    }
+
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            ErrorCode::Other => 1,
+            ErrorCode::Usage => 2,
+            ErrorCode::Config => 3,
+            ErrorCode::NotConfirmed => 4,
+            ErrorCode::Locked => 5,
+            ErrorCode::ApiRequest => 10,
+            ErrorCode::ApiSchema => 11,
+            ErrorCode::NoStations => 12,
+            ErrorCode::GeoIp => 13,
+            ErrorCode::NetDns => 20,
+            ErrorCode::NetTls => 21,
+            ErrorCode::NetConnect => 22,
+            ErrorCode::NetTimeout => 23,
+            ErrorCode::NetThrottled => 24,
+            ErrorCode::Http4xx => 25,
+            ErrorCode::Http5xx => 26,
+            ErrorCode::NetHostDown => 27,
+            ErrorCode::NetOther => 28,
+            ErrorCode::Insecure => 29,
+            ErrorCode::Decode => 30,
+            ErrorCode::Disk => 40,
+            ErrorCode::DiskFull => 41,
+            ErrorCode::Upload => 42,
+            ErrorCode::DeadAir => 50,
+            ErrorCode::Cancelled => 51,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl From<StreamError> for RecordingError {
+    fn from(e: StreamError) -> Self {
+        RecordingError::Stream(Box::new(e))
+    }
+}
+
+impl RecordingError {
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            RecordingError::Network(e) => network_category(e),
+            RecordingError::Io(e) if e.kind() == io::ErrorKind::TimedOut => ErrorCategory::Timeout,
+            RecordingError::Io(e) if e.kind() == io::ErrorKind::StorageFull => {
+                ErrorCategory::DiskFull
+            }
+            RecordingError::Io(_) => ErrorCategory::Disk,
+            RecordingError::Decode(_) => ErrorCategory::Decode,
+            RecordingError::Upload(_) => ErrorCategory::Upload,
+            RecordingError::Stream(e) => e.category(),
+            _ => ErrorCategory::Other,
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            RecordingError::Template(_)
+            | RecordingError::Config(_)
+            | RecordingError::Script(_)
+            | RecordingError::Encryption(_) => ErrorCode::Config,
+            RecordingError::OutputDirectory { source, .. }
+                if source.kind() == io::ErrorKind::StorageFull =>
+            {
+                ErrorCode::DiskFull
+            }
+            RecordingError::OutputDirectory { .. } => ErrorCode::Disk,
+            RecordingError::NotSingleStream(_) => ErrorCode::Usage,
+            RecordingError::Locked { .. } | RecordingError::DirectoryInUse { .. } => {
+                ErrorCode::Locked
+            }
+            RecordingError::GeoIp(_) => ErrorCode::GeoIp,
+            _ => self.category().code(),
+        }
+    }
+}
+
+impl DiscoveryError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            DiscoveryError::Places(source) | DiscoveryError::Channels { source, .. } => {
+                match network_category(source) {
+                    ErrorCategory::Http4xx | ErrorCategory::Http5xx => ErrorCode::ApiRequest,
+                    category => category.code(),
+                }
+            }
+            DiscoveryError::Timeout { .. } => ErrorCode::NetTimeout,
+            DiscoveryError::Parse { .. } => ErrorCode::ApiSchema,
+            DiscoveryError::Replay { .. } => ErrorCode::Disk,
+            DiscoveryError::NoStationsFound { .. } | DiscoveryError::NoSuchPlaces { .. } => {
+                ErrorCode::NoStations
+            }
+        }
+    }
+}
+
+impl StreamError {
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            StreamError::Connect { source, .. } | StreamError::Stream { source, .. } => {
+                network_category(source)
+            }
+            StreamError::Write { source, .. } => source.category(),
+            StreamError::HostDown { .. } => ErrorCategory::HostDown,
+            StreamError::Cancelled { .. } => ErrorCategory::Cancelled,
+            StreamError::Insecure { .. } => ErrorCategory::Insecure,
+            StreamError::Corrupt { .. } => ErrorCategory::Decode,
+        }
+    }
+}
+
+/**
+ * Sorts a transport error into a category. reqwest does not distinguish
+ * resolver and TLS failures itself, so the chain of underlying errors is
+ * inspected for them.
+ */
+pub fn network_category(error: &reqwest::Error) -> ErrorCategory {
+    if error.is_timeout() {
+        return ErrorCategory::Timeout;
+    }
+    if let Some(status) = error.status() {
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return ErrorCategory::Throttled;
+        }
+        if status.is_client_error() {
+            return ErrorCategory::Http4xx;
+        }
+        if status.is_server_error() {
+            return ErrorCategory::Http5xx;
+        }
+    }
+
+    let mut source = error.source();
+    while let Some(cause) = source {
+        if let Some(e) = cause.downcast_ref::<io::Error>() {
+            if e.kind() == io::ErrorKind::TimedOut {
+                return ErrorCategory::Timeout;
+            }
+        }
+        let message = cause.to_string().to_lowercase();
+        if message.contains("dns error") || message.contains("failed to lookup address") {
+            return ErrorCategory::Dns;
+        }
+        if message.contains("certificate") || message.contains("tls") || message.contains("ssl") {
+            return ErrorCategory::Tls;
+        }
+        source = cause.source();
+    }
+
+    if error.is_connect() {
+        ErrorCategory::Connect
+    } else {
+        ErrorCategory::Network
+    }
+}
@@ -0,0 +1,136 @@
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use super::opus::ArchiveCodec;
+use super::{ChannelId, Listener};
+
+/**
+ * Download rate assumed for stations not recorded before: 128 kbit/s, the
+ * most common bitrate of MP3 streams.
+ */
+const DEFAULT_BYTES_PER_SECOND: f64 = 16_000.0;
+
+/**
+ * Estimated volume above which recording needs confirmation, unless
+ * configured otherwise.
+ */
+pub const DEFAULT_CONFIRM_ABOVE: Size = Size(10_000_000_000);
+
+/**
+ * A number of bytes such as `500M` or `20GB`, in decimal units.
+ */
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Size(pub u64);
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid size {}, expected e.g. 500M or 20G", s);
+        let upper = s.trim().to_ascii_uppercase();
+        let number = upper.strip_suffix('B').unwrap_or(&upper);
+        let (digits, factor) = match number.chars().last() {
+            Some('K') => (&number[..number.len() - 1], 1e3),
+            Some('M') => (&number[..number.len() - 1], 1e6),
+            Some('G') => (&number[..number.len() - 1], 1e9),
+            Some('T') => (&number[..number.len() - 1], 1e12),
+            _ => (number, 1.0),
+        };
+        match digits.trim().parse::<f64>() {
+            Ok(value) if value.is_finite() && value >= 0.0 => Ok(Size((value * factor) as u64)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for Size {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self.0 as f64;
+        match self.0 {
+            0..=999_999 => write!(f, "{:.0} kB", bytes / 1e3),
+            1_000_000..=999_999_999 => write!(f, "{:.0} MB", bytes / 1e6),
+            _ => write!(f, "{:.1} GB", bytes / 1e9),
+        }
+    }
+}
+
+/**
+ * Expected volume of a recording session.
+ */
+pub struct Estimate {
+    pub stations: usize,
+    pub known: usize, // Stations whose bitrate is known from earlier runs
+    pub download: Size,
+    pub disk: Size, // Written locally, nothing when uploading
+}
+
+impl Estimate {
+    /**
+     * Whether the download or the disk usage exceeds `limit`.
+     */
+    pub fn exceeds(&self, limit: Size) -> bool {
+        self.download.0.max(self.disk.0) > limit.0
+    }
+}
+
+impl Listener {
+    /**
+     * Estimates what recording the stored streams for `duration_seconds`
+     * (or their own durations) will download and write, from the byte rates
+     * per channel ID observed in earlier runs, see `Store::byte_rates`.
+     * Opus recordings and copies are counted at their bitrate, and
+     * recordings limited in size stop at their limit.
+     */
+    pub fn estimate(
+        &self,
+        duration_seconds: u64,
+        byte_rates: &HashMap<ChannelId, f64>,
+    ) -> Estimate {
+        let streams = self.stored();
+        let mut estimate = Estimate {
+            stations: streams.len(),
+            known: 0,
+            download: Size(0),
+            disk: Size(0),
+        };
+        for stream in streams.iter() {
+            let rate = match byte_rates.get(&stream.id) {
+                Some(&rate) => {
+                    estimate.known += 1;
+                    rate
+                }
+                None => DEFAULT_BYTES_PER_SECOND,
+            };
+            let mut seconds = stream.duration.unwrap_or(duration_seconds) as f64;
+            if let Some(max) = self.max_bytes {
+                seconds = seconds.min(max as f64 / rate.max(1.0));
+            }
+            let download = rate * seconds;
+            estimate.download.0 += download as u64;
+            if self.upload.is_some() {
+                continue;
+            }
+            let mut disk = match stream.codec.unwrap_or(self.codec) {
+                ArchiveCodec::Mp3 => download,
+                ArchiveCodec::Opus(bitrate) => bitrate.0 as f64 / 8.0 * seconds,
+            };
+            if let Some(bitrate) = self.opus_copy {
+                disk += bitrate.0 as f64 / 8.0 * seconds;
+            }
+            estimate.disk.0 += disk as u64;
+        }
+        estimate
+    }
+}
@@ -0,0 +1,132 @@
+use log::warn;
+use serde_json::json;
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use super::progress::Progress;
+use super::{Listener, StreamOutcome};
+
+/**
+ * Time between `progress` events while a run records.
+ */
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+ * ----------------------------------------------------------------------------
+ * Progress of recording runs as newline-delimited JSON on standard output,
+ * one event per line, for programs wrapping radafi (`--progress json`). Logs
+ * stay on standard error.
+ *
+ *   {"event":"run_started","stations":3}
+ *   {"event":"recording_started","id":"AbCd1234","name":"Rádio A"}
+ *   {"event":"progress","total":3,"active":1,"recorded":0,"failed":0,
+ *    "recordings":[{"id":"AbCd1234","name":"Rádio A","bytes":65536,
+ *    "seconds":4,"paused":false}]}
+ *   {"event":"recording_finished","id":"AbCd1234","name":"Rádio A",
+ *    "bytes":480000,"ok":true,"category":null,"code":null}
+ *   {"event":"run_finished","recorded":3,"failed":0}
+ *
+ * Keys come in alphabetical order rather than as shown. `progress` events
+ * come every second while a run records.
+ */
+pub struct ProgressEvents {
+    out: Mutex<io::Stdout>,
+    progress: Arc<Progress>,
+    running: AtomicBool, // Between run_started and run_finished
+}
+
+impl ProgressEvents {
+    pub fn new(progress: Arc<Progress>) -> Arc<Self> {
+        let events = Arc::new(ProgressEvents {
+            out: Mutex::new(io::stdout()),
+            progress,
+            running: AtomicBool::new(false),
+        });
+        let weak = Arc::downgrade(&events);
+        thread::spawn(move || report_progress(weak));
+        events
+    }
+
+    /**
+     * Writes one event as a line of its own. A closed standard output is
+     * warned about and otherwise ignored, so recordings go on.
+     */
+    fn emit(&self, event: serde_json::Value) {
+        let mut out = self.out.lock().unwrap();
+        if let Err(e) = writeln!(out, "{}", event).and_then(|_| out.flush()) {
+            warn!("Failed to write a progress event: {}", e);
+        }
+    }
+
+    fn emit_progress(&self) {
+        let snapshot = self.progress.snapshot();
+        self.emit(json!({
+            "event": "progress",
+            "total": snapshot.total,
+            "active": snapshot.active,
+            "recorded": snapshot.recorded,
+            "failed": snapshot.failed,
+            "recordings": self.progress.recordings(),
+        }));
+    }
+
+    pub fn run_started(&self, stations: usize) {
+        self.running.store(true, Ordering::Relaxed);
+        self.emit(json!({ "event": "run_started", "stations": stations }));
+    }
+
+    pub fn recording_started(&self, id: &str, name: &str) {
+        self.emit(json!({ "event": "recording_started", "id": id, "name": name }));
+    }
+
+    pub fn recording_finished(&self, outcome: &StreamOutcome) {
+        self.emit(json!({
+            "event": "recording_finished",
+            "id": outcome.id,
+            "name": outcome.name,
+            "bytes": outcome.bytes,
+            "ok": outcome.ok(),
+            "category": outcome.failure_category(),
+            "code": outcome.failure_code(),
+        }));
+    }
+
+    pub fn run_finished(&self) {
+        self.running.store(false, Ordering::Relaxed);
+        let snapshot = self.progress.snapshot();
+        self.emit(json!({
+            "event": "run_finished",
+            "recorded": snapshot.recorded,
+            "failed": snapshot.failed,
+        }));
+    }
+}
+
+/**
+ * Emits a `progress` event every `PROGRESS_INTERVAL` while a run records,
+ * until the events are dropped.
+ */
+fn report_progress(events: Weak<ProgressEvents>) {
+    loop {
+        thread::sleep(PROGRESS_INTERVAL);
+        match events.upgrade() {
+            Some(events) if events.running.load(Ordering::Relaxed) => events.emit_progress(),
+            Some(_) => {}
+            None => return,
+        }
+    }
+}
+
+impl Listener {
+    /**
+     * Writes the progress of recordings to standard output as JSON lines,
+     * see `ProgressEvents`.
+     */
+    pub fn set_progress_json(&mut self) {
+        self.events = Some(ProgressEvents::new(self.progress()));
+    }
+}
@@ -0,0 +1,111 @@
+use serde_json::json;
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use super::Place;
+
+/**
+ * How many places and stations a country has.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountryCount {
+    pub country: String,
+    pub places: usize,
+    pub stations: u64, // Channels listed under its places
+}
+
+/**
+ * Counts the places and stations of each country in `places`, by country
+ * name. Channels listed under several places are counted for each.
+ */
+pub fn count_countries(places: &[Place]) -> Vec<CountryCount> {
+    let mut counts: BTreeMap<&str, CountryCount> = BTreeMap::new();
+    for place in places {
+        let count = counts
+            .entry(place.country.as_str())
+            .or_insert_with(|| CountryCount {
+                country: place.country.clone(),
+                places: 0,
+                stations: 0,
+            });
+        count.places += 1;
+        count.stations += place.size as u64;
+    }
+    counts.into_values().collect()
+}
+
+/**
+ * Writes places as CSV with a header row: id, title, country, latitude,
+ * longitude and station count.
+ */
+pub fn write_places_csv<W: Write>(places: &[Place], out: &mut W) -> io::Result<()> {
+    writeln!(out, "id,title,country,latitude,longitude,stations")?;
+    for place in places {
+        let (lat, lon) = coordinates(place);
+        writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            csv_field(place.id.as_str()),
+            csv_field(&place.title),
+            csv_field(&place.country),
+            lat.map(|v| v.to_string()).unwrap_or_default(),
+            lon.map(|v| v.to_string()).unwrap_or_default(),
+            place.size
+        )?;
+    }
+    Ok(())
+}
+
+/**
+ * Writes places as a GeoJSON FeatureCollection of points. Places without
+ * coordinates are left out.
+ */
+pub fn write_places_geojson<W: Write>(places: &[Place], out: &mut W) -> io::Result<()> {
+    let features: Vec<_> = places
+        .iter()
+        .filter(|p| p.geo.len() >= 2)
+        .map(|place| {
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": [place.geo[0], place.geo[1]],
+                },
+                "properties": {
+                    "id": place.id,
+                    "title": place.title,
+                    "country": place.country,
+                    "stations": place.size,
+                },
+            })
+        })
+        .collect();
+    let collection = json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+    serde_json::to_writer(&mut *out, &collection)?;
+    writeln!(out)
+}
+
+/**
+ * Radio Garden lists coordinates as [longitude, latitude].
+ */
+fn coordinates(place: &Place) -> (Option<f64>, Option<f64>) {
+    match place.geo.as_slice() {
+        [lon, lat, ..] => (Some(*lat), Some(*lon)),
+        _ => (None, None),
+    }
+}
+
+/**
+ * Quotes a CSV field if it contains a separator, quote or line break.
+ */
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,307 @@
+use chrono::{DateTime, FixedOffset};
+use log::warn;
+use serde::Deserialize;
+use url::Url;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::browse::content_type;
+use super::metadata;
+use super::quality::QUARANTINE_DIR;
+
+/**
+ * How recordings are split into feeds.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedGrouping {
+    All,     // One feed over the whole archive
+    Station, // One feed per channel ID
+    Country, // One feed per country of the runs
+}
+
+impl FromStr for FeedGrouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(FeedGrouping::All),
+            "station" => Ok(FeedGrouping::Station),
+            "country" => Ok(FeedGrouping::Country),
+            _ => Err(format!(
+                "invalid grouping {}, expected all, station or country",
+                s
+            )),
+        }
+    }
+}
+
+/**
+ * The parts of a sidecar that make up an episode.
+ */
+#[derive(Deserialize)]
+struct Sidecar {
+    station: String,
+    id: String,
+    started: String,
+    duration: u64,
+    bytes: u64,
+    #[serde(default)]
+    artwork: Option<String>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+    #[serde(default)]
+    quality: Option<SidecarQuality>,
+}
+
+#[derive(Deserialize)]
+struct SidecarQuality {
+    score: u8,
+}
+
+/**
+ * A recording in the archive.
+ */
+pub struct Episode {
+    pub path: PathBuf, // Relative to the archive
+    pub station: String,
+    pub id: String,
+    pub country: Option<String>, // From the `summary.json` of its run, if any
+    pub started: DateTime<FixedOffset>,
+    pub duration: u64,
+    pub length: u64, // File size, for the enclosure
+    pub artwork: Option<PathBuf>,
+    pub tags: BTreeMap<String, String>,
+    pub quality: Option<u8>, // Score from 0 to 100, if the sidecar has one
+}
+
+/**
+ * Finds the playable recordings below `root`: plain MP3 or Opus files with a
+ * sidecar and some audio, oldest first. Symlinks (such as `latest`) are not
+ * followed so recordings are not listed twice.
+ */
+pub fn scan_archive(root: &Path) -> io::Result<Vec<Episode>> {
+    let mut episodes = Vec::new();
+    let mut countries = HashMap::new();
+    scan_directory(root, Path::new(""), &mut countries, &mut episodes)?;
+    episodes.sort_by_key(|episode| episode.started);
+    Ok(episodes)
+}
+
+fn scan_directory(
+    root: &Path,
+    relative: &Path,
+    countries: &mut HashMap<PathBuf, Option<String>>,
+    episodes: &mut Vec<Episode>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(root.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        // Corrupt recordings set aside are not part of the archive
+        if file_type.is_dir() && entry.file_name() != QUARANTINE_DIR {
+            scan_directory(root, &path, countries, episodes)?;
+            continue;
+        }
+        let extension = path.extension().and_then(|e| e.to_str());
+        if !file_type.is_file() || !matches!(extension, Some("mp3" | "opus")) {
+            continue;
+        }
+
+        let recording = root.join(&path);
+        let sidecar: Sidecar = match fs::read(metadata::sidecar_path(&recording))
+            .map_err(|e| e.to_string())
+            .and_then(|body| serde_json::from_slice(&body).map_err(|e| e.to_string()))
+        {
+            Ok(sidecar) => sidecar,
+            // Opus copies have no sidecar of their own
+            Err(_) if extension == Some("opus") => continue,
+            Err(e) => {
+                warn!("Skipping {}: no usable sidecar ({})", path.display(), e);
+                continue;
+            }
+        };
+        let started = match DateTime::parse_from_rfc3339(&sidecar.started) {
+            Ok(started) if sidecar.bytes > 0 => started,
+            _ => continue,
+        };
+        episodes.push(Episode {
+            country: run_country(root, relative, countries),
+            length: entry.metadata()?.len(),
+            // Artwork is saved next to the recording
+            artwork: sidecar
+                .artwork
+                .and_then(|artwork| Some(relative.join(Path::new(&artwork).file_name()?))),
+            path,
+            station: sidecar.station,
+            id: sidecar.id,
+            started,
+            duration: sidecar.duration,
+            tags: sidecar.tags,
+            quality: sidecar.quality.map(|quality| quality.score),
+        });
+    }
+    Ok(())
+}
+
+/**
+ * The country of the run that `directory` (relative to `root`) belongs to,
+ * from the nearest `summary.json` in it or above it.
+ */
+fn run_country(
+    root: &Path,
+    directory: &Path,
+    countries: &mut HashMap<PathBuf, Option<String>>,
+) -> Option<String> {
+    if let Some(country) = countries.get(directory) {
+        return country.clone();
+    }
+    #[derive(Deserialize)]
+    struct Summary {
+        country: String,
+    }
+    let country = fs::read(root.join(directory).join("summary.json"))
+        .ok()
+        .and_then(|body| serde_json::from_slice::<Summary>(&body).ok())
+        .map(|summary| summary.country)
+        .or_else(|| {
+            let parent = directory.parent()?;
+            run_country(root, parent, countries)
+        });
+    countries.insert(directory.to_path_buf(), country.clone());
+    country
+}
+
+/**
+ * Writes RSS podcast feeds over `episodes` into `root`, newest episode
+ * first: `feed.xml`, or `feed-<channel ID>.xml` or `feed-<country>.xml`
+ * per group. Enclosures point below `base_url`, where the archive is served.
+ * Returns the paths written.
+ */
+pub fn write_feeds(
+    root: &Path,
+    episodes: &[Episode],
+    base_url: &Url,
+    grouping: FeedGrouping,
+    title: &str,
+) -> io::Result<Vec<PathBuf>> {
+    let mut groups: BTreeMap<String, Vec<&Episode>> = BTreeMap::new();
+    for episode in episodes {
+        let key = match grouping {
+            FeedGrouping::All => String::new(),
+            FeedGrouping::Station => episode.id.clone(),
+            FeedGrouping::Country => match &episode.country {
+                Some(country) => country.clone(),
+                None => {
+                    warn!("Leaving out {}: no run summary", episode.path.display());
+                    continue;
+                }
+            },
+        };
+        groups.entry(key).or_default().push(episode);
+    }
+
+    let mut written = Vec::new();
+    for (key, episodes) in groups {
+        let (file, feed_title) = match grouping {
+            FeedGrouping::All => ("feed.xml".to_string(), title.to_string()),
+            FeedGrouping::Station => (
+                format!("feed-{}.xml", key),
+                format!("{}: {}", title, episodes[0].station),
+            ),
+            FeedGrouping::Country => (
+                format!("feed-{}.xml", file_safe(&key)),
+                format!("{}: {}", title, key),
+            ),
+        };
+        let path = root.join(file);
+        fs::write(&path, rss(&feed_title, base_url, &episodes))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+fn rss(title: &str, base_url: &Url, episodes: &[&Episode]) -> String {
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\">\n\
+         <channel>\n\
+         <title>{}</title>\n\
+         <link>{}</link>\n\
+         <description>Radio recordings archived by radafi</description>\n",
+        escape(title),
+        escape(base_url.as_str())
+    );
+    if let Some(artwork) = episodes.iter().rev().find_map(|e| e.artwork.as_ref()) {
+        let _ = writeln!(
+            out,
+            "<itunes:image href=\"{}\"/>",
+            escape(url(base_url, artwork).as_str())
+        );
+    }
+    for episode in episodes.iter().rev() {
+        let enclosure = url(base_url, &episode.path);
+        let _ = write!(
+            out,
+            "<item>\n\
+             <title>{} {}</title>\n\
+             <guid isPermaLink=\"false\">{}</guid>\n\
+             <pubDate>{}</pubDate>\n\
+             <enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n\
+             <itunes:duration>{}</itunes:duration>\n",
+            escape(&episode.station),
+            episode.started.format("%Y-%m-%d %H:%M"),
+            escape(&episode.path.to_string_lossy()),
+            episode.started.to_rfc2822(),
+            escape(enclosure.as_str()),
+            episode.length,
+            content_type(&episode.path),
+            episode.duration
+        );
+        if let Some(artwork) = &episode.artwork {
+            let _ = writeln!(
+                out,
+                "<itunes:image href=\"{}\"/>",
+                escape(url(base_url, artwork).as_str())
+            );
+        }
+        out.push_str("</item>\n");
+    }
+    out.push_str("</channel>\n</rss>\n");
+    out
+}
+
+/**
+ * URL of `path` (relative to the archive) below `base_url`, with each
+ * component percent-encoded.
+ */
+pub fn url(base_url: &Url, path: &Path) -> Url {
+    let mut url = base_url.clone();
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop_if_empty();
+        segments.extend(path.components().map(|c| c.as_os_str().to_string_lossy()));
+    }
+    url
+}
+
+/**
+ * Escapes text for XML and HTML.
+ */
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn file_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
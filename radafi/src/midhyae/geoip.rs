@@ -0,0 +1,40 @@
+use log::info;
+use serde::Deserialize;
+
+use super::{Listener, RecordingError};
+
+/**
+ * IP geolocation service used to guess the local country. It answers with
+ * the country of the address the request comes from.
+ */
+const GEOIP_URL: &str = "http://ip-api.com/json/?fields=status,message,country";
+
+#[derive(Deserialize)]
+struct GeoIpResponse {
+    status: String, // "success" or "fail"
+    #[serde(default)]
+    message: String, // Reason for a failure
+    #[serde(default)]
+    country: String,
+}
+
+impl Listener {
+    /**
+     * Looks up the country of this machine's public IP address.
+     */
+    pub async fn local_country(&self) -> Result<String, RecordingError> {
+        info!("Looking up local country from {}", GEOIP_URL);
+        let response: GeoIpResponse = self
+            .client
+            .get(GEOIP_URL)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if response.status != "success" || response.country.is_empty() {
+            return Err(RecordingError::GeoIp(response.message));
+        }
+        Ok(response.country)
+    }
+}
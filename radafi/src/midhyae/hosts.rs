@@ -0,0 +1,300 @@
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::redirect::{Attempt, Policy};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use thiserror::Error;
+
+use std::collections::HashMap;
+use std::error::Error as _;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::dns::DnsCache;
+use super::error::{network_category, ErrorCategory};
+
+/**
+ * How long to leave a host alone after a 429 without a usable
+ * `Retry-After`.
+ */
+const DEFAULT_PAUSE: Duration = Duration::from_secs(30);
+
+/**
+ * Longest pause honoured; a host asking for more is given up on instead.
+ */
+const MAX_PAUSE: Duration = Duration::from_secs(10 * 60);
+
+/**
+ * Requests answered with 429 are sent again this many times, each after the
+ * pause the host asked for.
+ */
+const RATE_LIMIT_RETRIES: u32 = 3;
+
+/**
+ * Consecutive connection failures after which a host is considered down.
+ */
+const FAILURE_THRESHOLD: u32 = 3;
+
+/**
+ * How long a host that is down is left alone before a single connection is
+ * let through to probe it. Each failed probe doubles the time, up to
+ * `MAX_OPEN`.
+ */
+const OPEN_FOR: Duration = Duration::from_secs(60);
+const MAX_OPEN: Duration = Duration::from_secs(15 * 60);
+
+/**
+ * Redirects followed before giving up, as reqwest does by default.
+ */
+const MAX_REDIRECTS: usize = 10;
+
+/**
+ * Circuit breaker of one stream host.
+ */
+#[derive(Default)]
+struct Circuit {
+    failures: u32,               // Consecutive connection failures
+    open_until: Option<Instant>, // Set while the host is considered down
+    open_for: Duration,          // Doubled by each failed probe
+    probing: Option<Instant>,    // When the probe connection was let through
+}
+
+/**
+ * Refusal to connect to a host considered down.
+ */
+#[derive(Debug, Error)]
+#[error("{0} is down; not connecting to it for now")]
+pub struct HostDown(pub String);
+
+/**
+ * ----------------------------------------------------------------------------
+ * State of the hosts requests go to, shared through the `Budget` by every
+ * recording and session: hosts that asked for requests to stop for a while
+ * by answering 429 Too Many Requests, and stream hosts that failed so often
+ * in a row that connecting to them is pointless for now. Stations on a host
+ * that is down fail at once instead of holding a worker until they time out.
+ * Their addresses are cached as well.
+ */
+#[derive(Default)]
+pub struct Hosts {
+    paused: Mutex<HashMap<String, Instant>>, // Host name to end of its pause
+    circuits: Mutex<HashMap<String, Circuit>>, // By host name, once it failed
+    dns: DnsCache,
+}
+
+impl Hosts {
+    pub fn dns(&self) -> &DnsCache {
+        &self.dns
+    }
+
+    /**
+     * Sends `request` once its host is no longer paused. A 429 pauses the
+     * host for as long as its `Retry-After` says and the request is sent
+     * again, a few times at most. The last response is returned whatever its
+     * status.
+     */
+    pub async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let (client, request) = request.build_split();
+        let mut request = request?;
+        let host = request.url().host_str().unwrap_or_default().to_string();
+        let mut retries = 0;
+        loop {
+            self.wait(&host).await;
+            // Requests without a streamed body can always be cloned
+            let retry = request.try_clone();
+            let url = request.url().clone();
+            let response = client.execute(request).await?;
+            if response.status() != StatusCode::TOO_MANY_REQUESTS {
+                return Ok(response);
+            }
+            let pause = retry_after(response.headers()).unwrap_or(DEFAULT_PAUSE);
+            self.pause(&host, pause);
+            request = match retry {
+                Some(retry) if retries < RATE_LIMIT_RETRIES && pause <= MAX_PAUSE => retry,
+                _ => {
+                    warn!("{} keeps rate limiting {}; giving up", host, url);
+                    return Ok(response);
+                }
+            };
+            retries += 1;
+            warn!(
+                "{} is rate limiting requests; pausing it for {}s (retry {} of {})",
+                host,
+                pause.as_secs(),
+                retries,
+                RATE_LIMIT_RETRIES
+            );
+        }
+    }
+
+    /**
+     * Keeps every request to `host` waiting for `pause`, unless it is
+     * already paused for longer.
+     */
+    fn pause(&self, host: &str, pause: Duration) {
+        let until = Instant::now() + pause.min(MAX_PAUSE);
+        let mut paused = self.paused.lock().unwrap();
+        let entry = paused.entry(host.to_string()).or_insert(until);
+        *entry = (*entry).max(until);
+    }
+
+    /**
+     * Whether a connection to `host` may be attempted. Once a host that is
+     * down has been left alone long enough, one connection is let through
+     * to probe it; the others are refused until it reports back.
+     */
+    pub fn allow(&self, host: &str) -> bool {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = match circuits.get_mut(host) {
+            Some(circuit) => circuit,
+            None => return true,
+        };
+        let now = Instant::now();
+        // A probe that never reported back, e.g. because it was redirected
+        // elsewhere, is replaced after a while
+        let probing = circuit
+            .probing
+            .is_some_and(|probe| now - probe < circuit.open_for);
+        match circuit.open_until {
+            None => true,
+            Some(until) if now < until || probing => false,
+            Some(_) => {
+                info!("Probing whether {} is back up", host);
+                circuit.probing = Some(now);
+                true
+            }
+        }
+    }
+
+    /**
+     * Records that a connection to `host` succeeded, or at least got an
+     * answer, closing its circuit.
+     */
+    pub fn succeeded(&self, host: &str) {
+        let circuit = self.circuits.lock().unwrap().remove(host);
+        if circuit.is_some_and(|circuit| circuit.open_until.is_some()) {
+            info!("{} is reachable again", host);
+        }
+    }
+
+    /**
+     * Records that a connection to `host` failed, opening its circuit after
+     * too many failures in a row or if it was being probed.
+     */
+    pub fn failed(&self, host: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(host.to_string()).or_default();
+        circuit.failures += 1;
+        let now = Instant::now();
+        if circuit.open_until.is_some() {
+            circuit.open_for = (circuit.open_for * 2).min(MAX_OPEN);
+        } else if circuit.failures >= FAILURE_THRESHOLD {
+            circuit.open_for = OPEN_FOR;
+        } else {
+            return;
+        }
+        circuit.open_until = Some(now + circuit.open_for);
+        circuit.probing = None;
+        warn!(
+            "{} failed {} times in a row; not connecting to it for {}s",
+            host,
+            circuit.failures,
+            circuit.open_for.as_secs()
+        );
+    }
+
+    /**
+     * Records the outcome of connecting to `host`, which may have redirected
+     * the request to another host. Only failures that suggest the host
+     * is down count against it.
+     */
+    pub fn connected(&self, host: &str, result: &reqwest::Result<Response>) {
+        let (last, down) = match result {
+            Ok(response) => (response.url().host_str(), false),
+            Err(e) => (
+                e.url().and_then(|url| url.host_str()),
+                matches!(
+                    network_category(e),
+                    ErrorCategory::Dns
+                        | ErrorCategory::Tls
+                        | ErrorCategory::Connect
+                        | ErrorCategory::Timeout
+                        | ErrorCategory::Http5xx
+                        | ErrorCategory::Network
+                ),
+            ),
+        };
+        let last = last.unwrap_or(host);
+        if last != host {
+            // The first host answered with a redirect
+            self.succeeded(host);
+        }
+        match down {
+            true => self.failed(last),
+            false => self.succeeded(last),
+        }
+    }
+
+    /**
+     * Sleeps until the pause of `host`, if any, is over.
+     */
+    async fn wait(&self, host: &str) {
+        let until = self.paused.lock().unwrap().get(host).copied();
+        if let Some(wait) = until.and_then(|until| until.checked_duration_since(Instant::now())) {
+            info!(
+                "Waiting {:.1}s for {} to accept requests again",
+                wait.as_secs_f64(),
+                host
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/**
+ * The delay a `Retry-After` header asks for, given in seconds or as an HTTP
+ * date.
+ */
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/**
+ * A redirect policy refusing to follow redirects to hosts that are down.
+ */
+pub fn redirect_policy(hosts: Arc<Hosts>) -> Policy {
+    Policy::custom(move |attempt: Attempt| {
+        let host = attempt.url().host_str().unwrap_or_default().to_string();
+        if attempt.previous().len() > MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if !hosts.allow(&host) {
+            attempt.error(HostDown(host))
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/**
+ * The host a request failed to be redirected to because it is down, if
+ * that is why it failed.
+ */
+pub fn host_down(error: &reqwest::Error) -> Option<&str> {
+    let mut source = error.source();
+    while let Some(cause) = source {
+        if let Some(HostDown(host)) = cause.downcast_ref::<HostDown>() {
+            return Some(host);
+        }
+        source = cause.source();
+    }
+    None
+}
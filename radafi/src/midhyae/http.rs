@@ -0,0 +1,260 @@
+use futures::stream::{self, StreamExt};
+use log::{debug, info};
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client, ClientBuilder};
+use serde::Deserialize;
+use url::Url;
+
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::hosts::{self, Hosts};
+use super::RecordingError;
+
+/**
+ * Keepalive interval of connections unless `tcp_keepalive` is set, so
+ * idle pooled connections are not silently dropped by NAT gateways.
+ */
+const DEFAULT_TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/**
+ * How long an idle connection is kept for reuse unless `pool_idle_timeout`
+ * is set, long enough to outlast a dead-air retry.
+ */
+const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/**
+ * Longest the Radio Garden API may take to send the list of places, and the
+ * channels of one place, unless `places_timeout` and `channels_timeout` are
+ * set. The list of places is a few megabytes.
+ */
+const DEFAULT_PLACES_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_CHANNELS_TIMEOUT: Duration = Duration::from_secs(20);
+
+/**
+ * TLS implementation used for HTTPS connections.
+ */
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsBackend {
+    Native, // The platform's TLS library
+    Rustls,
+}
+
+/**
+ * Address family used to connect. Some stations publish broken AAAA
+ * records, which can make connections hang before falling back to IPv4.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IpVersion {
+    #[default]
+    Auto, // Whatever the resolver returns, IPv6 first
+    V4,
+    V6,
+}
+
+impl FromStr for IpVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(IpVersion::Auto),
+            "4" => Ok(IpVersion::V4),
+            "6" => Ok(IpVersion::V6),
+            _ => Err(format!("invalid IP version {}, expected 4, 6 or auto", s)),
+        }
+    }
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Tuning of the HTTP client used for both the Radio Garden API and the
+ * streams, for station CDNs that misbehave with the defaults. Read from the
+ * `[http]` table of the configuration:
+ *
+ *   [http]
+ *   pool_max_idle_per_host = 0
+ *   tcp_keepalive = 30
+ *   pool_idle_timeout = 300
+ *   places_timeout = 120
+ *   channels_timeout = 30
+ *   warm_up = false
+ *   http2 = false
+ *   tls = "rustls"
+ *   root_ca = "/etc/ssl/private-ca.pem"
+ *   allow_insecure = true
+ */
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct HttpOptions {
+    pub pool_max_idle_per_host: Option<usize>, // Idle connections kept per host
+    pub tcp_keepalive: Option<u64>,            // Keepalive interval in seconds, 0 for none
+    pub pool_idle_timeout: Option<u64>,        // Seconds idle connections are kept
+    pub places_timeout: Option<u64>,           // Seconds to fetch the places, 0 for no limit
+    pub channels_timeout: Option<u64>, // Seconds to fetch a place's channels, 0 for no limit
+    pub warm_up: Option<bool>,         // false skips `warm_up`
+    pub http2: Option<bool>,           // false restricts the client to HTTP/1.1
+    pub tls: Option<TlsBackend>,
+    pub root_ca: Option<PathBuf>, // Extra PEM certificate to trust
+    #[serde(default)]
+    pub allow_insecure: bool, // Record stations not streaming over HTTPS
+    #[serde(skip)]
+    pub ip_version: IpVersion, // Set from the command line
+}
+
+impl HttpOptions {
+    /**
+     * Builds a client with these options applied on top of reqwest's
+     * defaults. It looks host names up through the cache of `hosts` and does
+     * not follow redirects to hosts that are down according to it.
+     */
+    pub fn build(&self, hosts: &Arc<Hosts>) -> Result<Client, RecordingError> {
+        let builder = self.builder(hosts)?;
+        Ok(builder
+            .redirect(hosts::redirect_policy(Arc::clone(hosts)))
+            .build()?)
+    }
+
+    /**
+     * Whether connections are to be opened ahead of recording: not if
+     * `warm_up` is false or idle connections are not kept.
+     */
+    pub fn warms_up(&self) -> bool {
+        self.warm_up != Some(false) && self.pool_max_idle_per_host != Some(0)
+    }
+
+    /**
+     * How long fetching the list of places may take before giving up, if
+     * there is a limit.
+     */
+    pub fn places_timeout(&self) -> Option<Duration> {
+        limit(self.places_timeout, DEFAULT_PLACES_TIMEOUT)
+    }
+
+    /**
+     * How long fetching the channels of one place may take before giving
+     * up, if there is a limit.
+     */
+    pub fn channels_timeout(&self) -> Option<Duration> {
+        limit(self.channels_timeout, DEFAULT_CHANNELS_TIMEOUT)
+    }
+
+    /**
+     * Like `build`, but the client follows no redirects at all, to find out
+     * where they lead.
+     */
+    pub fn build_single_hop(&self, hosts: &Arc<Hosts>) -> Result<Client, RecordingError> {
+        Ok(self.builder(hosts)?.redirect(Policy::none()).build()?)
+    }
+
+    fn builder(&self, hosts: &Hosts) -> Result<ClientBuilder, RecordingError> {
+        let mut builder = Client::builder().dns_resolver(Arc::new(hosts.dns().clone()));
+        if let Some(max_idle) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        builder = match self.tcp_keepalive {
+            Some(0) => builder.tcp_keepalive(None),
+            Some(seconds) => builder.tcp_keepalive(Duration::from_secs(seconds)),
+            None => builder.tcp_keepalive(DEFAULT_TCP_KEEPALIVE),
+        };
+        builder = builder.pool_idle_timeout(
+            self.pool_idle_timeout
+                .map_or(DEFAULT_POOL_IDLE_TIMEOUT, Duration::from_secs),
+        );
+        // Binding to an unspecified local address of one family makes the
+        // connector skip remote addresses of the other
+        builder = match self.ip_version {
+            IpVersion::Auto => builder,
+            IpVersion::V4 => builder.local_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            IpVersion::V6 => builder.local_address(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+        };
+        if self.http2 == Some(false) {
+            builder = builder.http1_only();
+        }
+        builder = match self.tls {
+            Some(TlsBackend::Native) => builder.use_native_tls(),
+            Some(TlsBackend::Rustls) => builder.use_rustls_tls(),
+            None => builder,
+        };
+        if let Some(path) = &self.root_ca {
+            let pem = fs::read(path).map_err(|e| {
+                RecordingError::Config(format!("root_ca {}: {}", path.display(), e))
+            })?;
+            let certificate = Certificate::from_pem(&pem).map_err(|e| {
+                RecordingError::Config(format!("root_ca {}: {}", path.display(), e))
+            })?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        Ok(builder)
+    }
+}
+
+/**
+ * `seconds` as a time limit, 0 meaning none, or `default` if not set.
+ */
+fn limit(seconds: Option<u64>, default: Duration) -> Option<Duration> {
+    match seconds {
+        Some(0) => None,
+        Some(seconds) => Some(Duration::from_secs(seconds)),
+        None => Some(default),
+    }
+}
+
+/**
+ * Opens connections to the hosts of `urls` ahead of recording, for the
+ * recordings to find them in the pool of `client`: each URL is requested
+ * with HEAD, `concurrency` at a time, following redirects the way
+ * recordings do, so both the Radio Garden hop and the stream host are
+ * connected to. URLs are tried over HTTPS first and, if `allow_insecure`
+ * is set, over plain HTTP. Failures are of no consequence and do not count
+ * against the hosts, as many streams do not answer HEAD. Returns how many
+ * URLs answered.
+ */
+pub async fn warm_up(
+    client: &Client,
+    hosts: &Hosts,
+    urls: &[&str],
+    allow_insecure: bool,
+    concurrency: usize,
+) -> usize {
+    let started = Instant::now();
+    let head = |url: String| async move {
+        match hosts.send(client.head(&url)).await {
+            Ok(_) => true,
+            Err(e) => {
+                debug!("Could not warm up a connection for {}: {}", url, e);
+                false
+            }
+        }
+    };
+    // Owned, so the future stays `Send` for callers spawning it
+    let owned: Vec<String> = urls.iter().map(|url| url.to_string()).collect();
+    let warmed: Vec<bool> = stream::iter(owned)
+        .map(|url| async move {
+            let secure = Url::parse(&url).ok().and_then(|mut parsed| {
+                parsed.set_scheme("https").ok()?;
+                Some(parsed.to_string())
+            });
+            if let Some(secure) = secure {
+                if head(secure).await {
+                    return true;
+                }
+            }
+            allow_insecure && !url.starts_with("https:") && head(url).await
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    let warmed = warmed.into_iter().filter(|&warmed| warmed).count();
+    info!(
+        "Warmed up connections for {} of {} stations in {} ms",
+        warmed,
+        urls.len(),
+        started.elapsed().as_millis()
+    );
+    warmed
+}
@@ -0,0 +1,182 @@
+use reqwest::Response;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/**
+ * Response headers of a stream kept in its sidecar, as the only word on its
+ * name, genre and bitrate (in kbit/s) many stations give.
+ */
+const KEPT_HEADERS: [&str; 5] = ["icy-name", "icy-genre", "icy-br", "content-type", "server"];
+
+/**
+ * The headers of `response` that are kept, by lowercase name. Stations
+ * often send ICY headers in Latin-1, so values are read leniently.
+ */
+pub fn kept_headers(response: &Response) -> BTreeMap<String, String> {
+    KEPT_HEADERS
+        .iter()
+        .filter_map(|&name| {
+            let value = response.headers().get(name)?;
+            let value = String::from_utf8_lossy(value.as_bytes()).trim().to_string();
+            (!value.is_empty()).then(|| (name.to_string(), value))
+        })
+        .collect()
+}
+
+/**
+ * Separates the ICY metadata blocks that Shoutcast and Icecast servers
+ * interleave with the audio when asked to with `Icy-MetaData: 1`. Every
+ * `interval` bytes of audio are followed by a length byte (in units of 16
+ * bytes) and that much metadata, such as `StreamTitle='Artist - Song';`.
+ */
+pub struct IcyDemuxer {
+    interval: usize,
+    until_meta: usize,        // Audio bytes left before the next length byte
+    meta_left: Option<usize>, // Bytes left of the metadata block being read
+    title: Option<String>,    // Latest non-empty stream title
+    meta: Vec<u8>,
+}
+
+impl IcyDemuxer {
+    /**
+     * A demuxer for `response`, or None if the server does not interleave
+     * metadata.
+     */
+    pub fn from_response(response: &Response) -> Option<Self> {
+        let interval = response
+            .headers()
+            .get("icy-metaint")?
+            .to_str()
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&interval| interval > 0)?;
+        Some(IcyDemuxer {
+            interval,
+            until_meta: interval,
+            meta_left: None,
+            title: None,
+            meta: Vec::new(),
+        })
+    }
+
+    /**
+     * Removes the metadata from the next chunk of the stream and returns the
+     * audio, and the stream title if this chunk changed it.
+     */
+    pub fn feed(&mut self, chunk: &[u8]) -> (Vec<u8>, Option<String>) {
+        let mut audio = Vec::with_capacity(chunk.len());
+        let mut changed = None;
+        let mut rest = chunk;
+        while !rest.is_empty() {
+            match self.meta_left {
+                None if self.until_meta == 0 => {
+                    let length = rest[0] as usize * 16;
+                    rest = &rest[1..];
+                    if length == 0 {
+                        self.until_meta = self.interval;
+                    } else {
+                        self.meta.clear();
+                        self.meta_left = Some(length);
+                    }
+                }
+                None => {
+                    let n = self.until_meta.min(rest.len());
+                    audio.extend_from_slice(&rest[..n]);
+                    rest = &rest[n..];
+                    self.until_meta -= n;
+                }
+                Some(left) => {
+                    let n = left.min(rest.len());
+                    self.meta.extend_from_slice(&rest[..n]);
+                    rest = &rest[n..];
+                    if n < left {
+                        self.meta_left = Some(left - n);
+                        continue;
+                    }
+                    self.meta_left = None;
+                    self.until_meta = self.interval;
+                    let title = stream_title(&self.meta).filter(|t| !t.is_empty());
+                    if title.is_some() && title != self.title {
+                        self.title = title.clone();
+                        changed = title;
+                    }
+                }
+            }
+        }
+        (audio, changed)
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+}
+
+/**
+ * The `StreamTitle` of a metadata block.
+ */
+fn stream_title(meta: &[u8]) -> Option<String> {
+    let meta = String::from_utf8_lossy(meta);
+    let start = meta.find("StreamTitle='")? + "StreamTitle='".len();
+    // Titles may contain quotes themselves
+    let end = meta[start..]
+        .find("';")
+        .or_else(|| meta[start..].trim_end_matches('\0').rfind('\''))?;
+    Some(meta[start..start + end].trim().to_string())
+}
+
+/**
+ * A track within a recording: a stream title and when it started.
+ */
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub start: f64, // Seconds from the start of the file
+    pub title: String,
+}
+
+/**
+ * Writes a cue sheet next to `recording` (with the extension `.cue`) that
+ * lets players jump between its tracks. Titles of the form `Artist - Song`
+ * are split into performer and title.
+ */
+pub fn write_cue_sheet(recording: &Path, station: &str, tracks: &[Track]) -> io::Result<()> {
+    let file = recording
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut cue = format!(
+        "PERFORMER \"{}\"\nTITLE \"{}\"\nFILE \"{}\" MP3\n",
+        quote(station),
+        quote(station),
+        quote(&file)
+    );
+    for (number, track) in tracks.iter().enumerate() {
+        let (performer, title) = match track.title.split_once(" - ") {
+            Some((performer, title)) => (performer, title),
+            None => (station, track.title.as_str()),
+        };
+        // Cue sheets count in frames of 1/75 second
+        let frames = (track.start.max(0.0) * 75.0).round() as u64;
+        cue.push_str(&format!(
+            "  TRACK {:02} AUDIO\n    TITLE \"{}\"\n    PERFORMER \"{}\"\n    INDEX 01 {:02}:{:02}:{:02}\n",
+            number + 1,
+            quote(title),
+            quote(performer),
+            frames / 75 / 60,
+            frames / 75 % 60,
+            frames % 75
+        ));
+    }
+    fs::write(recording.with_extension("cue"), cue)
+}
+
+/**
+ * Cue sheets have no escapes, so double quotes become single ones.
+ */
+fn quote(value: &str) -> String {
+    value.replace('"', "'")
+}
@@ -0,0 +1,122 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/**
+ * An ID3v2.3 frame: its four-character ID and its body.
+ */
+pub struct Frame {
+    id: &'static [u8; 4],
+    body: Vec<u8>,
+}
+
+impl Frame {
+    /**
+     * A picture of the given type (3 is the front cover).
+     */
+    pub fn picture(mime: &str, picture_type: u8, image: &[u8]) -> Frame {
+        // Text encoding, MIME type, picture type, empty description
+        let mut body = vec![0];
+        body.extend_from_slice(mime.as_bytes());
+        body.extend_from_slice(&[0, picture_type, 0]);
+        body.extend_from_slice(image);
+        Frame { id: b"APIC", body }
+    }
+
+    /**
+     * A comment with a short description, in UTF-16 so any text fits.
+     */
+    pub fn comment(description: &str, text: &str) -> Frame {
+        let mut body = vec![1];
+        body.extend_from_slice(b"XXX"); // No particular language
+        body.extend(utf16(description));
+        body.extend_from_slice(&[0, 0]);
+        body.extend(utf16(text));
+        Frame { id: b"COMM", body }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.id.to_vec();
+        bytes.extend_from_slice(&(self.body.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+/**
+ * UTF-16 with a byte order mark, as ID3v2.3 expects it.
+ */
+fn utf16(text: &str) -> impl Iterator<Item = u8> + '_ {
+    [0xFF, 0xFE]
+        .into_iter()
+        .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+}
+
+/**
+ * Tag sizes are "syncsafe", seven bits per byte.
+ */
+fn syncsafe(size: u32) -> [u8; 4] {
+    [
+        (size >> 21) as u8 & 0x7F,
+        (size >> 14) as u8 & 0x7F,
+        (size >> 7) as u8 & 0x7F,
+        size as u8 & 0x7F,
+    ]
+}
+
+/**
+ * Adds `frames` to the ID3v2 tag at the start of the MP3 file at `path`,
+ * creating the tag if there is none. Tags of other versions, or using
+ * unsynchronisation or an extended header, are left alone with an error.
+ */
+pub fn add_frames(path: &Path, frames: &[Frame]) -> io::Result<()> {
+    let mut audio = File::open(path)?;
+    let mut header = [0u8; 10];
+    let mut existing = Vec::new();
+    let has_tag = audio.read_exact(&mut header).is_ok() && &header[..3] == b"ID3";
+    if has_tag {
+        if header[3] != 3 || header[5] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("ID3v2.{} tag with flags {:#x}", header[3], header[5]),
+            ));
+        }
+        let size = header[6..]
+            .iter()
+            .fold(0usize, |size, &b| size << 7 | (b & 0x7F) as usize);
+        existing.resize(size, 0);
+        audio.read_exact(&mut existing)?;
+        // Drop the padding, which starts where the next frame ID would
+        let mut end = 0;
+        while end + 10 <= existing.len() && existing[end] != 0 {
+            let size = u32::from_be_bytes([
+                existing[end + 4],
+                existing[end + 5],
+                existing[end + 6],
+                existing[end + 7],
+            ]);
+            end = (end + 10 + size as usize).min(existing.len());
+        }
+        existing.truncate(end);
+    } else {
+        audio.seek(SeekFrom::Start(0))?;
+    }
+
+    let mut body = existing;
+    for frame in frames {
+        body.extend(frame.to_bytes());
+    }
+    let mut tag = b"ID3\x03\x00\x00".to_vec();
+    tag.extend_from_slice(&syncsafe(body.len() as u32));
+    tag.extend(body);
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    let mut file = File::create(&temporary)?;
+    file.write_all(&tag)?;
+    io::copy(&mut audio, &mut file)?;
+    file.flush()?;
+    fs::rename(&temporary, path)
+}
@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::str::FromStr;
+
+/**
+ * Whether `id` has the form of a Radio Garden ID: one or more ASCII letters
+ * and digits.
+ */
+fn valid(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * The ID of a Radio Garden channel, e.g. "AbCd1234", as found in listen and
+ * channel page URLs. Checked once, when parsed, so a `ChannelId` is always
+ * safe to put in a URL path or a file name, and cannot be passed where a
+ * `PlaceId` is expected. Serialized as a plain string.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct ChannelId(String);
+
+/**
+ * The ID of a Radio Garden place, e.g. "Aq7xFU2G", the same way as
+ * `ChannelId`.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct PlaceId(String);
+
+macro_rules! id {
+    ($name:ident, $what:literal) => {
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                if valid(s) {
+                    Ok($name(s.to_string()))
+                } else {
+                    Err(format!(
+                        concat!("invalid ", $what, " ID {:?}, expected letters and digits"),
+                        s
+                    ))
+                }
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = String;
+
+            fn try_from(s: String) -> Result<Self, Self::Error> {
+                s.parse()
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> String {
+                id.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        // Lets maps keyed by ID be looked up with a `&str`
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+    };
+}
+
+id!(ChannelId, "channel");
+id!(PlaceId, "place");
@@ -0,0 +1,1301 @@
+use bytes::Bytes;
+use chrono::{DateTime, Local};
+use log::{error, info, warn};
+use reqwest::{Client, Response};
+use tracing::{info_span, Span};
+use url::Url;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::analysis::{self, Levels};
+use super::canonical::canonical_url;
+use super::dump::DebugDump;
+use super::encrypt::Encryption;
+use super::events::ProgressEvents;
+use super::hosts;
+use super::icy::{self, Track};
+use super::id3::{self, Frame};
+use super::journal::{Entry, Journal};
+use super::manager::Budget;
+use super::metadata::{self, Metadata};
+use super::mp3::{self, Trim};
+use super::mqtt::Mqtt;
+use super::opus::{ArchiveCodec, Bitrate, OpusEncoder};
+use super::pipeline::{Chunk, StreamPipeline};
+use super::postprocess::Pipeline;
+use super::progress::Progress;
+use super::quality::{self, Probe, Quality};
+use super::s3::S3Target;
+use super::session;
+use super::sink::Sink;
+use super::staging;
+use super::suspend::SuspendWatch;
+use super::template;
+use super::vad::SpeechGating;
+use super::{ChannelId, PlaceRef, RecordingError, StreamError, StreamOutcome};
+
+/**
+ * Settings shared by every recording of a `record_streams` call.
+ */
+pub struct JobSettings {
+    pub client: Client,
+    pub upload: Option<Arc<S3Target>>,
+    pub encryption: Option<Arc<Encryption>>,
+    pub post_processing: Pipeline,
+    pub check_levels: bool,
+    pub debug_dump: Option<Arc<DebugDump>>,
+    pub journal: Option<Arc<Journal>>,
+    pub mqtt: Option<Arc<Mqtt>>,
+    pub events: Option<Arc<ProgressEvents>>,
+    pub progress: Arc<Progress>,
+    pub budget: Arc<Budget>,
+    pub drop_when_paused: bool, // Close the connection of paused recordings
+    pub latest_links: Option<PathBuf>,
+    pub staging: Option<PathBuf>, // Where files are written until they are finished
+    pub write_buffer: u64,        // Bytes each recording may hold in memory, 0 for none
+    pub segment: Option<Duration>,
+    pub max_bytes: Option<u64>, // Of audio per recording
+    pub speech_only: bool,      // Write only audio classified as speech
+    pub trim: bool,
+    pub cue_sheets: bool,
+    pub opus_copy: Option<Bitrate>,
+    pub session: String,      // Identifies the session in log lines
+    pub purge_corrupt: bool,  // Delete corrupt files instead of quarantining them
+    pub allow_insecure: bool, // Fall back to plain HTTP for stations without HTTPS
+    pub audio_duration: bool, // Count the audio received towards the duration
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * One stream to record, detached from the `Listener` so it can run on a
+ * worker thread.
+ */
+pub struct Job {
+    pub id: ChannelId,
+    pub name: String,
+    pub url: String,
+    pub places: Vec<PlaceRef>,
+    pub duration: u64,
+    pub path_template: String, // Output directory joined with the filename template
+    pub filename: String,      // Filename template alone, used for upload keys
+    pub codec: ArchiveCodec,
+    pub tags: BTreeMap<String, String>, // Of the session and the station
+    pub settings: Arc<JobSettings>,
+}
+
+/**
+ * Recordings that turn out to be dead air are retried this many times; the
+ * listen URL may redirect to a different relay on the next attempt.
+ */
+const DEAD_AIR_RETRIES: u32 = 1;
+
+/**
+ * How often a paused recording checks whether it has been resumed.
+ */
+const PAUSE_POLL: Duration = Duration::from_millis(250);
+
+/**
+ * How far past the end of a segment to look for an MP3 frame to start the
+ * next one at, before giving up on alignment (e.g. for AAC streams).
+ */
+pub(super) const MAX_FRAME_SEARCH: usize = 64 * 1024;
+
+/**
+ * Longest a recording whose duration counts the audio received runs, as a
+ * multiple of that duration, so a stream that keeps stalling still ends.
+ */
+const MAX_AUDIO_WAIT: u32 = 2;
+
+/**
+ * Times a recording connects to its stream again after it dropped, in all,
+ * before giving up. The wait before each attempt grows by
+ * `RECONNECT_DELAY`.
+ */
+const MAX_RECONNECTS: u32 = 5;
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/**
+ * Longest a recording waits for the next chunk of its stream before
+ * checking whether the system was suspended meanwhile, as the connection
+ * rarely survives a suspend and may hang instead of failing.
+ */
+const SUSPEND_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/**
+ * How much of its duration a recording has done: the wall-clock time spent
+ * on it, pauses aside and time the system was suspended included, or with
+ * `by_audio` the audio received. Audio is
+ * counted by its MP3 frames or, for other streams, by its bytes at the
+ * bitrate the server announces; streams with neither fall back to the
+ * wall clock.
+ */
+struct Clock {
+    started: Instant,
+    paused: Duration,
+    suspended: Duration, // Not counted by `Instant`
+    by_audio: bool,
+    bitrate: Option<u64>, // Bits per second, from `icy-br`
+}
+
+impl Clock {
+    fn new(by_audio: bool, headers: &BTreeMap<String, String>) -> Self {
+        let bitrate = headers
+            .get("icy-br")
+            .and_then(|kbits| kbits.split(',').next()?.trim().parse::<u64>().ok())
+            .filter(|&kbits| kbits > 0)
+            .map(|kbits| kbits * 1000);
+        Clock {
+            started: Instant::now(),
+            paused: Duration::ZERO,
+            suspended: Duration::ZERO,
+            by_audio,
+            bitrate,
+        }
+    }
+
+    fn wall(&self) -> Duration {
+        self.started.elapsed() + self.suspended - self.paused
+    }
+
+    /**
+     * Given the seconds of MP3 frames and the bytes of audio received.
+     */
+    fn recorded(&self, frame_seconds: f64, bytes: u64) -> Duration {
+        if !self.by_audio {
+            return self.wall();
+        }
+        match self.bitrate {
+            _ if frame_seconds > 0.0 => Duration::from_secs_f64(frame_seconds),
+            Some(bitrate) => Duration::from_secs_f64(bytes as f64 * 8.0 / bitrate as f64),
+            None => self.wall(),
+        }
+    }
+}
+
+/**
+ * What came of waiting for the next chunk of a stream.
+ */
+enum Waited {
+    Chunk(reqwest::Result<Option<Bytes>>),
+    Suspended(Duration), // The system was, for this long, meanwhile
+    Nothing,             // Yet
+}
+
+/**
+ * A file written by `capture`: the whole recording, or one segment of it.
+ */
+struct Part {
+    path: PathBuf,                // Where the file is written
+    destination: Option<PathBuf>, // Where it is moved once finished, when staging
+    number: Option<usize>,        // Segment number, counting from 1
+    started: DateTime<Local>,
+    duration: u64, // Requested seconds of audio
+    bytes: u64,
+    trim: Option<Trim>,
+    tracks: Vec<Track>,           // Stream titles, for the cue sheet
+    speech: Option<SpeechGating>, // What was kept, when only speech is
+    stalls: u32,                  // Long waits for the stream, see `quality`
+    stalled: Duration,
+    scheme: Option<String>, // Of the stream URL connected to, "https" or "http"
+    headers: BTreeMap<String, String>, // Kept from the stream response
+}
+
+impl Job {
+    /**
+     * Records the stream on the current thread, which gets its own runtime.
+     */
+    pub fn run(self) -> StreamOutcome {
+        let settings = Arc::clone(&self.settings);
+        let worker = thread::current().name().unwrap_or("main").to_string();
+        let span = self.span(&worker);
+        let _entered = span.enter();
+        // Other sessions sharing the budget may hold every slot for a while
+        let _slot = settings.budget.acquire();
+        if settings.progress.is_cancelled() {
+            let mut outcome = self.outcome();
+            fail(
+                &mut outcome,
+                StreamError::Cancelled {
+                    station: self.name.clone(),
+                },
+            );
+            return outcome;
+        }
+        settings.progress.started(self.id.as_str(), &self.name);
+        if let Some(mqtt) = &settings.mqtt {
+            mqtt.recording_started(self.id.as_str(), &self.name);
+        }
+        if let Some(events) = &settings.events {
+            events.recording_started(self.id.as_str(), &self.name);
+        }
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let (outcome, _) = rt.block_on(self.record());
+        settings.progress.finished(self.id.as_str(), outcome.ok());
+        if let Some(mqtt) = &settings.mqtt {
+            mqtt.recording_finished(&outcome);
+        }
+        if let Some(events) = &settings.events {
+            events.recording_finished(&outcome);
+        }
+        outcome
+    }
+
+    /**
+     * Context of the log lines of the recording, on `worker`.
+     */
+    pub fn span(&self, worker: &str) -> Span {
+        info_span!(
+            "recording",
+            session = %self.settings.session,
+            worker = %worker,
+            channel = %self.id,
+            station = %self.name,
+        )
+    }
+
+    fn outcome(&self) -> StreamOutcome {
+        StreamOutcome {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            bytes: 0,
+            error: None,
+            category: None,
+            dead_air: false,
+            seconds: 0,
+            place: self.places.first().map(|place| place.title.clone()),
+            stream_url: None,
+            merged: Vec::new(),
+        }
+    }
+
+    /**
+     * Records the stream on the current runtime, returning the outcome and
+     * the path recorded to (the first segment's, when segmenting).
+     */
+    pub async fn record(&self) -> (StreamOutcome, PathBuf) {
+        let settings = &self.settings;
+        let local = settings.upload.is_none();
+        // Decoding is only possible on plain local MP3 files
+        let decodable = local && settings.encryption.is_none() && self.codec == ArchiveCodec::Mp3;
+
+        let mut attempt = 0;
+        loop {
+            let now = Local::now();
+            let target_path = self.target_path(&now);
+            let mut outcome = self.outcome();
+            let mut parts = self.capture(&now, &target_path, &mut outcome).await;
+            let first_path = parts[0]
+                .destination
+                .clone()
+                .unwrap_or(parts[0].path.clone());
+            if !local {
+                return (outcome, first_path);
+            }
+            if settings.trim && decodable {
+                self.trim(&mut parts);
+            }
+
+            let levels: Vec<Option<Levels>> = parts
+                .iter()
+                .map(|part| {
+                    if !(decodable && part.bytes > 0 && settings.check_levels) {
+                        return None;
+                    }
+                    analysis::measure_levels(&part.path)
+                        .map_err(|e| error!("Error measuring {}: {}", part.path.display(), e))
+                        .ok()
+                })
+                .collect();
+            outcome.dead_air = levels
+                .iter()
+                .all(|l| l.as_ref().is_some_and(|l| l.dead_air));
+            if outcome.dead_air && attempt < DEAD_AIR_RETRIES && !settings.progress.is_cancelled() {
+                attempt += 1;
+                warn!(
+                    "{} recorded only dead air; retrying (attempt {})",
+                    self.name, attempt
+                );
+                continue;
+            }
+            if outcome.dead_air {
+                warn!("{} recorded only dead air", self.name);
+            }
+
+            let probes: Vec<Option<Probe>> = parts
+                .iter()
+                .map(|part| {
+                    if !(decodable && part.bytes > 0) {
+                        return None;
+                    }
+                    quality::probe(&part.path)
+                        .map_err(|e| error!("Error probing {}: {}", part.path.display(), e))
+                        .ok()
+                })
+                .collect();
+            let last = parts.len() - 1;
+            let failure = outcome.error.clone();
+            let mut corrupt = None;
+            let finished = parts.into_iter().zip(levels).zip(probes);
+            for (index, ((part, levels), probe)) in finished.enumerate() {
+                // Only the part being written when the recording failed has the error
+                let error = failure.clone().filter(|_| index == last);
+                let corruption = probe.and_then(|probe| probe.corruption());
+                if corrupt.is_none() {
+                    corrupt.clone_from(&corruption);
+                }
+                self.finish_part(&part, error, levels, probe, corruption)
+                    .await;
+            }
+            if let (Some(reason), None) = (corrupt, &outcome.error) {
+                let station = self.name.clone();
+                fail(&mut outcome, StreamError::Corrupt { station, reason });
+            }
+            return (outcome, first_path);
+        }
+    }
+
+    /**
+     * Cuts the recorded files to the requested length of audio, counted
+     * frame by frame, since buffering makes the wall-clock duration of the
+     * capture differ from the audio received. Only the last files are cut
+     * when segmenting, so no audio is lost between segments.
+     */
+    fn trim(&self, parts: &mut [Part]) {
+        let mut remaining = self.duration as f64;
+        for part in parts.iter_mut().filter(|part| part.bytes > 0) {
+            let mut trim = match mp3::trim_file(&part.path, remaining.max(0.0)) {
+                Ok(trim) => trim,
+                Err(e) => {
+                    error!("Error trimming {}: {}", part.path.display(), e);
+                    return;
+                }
+            };
+            remaining -= trim.kept_seconds;
+            // Report each file against its own requested length
+            trim.delta_seconds = trim.kept_seconds - part.duration as f64;
+            part.trim = Some(trim);
+            if let Ok(file) = fs::metadata(&part.path) {
+                part.bytes = file.len();
+            }
+        }
+        info!(
+            "Trimmed {} to {:.2} s of audio for {} s requested ({:+.2} s)",
+            self.name,
+            self.duration as f64 - remaining,
+            self.duration,
+            -remaining
+        );
+    }
+
+    /**
+     * Writes the sidecar of a finished local file, with its quality from
+     * `probe` if it could be decoded, and runs the post-processing stages
+     * on it. Files found `corrupt` are set aside instead.
+     */
+    async fn finish_part(
+        &self,
+        part: &Part,
+        error: Option<String>,
+        levels: Option<Levels>,
+        probe: Option<Probe>,
+        corrupt: Option<String>,
+    ) {
+        let settings = &self.settings;
+        // Audio dropped for not being speech is not missing
+        let expected = part
+            .speech
+            .map_or(part.duration, |speech| speech.kept_seconds.round() as u64);
+        let quality = Quality::new(
+            expected,
+            part.bytes,
+            error.is_some(),
+            part.stalls,
+            part.stalled,
+            probe,
+        );
+        let mut metadata = Metadata {
+            station: self.name.clone(),
+            id: self.id.to_string(),
+            url: self.url.clone(),
+            places: self.places.clone(),
+            started: part.started.to_rfc3339(),
+            duration: part.duration,
+            bytes: part.bytes,
+            error,
+            encryption: settings.encryption.as_ref().map(|e| e.info()),
+            classification: None,
+            levels,
+            trim: part.trim,
+            speech: part.speech,
+            quality: Some(quality),
+            corrupt,
+            scheme: part.scheme.clone(),
+            headers: part.headers.clone(),
+            artwork: None,
+            codec: match self.codec {
+                ArchiveCodec::Mp3 => None,
+                codec => Some(codec.to_string()),
+            },
+            tags: self.tags.clone(),
+        };
+        if metadata.corrupt.is_some() {
+            let path = self.unstage(part);
+            self.set_aside(&path, &metadata);
+            self.journal_finished(part);
+            return;
+        }
+        let ok = metadata.error.is_none();
+        let plain_mp3 = settings.encryption.is_none() && self.codec == ArchiveCodec::Mp3;
+        if plain_mp3 && part.bytes > 0 && !self.tags.is_empty() {
+            let frames: Vec<Frame> = self
+                .tags
+                .iter()
+                .map(|(key, value)| Frame::comment(key, value))
+                .collect();
+            if let Err(e) = id3::add_frames(&part.path, &frames) {
+                warn!("Failed to tag {}: {}", part.path.display(), e);
+            }
+        }
+        settings
+            .post_processing
+            .run(&settings.client, &part.path, &mut metadata)
+            .await;
+        if let Err(e) = metadata::write_sidecar(&part.path, &metadata) {
+            error!("Error writing metadata for {}: {}", part.path.display(), e);
+        }
+        if settings.cue_sheets && plain_mp3 {
+            // Tracks starting after a trimmed end are gone
+            let end = part.trim.map_or(f64::INFINITY, |trim| trim.kept_seconds);
+            let tracks: Vec<Track> = part
+                .tracks
+                .iter()
+                .filter(|track| track.start < end)
+                .cloned()
+                .collect();
+            if !tracks.is_empty() {
+                if let Err(e) = icy::write_cue_sheet(&part.path, &self.name, &tracks) {
+                    error!("Error writing cue sheet for {}: {}", part.path.display(), e);
+                }
+            }
+        }
+        let path = self.unstage(part);
+        if let (Some(root), true) = (&settings.latest_links, ok) {
+            if let Err(e) = session::update_latest_recording(root, &self.name, &path) {
+                warn!("Failed to link latest recording of {}: {}", self.name, e);
+            }
+        }
+        self.journal_finished(part);
+    }
+
+    /**
+     * Moves the files of a staged part to its destination, returning where
+     * the file is now. Files that fail to move are left in staging.
+     */
+    fn unstage(&self, part: &Part) -> PathBuf {
+        let Some(destination) = &part.destination else {
+            return part.path.clone();
+        };
+        match staging::unstage(&part.path, destination) {
+            Ok(()) => destination.clone(),
+            Err(e) => {
+                error!(
+                    "Failed to move {} to {}: {}",
+                    part.path.display(),
+                    destination.display(),
+                    e
+                );
+                part.path.clone()
+            }
+        }
+    }
+
+    /**
+     * Moves the corrupt file at `path` and its sidecar to the quarantine
+     * directory next to it, or deletes the file when purging, see
+     * `quality::quarantine`.
+     */
+    fn set_aside(&self, path: &Path, metadata: &Metadata) {
+        let reason = metadata.corrupt.as_deref().unwrap_or_default();
+        if let Err(e) = metadata::write_sidecar(path, metadata) {
+            error!("Error writing metadata for {}: {}", path.display(), e);
+            return;
+        }
+        let companions = [path.with_extension("opus")];
+        let companions = &companions[..self.settings.opus_copy.map_or(0, |_| 1)];
+        let purge = self.settings.purge_corrupt;
+        match quality::quarantine(path, companions, purge) {
+            Ok(_) if purge => warn!("Deleted {}: {}", path.display(), reason),
+            Ok(sidecar) => warn!(
+                "Quarantined {} in {}: {}",
+                path.display(),
+                sidecar.parent().unwrap_or(Path::new("")).display(),
+                reason
+            ),
+            Err(e) => error!("Failed to set aside {}: {}", path.display(), e),
+        }
+    }
+
+    /**
+     * Notes in the journal that a file needs no recovery.
+     */
+    fn journal_finished(&self, part: &Part) {
+        if let Some(journal) = &self.settings.journal {
+            journal.record(&Entry::RecordingFinished {
+                path: part.path.to_string_lossy().into_owned(),
+            });
+        }
+    }
+
+    /**
+     * Expands the output path for a recording starting at `now`.
+     */
+    fn target_path(&self, now: &DateTime<Local>) -> PathBuf {
+        let mut target_path = PathBuf::from(template::expand(
+            &self.path_template,
+            now,
+            &self.name,
+            self.id.as_str(),
+        ));
+        if self.codec != ArchiveCodec::Mp3 {
+            target_path.set_extension(self.codec.extension());
+        }
+        if self.settings.encryption.is_some() {
+            let mut name = target_path.into_os_string();
+            name.push(".age");
+            target_path = PathBuf::from(name);
+        }
+        if self.settings.upload.is_none() {
+            target_path = template::native_path(target_path);
+            if let Some(parent) = target_path.parent() {
+                if let Err(e) = fs::create_dir_all(parent) {
+                    error!("Error creating directory {}: {}", parent.display(), e);
+                }
+            }
+        }
+        target_path
+    }
+
+    /**
+     * Copies the stream into its sink for the requested duration, or until
+     * the configured number of bytes has been received, recording
+     * progress and failures in `outcome`. When segmenting, a new file is
+     * started at the first MP3 frame after each segment length of audio, so
+     * every segment plays on its own; likewise when rotation is asked for
+     * through `Progress::rotate`. Returns the files written, of which
+     * there is at least one even if connecting failed.
+     */
+    async fn capture(
+        &self,
+        now: &DateTime<Local>,
+        target_path: &Path,
+        outcome: &mut StreamOutcome,
+    ) -> Vec<Part> {
+        let settings = &self.settings;
+        let total = Duration::from_secs(self.duration);
+        let segment = settings.segment.filter(|segment| *segment < total);
+        let first = segment.map_or(total, |segment| segment.min(total));
+        let mut parts = vec![self.part(target_path, segment.map(|_| 1), *now, first)];
+
+        let mut response = match self.connect().await {
+            Ok(response) => response,
+            Err(e) => {
+                fail(outcome, e);
+                return parts;
+            }
+        };
+        let mut sink = match self.open_sink(now, &parts[0]).await {
+            Ok(sink) => sink,
+            Err(e) => {
+                fail(outcome, e);
+                return parts;
+            }
+        };
+
+        let mut scheme = response.url().scheme().to_string();
+        outcome.stream_url = Some(canonical_url(response.url().as_str()));
+        let mut headers = icy::kept_headers(&response);
+        // Frames are followed to cut files and to time track changes
+        let mut pipeline = StreamPipeline::new(
+            self.id.as_str(),
+            &self.name,
+            &response,
+            settings.speech_only,
+        );
+        pipeline.tap(settings.progress.clone());
+        let mut part_start = 0.0; // Seconds of audio before the current part
+        let mut searched = 0; // Bytes written since the current part was due to end
+        let mut part_began = Duration::ZERO; // Recorded time before the current part
+        let mut rotations = settings.progress.rotations();
+        let mut rotating = false; // A new file was asked for, at the next frame
+        let mut clock = Clock::new(settings.audio_duration, &headers);
+        let mut full = false; // Stopped early at the size limit
+        let mut gated = SpeechGating::default(); // Gating before the current part
+        let mut suspend = SuspendWatch::default();
+        let mut woke = None; // Time suspended, noticed while waiting for the stream
+        let mut reconnects = 0; // After the stream dropped
+        let mut waiting = None; // Since when the next chunk is awaited
+        while clock.recorded(pipeline.seconds(), outcome.bytes) < total {
+            // The stream went on without us while the system was suspended
+            if let Some(slept) = woke.take().or_else(|| suspend.check()) {
+                clock.suspended += slept;
+                if clock.wall() >= total {
+                    warn!(
+                        "{} was suspended for {}s, past the end of its recording; finishing",
+                        self.name,
+                        slept.as_secs()
+                    );
+                    break;
+                }
+                warn!(
+                    "{} was suspended for {}s; reconnecting into a new file",
+                    self.name,
+                    slept.as_secs()
+                );
+                response = match self.connect().await {
+                    Ok(response) => response,
+                    Err(e) => {
+                        fail(outcome, e);
+                        break;
+                    }
+                };
+                scheme = response.url().scheme().to_string();
+                outcome.stream_url = Some(canonical_url(response.url().as_str()));
+                headers = icy::kept_headers(&response);
+                pipeline.reconnected(&response);
+                let recorded = clock.recorded(pipeline.seconds(), outcome.bytes);
+                let rest = total.saturating_sub(recorded);
+                let duration = segment.map_or(rest, |segment| segment.min(rest));
+                if let Err(e) = self
+                    .next_part(&mut sink, &mut parts, target_path, now, duration)
+                    .await
+                {
+                    fail(outcome, e);
+                    break;
+                }
+                part_start = pipeline.seconds();
+                part_began = recorded;
+                searched = 0;
+                rotating = false;
+                if let Some(gating) = pipeline.gating() {
+                    let finished = parts.len() - 2;
+                    parts[finished].speech = Some(gating.since(gated));
+                    gated = gating;
+                }
+                continue;
+            }
+            if clock.wall() >= total * MAX_AUDIO_WAIT {
+                warn!(
+                    "{} delivered too little audio in {} times its duration; stopping",
+                    self.name, MAX_AUDIO_WAIT
+                );
+                break;
+            }
+            if settings.progress.is_cancelled() {
+                fail(
+                    outcome,
+                    StreamError::Cancelled {
+                        station: self.name.clone(),
+                    },
+                );
+                break;
+            }
+            if settings.progress.is_paused(self.id.as_str()) {
+                let paused_at = Instant::now();
+                info!("Paused {}", self.name);
+                response = match self.wait_while_paused(response, &mut pipeline).await {
+                    Ok(response) => response,
+                    Err(e) => {
+                        fail(outcome, e);
+                        break;
+                    }
+                };
+                clock.paused += paused_at.elapsed();
+                scheme = response.url().scheme().to_string();
+                outcome.stream_url = Some(canonical_url(response.url().as_str()));
+                headers = icy::kept_headers(&response);
+                // Frames were skipped or a new connection started new ones
+                pipeline.resync();
+                // The title may have changed meanwhile
+                if let Some(title) = pipeline.title() {
+                    let part = parts.last_mut().unwrap();
+                    if part.tracks.last().map(|t| t.title.as_str()) != Some(title) {
+                        part.tracks.push(Track {
+                            start: pipeline.seconds() - part_start,
+                            title: title.to_string(),
+                        });
+                    }
+                }
+                info!("Resumed {} after {:?}", self.name, paused_at.elapsed());
+                continue;
+            }
+            // Back to the checks above at least every few seconds
+            let left = total
+                .saturating_sub(clock.recorded(pipeline.seconds(), outcome.bytes))
+                .min((total * MAX_AUDIO_WAIT).saturating_sub(clock.wall()));
+            let since = *waiting.get_or_insert_with(Instant::now);
+            let next = match self.next_chunk(&mut response, &mut suspend, left).await {
+                Waited::Chunk(next) => next,
+                Waited::Suspended(slept) => {
+                    woke = Some(slept);
+                    continue;
+                }
+                Waited::Nothing => continue,
+            };
+            waiting = None;
+            if since.elapsed() >= quality::STALL_AFTER {
+                let part = parts.last_mut().unwrap();
+                part.stalls += 1;
+                part.stalled += since.elapsed();
+            }
+            match next {
+                Ok(Some(received)) => {
+                    let position = pipeline.seconds();
+                    let Chunk {
+                        audio: chunk,
+                        received: audio,
+                        title,
+                        boundary,
+                    } = pipeline.process(&received);
+                    if let Some(title) = title {
+                        info!("{} is playing {}", self.name, title);
+                        let part = parts.last_mut().unwrap();
+                        part.tracks.push(Track {
+                            start: position - part_start,
+                            title,
+                        });
+                    }
+                    if settings.progress.rotations() != rotations {
+                        rotations = settings.progress.rotations();
+                        rotating = true;
+                    }
+                    let recorded = clock.recorded(position, outcome.bytes);
+                    let mut split = None;
+                    if rotating || segment.is_some_and(|segment| recorded - part_began >= segment) {
+                        split = boundary;
+                        searched += chunk.len();
+                        if split.is_none() && searched > MAX_FRAME_SEARCH {
+                            warn!(
+                                "No MP3 frame boundary found in {}; splitting between chunks",
+                                self.name
+                            );
+                            split = Some(0);
+                        }
+                    }
+                    let (head, tail) = chunk.split_at(split.unwrap_or(chunk.len()));
+                    let mut written = self.write_part(&mut sink, &mut parts, head).await;
+                    if let (Ok(()), Some(_)) = (&written, split) {
+                        searched = 0;
+                        // Segments keep to their schedule, rotated files end early
+                        let began = match (rotating, segment) {
+                            (false, Some(segment)) => part_began + segment,
+                            _ => recorded,
+                        };
+                        if rotating {
+                            let last = parts.last_mut().unwrap();
+                            last.duration = (began - part_began).as_secs().min(last.duration);
+                            rotating = false;
+                        }
+                        let rest = total.saturating_sub(began);
+                        let duration = segment.map_or(rest, |segment| segment.min(rest));
+                        written = self
+                            .next_part(&mut sink, &mut parts, target_path, now, duration)
+                            .await;
+                        if written.is_ok() {
+                            part_start = position;
+                            part_began = began;
+                            if let Some(gating) = pipeline.gating() {
+                                let finished = parts.len() - 2;
+                                parts[finished].speech = Some(gating.since(gated));
+                                gated = gating;
+                            }
+                            if let Some(title) = pipeline.title() {
+                                parts.last_mut().unwrap().tracks.push(Track {
+                                    start: 0.0,
+                                    title: title.to_string(),
+                                });
+                            }
+                            written = self.write_part(&mut sink, &mut parts, tail).await;
+                        }
+                    }
+                    if let Err(e) = written {
+                        fail(outcome, e);
+                        break;
+                    }
+                    outcome.bytes += audio as u64;
+                    settings.budget.throttle(received.len()).await;
+                    if settings.max_bytes.is_some_and(|max| outcome.bytes >= max) {
+                        info!("{} reached {} bytes; stopping", self.name, outcome.bytes);
+                        full = true;
+                        break;
+                    }
+                }
+                dropped => {
+                    let reason = match &dropped {
+                        Err(e) => e.to_string(),
+                        _ => "the stream ended".to_string(),
+                    };
+                    let left =
+                        total.saturating_sub(clock.recorded(pipeline.seconds(), outcome.bytes));
+                    let down = Instant::now();
+                    if let Some(reconnected) = self.reconnect(&reason, &mut reconnects, left).await
+                    {
+                        // The file goes on from the first frame of the new connection
+                        response = reconnected;
+                        scheme = response.url().scheme().to_string();
+                        outcome.stream_url = Some(canonical_url(response.url().as_str()));
+                        headers = icy::kept_headers(&response);
+                        pipeline.reconnected(&response);
+                        let part = parts.last_mut().unwrap();
+                        part.stalls += 1;
+                        part.stalled += down.elapsed();
+                        continue;
+                    }
+                    if let Err(e) = dropped {
+                        fail(
+                            outcome,
+                            StreamError::Stream {
+                                station: self.name.clone(),
+                                source: e,
+                            },
+                        );
+                    }
+                    break;
+                }
+            }
+        }
+
+        outcome.seconds = clock
+            .recorded(pipeline.seconds(), outcome.bytes)
+            .min(total)
+            .as_secs();
+        for part in &mut parts {
+            part.scheme = Some(scheme.clone());
+            part.headers.clone_from(&headers);
+        }
+        if let Some(rest) = pipeline.finish() {
+            if outcome.error.is_none() {
+                if let Err(e) = self.write_part(&mut sink, &mut parts, &rest).await {
+                    fail(outcome, e);
+                }
+            }
+            let gating = pipeline.gating().unwrap_or_default();
+            parts.last_mut().unwrap().speech = Some(gating.since(gated));
+            info!(
+                "Kept {:.0} s of speech from {:.0} s of {}",
+                gating.kept_seconds, gating.listened_seconds, self.name
+            );
+        }
+        if full {
+            // The last file is shorter than requested
+            let last = parts.last_mut().unwrap();
+            last.duration = outcome
+                .seconds
+                .saturating_sub(part_began.as_secs())
+                .min(last.duration);
+        }
+
+        let path = &parts[parts.len() - 1].path;
+        match sink.finish().await {
+            Ok(()) => info!("Successfully recorded: {}", path.display()),
+            // Keep the first error if the recording had already failed
+            Err(e) if outcome.error.is_some() => {
+                error!("Error finishing output {}: {}", path.display(), e)
+            }
+            Err(e) => fail(outcome, self.write_error(e)),
+        }
+        parts
+    }
+
+    /**
+     * Part `number` of the recording to `target_path`, or the whole
+     * recording if None, starting at `started` for `duration`. When
+     * staging, the part is written to the stream's working directory and
+     * moved to its path once finished.
+     */
+    fn part(
+        &self,
+        target_path: &Path,
+        number: Option<usize>,
+        started: DateTime<Local>,
+        duration: Duration,
+    ) -> Part {
+        let path = match number {
+            Some(number) => PathBuf::from(template::segment_name(
+                &target_path.to_string_lossy(),
+                number,
+            )),
+            None => target_path.to_path_buf(),
+        };
+        let (path, destination) = match (&self.settings.staging, path.file_name()) {
+            (Some(root), Some(name)) if self.settings.upload.is_none() => {
+                let directory = staging::stream_dir(root, &self.settings.session, self.id.as_str());
+                if let Err(e) = fs::create_dir_all(&directory) {
+                    error!("Error creating directory {}: {}", directory.display(), e);
+                }
+                (directory.join(name), Some(path))
+            }
+            _ => (path, None),
+        };
+        Part {
+            path,
+            destination,
+            number,
+            started,
+            duration: duration.as_secs(),
+            bytes: 0,
+            trim: None,
+            tracks: Vec::new(),
+            speech: None,
+            stalls: 0,
+            stalled: Duration::ZERO,
+            scheme: None,
+            headers: BTreeMap::new(),
+        }
+    }
+
+    /**
+     * Opens the file or upload that `part` is written to.
+     */
+    async fn open_sink(&self, now: &DateTime<Local>, part: &Part) -> Result<Sink, StreamError> {
+        let settings = &self.settings;
+        let sink = match &settings.upload {
+            Some(target) => {
+                let mut name = template::expand(&self.filename, now, &self.name, self.id.as_str());
+                if let Some(number) = part.number {
+                    name = template::segment_name(&name, number);
+                }
+                let key = target.key(&name);
+                let journal = settings.journal.clone();
+                Sink::start_upload(&settings.client, Arc::clone(target), key, journal).await
+            }
+            None => {
+                // The journal lets `recover_journal` finish interrupted files
+                if let Some(journal) = &settings.journal {
+                    journal.record(&Entry::RecordingStarted {
+                        id: self.id.to_string(),
+                        name: self.name.clone(),
+                        url: self.url.clone(),
+                        path: part.path.to_string_lossy().into_owned(),
+                        destination: part
+                            .destination
+                            .as_ref()
+                            .map(|path| path.to_string_lossy().into_owned()),
+                        started: part.started.to_rfc3339(),
+                        duration: part.duration,
+                    });
+                }
+                let sink = match self.codec {
+                    ArchiveCodec::Opus(bitrate) => {
+                        OpusEncoder::spawn(&part.path, bitrate).map(Sink::Encoded)
+                    }
+                    ArchiveCodec::Mp3 => {
+                        Sink::create_file(&part.path, settings.encryption.as_deref())
+                    }
+                };
+                match (sink, settings.opus_copy) {
+                    (Ok(sink), Some(bitrate)) if self.codec == ArchiveCodec::Mp3 => {
+                        let copy = part.path.with_extension("opus");
+                        match OpusEncoder::spawn(&copy, bitrate) {
+                            Ok(encoder) => Ok(sink.with_copy(encoder)),
+                            Err(e) => {
+                                warn!("No Opus copy of {}: {}", part.path.display(), e);
+                                Ok(sink)
+                            }
+                        }
+                    }
+                    (sink, _) => sink,
+                }
+            }
+        };
+        let sink = match settings.write_buffer {
+            0 => sink,
+            capacity => sink.map(|sink| {
+                sink.buffered(capacity, settings.progress.buffer_level(self.id.as_str()))
+            }),
+        };
+        sink.map_err(|e| self.write_error(e))
+    }
+
+    async fn write_part(
+        &self,
+        sink: &mut Sink,
+        parts: &mut [Part],
+        bytes: &[u8],
+    ) -> Result<(), StreamError> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        sink.write(bytes).await.map_err(|e| self.write_error(e))?;
+        if let Some(part) = parts.last_mut() {
+            part.bytes += bytes.len() as u64;
+        }
+        Ok(())
+    }
+
+    /**
+     * Finishes the current part and continues in the next one, which is to
+     * last `duration`.
+     */
+    async fn next_part(
+        &self,
+        sink: &mut Sink,
+        parts: &mut Vec<Part>,
+        target_path: &Path,
+        now: &DateTime<Local>,
+        duration: Duration,
+    ) -> Result<(), StreamError> {
+        let part = self.part(target_path, Some(parts.len() + 1), Local::now(), duration);
+        let next = self.open_sink(now, &part).await?;
+        let finished = std::mem::replace(sink, next);
+        finished.finish().await.map_err(|e| self.write_error(e))?;
+        info!(
+            "Recorded segment: {}",
+            parts[parts.len() - 1].path.display()
+        );
+        parts.push(part);
+        Ok(())
+    }
+
+    /**
+     * Waits for the next chunk of the stream, at most `SUSPEND_CHECK_INTERVAL`
+     * and at most `left`, the time the recording has to go, so the caller
+     * gets to check for the end, a pause or cancellation even while the
+     * stream sends nothing.
+     */
+    async fn next_chunk(
+        &self,
+        response: &mut Response,
+        suspend: &mut SuspendWatch,
+        left: Duration,
+    ) -> Waited {
+        match tokio::time::timeout(SUSPEND_CHECK_INTERVAL.min(left), response.chunk()).await {
+            Ok(next) => Waited::Chunk(next),
+            Err(_) => match suspend.check() {
+                Some(slept) => Waited::Suspended(slept),
+                None => Waited::Nothing,
+            },
+        }
+    }
+
+    /**
+     * Connects to the stream again after it dropped for `reason`, waiting a
+     * little longer before each attempt, as long as `reconnects` has not
+     * reached `MAX_RECONNECTS` and the recording, with `left` to go, would
+     * not be over by then. None once it gives up or is cancelled.
+     */
+    async fn reconnect(
+        &self,
+        reason: &str,
+        reconnects: &mut u32,
+        left: Duration,
+    ) -> Option<Response> {
+        let started = Instant::now();
+        while *reconnects < MAX_RECONNECTS && !self.settings.progress.is_cancelled() {
+            let delay = RECONNECT_DELAY * (*reconnects + 1);
+            if started.elapsed() + delay >= left {
+                break;
+            }
+            *reconnects += 1;
+            warn!(
+                "{} dropped ({}); reconnecting in {}s to append to the same file (attempt {} of {})",
+                self.name,
+                reason,
+                delay.as_secs(),
+                reconnects,
+                MAX_RECONNECTS
+            );
+            tokio::time::sleep(delay).await;
+            match self.connect().await {
+                Ok(response) => {
+                    info!("Reconnected to {}", self.name);
+                    return Some(response);
+                }
+                Err(e) => warn!("Reconnecting to {} failed: {}", self.name, e),
+            }
+        }
+        None
+    }
+
+    fn write_error(&self, source: RecordingError) -> StreamError {
+        StreamError::Write {
+            station: self.name.clone(),
+            source,
+        }
+    }
+
+    /**
+     * Connects to the stream over HTTPS if it can: to the HTTPS variant of
+     * the listen URL and, should that redirect to a plain HTTP stream, to
+     * the HTTPS variant of the stream. Plain HTTP is only used if
+     * `allow_insecure` is set; otherwise failing over HTTPS is reported as
+     * `StreamError::Insecure`. Either way it does not count against the
+     * host, as many stations do not serve HTTPS.
+     */
+    pub(super) async fn connect(&self) -> Result<Response, StreamError> {
+        let hosts = self.settings.budget.hosts();
+        let host = Url::parse(&self.url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+        if !hosts.allow(&host) {
+            return Err(StreamError::HostDown {
+                station: self.name.clone(),
+                host,
+            });
+        }
+        let allow_insecure = self.settings.allow_insecure;
+        let failure = match self.send(&with_scheme(&self.url, "https")).await {
+            Ok(response) if response.url().scheme() == "https" => {
+                return self.settle(&host, Ok(response))
+            }
+            // Redirected to a plain HTTP stream, which may be served over HTTPS too
+            Ok(response) => {
+                let stream = with_scheme(response.url().as_str(), "https");
+                match self.send(&stream).await {
+                    Ok(secure) if secure.url().scheme() == "https" => {
+                        return self.settle(&host, Ok(secure))
+                    }
+                    _ => {}
+                }
+                let response = self.settle(&host, Ok(response))?;
+                if allow_insecure {
+                    return Ok(response);
+                }
+                return Err(StreamError::Insecure {
+                    station: self.name.clone(),
+                    url: response.url().to_string(),
+                });
+            }
+            Err(e) => e,
+        };
+        if self.url.starts_with("https:") {
+            return self.settle(&host, Err(failure));
+        }
+        // Whether plain HTTP would work is not known without trying it, so
+        // this is a refusal rather than a failure of the station
+        if !allow_insecure {
+            info!("{} fails over HTTPS: {}", self.name, failure);
+            return Err(StreamError::Insecure {
+                station: self.name.clone(),
+                url: with_scheme(&self.url, "http"),
+            });
+        }
+        info!("{} fails over HTTPS; trying plain HTTP", self.name);
+        let result = self.send(&with_scheme(&self.url, "http")).await;
+        self.settle(&host, result)
+    }
+
+    /**
+     * Requests the stream at `url`.
+     */
+    async fn send(&self, url: &str) -> reqwest::Result<Response> {
+        let settings = &self.settings;
+        let mut request = settings.client.get(url);
+        if settings.cue_sheets {
+            // Ask the server to interleave stream titles with the audio
+            request = request.header("Icy-MetaData", "1");
+        }
+        settings.budget.hosts().send(request).await.and_then(|r| {
+            if let Some(dump) = &settings.debug_dump {
+                dump.save_headers(self.id.as_str(), &r);
+            }
+            r.error_for_status()
+        })
+    }
+
+    /**
+     * Counts the outcome of connecting to `host` for or against it.
+     */
+    fn settle(
+        &self,
+        host: &str,
+        result: reqwest::Result<Response>,
+    ) -> Result<Response, StreamError> {
+        if let Err(e) = &result {
+            if let Some(down) = hosts::host_down(e) {
+                return Err(StreamError::HostDown {
+                    station: self.name.clone(),
+                    host: down.to_string(),
+                });
+            }
+        }
+        self.settings.budget.hosts().connected(host, &result);
+        result.map_err(|e| StreamError::Connect {
+            station: self.name.clone(),
+            source: e,
+        })
+    }
+
+    /**
+     * Returns once the recording is resumed or cancelled. Meanwhile the
+     * stream is read and discarded, or closed and reopened on resuming if
+     * `drop_when_paused` is set.
+     */
+    async fn wait_while_paused(
+        &self,
+        mut response: Response,
+        pipeline: &mut StreamPipeline,
+    ) -> Result<Response, StreamError> {
+        let progress = &self.settings.progress;
+        let waiting = || progress.is_paused(self.id.as_str()) && !progress.is_cancelled();
+        if self.settings.drop_when_paused {
+            drop(response);
+            while waiting() {
+                tokio::time::sleep(PAUSE_POLL).await;
+            }
+            if progress.is_cancelled() {
+                return Err(StreamError::Cancelled {
+                    station: self.name.clone(),
+                });
+            }
+            let response = self.connect().await?;
+            pipeline.reconnected(&response);
+            return Ok(response);
+        }
+
+        while waiting() {
+            match tokio::time::timeout(PAUSE_POLL, response.chunk()).await {
+                Ok(Ok(Some(chunk))) => {
+                    // Keep following the metadata to know the title on resuming
+                    pipeline.skip(&chunk);
+                    self.settings.budget.throttle(chunk.len()).await
+                }
+                Ok(Ok(None)) => break, // The stream ended; the capture loop stops too
+                Ok(Err(e)) => {
+                    return Err(StreamError::Stream {
+                        station: self.name.clone(),
+                        source: e,
+                    })
+                }
+                Err(_) => {} // No data yet; check again
+            }
+        }
+        Ok(response)
+    }
+}
+
+/**
+ * `url` with its scheme replaced by `scheme`, or as it is if that is not
+ * possible.
+ */
+pub(super) fn with_scheme(url: &str, scheme: &str) -> String {
+    let mut parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.to_string(),
+    };
+    match parsed.set_scheme(scheme) {
+        Ok(()) => parsed.into(),
+        Err(()) => url.to_string(),
+    }
+}
+
+fn fail(outcome: &mut StreamOutcome, error: StreamError) {
+    error!("{}", error);
+    outcome.category = Some(error.category());
+    outcome.error = Some(error.to_string());
+}
@@ -0,0 +1,312 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::lock::InstanceLock;
+use super::metadata::{self, Metadata};
+use super::s3::{MultipartUpload, S3Target};
+use super::staging;
+use super::{Listener, RecordingError};
+
+/**
+ * One line of the journal. Every entry is flushed to disk before the
+ * recording moves on, so after a crash the journal tells which recordings
+ * were cut short and which uploads were left open.
+ */
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Entry {
+    RecordingStarted {
+        id: String,
+        name: String,
+        url: String,
+        path: String, // Local recording
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        destination: Option<String>, // Where a staged recording belongs
+        started: String, // RFC 3339
+        duration: u64,
+    },
+    RecordingFinished {
+        path: String,
+    },
+    UploadStarted {
+        target: String, // s3:// URL the upload belongs to
+        key: String,
+        upload_id: String,
+    },
+    PartUploaded {
+        upload_id: String,
+        part: usize,
+        etag: String,
+    },
+    UploadFinished {
+        upload_id: String, // Completed or aborted
+    },
+}
+
+/**
+ * Journals created so far by this process, which may run several sessions
+ * in the same second.
+ */
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+/**
+ * ----------------------------------------------------------------------------
+ * Append-only log of the progress of one `record_streams` call. It is
+ * removed when the call returns; a journal left behind belongs to a run that
+ * was interrupted and can be replayed with `Listener::recover_journal`.
+ *
+ * While the run goes on, a lock file next to the journal is held, which
+ * tells the journals of runs still recording, in this or another process,
+ * from those left behind.
+ */
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+    lock: InstanceLock, // Held for as long as the run goes on
+}
+
+impl Journal {
+    /**
+     * Directory holding journals, next to the store.
+     */
+    pub fn directory(store_path: &Path) -> PathBuf {
+        store_path.with_file_name("journals")
+    }
+
+    /**
+     * Starts a journal in `dir`.
+     */
+    pub fn create(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let count = CREATED.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("{}-{}-{}.jsonl", started, process::id(), count));
+        // Locked first, so the journal is never seen without its lock
+        let lock = InstanceLock::acquire(&path.with_extension("lock")).map_err(io::Error::other)?;
+        let file = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Journal {
+            path,
+            file: Mutex::new(file),
+            lock,
+        })
+    }
+
+    /**
+     * Appends `entry` and waits for it to reach the disk. Failures are
+     * logged; journaling never interrupts a recording.
+     */
+    pub fn record(&self, entry: &Entry) {
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(e) => return warn!("Failed to encode journal entry: {}", e),
+        };
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = file.write_all(&line).and_then(|_| file.sync_data()) {
+            warn!("Failed to write journal {}: {}", self.path.display(), e);
+        }
+    }
+
+    /**
+     * Deletes the journal once everything it tracks has finished, along with
+     * its lock file, which no other run uses, while still holding it.
+     */
+    pub fn remove(&self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove journal {}: {}", self.path.display(), e);
+        }
+        let _ = fs::remove_file(self.lock.path());
+    }
+
+    /**
+     * Journals left behind in `dir` by interrupted runs, oldest first.
+     * Journals of runs still going are left out.
+     */
+    pub fn leftovers(dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut journals = all(dir)?;
+        journals.retain(|journal| running(journal).is_none());
+        Ok(journals)
+    }
+}
+
+/**
+ * The process holding the lock of `journal`, if its run is still going.
+ */
+fn running(journal: &Path) -> Option<String> {
+    match InstanceLock::acquire(&journal.with_extension("lock")) {
+        Err(RecordingError::Locked { owner, .. }) => Some(owner),
+        // Released when dropped
+        _ => None,
+    }
+}
+
+/**
+ * Every journal in `dir`, oldest first.
+ */
+fn all(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut journals = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "jsonl") {
+            journals.push(path);
+        }
+    }
+    journals.sort();
+    Ok(journals)
+}
+
+/**
+ * Reads a journal, skipping lines that cannot be parsed, such as one torn by
+ * a crash in the middle of a write.
+ */
+fn read_entries(path: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        match serde_json::from_str(&line?) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!("Skipping unreadable entry in {}: {}", path.display(), e),
+        }
+    }
+    Ok(entries)
+}
+
+/**
+ * What `recover_journal` repaired.
+ */
+pub struct Recovery {
+    pub partial_recordings: usize, // Recordings marked as interrupted
+    pub uploads: usize,            // Open uploads completed or aborted
+}
+
+struct OpenUpload {
+    target: String,
+    key: String,
+    parts: Vec<(usize, String)>, // Part number and ETag
+}
+
+impl Listener {
+    /**
+     * Finishes what an interrupted run left undone according to its
+     * journal: recordings that never finished get a sidecar marking them as
+     * interrupted, and open uploads are completed with the parts already
+     * stored (or aborted if there are none). The journal is deleted
+     * afterwards.
+     */
+    pub async fn recover_journal(&self, path: &Path) -> Result<Recovery, RecordingError> {
+        let mut started = Vec::new(); // Recording path, destination and sidecar
+        let mut finished = HashSet::new();
+        let mut uploads: HashMap<String, OpenUpload> = HashMap::new();
+        for entry in read_entries(path)? {
+            match entry {
+                Entry::RecordingStarted {
+                    id,
+                    name,
+                    url,
+                    path,
+                    destination,
+                    started: at,
+                    duration,
+                } => {
+                    let metadata = Metadata {
+                        station: name,
+                        id,
+                        url,
+                        places: Vec::new(),
+                        started: at,
+                        duration,
+                        bytes: 0,
+                        error: Some("interrupted".to_string()),
+                        encryption: None,
+                        classification: None,
+                        levels: None,
+                        trim: None,
+                        speech: None,
+                        quality: None,
+                        corrupt: None,
+                        scheme: None,
+                        headers: BTreeMap::new(),
+                        artwork: None,
+                        codec: None,
+                        tags: BTreeMap::new(),
+                    };
+                    started.push((path, destination, metadata));
+                }
+                Entry::RecordingFinished { path } => {
+                    finished.insert(path);
+                }
+                Entry::UploadStarted {
+                    target,
+                    key,
+                    upload_id,
+                } => {
+                    let parts = Vec::new();
+                    uploads.insert(upload_id, OpenUpload { target, key, parts });
+                }
+                Entry::PartUploaded {
+                    upload_id,
+                    part,
+                    etag,
+                } => {
+                    if let Some(upload) = uploads.get_mut(&upload_id) {
+                        upload.parts.push((part, etag));
+                    }
+                }
+                Entry::UploadFinished { upload_id } => {
+                    uploads.remove(&upload_id);
+                }
+            }
+        }
+
+        let mut recovery = Recovery {
+            partial_recordings: 0,
+            uploads: 0,
+        };
+        for (recording, destination, mut metadata) in started {
+            if finished.contains(&recording) {
+                continue;
+            }
+            let recording = PathBuf::from(recording);
+            metadata.bytes = fs::metadata(&recording).map(|m| m.len()).unwrap_or(0);
+            metadata::write_sidecar(&recording, &metadata)?;
+            info!("Marked {} as interrupted", recording.display());
+            if let (Some(destination), true) = (destination, recording.exists()) {
+                staging::unstage(&recording, Path::new(&destination))?;
+            }
+            recovery.partial_recordings += 1;
+        }
+
+        for (upload_id, mut upload) in uploads {
+            let target = S3Target::from_url(&upload.target)?;
+            upload.parts.sort();
+            let etags = upload.parts.into_iter().map(|(_, etag)| etag).collect();
+            MultipartUpload::resume(upload.key, upload_id, etags)
+                .finish(&self.client, &target)
+                .await?;
+            recovery.uploads += 1;
+        }
+
+        fs::remove_file(path)?;
+        let _ = fs::remove_file(path.with_extension("lock"));
+        Ok(recovery)
+    }
+}
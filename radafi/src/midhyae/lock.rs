@@ -0,0 +1,86 @@
+use fs2::FileExt;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::RecordingError;
+
+/**
+ * Name of the lock file that `InstanceLock::for_directory` puts into a
+ * directory. Hidden, so it does not show up among recordings.
+ */
+const DIRECTORY_LOCK: &str = ".radafi.lock";
+
+/**
+ * ----------------------------------------------------------------------------
+ * A PID file held locked for as long as it lives, so that two runs started
+ * e.g. by overlapping cron jobs cannot write into the same directory at
+ * once. The lock is released when the process ends, however it ends: a
+ * file left behind by a process that crashed is taken over. Dropping it
+ * only unlocks the file, which stays: removing it would let a run waiting
+ * on the old file and a run creating a new one both hold "the" lock.
+ */
+pub struct InstanceLock {
+    path: PathBuf,
+    file: File, // Holds the lock until closed
+}
+
+impl InstanceLock {
+    /**
+     * Locks `path`, creating it if needed, and writes the ID of this process
+     * into it. Fails with `RecordingError::Locked` while another process
+     * holds it.
+     */
+    pub fn acquire(path: &Path) -> Result<Self, RecordingError> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if let Err(e) = file.try_lock_exclusive() {
+            if e.kind() != fs2::lock_contended_error().kind() {
+                return Err(e.into());
+            }
+            // Unreadable on some platforms while locked
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            let owner = match pid.trim() {
+                "" => "another process".to_string(),
+                pid => format!("process {}", pid),
+            };
+            return Err(RecordingError::Locked {
+                path: path.to_path_buf(),
+                owner,
+            });
+        }
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+        Ok(InstanceLock {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    /**
+     * Locks `directory` through a `.radafi.lock` inside it.
+     */
+    pub fn for_directory(directory: &Path) -> Result<Self, RecordingError> {
+        InstanceLock::acquire(&directory.join(DIRECTORY_LOCK))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
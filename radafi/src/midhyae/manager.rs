@@ -0,0 +1,185 @@
+use reqwest::Client;
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::hosts::Hosts;
+use super::http::HttpOptions;
+use super::{Listener, RecordingError};
+
+/**
+ * Token bucket holding up to one second worth of bytes. Consumers may take
+ * it into debt and then wait until the debt would have been paid off, so
+ * concurrent streams share the rate fairly.
+ */
+struct Bucket {
+    rate: f64,   // Bytes per second
+    tokens: f64, // Negative while in debt
+    updated: Instant,
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Limits shared by every session of a `SessionManager`: how many streams
+ * are recorded at once across all sessions, their total download rate, and
+ * the hosts that asked to be left alone for a while.
+ */
+pub struct Budget {
+    max_recordings: Mutex<Option<usize>>,
+    active: Mutex<usize>,
+    freed: Condvar,
+    bandwidth: Mutex<Option<Bucket>>,
+    hosts: Arc<Hosts>,
+}
+
+/**
+ * Permission to record one stream, returned to the budget when dropped.
+ */
+pub struct Slot<'a> {
+    budget: &'a Budget,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        *self.budget.active.lock().unwrap() -= 1;
+        self.budget.freed.notify_one();
+    }
+}
+
+impl Default for Budget {
+    /**
+     * No limits beyond each session's own concurrency.
+     */
+    fn default() -> Self {
+        Budget::new(None, None)
+    }
+}
+
+impl Budget {
+    pub fn new(max_recordings: Option<usize>, max_bytes_per_second: Option<u64>) -> Self {
+        let budget = Budget {
+            max_recordings: Mutex::new(None),
+            active: Mutex::new(0),
+            freed: Condvar::new(),
+            bandwidth: Mutex::new(None),
+            hosts: Arc::new(Hosts::default()),
+        };
+        budget.set_limits(max_recordings, max_bytes_per_second);
+        budget
+    }
+
+    /**
+     * Changes the limits. Recordings in progress carry on even if there are
+     * more of them than now allowed; new ones wait until enough finish.
+     */
+    pub fn set_limits(&self, max_recordings: Option<usize>, max_bytes_per_second: Option<u64>) {
+        *self.max_recordings.lock().unwrap() = max_recordings.map(|max| max.max(1));
+        *self.bandwidth.lock().unwrap() = max_bytes_per_second.map(|rate| {
+            let rate = rate.max(1) as f64;
+            Bucket {
+                rate,
+                tokens: rate,
+                updated: Instant::now(),
+            }
+        });
+        // Recordings waiting for a slot may have one now
+        self.freed.notify_all();
+    }
+
+    /**
+     * How many streams may be recorded at once, if that is limited.
+     */
+    pub fn max_recordings(&self) -> Option<usize> {
+        *self.max_recordings.lock().unwrap()
+    }
+
+    pub fn hosts(&self) -> &Arc<Hosts> {
+        &self.hosts
+    }
+
+    /**
+     * Waits, blocking the thread, until another stream may be recorded.
+     */
+    pub fn acquire(&self) -> Slot<'_> {
+        let mut active = self.active.lock().unwrap();
+        while self
+            .max_recordings
+            .lock()
+            .unwrap()
+            .is_some_and(|max| *active >= max)
+        {
+            active = self.freed.wait(active).unwrap();
+        }
+        *active += 1;
+        Slot { budget: self }
+    }
+
+    /**
+     * Accounts for `bytes` just received and waits as long as needed to
+     * keep the total rate within the limit.
+     */
+    pub async fn throttle(&self, bytes: usize) {
+        let wait = match self.bandwidth.lock().unwrap().as_mut() {
+            Some(bucket) => {
+                let now = Instant::now();
+                let refill = now.duration_since(bucket.updated).as_secs_f64() * bucket.rate;
+                bucket.tokens = (bucket.tokens + refill).min(bucket.rate) - bytes as f64;
+                bucket.updated = now;
+                if bucket.tokens < 0.0 {
+                    Duration::from_secs_f64(-bucket.tokens / bucket.rate)
+                } else {
+                    Duration::ZERO
+                }
+            }
+            None => Duration::ZERO,
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Runs independent recording sessions (different countries, durations and
+ * output directories) in one process. Every `Listener` it hands out shares
+ * the same HTTP client, and with it the connection pool, and the same
+ * `Budget`.
+ */
+pub struct SessionManager {
+    base_url: String,
+    http: HttpOptions,
+    client: Client,
+    budget: Arc<Budget>,
+}
+
+impl SessionManager {
+    pub fn new(base_url: &str, http: &HttpOptions, budget: Budget) -> Result<Self, RecordingError> {
+        let budget = Arc::new(budget);
+        Ok(SessionManager {
+            base_url: base_url.to_string(),
+            http: http.clone(),
+            client: http.build(budget.hosts())?,
+            budget,
+        })
+    }
+
+    /**
+     * The budget shared by every session.
+     */
+    pub fn budget(&self) -> &Budget {
+        &self.budget
+    }
+
+    /**
+     * A new session drawing on the shared client and budget.
+     */
+    pub fn listener(&self) -> Listener {
+        Listener::with_shared(
+            &self.base_url,
+            self.http.clone(),
+            self.client.clone(),
+            Arc::clone(&self.budget),
+        )
+    }
+}
@@ -0,0 +1,100 @@
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::analysis::{Classification, Levels};
+use super::encrypt::EncryptionInfo;
+use super::mp3::Trim;
+use super::quality::Quality;
+use super::store::Run;
+use super::vad::SpeechGating;
+use super::{ErrorCategory, ErrorCode, PlaceRef};
+
+/**
+ * Sidecar metadata written as `<recording>.json` next to every recording.
+ */
+#[derive(Serialize, Debug)]
+pub struct Metadata {
+    pub station: String,
+    pub id: String,
+    pub url: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub places: Vec<PlaceRef>, // Places the channel is listed under
+    pub started: String, // RFC 3339 local time at which recording began
+    pub duration: u64,   // Requested duration in seconds
+    pub bytes: u64,
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub classification: Option<Classification>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub levels: Option<Levels>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trim: Option<Trim>, // Audio length before and after trimming
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speech: Option<SpeechGating>, // Audio kept when recording only speech
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<Quality>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corrupt: Option<String>, // Why the recording was quarantined or purged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>, // "https", or "http" if plain HTTP was allowed
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>, // Of the stream response, see `icy::kept_headers`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artwork: Option<String>, // Station image saved next to the recording
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>, // Set if the audio was re-encoded, e.g. "opus 32k"
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>, // User-defined, of the session and station
+}
+
+/**
+ * Path of the sidecar belonging to the recording at `recording`.
+ */
+pub fn sidecar_path(recording: &Path) -> PathBuf {
+    let mut name = recording.as_os_str().to_owned();
+    name.push(".json");
+    PathBuf::from(name)
+}
+
+pub fn write_sidecar(recording: &Path, metadata: &Metadata) -> io::Result<()> {
+    let contents = serde_json::to_string_pretty(metadata)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(sidecar_path(recording), contents)
+}
+
+/**
+ * Contents of `summary.json`: the run itself plus its failures by cause and
+ * by code, and the code of the run if it failed as a whole.
+ */
+#[derive(Serialize)]
+struct Summary<'a> {
+    #[serde(flatten)]
+    run: &'a Run,
+    failures: BTreeMap<ErrorCategory, usize>,
+    codes: BTreeMap<ErrorCode, usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<ErrorCode>,
+}
+
+/**
+ * Writes `summary.json` describing a whole run into its output directory.
+ */
+pub fn write_summary(directory: &Path, run: &Run) -> io::Result<PathBuf> {
+    let summary = Summary {
+        run,
+        failures: run.failures_by_category(),
+        codes: run.failures_by_code(),
+        code: run.failure_code(),
+    };
+    let contents = serde_json::to_string_pretty(&summary)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let path = directory.join("summary.json");
+    fs::write(&path, contents)?;
+    Ok(path)
+}
@@ -0,0 +1,265 @@
+use axum::body::Body;
+use axum::extract::{Path as UrlPath, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use bytes::Bytes;
+use futures::stream;
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/**
+ * A silent MPEG-1 Layer III frame at 128 kbit/s and 44.1 kHz.
+ */
+const FRAME_HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0x00];
+const FRAME_LENGTH: usize = 417;
+
+/**
+ * Frames sent at once; ten take 261 ms to play.
+ */
+const FRAMES_PER_CHUNK: usize = 10;
+const CHUNK_INTERVAL: Duration = Duration::from_micros(261_224);
+
+/**
+ * What the listen URL of a mock station answers.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockStream {
+    Mp3,         // Silent MP3 in real time, for as long as it is read
+    Status(u16), // An error status, e.g. 404 or 503
+    Empty,       // An MP3 response that ends at once
+}
+
+/**
+ * A station served by `MockRadioGarden`.
+ */
+#[derive(Debug, Clone)]
+pub struct MockStation {
+    pub id: String, // Channel ID, alphanumeric
+    pub title: String,
+    pub place: String, // Title of the place it is listed under
+    pub country: String,
+    pub stream: MockStream,
+}
+
+impl MockStation {
+    /**
+     * A station streaming silent MP3.
+     */
+    pub fn new(id: &str, title: &str, place: &str, country: &str) -> Self {
+        MockStation {
+            id: id.to_string(),
+            title: title.to_string(),
+            place: place.to_string(),
+            country: country.to_string(),
+            stream: MockStream::Mp3,
+        }
+    }
+
+    pub fn with_stream(mut self, stream: MockStream) -> Self {
+        self.stream = stream;
+        self
+    }
+}
+
+/**
+ * Place listings and channel pages as the API returns them, by place ID.
+ */
+struct Catalog {
+    places: Value,
+    channels: BTreeMap<String, Value>,
+    streams: BTreeMap<String, MockStream>, // By channel ID
+    requests: Mutex<Vec<String>>,          // Paths requested, in order
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * A local stand-in for the Radio Garden API, to exercise discovery and
+ * recording end to end without the network:
+ *
+ *   let mock = MockRadioGarden::start(vec![
+ *       MockStation::new("Ch01", "Radio One", "Recife", "Brazil"),
+ *       MockStation::new("Ch02", "Radio Two", "Recife", "Brazil")
+ *           .with_stream(MockStream::Status(503)),
+ *   ])?;
+ *   let mut listener = Listener::new(&mock.base_url());
+ *   listener.set_allow_insecure(true);
+ *   let discovery = listener.store_streams("Brazil").await?;
+ *   listener.record_streams(&discovery.streams, 2, "recordings").await?;
+ *
+ * The mock only speaks plain HTTP, hence `set_allow_insecure`. Stations in
+ * the same place and country share a place. The server runs on a thread of
+ * its own and stops when dropped. Available with the `mock-server`
+ * feature.
+ */
+pub struct MockRadioGarden {
+    address: SocketAddr,
+    catalog: Arc<Catalog>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockRadioGarden {
+    /**
+     * Starts serving `stations` on a free port of the loopback interface.
+     */
+    pub fn start(stations: Vec<MockStation>) -> io::Result<Self> {
+        let catalog = Arc::new(catalog(&stations));
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let app = Router::new()
+            .route("/api/ara/content/places", get(places))
+            .route("/api/ara/content/page/:id/channels", get(channels))
+            .route("/api/ara/content/listen/:id/channel.mp3", get(listen))
+            .layer(middleware::from_fn_with_state(Arc::clone(&catalog), log))
+            .with_state(Arc::clone(&catalog));
+
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        thread::spawn(move || {
+            runtime.block_on(async move {
+                let listener = match tokio::net::TcpListener::from_std(listener) {
+                    Ok(listener) => listener,
+                    Err(_) => return,
+                };
+                let _ = axum::serve(listener, app)
+                    .with_graceful_shutdown(async {
+                        let _ = stopped.await;
+                    })
+                    .await;
+            })
+        });
+        Ok(MockRadioGarden {
+            address,
+            catalog,
+            shutdown: Some(shutdown),
+        })
+    }
+
+    /**
+     * API URL to pass to `Listener::new`.
+     */
+    pub fn base_url(&self) -> String {
+        format!("http://{}/api/ara/content/", self.address)
+    }
+
+    /**
+     * Paths requested so far, in order.
+     */
+    pub fn requests(&self) -> Vec<String> {
+        self.catalog.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockRadioGarden {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+fn catalog(stations: &[MockStation]) -> Catalog {
+    let mut places: BTreeMap<(&str, &str), Vec<&MockStation>> = BTreeMap::new();
+    for station in stations {
+        places
+            .entry((&station.country, &station.place))
+            .or_default()
+            .push(station);
+    }
+    let mut list = Vec::new();
+    let mut channels = BTreeMap::new();
+    for (index, ((country, title), stations)) in places.into_iter().enumerate() {
+        let id = format!("place{}", index);
+        list.push(json!({
+            "id": id,
+            "country": country,
+            "title": title,
+            "geo": [0.0, 0.0],
+            "size": stations.len(),
+        }));
+        let items: Vec<Value> = stations
+            .iter()
+            .map(|station| {
+                json!({"page": {
+                    "url": format!("/listen/{}/{}", slug(&station.title), station.id),
+                    "title": station.title,
+                }})
+            })
+            .collect();
+        channels.insert(id, json!({"data": {"content": [{"items": items}]}}));
+    }
+    Catalog {
+        places: json!({"data": {"list": list}}),
+        channels,
+        streams: stations
+            .iter()
+            .map(|station| (station.id.clone(), station.stream))
+            .collect(),
+        requests: Mutex::new(Vec::new()),
+    }
+}
+
+fn slug(title: &str) -> String {
+    title
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_lowercase(),
+            false => '-',
+        })
+        .collect()
+}
+
+async fn log(State(catalog): State<Arc<Catalog>>, request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+    catalog.requests.lock().unwrap().push(path);
+    next.run(request).await
+}
+
+async fn places(State(catalog): State<Arc<Catalog>>) -> Json<Value> {
+    Json(catalog.places.clone())
+}
+
+async fn channels(State(catalog): State<Arc<Catalog>>, UrlPath(id): UrlPath<String>) -> Response {
+    match catalog.channels.get(&id) {
+        Some(channels) => Json(channels.clone()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn listen(State(catalog): State<Arc<Catalog>>, UrlPath(id): UrlPath<String>) -> Response {
+    let body = match catalog.streams.get(&id) {
+        None => return StatusCode::NOT_FOUND.into_response(),
+        Some(MockStream::Status(status)) => {
+            return StatusCode::from_u16(*status)
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+                .into_response()
+        }
+        Some(MockStream::Empty) => Body::empty(),
+        Some(MockStream::Mp3) => {
+            let mut frame = FRAME_HEADER.to_vec();
+            frame.resize(FRAME_LENGTH, 0);
+            let chunk = Bytes::from(frame.repeat(FRAMES_PER_CHUNK));
+            let interval = tokio::time::interval(CHUNK_INTERVAL);
+            Body::from_stream(stream::unfold(interval, move |mut interval| {
+                let chunk = chunk.clone();
+                async move {
+                    interval.tick().await;
+                    Some((Ok::<_, io::Error>(chunk), interval))
+                }
+            }))
+        }
+    };
+    ([(header::CONTENT_TYPE, "audio/mpeg")], body).into_response()
+}
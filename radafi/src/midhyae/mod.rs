@@ -1,255 +1,1662 @@
-use log::{error, info};
-use reqwest::{Client, Error};
+use bytes::Bytes;
+use chrono::{DateTime, Local};
+use futures::stream::{self, StreamExt};
+use log::{info, warn};
+use rand::seq::SliceRandom;
+use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use thiserror::Error;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tracing::Instrument;
 use url::Url;
 
-use std::fs::{self, File};
-use std::io::Write;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::{Duration, Instant};
 
+mod access;
+mod analysis;
+mod archive;
+mod artwork;
+mod bench;
+mod browse;
+mod buffer;
+mod canonical;
+mod catalog;
+mod chat;
+mod cluster;
+mod config;
+mod country;
+#[cfg(feature = "desktop-notifications")]
+mod desktop;
+mod dns;
+mod doctor;
+mod dump;
+mod email;
+mod encrypt;
+mod error;
+mod estimate;
+mod events;
+mod export;
+mod feed;
+mod geoip;
+mod hosts;
+mod http;
+mod icy;
+mod id3;
+mod ids;
+mod job;
+mod journal;
+mod lock;
+mod manager;
+mod metadata;
+#[cfg(feature = "mock-server")]
+mod mock;
+mod mp3;
+mod mqtt;
+mod notify;
+mod opus;
+mod pipeline;
+mod places;
+mod postprocess;
+mod progress;
+mod quality;
+mod queue;
+mod relay;
+mod resources;
+mod s3;
+mod schedule;
+mod schema;
+mod script;
+mod server;
+mod session;
+mod sink;
+mod staging;
+mod store;
+mod suspend;
+mod template;
 mod threadpool;
+mod timeshift;
+mod transcribe;
+mod vad;
+pub use self::access::ServerOptions;
+pub use self::archive::compress_dir;
+pub use self::artwork::Artwork;
+pub use self::browse::serve_archive;
+pub use self::buffer::DEFAULT_WRITE_BUFFER;
+pub use self::canonical::canonical_url;
+pub use self::catalog::{Length, Query};
+pub use self::config::{Config, Limits, LiveConfig, Profile};
+use self::country::Countries;
+#[cfg(feature = "desktop-notifications")]
+pub use self::desktop::notify_desktop;
+pub use self::doctor::Status;
+use self::dump::DebugDump;
+pub use self::encrypt::Encryption;
+pub use self::error::{DiscoveryError, ErrorCategory, ErrorCode, RecordingError, StreamError};
+pub use self::estimate::{Estimate, Size, DEFAULT_CONFIRM_ABOVE};
+use self::events::ProgressEvents;
+pub use self::export::{count_countries, write_places_csv, write_places_geojson, CountryCount};
+pub use self::feed::{scan_archive, write_feeds, Episode, FeedGrouping};
+use self::http::HttpOptions;
+pub use self::http::IpVersion;
+pub use self::ids::{ChannelId, PlaceId};
+use self::job::{Job, JobSettings};
+use self::journal::Journal;
+pub use self::lock::InstanceLock;
+pub use self::manager::Budget;
+pub use self::metadata::write_summary;
+#[cfg(feature = "mock-server")]
+pub use self::mock::{MockRadioGarden, MockStation, MockStream};
+use self::mqtt::Mqtt;
+pub use self::notify::Report;
+pub use self::opus::{ArchiveCodec, Bitrate};
+use self::places::PlaceListing;
+use self::postprocess::Pipeline;
+pub use self::postprocess::{Classify, PostProcessor, Registry};
+use self::progress::Progress;
+pub use self::relay::IcecastTarget;
+pub use self::s3::S3Target;
+pub use self::schedule::{record_schedule, Show};
+use self::schema::{ChannelLayout, ChannelPage};
+pub use self::script::FilterScript;
+pub use self::server::serve;
+pub use self::session::create_session_dir;
+pub use self::store::{DeadStation, Favorite, Run, Store, UsageGrouping};
+pub use self::template::{expand_directory, safe_name, segment_name, windows_long_path};
 use self::threadpool::ThreadPool;
+pub use self::timeshift::TimeShift;
+pub use self::transcribe::Transcriber;
 
 /**
- * Defines the categories of errors that may occur when recording radio streams
- * from Radio Garden.
+ * Longest wait for the headers of a stream when probing its genre.
  */
-#[derive(Debug, Error)]
-pub enum RecordingError {
-    #[error("network error: {0}")]
-    Network(#[from] reqwest::Error),
-
-    #[error("I/O error: {0}")]
-    Io(#[from] std::io::Error),
-
-    #[error("MP3 decoding error: {0}")]
-    Decode(#[from] minimp3::Error),
-}
+const GENRE_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
 
 /**
  * ----------------------------------------------------------------------------
  * The following are structures for storing results returned by the Radio
  * Garden API.
  */
-#[derive(Deserialize)]
-struct Place {
-    id: String,
-    country: String,
+#[derive(Deserialize, Debug, Clone)]
+pub struct Place {
+    pub id: PlaceId,
+    pub country: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub geo: Vec<f64>, // [longitude, latitude]
+    #[serde(default)]
+    pub size: u32, // Number of channels listed for the place
+}
+
+/**
+ * A channel to record, as discovered by `store_streams` or added with
+ * `add_stream`.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Stream {
+    pub id: ChannelId, // Radio Garden channel ID
+    pub name: String,  // Station title, reduced to alphanumerics
+    pub url: String,   // Listen URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_url: Option<String>, // Canonical URL the listen URL leads to, once looked up
+    pub places: Vec<PlaceRef>, // Every place the channel is listed under
+    pub favorite: bool,
+    pub duration: Option<u64>,    // Overrides the session duration when set
+    pub filename: Option<String>, // Overrides the filename template when set
+    pub codec: Option<ArchiveCodec>, // Overrides the archive codec when set
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>, // Added to the session's tags
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged: Vec<ChannelId>, // Channels playing the same stream, recorded as this one
 }
 
-#[derive(Deserialize)]
-struct Data {
-    list: Vec<Place>,
+/**
+ * Result of `store_streams`: the streams found, which places could not be
+ * searched and which channel page URLs had no usable channel ID.
+ */
+#[derive(Debug)]
+pub struct Discovery {
+    pub streams: Vec<Stream>,   // Also stored, to be narrowed down
+    pub countries: Vec<String>, // Of the places searched
+    pub duplicates: usize,      // Listings of channels already found under another place
+    pub failed_places: Vec<PlaceFailure>,
+    pub malformed_urls: Vec<String>,
 }
 
-#[derive(Deserialize)]
-struct PlaceList {
-    data: Data,
+#[derive(Debug)]
+pub struct PlaceFailure {
+    pub place: Place,
+    pub error: DiscoveryError,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ChannelResponse {
-    #[serde(rename = "data")]
-    channel_data: ChannelData,
+/**
+ * Which streams `cap_per_place` keeps within a place.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaceSelection {
+    First,     // In discovery order
+    Random,    // A random sample
+    Favorites, // Favorites, then in discovery order
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ChannelData {
-    content: Vec<Content>,
+impl std::str::FromStr for PlaceSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(PlaceSelection::First),
+            "random" => Ok(PlaceSelection::Random),
+            "favorites" => Ok(PlaceSelection::Favorites),
+            _ => Err(format!("invalid place selection {}", s)),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Content {
-    items: Vec<Item>,
+/**
+ * A place a channel is listed under.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlaceRef {
+    pub id: PlaceId,
+    pub title: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Item {
-    page: Page,
+/**
+ * Result of recording a single stream, as reported by `record_streams`.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamOutcome {
+    pub id: ChannelId,         // Radio Garden channel ID
+    pub name: String,          // Station title
+    pub bytes: u64,            // Bytes written to disk
+    pub error: Option<String>, // Why the recording failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<ErrorCategory>, // Cause of `error`
+    #[serde(default)]
+    pub dead_air: bool, // Level check found the recording effectively silent
+    #[serde(default)]
+    pub seconds: u64, // Of audio recorded, not counting pauses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub place: Option<String>, // Title of the first place the station is listed under
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_url: Option<String>, // Canonical URL of the stream connected to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged: Vec<ChannelId>, // Channels recorded along with this one, see `Stream`
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Page {
-    url: String,
-    title: String,
+impl StreamOutcome {
+    /**
+     * Whether this is the outcome of `channel`, recorded itself or along
+     * with the channel it plays the same stream as.
+     */
+    pub fn covers(&self, channel: &ChannelId) -> bool {
+        self.id == *channel || self.merged.contains(channel)
+    }
+
+    /**
+     * A recording counts as successful if it produced data without errors
+     * and, when levels were checked, was not dead air.
+     */
+    pub fn ok(&self) -> bool {
+        self.error.is_none() && self.bytes > 0 && !self.dead_air
+    }
+
+    /**
+     * Why an unsuccessful recording failed. Outcomes recorded before errors
+     * were categorized count as `Other`.
+     */
+    pub fn failure_category(&self) -> Option<ErrorCategory> {
+        if self.ok() {
+            None
+        } else if let Some(category) = self.category {
+            Some(category)
+        } else if self.dead_air {
+            Some(ErrorCategory::DeadAir)
+        } else {
+            Some(ErrorCategory::Other)
+        }
+    }
+
+    /**
+     * Stable code of `failure_category`.
+     */
+    pub fn failure_code(&self) -> Option<ErrorCode> {
+        self.failure_category().map(|category| category.code())
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Stream {
-    name: String,
-    url: String,
+/**
+ * Result of `record_stream`.
+ */
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub outcome: StreamOutcome,
+    pub path: PathBuf, // Recording, or the path its upload key was derived from
+    pub started: DateTime<Local>,
+    pub elapsed: Duration, // Including connecting and post-processing
 }
 
 /**
  * ----------------------------------------------------------------------------
  * This struct provides the functionality to obtain mp3 radio recordings from
  * via Radio Garden.
+ *
+ * Once configured through its setters, a listener can be shared behind an
+ * `Arc`: discovery and recording take `&self`, so e.g. places can be listed
+ * while a recording runs. Discovery returns the streams it found, and
+ * `record_streams` records the streams it is given, so tasks sharing a
+ * listener do not record each other's stations. The streams last found are
+ * also stored, behind a lock never held across an await, for the methods
+ * narrowing them down.
  */
 pub struct Listener {
-    url: Url,             // Radio Garden API URL
-    client: Client,       // HTTP client
-    streams: Vec<Stream>, // Radio broadcast links to record
+    url: Url,                            // Radio Garden API URL
+    client: Client,                      // HTTP client
+    http: HttpOptions,                   // What the client was built with
+    streams: Mutex<Vec<Stream>>,         // Radio broadcast links to record
+    concurrency: usize,                  // Maximum number of simultaneous connections
+    filename: String,                    // Output filename template, see `template`
+    upload: Option<Arc<S3Target>>,       // Stream recordings to S3 instead of disk
+    encryption: Option<Arc<Encryption>>, // Encrypt local recordings at rest
+    post_processing: Pipeline,           // Stages run on finished recordings
+    check_levels: bool,                  // Measure levels and retry dead air
+    debug_dump: Option<Arc<DebugDump>>,  // Save raw API bodies and stream headers
+    replay: Option<PathBuf>,             // Read API responses from a dump instead
+    countries: Countries,                // Country names and aliases
+    journal_dir: PathBuf,                // Where progress journals are kept
+    mqtt: Option<Arc<Mqtt>>,             // Publishes progress to a broker
+    events: Option<Arc<ProgressEvents>>, // Writes progress to stdout as JSON lines
+    progress: Arc<Progress>,             // Counters and cancellation of recordings
+    budget: Arc<Budget>,                 // Limits shared with other sessions
+    drop_when_paused: bool,              // Close connections of paused recordings
+    latest_links: bool,                  // Link the newest recording of each station
+    segment: Option<Duration>,           // Split recordings into files this long
+    max_bytes: Option<u64>,              // Stop each recording once it has this much
+    speech_only: bool,                   // Drop audio that is not speech
+    trim: bool,                          // Cut recordings to the exact duration
+    cue_sheets: bool,                    // Mark stream title changes in cue sheets
+    opus_copy: Option<Bitrate>,          // Also encode each recording to Opus
+    codec: ArchiveCodec,                 // How recordings are stored
+    tags: BTreeMap<String, String>,      // Attached to every recording
+    session: Option<String>,             // Identifies the session in log lines
+    purge_corrupt: bool,                 // Delete corrupt recordings, not quarantine them
+    audio_duration: bool,                // Record until the duration of audio is received
+    staging: Option<PathBuf>,            // Write files in progress here, then move them
+    write_buffer: u64,                   // Bytes each recording may hold in memory
+    shared_directory: bool,              // Record where another process records too
 }
 
+// Listeners are shared between tasks and threads, see above
+const _: fn() = || {
+    fn shareable<T: Send + Sync>() {}
+    shareable::<Listener>();
+};
+
 impl Listener {
     pub fn new(base_url: &str) -> Self {
+        let budget = Arc::new(Budget::default());
+        let http = HttpOptions::default();
+        let client = http
+            .build(budget.hosts())
+            .expect("Failed to build HTTP client");
+        Listener::with_shared(base_url, http, client, budget)
+    }
+
+    /**
+     * A listener using `client`, built from `http`, and drawing on `budget`,
+     * see `SessionManager`.
+     */
+    fn with_shared(base_url: &str, http: HttpOptions, client: Client, budget: Arc<Budget>) -> Self {
         let url = Url::parse(base_url).expect("Failed to parse base URL");
         info!("Initialized Listener with URL: {}", url);
         Listener {
             url,
-            client: Client::new(),
-            streams: Vec::new(),
+            client,
+            http,
+            streams: Mutex::new(Vec::new()),
+            concurrency: 10,
+            filename: template::DEFAULT_FILENAME.to_string(),
+            upload: None,
+            encryption: None,
+            post_processing: Pipeline::default(),
+            check_levels: false,
+            debug_dump: None,
+            replay: None,
+            countries: Countries::default(),
+            journal_dir: Journal::directory(&Store::default_path()),
+            mqtt: None,
+            events: None,
+            progress: Arc::new(Progress::default()),
+            budget,
+            drop_when_paused: false,
+            latest_links: false,
+            segment: None,
+            max_bytes: None,
+            speech_only: false,
+            trim: false,
+            cue_sheets: false,
+            opus_copy: None,
+            codec: ArchiveCodec::Mp3,
+            tags: BTreeMap::new(),
+            session: None,
+            purge_corrupt: false,
+            audio_duration: false,
+            staging: None,
+            write_buffer: DEFAULT_WRITE_BUFFER,
+            shared_directory: false,
+        }
+    }
+
+    /**
+     * Appends a stage to run on every finished local recording, such as
+     * `Classify` (speech/music/silence breakdown in the sidecar) or a
+     * `Transcriber` (transcript saved next to the recording). The built-in
+     * stages skip encrypted recordings.
+     */
+    pub fn add_post_processor(&mut self, stage: Box<dyn PostProcessor>) {
+        self.post_processing.push(stage);
+    }
+
+    /**
+     * Measures the RMS and peak levels of finished recordings and adds them
+     * to each sidecar. Recordings that are effectively dead air are retried
+     * once and, if still silent, do not count as successful.
+     */
+    pub fn set_level_check(&mut self, check_levels: bool) {
+        self.check_levels = check_levels;
+    }
+
+    /**
+     * Encrypts local recordings, which are then written as `<name>.age`.
+     */
+    pub fn set_encryption(&mut self, encryption: Encryption) {
+        self.encryption = Some(Arc::new(encryption));
+    }
+
+    /**
+     * Uploads recordings to S3 while they are being recorded instead of
+     * writing them to the local directory. Object keys are the upload
+     * prefix followed by the expanded filename template.
+     */
+    pub fn set_upload_target(&mut self, target: S3Target) {
+        self.upload = Some(Arc::new(target));
+    }
+
+    /**
+     * Sets the output filename template. Besides strftime tokens, `{name}`
+     * and `{id}` are replaced by the station title and channel ID.
+     */
+    pub fn set_filename_template(&mut self, filename: &str) -> Result<(), RecordingError> {
+        template::validate(filename).map_err(RecordingError::Template)?;
+        self.filename = filename.to_string();
+        Ok(())
+    }
+
+    /**
+     * Saves the raw bodies of API responses and the headers of stream
+     * responses under `dir`, see `DebugDump`.
+     */
+    pub fn set_debug_dump(&mut self, dir: &Path) -> Result<(), RecordingError> {
+        self.debug_dump = Some(Arc::new(DebugDump::new(dir)?));
+        Ok(())
+    }
+
+    /**
+     * Answers places and channels requests from a directory previously
+     * written by `set_debug_dump` instead of the Radio Garden API. Streams
+     * are still recorded live.
+     */
+    pub fn set_replay(&mut self, dir: &Path) {
+        self.replay = Some(dir.to_path_buf());
+    }
+
+    /**
+     * Adds aliases, mapping each to a country name, to the built-in ones
+     * accepted wherever a country is given.
+     */
+    pub fn add_country_aliases(&mut self, aliases: &HashMap<String, String>) {
+        self.countries.add_aliases(aliases);
+    }
+
+    /**
+     * Journals left behind by interrupted runs, to be passed to
+     * `recover_journal`.
+     */
+    pub fn pending_journals(&self) -> std::io::Result<Vec<PathBuf>> {
+        Journal::leftovers(&self.journal_dir)
+    }
+
+    /**
+     * Keeps progress journals in `dir` instead of next to the store.
+     */
+    pub fn set_journal_dir(&mut self, dir: &Path) {
+        self.journal_dir = dir.to_path_buf();
+    }
+
+    /**
+     * Replaces the HTTP client with one tuned by `options`.
+     */
+    pub fn set_http_options(&mut self, options: &HttpOptions) -> Result<(), RecordingError> {
+        self.client = options.build(self.budget.hosts())?;
+        self.http = options.clone();
+        Ok(())
+    }
+
+    /**
+     * Records stations that cannot be reached over HTTPS over plain HTTP.
+     * Recordings connect to the HTTPS variant of the listen URL, and of the
+     * stream it redirects to, first; without this, stations streaming only
+     * over plain HTTP fail with `StreamError::Insecure`.
+     */
+    pub fn set_allow_insecure(&mut self, allow_insecure: bool) {
+        self.http.allow_insecure = allow_insecure;
+    }
+
+    /**
+     * Sets how many streams are recorded (or benchmarked) at once.
+     */
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = concurrency.max(1);
+    }
+
+    /**
+     * Titles of the stored streams, in recording order.
+     */
+    pub fn stream_titles(&self) -> Vec<String> {
+        self.stored().iter().map(|s| s.name.clone()).collect()
+    }
+
+    /**
+     * A copy of the stored streams, in recording order.
+     */
+    pub fn streams(&self) -> Vec<Stream> {
+        self.stored().clone()
+    }
+
+    /**
+     * The stored streams, locked until the guard is dropped.
+     */
+    fn stored(&self) -> MutexGuard<'_, Vec<Stream>> {
+        self.streams.lock().unwrap()
+    }
+
+    /**
+     * A stream for the channel `id`, titled `title`, with its listen URL
+     * and no overrides. Add it with `add_stream`.
+     */
+    pub fn channel_stream(&self, id: &ChannelId, title: &str) -> Stream {
+        Stream {
+            id: id.clone(),
+            name: title.chars().filter(|c| c.is_alphanumeric()).collect(),
+            url: format!("{}listen/{}/channel.mp3", self.url, id),
+            stream_url: None,
+            places: Vec::new(),
+            favorite: false,
+            duration: None,
+            filename: None,
+            codec: None,
+            tags: BTreeMap::new(),
+            merged: Vec::new(),
+        }
+    }
+
+    /**
+     * Appends `stream` to the stored streams, replacing the stream of the
+     * same channel if there is one, since every channel is recorded once.
+     */
+    pub fn add_stream(&self, stream: Stream) {
+        let mut streams = self.stored();
+        match streams.iter_mut().find(|s| s.id == stream.id) {
+            Some(existing) => *existing = stream,
+            None => streams.push(stream),
+        }
+    }
+
+    /**
+     * Removes the stream of channel `id` from the stored streams.
+     */
+    pub fn remove_stream(&self, id: &ChannelId) -> Option<Stream> {
+        let mut streams = self.stored();
+        let index = streams.iter().position(|s| &s.id == id)?;
+        Some(streams.remove(index))
+    }
+
+    /**
+     * Keeps only the stored streams for which `keep` returns true, in
+     * order.
+     */
+    pub fn retain<F>(&self, keep: F)
+    where
+        F: FnMut(&Stream) -> bool,
+    {
+        self.stored().retain(keep);
+    }
+
+    /**
+     * Keeps only the stored streams at the given positions (as returned by
+     * `stream_titles`), preserving their order.
+     */
+    pub fn select_streams(&self, selected: &[usize]) {
+        let mut index = 0;
+        self.stored().retain(|_| {
+            let keep = selected.contains(&index);
+            index += 1;
+            keep
+        });
+    }
+
+    /**
+     * Keeps only the stored streams whose channel ID or title matches one of
+     * `stations` (titles compare case-insensitively). Returns how many remain.
+     */
+    pub fn filter_stations(&self, stations: &[&str]) -> usize {
+        let mut streams = self.stored();
+        streams.retain(|s| {
+            stations
+                .iter()
+                .any(|&station| s.id == station || s.name.eq_ignore_ascii_case(station))
+        });
+        streams.len()
+    }
+
+    /**
+     * Keeps only the stored streams whose `icy-genre` header contains one of
+     * `genres` (case-insensitively), as found by connecting to each stream,
+     * `concurrency` at a time, and closing it after the headers. Streams
+     * reporting no genre are left out. Returns how many remain.
+     */
+    pub async fn filter_genres(&self, genres: &[String]) -> usize {
+        let genres: Vec<String> = genres.iter().map(|g| g.to_lowercase()).collect();
+        let probed = self.streams();
+        let reported: HashMap<&str, Option<String>> = stream::iter(&probed)
+            .map(|stream| async move { (stream.id.as_str(), self.stream_genre(stream).await) })
+            .buffered(self.concurrency.max(1))
+            .collect()
+            .await;
+        let mut streams = self.stored();
+        streams.retain(|stream| {
+            // Streams stored meanwhile were not probed and are kept
+            let Some(genre) = reported.get(stream.id.as_str()) else {
+                return true;
+            };
+            let keep = genre.as_ref().is_some_and(|genre| {
+                let genre = genre.to_lowercase();
+                genres.iter().any(|wanted| genre.contains(wanted.as_str()))
+            });
+            match genre {
+                Some(genre) if !keep => info!("Leaving out {} ({})", stream.name, genre),
+                Some(_) => {}
+                None => info!("Leaving out {}, which reports no genre", stream.name),
+            }
+            keep
+        });
+        streams.len()
+    }
+
+    /**
+     * The `icy-genre` the stream is served with, over HTTPS or, if allowed,
+     * plain HTTP. Only the headers are read.
+     */
+    async fn stream_genre(&self, stream: &Stream) -> Option<String> {
+        let mut urls = vec![job::with_scheme(&stream.url, "https")];
+        if self.http.allow_insecure && !stream.url.starts_with("https:") {
+            urls.push(job::with_scheme(&stream.url, "http"));
+        }
+        let mut failure = None;
+        for url in urls {
+            let request = self.client.get(&url).timeout(GENRE_PROBE_TIMEOUT);
+            match self.budget.hosts().send(request).await {
+                // Dropping the response closes the connection before any audio
+                Ok(response) if response.status().is_success() => {
+                    return icy::kept_headers(&response).remove("icy-genre")
+                }
+                Ok(response) => failure = Some(format!("{} answers {}", url, response.status())),
+                Err(e) => failure = Some(e.to_string()),
+            }
+        }
+        if let Some(failure) = failure {
+            warn!("Could not probe the genre of {}: {}", stream.name, failure);
+        }
+        None
+    }
+
+    /**
+     * Closes the connections of paused recordings instead of reading and
+     * discarding their streams, reconnecting when they are resumed.
+     */
+    pub fn set_drop_when_paused(&mut self, drop_when_paused: bool) {
+        self.drop_when_paused = drop_when_paused;
+    }
+
+    /**
+     * Keeps a `latest/<name>.mp3` symlink to the newest successful recording
+     * of each station in the output directory, i.e. the part of it before
+     * the first date token. Only applies to local recordings.
+     */
+    pub fn set_latest_links(&mut self, latest_links: bool) {
+        self.latest_links = latest_links;
+    }
+
+    /**
+     * Splits each recording into files of `seconds` of audio, numbered
+     * `_001`, `_002`, ... before the extension. Files are cut at MP3 frame
+     * boundaries so each of them plays on its own.
+     */
+    pub fn set_segment_length(&mut self, seconds: Option<u64>) {
+        self.segment = seconds.filter(|&s| s > 0).map(Duration::from_secs);
+    }
+
+    /**
+     * Stops each recording once it has received `size` of audio, even if
+     * its duration is not up yet, to get the same amount from every station
+     * whatever its bitrate. The chunk crossing the limit is kept whole.
+     */
+    pub fn set_max_bytes(&mut self, size: Option<Size>) {
+        self.max_bytes = size.map(|size| size.0).filter(|&bytes| bytes > 0);
+    }
+
+    /**
+     * Writes only the parts of each recording that contain speech, detected
+     * on the decoded stream second by second, plus a little before and after
+     * them. Music and silence are dropped as they arrive. Needs MP3 streams;
+     * others are recorded whole.
+     */
+    pub fn set_speech_only(&mut self, speech_only: bool) {
+        self.speech_only = speech_only;
+    }
+
+    /**
+     * Keeps the last `minutes` of audio of each recording in memory, to be
+     * saved after the fact through `Progress::time_shift`.
+     */
+    pub fn set_time_shift(&mut self, minutes: Option<u64>) {
+        let window = minutes.map(|minutes| Duration::from_secs(minutes * 60));
+        self.progress.time_shift().set_window(window);
+    }
+
+    /**
+     * Cuts plain local MP3 recordings to the requested duration of audio,
+     * counted frame by frame, and reports the remaining difference in the
+     * sidecar. Recordings that fell short are left as they are.
+     */
+    pub fn set_trim(&mut self, trim: bool) {
+        self.trim = trim;
+    }
+
+    /**
+     * Asks stations for their ICY stream titles and writes a cue sheet next
+     * to each plain local recording, with a track for every title, so
+     * players can jump between songs and programs.
+     */
+    pub fn set_cue_sheets(&mut self, cue_sheets: bool) {
+        self.cue_sheets = cue_sheets;
+    }
+
+    /**
+     * Encodes an Opus copy (`<name>.opus`) of each local recording at
+     * `bitrate` while it is recorded, from the same connection. Needs
+     * `ffmpeg` with libopus; the MP3 original is kept in any case.
+     */
+    pub fn set_opus_copy(&mut self, bitrate: Option<Bitrate>) {
+        self.opus_copy = bitrate;
+    }
+
+    /**
+     * Stores local recordings as `codec`. With Opus, the stream is encoded
+     * by `ffmpeg` while it is recorded and written as `<name>.opus`; steps
+     * that decode MP3 (trimming, level checks, cue sheets, classification
+     * and artwork) are skipped. Encrypted and uploaded recordings stay MP3.
+     */
+    pub fn set_archive_codec(&mut self, codec: ArchiveCodec) {
+        self.codec = codec;
+    }
+
+    /**
+     * Attaches user-defined tags to every recording: they are written to
+     * the sidecars, where `find` can filter on them, and as ID3 comments
+     * into plain local MP3 recordings. Tags of a station in the
+     * configuration are added to these, replacing any of the same name.
+     */
+    pub fn set_tags(&mut self, tags: BTreeMap<String, String>) {
+        self.tags = tags;
+    }
+
+    /**
+     * Deletes plain local MP3 recordings that turn out to be mostly
+     * undecodable, instead of moving them into a `quarantine` directory next
+     * to them. Either way their sidecar, with the reason, is kept in
+     * `quarantine` and the recording counts as failed.
+     */
+    pub fn set_purge_corrupt(&mut self, purge_corrupt: bool) {
+        self.purge_corrupt = purge_corrupt;
+    }
+
+    /**
+     * Counts the audio received towards the duration of each recording
+     * instead of the time spent, so stalls are made up for by recording
+     * longer, up to twice the duration.
+     */
+    pub fn set_audio_duration(&mut self, audio_duration: bool) {
+        self.audio_duration = audio_duration;
+    }
+
+    /**
+     * Writes local recordings in progress below `staging`, each stream in a
+     * working directory of its own, and moves them to the output directory
+     * once finished, e.g. to keep slow network storage out of the capture.
+     */
+    pub fn set_staging_dir(&mut self, staging: Option<PathBuf>) {
+        self.staging = staging;
+    }
+
+    /**
+     * Records even into a directory another run is recording into, which
+     * `record_streams` refuses otherwise: files of the same name would be
+     * written by both. Also for callers that lock the directory themselves,
+     * for longer than the recording.
+     */
+    pub fn set_shared_directory(&mut self, shared: bool) {
+        self.shared_directory = shared;
+    }
+
+    /**
+     * Lets each recording hold up to `bytes` of audio in memory while its
+     * output catches up, 4 MB by default. With 0, audio is written as it is
+     * received and a slow output holds up reading the stream.
+     */
+    pub fn set_write_buffer(&mut self, bytes: u64) {
+        self.write_buffer = bytes;
+    }
+
+    /**
+     * Names the session in the context of every log line of its recordings,
+     * e.g. after its directory. Without one, the time recording started is
+     * used.
+     */
+    pub fn set_session(&mut self, session: &str) {
+        self.session = Some(session.to_string());
+    }
+
+    /**
+     * The codec of a recording, `stream_codec` if the station overrides it.
+     */
+    fn codec(&self, stream_codec: Option<ArchiveCodec>) -> ArchiveCodec {
+        let codec = stream_codec.unwrap_or(self.codec);
+        if codec != ArchiveCodec::Mp3 && (self.upload.is_some() || self.encryption.is_some()) {
+            warn!(
+                "Recording as MP3: {} applies to plain local recordings",
+                codec
+            );
+            return ArchiveCodec::Mp3;
+        }
+        codec
+    }
+
+    /**
+     * Counters of the recordings in progress, which also allow pausing and
+     * cancelling them from another thread.
+     */
+    pub fn progress(&self) -> Arc<Progress> {
+        Arc::clone(&self.progress)
+    }
+
+    /**
+     * Marks the stored streams that are favorites, applying their duration
+     * overrides and moving them to the front of the recording order.
+     */
+    pub fn apply_favorites(&self, favorites: &[Favorite]) -> usize {
+        let mut streams = self.stored();
+        for stream in streams.iter_mut() {
+            if let Some(favorite) = favorites.iter().find(|f| f.id == stream.id) {
+                stream.favorite = true;
+                stream.duration = favorite.duration.or(stream.duration);
+            }
         }
+        // Stable sort keeps the discovery order within each group
+        streams.sort_by_key(|s| !s.favorite);
+        streams.iter().filter(|s| s.favorite).count()
     }
 
     /**
-     * Saves mp3 recordings for a given duration and directory.
-     * It will record up to ten channels at once.
+     * Keeps at most `max` streams from each place, so places with hundreds of
+     * channels don't dominate a country-level sample. Streams are grouped by
+     * the first place they were listed under and the discovery order is
+     * otherwise kept. Returns the number of streams removed.
+     */
+    pub fn cap_per_place(
+        &self,
+        max: usize,
+        selection: PlaceSelection,
+        favorites: &[Favorite],
+    ) -> usize {
+        let mut streams = self.stored();
+        let mut by_place: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (index, stream) in streams.iter().enumerate() {
+            let place = stream.places.first().map_or("", |p| p.id.as_str());
+            by_place.entry(place).or_default().push(index);
+        }
+
+        let mut keep = vec![false; streams.len()];
+        for mut indices in by_place.into_values() {
+            match selection {
+                PlaceSelection::First => {}
+                PlaceSelection::Random => indices.shuffle(&mut rand::thread_rng()),
+                PlaceSelection::Favorites => {
+                    indices.sort_by_key(|&i| !favorites.iter().any(|f| f.id == streams[i].id))
+                }
+            }
+            for index in indices.into_iter().take(max) {
+                keep[index] = true;
+            }
+        }
+
+        let before = streams.len();
+        let mut keep = keep.into_iter();
+        streams.retain(|_| keep.next().unwrap_or(false));
+        before - streams.len()
+    }
+
+    /**
+     * Applies the per-station overrides from the configuration, which take
+     * precedence over favorites and session-wide settings. Returns the number
+     * of streams affected.
+     */
+    pub fn apply_overrides(&self, config: &Config) -> usize {
+        let mut applied = 0;
+        for stream in self.stored().iter_mut() {
+            if let Some(station) = config.stations.get(&stream.id) {
+                stream.duration = station.duration.or(stream.duration);
+                stream.filename = station.filename.clone().or(stream.filename.take());
+                // Validated when the configuration was loaded
+                stream.codec = station.codec().ok().flatten().or(stream.codec);
+                stream.tags.extend(station.tags.clone());
+                applied += 1;
+            }
+        }
+        applied
+    }
+
+    /**
+     * Saves mp3 recordings for a given duration and directory. Date tokens
+     * in the directory and filename templates are expanded when each
+     * recording starts; relative directories are resolved against the
+     * current directory first. A local output directory that cannot be
+     * created or written to, or that another process is recording into,
+     * fails the call before any stream is contacted.
+     * It will record up to `concurrency` of `streams` at once, in their
+     * order. Returns the outcome of every recording once all of them have
+     * finished; the recordings run on threads of their own meanwhile, so the
+     * caller's runtime is not held up.
      */
     pub async fn record_streams(
-        &mut self,
+        &self,
+        streams: &[Stream],
+        duration_seconds: u64,
+        directory: &str,
+    ) -> Result<Vec<StreamOutcome>, RecordingError> {
+        self.record_streams_each(streams, duration_seconds, directory, |_| {})
+            .await
+    }
+
+    /**
+     * Like `record_streams`, also handing each outcome to `finished` as soon
+     * as its recording finishes.
+     */
+    pub async fn record_streams_each<F>(
+        &self,
+        streams: &[Stream],
         duration_seconds: u64,
         directory: &str,
-    ) -> Result<(), RecordingError> {
-        fs::create_dir_all(directory)?;
+        mut finished: F,
+    ) -> Result<Vec<StreamOutcome>, RecordingError>
+    where
+        F: FnMut(&StreamOutcome),
+    {
+        template::validate(directory).map_err(RecordingError::Template)?;
+        // Check the directory up front instead of failing every recording
+        let (directory, output) = if self.upload.is_none() {
+            let absolute = std::path::absolute(directory).map_err(|source| {
+                RecordingError::OutputDirectory {
+                    path: PathBuf::from(directory),
+                    source,
+                }
+            })?;
+            let expanded = template::expand_directory(&absolute.to_string_lossy(), &Local::now());
+            session::ensure_writable(&expanded).map_err(|source| {
+                RecordingError::OutputDirectory {
+                    path: expanded.clone(),
+                    source,
+                }
+            })?;
+            if let Some(staging) = &self.staging {
+                session::ensure_writable(staging).map_err(|source| {
+                    RecordingError::OutputDirectory {
+                        path: staging.clone(),
+                        source,
+                    }
+                })?;
+            }
+            (absolute.to_string_lossy().into_owned(), Some(expanded))
+        } else {
+            (directory.to_string(), None)
+        };
+        // Two runs would write to the same filenames; held until returning
+        let _directory_lock = match (&output, self.shared_directory) {
+            (Some(output), false) => match InstanceLock::for_directory(output) {
+                Ok(lock) => Some(lock),
+                Err(RecordingError::Locked { owner, .. }) => {
+                    return Err(RecordingError::DirectoryInUse {
+                        path: output.clone(),
+                        owner,
+                    })
+                }
+                Err(e) => return Err(e),
+            },
+            _ => None,
+        };
+
+        // The journal lets `recover_journal` clean up if this run is cut short
+        let journal = match Journal::create(&self.journal_dir) {
+            Ok(journal) => Some(Arc::new(journal)),
+            Err(e) => {
+                warn!("Recording without a journal: {}", e);
+                None
+            }
+        };
+
+        // Lookups and warm-up connect as many at a time as recordings do
+        let mut streams = streams.to_vec();
+        let num_workers = self.concurrency.min(streams.len()).max(1);
+        let num_workers = resources::fit_recordings(num_workers)?;
+        let concurrency = self.concurrency.min(num_workers);
+        self.pre_resolve(&mut streams, concurrency).await;
+        self.warm_up(&streams, concurrency).await;
 
-        let num_workers = std::cmp::min(10, self.streams.len());
         let pool = ThreadPool::new(num_workers);
+        let (done, mut outcomes) = mpsc::unbounded_channel();
+        let latest_links = Some(template::static_prefix(&directory))
+            .filter(|_| self.latest_links && self.upload.is_none());
+        let settings = self.job_settings(
+            journal.clone(),
+            self.mqtt.clone(),
+            self.events.clone(),
+            latest_links,
+        );
+
+        self.progress.set_stations(streams.len());
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.run_started(streams.len());
+        }
+        if let Some(events) = &self.events {
+            events.run_started(streams.len());
+        }
 
         // Record stream from each channel identified in the region
-        for stream_info in self.streams.iter() {
-            let stream_url = stream_info.url.clone();
-            let filename = format!("stream_{}.mp3", stream_info.name);
-            let target_path = Path::new(directory).join(filename);
-            let client = self.client.clone();
-            let duration = duration_seconds;
+        for stream_info in streams.iter() {
+            let job = self.job(
+                stream_info,
+                duration_seconds,
+                &directory,
+                Arc::clone(&settings),
+            );
+            let done = done.clone();
+            let merged = stream_info.merged.clone();
 
             // Add a recording task to be scheduled by the threadpool
             pool.execute(move || {
-                let rt = tokio::runtime::Runtime::new().unwrap();
-                rt.block_on(async {
-                    match client.get(&stream_url).send().await {
-                        Ok(mut response) => {
-                            if let Ok(mut file) = File::create(&target_path) {
-                                let start_time = Instant::now();
-                                while start_time.elapsed() < Duration::from_secs(duration) {
-                                    match response.chunk().await {
-                                        Ok(Some(chunk)) => {
-                                            if let Err(e) = file.write_all(&chunk) {
-                                                error!("Error writing to file: {}", e);
-                                                break;
-                                            }
-                                        }
-                                        Ok(None) => break,
-                                        Err(e) => {
-                                            error!("Error reading from response: {}", e);
-                                            break;
-                                        }
-                                    }
-                                }
-                                info!("Successfully recorded: {}", target_path.display());
-                            } else {
-                                error!("Error creating file: {}", target_path.display());
-                            }
-                        }
-                        Err(e) => {
-                            error!("Error fetching stream URL: {}", e);
-                        }
-                    }
-                });
+                let mut outcome = job.run();
+                outcome.merged = merged;
+                let _ = done.send(outcome);
             });
         }
 
-        pool.terminate();
+        // Every recording has finished once every sender is dropped
+        drop(done);
+        let mut recorded = Vec::with_capacity(streams.len());
+        while let Some(outcome) = outcomes.recv().await {
+            finished(&outcome);
+            recorded.push(outcome);
+        }
+        // Its workers are idle by now
+        drop(pool);
+        if let Some(journal) = journal {
+            journal.remove();
+        }
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.run_finished();
+        }
+        if let Some(events) = &self.events {
+            events.run_finished();
+        }
 
-        Ok(())
+        Ok(recorded)
     }
 
     /**
-     * Obtains a list of Radio Garden locations with IDs for a given country.
+     * Looks up every stream's host before recording, so the recordings
+     * starting together find their addresses cached and stations on hosts
+     * that do not resolve are reported up front. Channels found to be served
+     * from the same stream are recorded once, see `merge_same_streams`.
      */
-    async fn fetch_places(&self, country: &str) -> Result<Vec<Place>, Error> {
-        let places_url = self
-            .url
-            .join("places")
-            .expect("Failed to construct places URL");
-        info!("Fetching places from URL: {}", places_url);
-
-        let places_response: PlaceList = self
-            .client
-            .get(places_url)
-            .send()
-            .await?
-            .json::<PlaceList>()
+    async fn pre_resolve(&self, streams: &mut Vec<Stream>, concurrency: usize) {
+        let single_hop = match self.http.build_single_hop(self.budget.hosts()) {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("Not looking up stream hosts ahead: {}", e);
+                return;
+            }
+        };
+        let urls: Vec<(&str, &str)> = streams
+            .iter()
+            .map(|stream| (stream.name.as_str(), stream.url.as_str()))
+            .collect();
+        let resolved = dns::pre_resolve(&single_hop, self.budget.hosts(), &urls, concurrency).await;
+        if resolved.unresolved > 0 {
+            warn!(
+                "{} of {} stations are on hosts that do not resolve",
+                resolved.unresolved,
+                urls.len()
+            );
+        }
+        for (stream, url) in streams.iter_mut().zip(resolved.stream_urls) {
+            stream.stream_url = url;
+        }
+        let merged = merge_same_streams(streams);
+        if merged > 0 {
+            info!(
+                "{} channels share their stream with another; recording each stream once",
+                merged
+            );
+        }
+    }
+
+    /**
+     * Connects to every stream ahead of recording, so the recordings
+     * starting together (and their retries) reuse pooled connections
+     * instead of each waiting for TCP and TLS handshakes.
+     */
+    async fn warm_up(&self, streams: &[Stream], concurrency: usize) {
+        if !self.http.warms_up() {
+            return;
+        }
+        let urls: Vec<&str> = streams.iter().map(|s| s.url.as_str()).collect();
+        http::warm_up(
+            &self.client,
+            self.budget.hosts(),
+            &urls,
+            self.http.allow_insecure,
+            concurrency,
+        )
+        .await;
+    }
+
+    /**
+     * Records the stream at `url` to `path` on the caller's runtime, without
+     * a thread pool, journal or MQTT reporting. `path` may use the
+     * placeholders of the filename template, with the channel ID of a Radio
+     * Garden listen URL (or else the host) as `{name}`; other streams get the
+     * letters and digits of their host as their ID. The rest of the
+     * configuration (upload target, encryption, level checks,
+     * post-processing) applies as in `record_streams`.
+     */
+    pub async fn record_stream(
+        &self,
+        url: &str,
+        duration_seconds: u64,
+        path: &Path,
+    ) -> Result<Recording, RecordingError> {
+        let path_template = path.to_string_lossy().into_owned();
+        template::validate(&path_template).map_err(RecordingError::Template)?;
+        let url = &canonical_url(url);
+        // Radio Garden listen URLs name their channel; otherwise use the host
+        let channel = listen_channel_id(url);
+        let name = channel
+            .as_ref()
+            .map(ChannelId::to_string)
+            .or_else(|| Url::parse(url).ok()?.host_str().map(str::to_string))
+            .unwrap_or_else(|| "stream".to_string());
+        let id = channel.unwrap_or_else(|| {
+            let host: String = name.chars().filter(char::is_ascii_alphanumeric).collect();
+            host.parse()
+                .unwrap_or_else(|_| "stream".parse().expect("valid ID"))
+        });
+        let job = Job {
+            id,
+            name,
+            url: url.to_string(),
+            places: Vec::new(),
+            duration: duration_seconds,
+            filename: path
+                .file_name()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            path_template,
+            codec: self.codec(None),
+            tags: self.tags.clone(),
+            settings: self.job_settings(None, None, None, None),
+        };
+
+        let started = Local::now();
+        let timer = Instant::now();
+        let span = job.span("main");
+        let (outcome, path) = job.record().instrument(span).await;
+        Ok(Recording {
+            outcome,
+            path,
+            started,
+            elapsed: timer.elapsed(),
+        })
+    }
+
+    /**
+     * The job recording `stream_info` into `directory`.
+     */
+    fn job(
+        &self,
+        stream_info: &Stream,
+        duration_seconds: u64,
+        directory: &str,
+        settings: Arc<JobSettings>,
+    ) -> Job {
+        let filename = stream_info.filename.as_ref().unwrap_or(&self.filename);
+        Job {
+            id: stream_info.id.clone(),
+            name: stream_info.name.clone(),
+            url: stream_info.url.clone(),
+            places: stream_info.places.clone(),
+            duration: stream_info.duration.unwrap_or(duration_seconds),
+            path_template: Path::new(directory)
+                .join(filename)
+                .to_string_lossy()
+                .into_owned(),
+            filename: filename.clone(),
+            codec: self.codec(stream_info.codec),
+            tags: self
+                .tags
+                .clone()
+                .into_iter()
+                .chain(stream_info.tags.clone())
+                .collect(),
+            settings,
+        }
+    }
+
+    /**
+     * Settings for the jobs of one recording call.
+     */
+    fn job_settings(
+        &self,
+        journal: Option<Arc<Journal>>,
+        mqtt: Option<Arc<Mqtt>>,
+        events: Option<Arc<ProgressEvents>>,
+        latest_links: Option<PathBuf>,
+    ) -> Arc<JobSettings> {
+        // Decoding is not possible once the audio has been encrypted
+        Arc::new(JobSettings {
+            client: self.client.clone(),
+            upload: self.upload.clone(),
+            encryption: self.encryption.clone(),
+            post_processing: self.post_processing.clone(),
+            check_levels: self.check_levels && self.encryption.is_none(),
+            debug_dump: self.debug_dump.clone(),
+            journal,
+            mqtt,
+            events,
+            progress: Arc::clone(&self.progress),
+            budget: Arc::clone(&self.budget),
+            drop_when_paused: self.drop_when_paused,
+            latest_links,
+            staging: self.staging.clone(),
+            write_buffer: self.write_buffer,
+            segment: self.segment,
+            max_bytes: self.max_bytes,
+            speech_only: self.speech_only,
+            trim: self.trim,
+            cue_sheets: self.cue_sheets,
+            opus_copy: self.opus_copy,
+            purge_corrupt: self.purge_corrupt,
+            allow_insecure: self.http.allow_insecure,
+            audio_duration: self.audio_duration,
+            session: self
+                .session
+                .clone()
+                .unwrap_or_else(|| Local::now().format("%Y%m%d-%H%M%S").to_string()),
+        })
+    }
+
+    /**
+     * The only stored stream, for outputs that handle a single station.
+     */
+    fn single_stream(&self) -> Result<Stream, RecordingError> {
+        match self.stored().as_slice() {
+            [stream] => Ok(stream.clone()),
+            streams => Err(RecordingError::NotSingleStream(streams.len())),
+        }
+    }
+
+    /**
+     * Writes the raw audio of the single stored stream to `out` for the given
+     * duration instead of saving it to disk, e.g. to pipe it into another
+     * program. The stream is connected to the way recordings are, over
+     * HTTPS where the station serves it. A closed pipe ends the recording
+     * early without error. Returns the number of bytes written.
+     */
+    pub async fn pipe_stream<W>(
+        &self,
+        duration_seconds: u64,
+        out: &mut W,
+    ) -> Result<u64, RecordingError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let stream = self.single_stream()?;
+        let duration = stream.duration.unwrap_or(duration_seconds);
+        info!("Piping {} for {} seconds", stream.name, duration);
+
+        let mut settings = self.job_settings(None, None, None, None);
+        // Stream titles interleaved with the audio would end up in the pipe
+        Arc::get_mut(&mut settings)
+            .expect("not shared yet")
+            .cue_sheets = false;
+        let job = self.job(&stream, duration_seconds, "", settings);
+        let mut response = job.connect().await?;
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(duration);
+        let mut bytes = 0;
+        // A stream that stalls still ends the pipe at the deadline
+        while let Ok(chunk) = tokio::time::timeout_at(deadline, response.chunk()).await {
+            let chunk = match chunk? {
+                Some(chunk) => chunk,
+                None => break,
+            };
+            match out.write_all(&chunk).await {
+                Ok(()) => bytes += chunk.len() as u64,
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        out.flush().await?;
+        Ok(bytes)
+    }
+
+    /**
+     * Obtains a list of Radio Garden locations with IDs for a given country,
+     * which may be given by any of its aliases.
+     */
+    pub async fn fetch_places(&self, country: &str) -> Result<Vec<Place>, DiscoveryError> {
+        let listing = self
+            .fetch_places_where(|p| self.countries.matches(country, &p.country))
             .await?;
+        Ok(listing.places)
+    }
+
+    /**
+     * Obtains every Radio Garden location, with coordinates and the number
+     * of channels listed for each.
+     */
+    pub async fn fetch_all_places(&self) -> Result<Vec<Place>, DiscoveryError> {
+        Ok(self.fetch_places_where(|_| true).await?.places)
+    }
 
-        Ok(places_response
-            .data
-            .list
-            .into_iter()
-            .filter(|p| p.country == country)
-            .collect())
+    /**
+     * Obtains the Radio Garden locations that `keep` accepts, dropping the
+     * others as the list is parsed.
+     */
+    async fn fetch_places_where<F>(&self, keep: F) -> Result<PlaceListing, DiscoveryError>
+    where
+        F: FnMut(&Place) -> bool,
+    {
+        let timeout = self.http.places_timeout();
+        let body = self
+            .fetch_body(
+                "places",
+                (timeout, "places_timeout"),
+                DiscoveryError::Places,
+            )
+            .await?;
+        places::parse_places(&body, keep).map_err(|source| DiscoveryError::Parse {
+            path: "places".to_string(),
+            source,
+        })
+    }
+
+    /**
+     * Requests `api_path` relative to the API URL, saving the raw body when
+     * dumping. When replaying, the body is read from the dump instead.
+     * Taking longer than the limit in `timeout`, if any, fails with
+     * `DiscoveryError::Timeout` naming the setting for it; other transport
+     * errors are wrapped by `fetch_error`.
+     */
+    async fn fetch_body<F>(
+        &self,
+        api_path: &str,
+        timeout: (Option<Duration>, &'static str),
+        fetch_error: F,
+    ) -> Result<Bytes, DiscoveryError>
+    where
+        F: FnOnce(reqwest::Error) -> DiscoveryError,
+    {
+        if let Some(dir) = &self.replay {
+            let path = DebugDump::body_path(dir, api_path);
+            info!("Replaying {}", path.display());
+            let body = fs::read(&path).map_err(|source| DiscoveryError::Replay { path, source })?;
+            return Ok(Bytes::from(body));
+        }
+
+        let url = self
+            .url
+            .join(api_path)
+            .expect("Failed to construct API URL");
+        info!("Fetching {}", url);
+
+        let (limit, setting) = timeout;
+        let mut request = self.client.get(url);
+        if let Some(limit) = limit {
+            // Covers connecting as well as a body that stops arriving
+            request = request.timeout(limit);
+        }
+        let body = match self.budget.hosts().send(request).await {
+            Ok(response) => match response.error_for_status() {
+                Ok(response) => response.bytes().await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        }
+        .map_err(|e| match limit {
+            Some(limit) if e.is_timeout() => DiscoveryError::Timeout {
+                path: api_path.to_string(),
+                seconds: limit.as_secs(),
+                setting,
+            },
+            _ => fetch_error(e),
+        })?;
+        if let Some(dump) = &self.debug_dump {
+            dump.save_body(api_path, &body);
+        }
+        Ok(body)
     }
 
     /**
      * Obtains channel information for a particular location (represented by
      * its Radio Garden ID).
      */
-    async fn fetch_channels(&self, place_id: &str) -> Result<Vec<Item>, Error> {
-        let channels_url: Url = self
-            .url
-            .join(&format!("page/{}/channels", place_id))
-            .expect("Failed to construct channels URL");
-        info!("Fetching channels from URL: {}", channels_url);
-
-        let channel_response: ChannelResponse = self
-            .client
-            .get(channels_url)
-            .send()
-            .await?
-            .json::<ChannelResponse>()
+    async fn fetch_channels(&self, place_id: &PlaceId) -> Result<ChannelPage, DiscoveryError> {
+        let api_path = format!("page/{}/channels", place_id);
+        let timeout = (self.http.channels_timeout(), "channels_timeout");
+        let body = self
+            .fetch_body(&api_path, timeout, |source| DiscoveryError::Channels {
+                place: place_id.to_string(),
+                source,
+            })
+            .await?;
+        schema::parse_channels(&body).map_err(|source| DiscoveryError::Parse {
+            path: api_path,
+            source,
+        })
+    }
+
+    /**
+     * Obtains the links to radio streams in a given country. The channels
+     * of its places are fetched concurrently, as many at a time as
+     * recordings may connect at once. A place whose channels cannot be
+     * fetched is skipped and reported rather than failing the whole
+     * country. Failing to list places is an error, and so is finding no
+     * stations at all, e.g. because the country name has a typo;
+     * `NoStationsFound` then suggests similar country names.
+     */
+    pub async fn store_streams(&self, country: &str) -> Result<Discovery, DiscoveryError> {
+        let listing = self
+            .fetch_places_where(|p| self.countries.matches(country, &p.country))
             .await?;
+        if listing.places.is_empty() {
+            let known = listing.countries.iter().map(String::as_str);
+            return Err(DiscoveryError::NoStationsFound {
+                country: country.to_string(),
+                suggestions: self.countries.suggest(country, known),
+            });
+        }
+        let discovery = self.store_places(listing.places).await;
+        // Places that could not be searched are reported instead
+        if discovery.streams.is_empty() && discovery.failed_places.is_empty() {
+            return Err(DiscoveryError::NoStationsFound {
+                country: country.to_string(),
+                suggestions: Vec::new(),
+            });
+        }
+        Ok(discovery)
+    }
 
-        Ok(channel_response
-            .channel_data
-            .content
-            .into_iter()
-            .flat_map(|c| c.items)
-            .collect())
+    /**
+     * Like `store_streams`, but obtains the streams of the places with the
+     * given IDs (as listed by `fetch_places`), in whichever country they
+     * are. IDs of no place are reported; it is an error if none is known.
+     */
+    pub async fn store_place_streams(
+        &self,
+        place_ids: &[PlaceId],
+    ) -> Result<Discovery, DiscoveryError> {
+        let listing = self
+            .fetch_places_where(|p| place_ids.contains(&p.id))
+            .await?;
+        if listing.places.is_empty() {
+            return Err(DiscoveryError::NoSuchPlaces {
+                places: place_ids.to_vec(),
+            });
+        }
+        for id in place_ids {
+            if !listing.places.iter().any(|place| place.id == *id) {
+                warn!("No place has the ID {}", id);
+            }
+        }
+        let discovery = self.store_places(listing.places).await;
+        if discovery.streams.is_empty() && discovery.failed_places.is_empty() {
+            return Err(DiscoveryError::NoStationsFound {
+                country: format!("places {}", place_ids.join(", ")),
+                suggestions: Vec::new(),
+            });
+        }
+        Ok(discovery)
     }
 
     /**
-     * Obtains the links to radio streams in a given country. Returns the
-     * number of channels identified in the region.
+     * Replaces the stored streams with those of `places`, fetching the
+     * channels of the places concurrently.
      */
-    pub async fn store_streams(&mut self, country: &str) -> Result<usize, Error> {
-        let places = self.fetch_places(country).await?;
+    async fn store_places(&self, places: Vec<Place>) -> Discovery {
+        let mut countries: Vec<String> = places.iter().map(|p| p.country.clone()).collect();
+        countries.sort();
+        countries.dedup();
+        // Listed in the order of the places, however the requests finish
+        let listings: Vec<(Place, Result<ChannelPage, DiscoveryError>)> = stream::iter(places)
+            .map(|place| async move {
+                let items = self.fetch_channels(&place.id).await;
+                (place, items)
+            })
+            .buffered(self.concurrency.max(1))
+            .collect()
+            .await;
+
         // Replace list of streams with those from new country
-        self.streams.clear();
-
-        for place in places {
-            let items = self.fetch_channels(&place.id).await?;
-            for item in items {
-                let name: String = item
-                    .page
-                    .title
-                    .chars()
-                    .filter(|c| c.is_alphanumeric())
-                    .collect();
-                // The channel ID is the last element of the path in the URL
-                let parts: Vec<&str> = item.page.url.split('/').collect();
-                if let Some(last_part) = parts.last() {
-                    let stream_url = format!("{}listen/{}/channel.mp3", self.url, last_part);
-                    self.streams.push(Stream {
-                        url: stream_url,
-                        name: name,
-                    });
+        let mut streams: Vec<Stream> = Vec::new();
+        let mut failed_places = Vec::new();
+        let mut malformed_urls = Vec::new();
+        let mut seen: HashMap<ChannelId, usize> = HashMap::new(); // Index in `streams`
+        let mut duplicates = 0;
+
+        let mut layouts = HashSet::new();
+
+        for (place, page) in listings {
+            let page = match page {
+                Ok(page) => page,
+                Err(error) => {
+                    failed_places.push(PlaceFailure { place, error });
+                    continue;
+                }
+            };
+            layouts.insert(page.layout);
+            for link in page.links {
+                let id = match channel_id(&self.url, &link.url) {
+                    Some(id) => id,
+                    None => {
+                        malformed_urls.push(link.url);
+                        continue;
+                    }
+                };
+                let listing = PlaceRef {
+                    id: place.id.clone(),
+                    title: place.title.clone(),
+                };
+                // A channel listed under several places is recorded once
+                if let Some(&index) = seen.get(&id) {
+                    let stream = &mut streams[index];
+                    if !stream.places.iter().any(|p| p.id == listing.id) {
+                        stream.places.push(listing);
+                    }
+                    duplicates += 1;
+                    continue;
                 }
+                seen.insert(id.clone(), streams.len());
+                let mut stream = self.channel_stream(&id, &link.title);
+                stream.places.push(listing);
+                streams.push(stream);
             }
         }
 
-        Ok(self.streams.len())
+        // The API changing its layout should be noticed before it breaks
+        layouts.remove(&ChannelLayout::Pages);
+        for layout in layouts {
+            warn!("Channels were listed in the alternative layout {}", layout);
+        }
+
+        *self.stored() = streams.clone();
+        Discovery {
+            streams,
+            countries,
+            duplicates,
+            failed_places,
+            malformed_urls,
+        }
     }
 }
+
+/**
+ * Keeps one of the channels in `streams` whose streams are the same once
+ * their URLs are put in canonical form, the first, which is then listed
+ * under the places of all of them. Returns how many were dropped.
+ */
+fn merge_same_streams(streams: &mut Vec<Stream>) -> usize {
+    let mut seen: HashMap<String, usize> = HashMap::new(); // Stream key to index
+    let mut kept: Vec<Stream> = Vec::with_capacity(streams.len());
+    let mut merged = 0;
+    for stream in std::mem::take(streams) {
+        let key = canonical::stream_key(stream.stream_url.as_deref().unwrap_or(&stream.url));
+        let Some(&index) = seen.get(&key) else {
+            seen.insert(key, kept.len());
+            kept.push(stream);
+            continue;
+        };
+        let first = &mut kept[index];
+        info!(
+            "{} plays the same stream as {}; recording it once",
+            stream.name, first.name
+        );
+        for place in stream.places {
+            if !first.places.iter().any(|p| p.id == place.id) {
+                first.places.push(place);
+            }
+        }
+        first.favorite |= stream.favorite;
+        first.merged.push(stream.id);
+        first.merged.extend(stream.merged);
+        merged += 1;
+    }
+    *streams = kept;
+    merged
+}
+
+/**
+ * Extracts the channel ID from a listen URL such as
+ * "http://radio.garden/api/ara/content/listen/AbCd1234/channel.mp3".
+ */
+fn listen_channel_id(url: &str) -> Option<ChannelId> {
+    let url = Url::parse(url).ok()?;
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    match segments.as_slice() {
+        [.., "listen", id, "channel.mp3"] => id.parse().ok(),
+        _ => None,
+    }
+}
+
+/**
+ * Extracts the channel ID from a channel page URL such as
+ * "/listen/radio-name/AbCd1234", which is the last non-empty path segment.
+ * Query strings and fragments are ignored. Returns `None` unless the ID is
+ * purely alphanumeric.
+ */
+fn channel_id(base: &Url, page_url: &str) -> Option<ChannelId> {
+    let url = base.join(page_url).ok()?;
+    url.path_segments()?.rfind(|s| !s.is_empty())?.parse().ok()
+}
@@ -0,0 +1,282 @@
+use serde::Serialize;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+/**
+ * Bitrates in kbit/s by bitrate index (1 to 14) for MPEG-1 layers I, II and
+ * III, and MPEG-2/2.5 layer I and layers II/III.
+ */
+const BITRATES: [[u32; 14]; 5] = [
+    [
+        32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+    ],
+    [
+        32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+    ],
+    [
+        32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    [
+        32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+    ],
+    [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+/**
+ * Sample rates in Hz by sample rate index for MPEG-1, MPEG-2 and MPEG-2.5.
+ */
+const SAMPLE_RATES: [[u32; 3]; 3] = [
+    [44100, 48000, 32000],
+    [22050, 24000, 16000],
+    [11025, 12000, 8000],
+];
+
+/**
+ * The parts of an MPEG audio frame header needed to find the next frame and
+ * to tell how much audio it holds.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub length: usize,    // Bytes including the header
+    pub samples: u32,     // Samples per channel
+    pub sample_rate: u32, // Hz
+}
+
+impl FrameHeader {
+    /**
+     * Parses the four header bytes at the start of `bytes`. Free-format and
+     * reserved values are rejected, as they cannot be told apart from audio
+     * data that happens to contain a sync word.
+     */
+    pub fn parse(bytes: &[u8]) -> Option<FrameHeader> {
+        let [b0, b1, b2, _] = *bytes.get(..4)? else {
+            return None;
+        };
+        if b0 != 0xFF || b1 & 0xE0 != 0xE0 {
+            return None;
+        }
+        // Version 3 is MPEG-1, 2 is MPEG-2 and 0 is MPEG-2.5
+        let version = (b1 >> 3) & 0x03;
+        // Layer 3 is layer I, 2 is layer II and 1 is layer III
+        let layer = (b1 >> 1) & 0x03;
+        let bitrate_index = (b2 >> 4) as usize;
+        let rate_index = ((b2 >> 2) & 0x03) as usize;
+        if version == 1
+            || layer == 0
+            || bitrate_index == 0
+            || bitrate_index == 15
+            || rate_index == 3
+        {
+            return None;
+        }
+
+        let mpeg1 = version == 3;
+        let table = match (mpeg1, layer) {
+            (true, 3) => 0,
+            (true, 2) => 1,
+            (true, _) => 2,
+            (false, 3) => 3,
+            (false, _) => 4,
+        };
+        let bitrate = BITRATES[table][bitrate_index - 1] * 1000;
+        let sample_rate = SAMPLE_RATES[match version {
+            3 => 0,
+            2 => 1,
+            _ => 2,
+        }][rate_index];
+        let padding = ((b2 >> 1) & 0x01) as u32;
+
+        let (length, samples) = match layer {
+            3 => ((12 * bitrate / sample_rate + padding) * 4, 384),
+            // MPEG-2/2.5 layer III frames hold half as many samples
+            1 if !mpeg1 => (72 * bitrate / sample_rate + padding, 576),
+            _ => (144 * bitrate / sample_rate + padding, 1152),
+        };
+        Some(FrameHeader {
+            length: length as usize,
+            samples,
+            sample_rate,
+        })
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.samples as f64 / self.sample_rate as f64
+    }
+}
+
+/**
+ * Follows the frames of an MP3 stream fed to it in arbitrary chunks, to
+ * find places where it can be cut without splitting a frame. A frame found
+ * while searching only counts once the frame after it is found as well.
+ */
+#[derive(Default)]
+pub struct FrameScanner {
+    buffer: Vec<u8>,   // Unconsumed bytes, from the stream offset `position`
+    position: u64,     // Stream offset of `buffer[0]`
+    next: Option<u64>, // Stream offset of the next frame, unless searching
+    seconds: f64,      // Audio in the frames found so far
+}
+
+impl FrameScanner {
+    /**
+     * Consumes the next chunk of the stream and returns the offset within
+     * it of the first frame that starts in it, if any.
+     */
+    pub fn feed(&mut self, chunk: &[u8]) -> Option<usize> {
+        let chunk_start = self.position + self.buffer.len() as u64;
+        self.buffer.extend_from_slice(chunk);
+
+        let mut first = None;
+        let mut search = 0;
+        loop {
+            match self.next {
+                Some(next) => {
+                    let at = (next - self.position) as usize;
+                    if at + 4 > self.buffer.len() {
+                        break;
+                    }
+                    match FrameHeader::parse(&self.buffer[at..]) {
+                        Some(header) => {
+                            if next >= chunk_start && first.is_none() {
+                                first = Some((next - chunk_start) as usize);
+                            }
+                            self.next = Some(next + header.length as u64);
+                            self.seconds += header.seconds();
+                        }
+                        None => {
+                            // Lost sync, e.g. on a metadata block or a gap
+                            self.next = None;
+                            search = at + 1;
+                        }
+                    }
+                }
+                None => match self.find_frame(search) {
+                    Ok(at) => self.next = Some(self.position + at as u64),
+                    Err(resume) => {
+                        search = resume;
+                        break;
+                    }
+                },
+            }
+        }
+
+        // Keep only what the next call still has to look at
+        let keep_from = match self.next {
+            Some(next) => ((next - self.position) as usize).min(self.buffer.len()),
+            None => search.min(self.buffer.len()),
+        };
+        self.buffer.drain(..keep_from);
+        self.position += keep_from as u64;
+        first
+    }
+
+    /**
+     * Seconds of audio in the frames found so far.
+     */
+    pub fn seconds(&self) -> f64 {
+        self.seconds
+    }
+
+    /**
+     * Looks for a frame followed by another one from `from` on. Returns its
+     * offset in the buffer, or where to resume once more data arrives.
+     */
+    fn find_frame(&self, from: usize) -> Result<usize, usize> {
+        let mut at = from;
+        while at + 4 <= self.buffer.len() {
+            if let Some(header) = FrameHeader::parse(&self.buffer[at..]) {
+                let following = at + header.length;
+                if following + 4 > self.buffer.len() {
+                    return Err(at);
+                }
+                if FrameHeader::parse(&self.buffer[following..]).is_some() {
+                    return Ok(at);
+                }
+            }
+            at += 1;
+        }
+        Err(at)
+    }
+}
+
+/**
+ * How much of an MP3 file `trim_file` kept, recorded in the sidecar.
+ */
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Trim {
+    pub recorded_seconds: f64, // Audio in the complete frames before trimming
+    pub kept_seconds: f64,
+    pub delta_seconds: f64, // Kept minus requested; negative if the stream fell short
+}
+
+/**
+ * Cuts the MP3 file at `path` after the frame closest to `seconds` of
+ * audio, counting the samples of every frame, and drops an incomplete frame
+ * at its end. Bytes between frames that are kept stay but hold no audio.
+ */
+pub fn trim_file(path: &Path, seconds: f64) -> io::Result<Trim> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut offset = 0; // Of `window` in the file
+    let mut window = [0u8; 4];
+    let mut recorded = 0.0;
+    let mut kept = 0.0;
+    let mut keep = 0; // Bytes up to the end of the last frame kept
+    let mut cut = false;
+    if read_fully(&mut reader, &mut window)? {
+        loop {
+            match FrameHeader::parse(&window) {
+                Some(header) if offset + header.length as u64 <= size => {
+                    let frame = header.seconds();
+                    recorded += frame;
+                    // Keep the frame if that gets closer to the requested length
+                    if !cut && kept + frame / 2.0 <= seconds {
+                        kept += frame;
+                        keep = offset + header.length as u64;
+                    } else {
+                        cut = true;
+                    }
+                    reader.seek_relative(header.length as i64 - 4)?;
+                    offset += header.length as u64;
+                    if !read_fully(&mut reader, &mut window)? {
+                        break;
+                    }
+                }
+                // An incomplete frame at the end
+                Some(_) => break,
+                None => {
+                    // Resynchronize one byte at a time
+                    window.rotate_left(1);
+                    offset += 1;
+                    if !read_fully(&mut reader, &mut window[3..])? {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    if keep < size {
+        OpenOptions::new().write(true).open(path)?.set_len(keep)?;
+    }
+    Ok(Trim {
+        recorded_seconds: recorded,
+        kept_seconds: kept,
+        delta_seconds: kept - seconds,
+    })
+}
+
+/**
+ * Fills `buffer`, returning false if the reader ends first.
+ */
+fn read_fully<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buffer) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
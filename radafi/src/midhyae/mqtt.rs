@@ -0,0 +1,262 @@
+use log::{info, warn};
+use rumqttc::{Client, Connection, Event, LastWill, Outgoing, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use super::{Listener, StreamOutcome};
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(3); // Wait for queued messages on exit
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "radafi".to_string()
+}
+
+fn default_topic() -> String {
+    "radafi".to_string()
+}
+
+fn default_status_interval() -> u64 {
+    30
+}
+
+/**
+ * MQTT broker to publish progress to, read from an `[mqtt]` table:
+ *
+ *   [mqtt]
+ *   host = "homeassistant.local"
+ *   topic = "radafi"
+ *
+ * Messages go to topics under `topic`:
+ *
+ *   <topic>/availability  "online" or "offline" (retained, also the last will)
+ *   <topic>/status        JSON state, retained, every `status_interval` seconds
+ *   <topic>/events        JSON lifecycle events as they happen
+ */
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MqttOptions {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default = "default_topic")]
+    pub topic: String,
+    #[serde(default = "default_status_interval")]
+    pub status_interval: u64, // Seconds between status messages
+}
+
+/**
+ * State reported on `<topic>/status`.
+ */
+#[derive(Serialize, Default, Clone)]
+struct Status {
+    state: &'static str, // "idle" or "recording"
+    stations: usize,     // Stations in the current or last run
+    active: usize,       // Recordings in progress
+    recorded: usize,
+    failed: usize,
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Connection to the broker. Publishing never blocks a recording: messages
+ * are queued and sent by a background thread, and dropped with a warning if
+ * the queue is full.
+ */
+pub struct Mqtt {
+    client: Client,
+    topic: String,
+    status: Mutex<Status>,
+    disconnected: Mutex<mpsc::Receiver<()>>,
+}
+
+impl Mqtt {
+    pub fn connect(options: &MqttOptions) -> Arc<Self> {
+        let availability = format!("{}/availability", options.topic);
+        let mut mqtt_options =
+            rumqttc::MqttOptions::new(&options.client_id, &options.host, options.port);
+        mqtt_options.set_keep_alive(Duration::from_secs(30));
+        mqtt_options.set_last_will(LastWill::new(
+            &availability,
+            OFFLINE,
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let (Some(username), Some(password)) = (&options.username, &options.password) {
+            mqtt_options.set_credentials(username, password);
+        }
+
+        let (client, connection) = Client::new(mqtt_options, 100);
+        let (disconnected_tx, disconnected_rx) = mpsc::channel();
+        thread::spawn(move || drive(connection, disconnected_tx));
+
+        let mqtt = Arc::new(Mqtt {
+            client,
+            topic: options.topic.clone(),
+            status: Mutex::new(Status {
+                state: "idle",
+                ..Status::default()
+            }),
+            disconnected: Mutex::new(disconnected_rx),
+        });
+        mqtt.publish("availability", ONLINE, true);
+        mqtt.publish_status();
+
+        let weak = Arc::downgrade(&mqtt);
+        let interval = Duration::from_secs(options.status_interval.max(1));
+        thread::spawn(move || report_status(weak, interval));
+        info!(
+            "Publishing to MQTT broker {}:{}",
+            options.host, options.port
+        );
+        mqtt
+    }
+
+    fn publish(&self, subtopic: &str, payload: impl Into<Vec<u8>>, retain: bool) {
+        let topic = format!("{}/{}", self.topic, subtopic);
+        if let Err(e) = self
+            .client
+            .try_publish(&topic, QoS::AtLeastOnce, retain, payload)
+        {
+            warn!("Failed to publish to {}: {}", topic, e);
+        }
+    }
+
+    fn publish_status(&self) {
+        let status = self.status.lock().unwrap().clone();
+        match serde_json::to_vec(&status) {
+            Ok(payload) => self.publish("status", payload, true),
+            Err(e) => warn!("Failed to encode MQTT status: {}", e),
+        }
+    }
+
+    fn event(&self, event: serde_json::Value) {
+        self.publish("events", event.to_string(), false);
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Status)) {
+        change(&mut self.status.lock().unwrap());
+        self.publish_status();
+    }
+
+    pub fn run_started(&self, stations: usize) {
+        self.update(|status| {
+            *status = Status {
+                state: "recording",
+                stations,
+                ..Status::default()
+            }
+        });
+        self.event(json!({ "event": "run_started", "stations": stations }));
+    }
+
+    pub fn recording_started(&self, id: &str, name: &str) {
+        self.update(|status| status.active += 1);
+        self.event(json!({ "event": "recording_started", "id": id, "name": name }));
+    }
+
+    pub fn recording_finished(&self, outcome: &StreamOutcome) {
+        self.update(|status| {
+            status.active = status.active.saturating_sub(1);
+            if outcome.ok() {
+                status.recorded += 1;
+            } else {
+                status.failed += 1;
+            }
+        });
+        self.event(json!({
+            "event": "recording_finished",
+            "id": outcome.id,
+            "name": outcome.name,
+            "bytes": outcome.bytes,
+            "ok": outcome.ok(),
+            "category": outcome.failure_category(),
+            "code": outcome.failure_code(),
+        }));
+    }
+
+    pub fn run_finished(&self) {
+        let (recorded, failed) = {
+            let status = self.status.lock().unwrap();
+            (status.recorded, status.failed)
+        };
+        self.update(|status| status.state = "idle");
+        self.event(json!({ "event": "run_finished", "recorded": recorded, "failed": failed }));
+    }
+}
+
+impl Drop for Mqtt {
+    /**
+     * Announces going offline and gives queued messages a moment to be
+     * sent.
+     */
+    fn drop(&mut self) {
+        self.publish("availability", OFFLINE, true);
+        if self.client.try_disconnect().is_ok() {
+            let _ = self
+                .disconnected
+                .lock()
+                .unwrap()
+                .recv_timeout(FLUSH_TIMEOUT);
+        }
+    }
+}
+
+/**
+ * Sends queued messages and keeps the connection alive, reconnecting after
+ * errors, until the client disconnects.
+ */
+fn drive(mut connection: Connection, disconnected: mpsc::Sender<()>) {
+    let mut connected = true;
+    for notification in connection.iter() {
+        match notification {
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+            Ok(_) => connected = true,
+            Err(e) => {
+                // Warn once per outage; rumqttc reconnects on the next poll
+                if connected {
+                    warn!("MQTT connection error: {}", e);
+                    connected = false;
+                }
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+    let _ = disconnected.send(());
+}
+
+/**
+ * Republishes the status every `interval` until the connection is dropped.
+ */
+fn report_status(mqtt: Weak<Mqtt>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        match mqtt.upgrade() {
+            Some(mqtt) => mqtt.publish_status(),
+            None => return,
+        }
+    }
+}
+
+impl Listener {
+    /**
+     * Publishes recording progress to an MQTT broker, see `MqttOptions`.
+     */
+    pub fn set_mqtt(&mut self, options: &MqttOptions) {
+        self.mqtt = Some(Mqtt::connect(options));
+    }
+}
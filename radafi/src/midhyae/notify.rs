@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::store::Run;
+use super::ErrorCategory;
+
+/**
+ * Summary of a finished run, as sent by notifiers.
+ */
+pub struct Report {
+    pub country: String,
+    pub recorded: usize, // Successful recordings
+    pub failed: usize,
+    pub bytes: u64,        // Total over all recordings
+    pub elapsed: Duration, // Wall-clock time from start to finish
+    pub failures: BTreeMap<ErrorCategory, usize>,
+    pub failed_stations: Vec<(String, String)>, // Name and error
+}
+
+impl Report {
+    pub fn new(run: &Run, finished: SystemTime) -> Self {
+        let started = UNIX_EPOCH + Duration::from_secs(run.started);
+        let failed_stations: Vec<(String, String)> = run
+            .outcomes
+            .iter()
+            .filter(|o| !o.ok())
+            .map(|o| {
+                let error = match (&o.error, o.dead_air) {
+                    (Some(error), _) => error.clone(),
+                    (None, true) => "dead air".to_string(),
+                    (None, false) => "no data".to_string(),
+                };
+                (o.name.clone(), error)
+            })
+            .collect();
+        Report {
+            country: run.country.clone(),
+            recorded: run.outcomes.len() - failed_stations.len(),
+            failed: failed_stations.len(),
+            bytes: run.outcomes.iter().map(|o| o.bytes).sum(),
+            elapsed: finished.duration_since(started).unwrap_or_default(),
+            failures: run.failures_by_category(),
+            failed_stations,
+        }
+    }
+
+    pub fn subject(&self) -> String {
+        format!(
+            "radafi: {} recorded, {} failed ({})",
+            self.recorded, self.failed, self.country
+        )
+    }
+
+    /**
+     * Plain-text summary with one line per failed station.
+     */
+    pub fn body(&self) -> String {
+        let secs = self.elapsed.as_secs();
+        let mut body = format!(
+            "Country:  {}\nRecorded: {}\nFailed:   {}\nSize:     {:.1} MB\nDuration: {}:{:02}:{:02}\n",
+            self.country,
+            self.recorded,
+            self.failed,
+            self.bytes as f64 / 1_000_000.0,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        );
+        if !self.failures.is_empty() {
+            let causes: Vec<String> = self
+                .failures
+                .iter()
+                .map(|(category, count)| format!("{} {}", category.name(), count))
+                .collect();
+            let _ = writeln!(body, "Causes:   {}", causes.join(", "));
+            body.push_str("\nFailed stations:\n");
+            for (name, error) in &self.failed_stations {
+                let _ = writeln!(body, "- {}: {}", name, error);
+            }
+        }
+        body
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, ChildStdin, Command};
+
+use std::fmt;
+use std::path::Path;
+use std::process::Stdio;
+use std::str::FromStr;
+
+use super::RecordingError;
+
+/**
+ * An audio bitrate such as `32k`, in bit/s.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bitrate(pub u32);
+
+impl Default for Bitrate {
+    fn default() -> Self {
+        Bitrate(32_000)
+    }
+}
+
+impl FromStr for Bitrate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (digits, factor) = match s.strip_suffix(['k', 'K']) {
+            Some(digits) => (digits, 1000),
+            None => (s, 1),
+        };
+        match digits
+            .parse::<u32>()
+            .ok()
+            .and_then(|n| n.checked_mul(factor))
+        {
+            // The range libopus accepts
+            Some(bits) if (6_000..=510_000).contains(&bits) => Ok(Bitrate(bits)),
+            _ => Err(format!(
+                "invalid bitrate {}, expected 6k to 510k, e.g. 32k",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Bitrate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}k", self.0 / 1000)
+    }
+}
+
+/**
+ * How recordings are stored: as the MP3 the station sends, or re-encoded to
+ * Opus, which takes a fraction of the space at speech and music quality.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveCodec {
+    #[default]
+    Mp3,
+    Opus(Bitrate),
+}
+
+impl ArchiveCodec {
+    /**
+     * The codec called `name`, "mp3" or "opus"; `bitrate` only applies to
+     * Opus.
+     */
+    pub fn new(name: &str, bitrate: Option<Bitrate>) -> Result<Self, String> {
+        match name {
+            "mp3" => Ok(ArchiveCodec::Mp3),
+            "opus" => Ok(ArchiveCodec::Opus(bitrate.unwrap_or_default())),
+            _ => Err(format!("unsupported codec {}, expected mp3 or opus", name)),
+        }
+    }
+
+    /**
+     * Extension of the files written.
+     */
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveCodec::Mp3 => "mp3",
+            ArchiveCodec::Opus(_) => "opus",
+        }
+    }
+}
+
+impl fmt::Display for ArchiveCodec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveCodec::Mp3 => write!(f, "mp3"),
+            ArchiveCodec::Opus(bitrate) => write!(f, "opus {}", bitrate),
+        }
+    }
+}
+
+/**
+ * Encodes MP3 audio fed to it in chunks into an Ogg Opus file, through an
+ * `ffmpeg` process reading from a pipe.
+ */
+pub struct OpusEncoder {
+    child: Child,
+    stdin: ChildStdin,
+}
+
+impl OpusEncoder {
+    pub fn spawn(path: &Path, bitrate: Bitrate) -> Result<Self, RecordingError> {
+        let bitrate = bitrate.0.to_string();
+        let mut child = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-y", "-f", "mp3", "-i", "pipe:0"])
+            .args(["-vn", "-c:a", "libopus", "-b:a", &bitrate, "-f", "ogg"])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| RecordingError::Encoding(format!("failed to run ffmpeg: {}", e)))?;
+        let stdin = child.stdin.take().expect("ffmpeg stdin is piped");
+        Ok(OpusEncoder { child, stdin })
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), RecordingError> {
+        self.stdin
+            .write_all(chunk)
+            .await
+            .map_err(|e| RecordingError::Encoding(format!("ffmpeg stopped reading: {}", e)))
+    }
+
+    /**
+     * Ends the input and waits for the file to be completed.
+     */
+    pub async fn finish(self) -> Result<(), RecordingError> {
+        let OpusEncoder { mut child, stdin } = self;
+        drop(stdin);
+        let status = child.wait().await?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(RecordingError::Encoding(format!(
+                "ffmpeg failed: {}",
+                status
+            ))),
+        }
+    }
+}
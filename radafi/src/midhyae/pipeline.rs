@@ -0,0 +1,196 @@
+use reqwest::Response;
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use super::icy::IcyDemuxer;
+use super::job::MAX_FRAME_SEARCH;
+use super::mp3::FrameScanner;
+use super::progress::Progress;
+use super::vad::{SpeechGate, SpeechGating};
+
+/**
+ * Receives the audio of a recording as it passes through its pipeline, in
+ * full: after the stream metadata is taken out and before any of it is
+ * filtered. Taps must not block, as they hold up the stream.
+ */
+pub trait Tap: Send + Sync {
+    fn audio(&self, station: &str, audio: &[u8]);
+}
+
+/**
+ * Feeds preview listeners and the time-shift, see `Progress::tee`.
+ */
+impl Tap for Progress {
+    fn audio(&self, station: &str, audio: &[u8]) {
+        self.tee(station, audio);
+    }
+}
+
+/**
+ * What became of one chunk of the stream in the pipeline.
+ */
+pub struct Chunk<'a> {
+    pub audio: Cow<'a, [u8]>,    // To be written
+    pub received: usize,         // Bytes of audio before filtering
+    pub title: Option<String>,   // Stream title starting in the chunk
+    pub boundary: Option<usize>, // Offset of the first MP3 frame in `audio`
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * The stages the chunks of a stream go through between being fetched and
+ * being written, in order:
+ *
+ *   demux    in-band ICY metadata is taken out, reporting title changes
+ *   tee      every `Tap` gets the audio, e.g. preview listeners
+ *   filter   with `speech_only`, audio that is not speech is dropped
+ *   inspect  MP3 frames are found, to time the audio and cut files
+ *   align    after a reconnection, audio before the first frame is dropped
+ *
+ * Fetching and writing are left to the caller, which decides where files
+ * start and end from what the chunks carry.
+ */
+pub struct StreamPipeline {
+    station: String, // Channel ID, for taps
+    name: String,    // Station title, for log lines
+    demux: Option<IcyDemuxer>,
+    taps: Vec<Arc<dyn Tap>>,
+    gate: Option<SpeechGate>,
+    scanner: FrameScanner,
+    earlier: f64, // Seconds of audio inspected before the frames were lost track of
+    aligning: Option<usize>, // Bytes dropped looking for a frame since reconnecting
+}
+
+impl StreamPipeline {
+    pub fn new(station: &str, name: &str, response: &Response, speech_only: bool) -> Self {
+        StreamPipeline {
+            station: station.to_string(),
+            name: name.to_string(),
+            demux: IcyDemuxer::from_response(response),
+            taps: Vec::new(),
+            gate: speech_only.then(SpeechGate::default),
+            scanner: FrameScanner::default(),
+            earlier: 0.0,
+            aligning: None,
+        }
+    }
+
+    /**
+     * Adds a tap after the ones added before.
+     */
+    pub fn tap(&mut self, tap: Arc<dyn Tap>) {
+        self.taps.push(tap);
+    }
+
+    /**
+     * Passes a chunk received from the stream through every stage.
+     */
+    pub fn process<'a>(&mut self, received: &'a [u8]) -> Chunk<'a> {
+        let (audio, title) = match &mut self.demux {
+            Some(demux) => {
+                let (audio, title) = demux.feed(received);
+                (Cow::Owned(audio), title)
+            }
+            None => (Cow::Borrowed(received), None),
+        };
+        for tap in &self.taps {
+            tap.audio(&self.station, &audio);
+        }
+        let length = audio.len();
+        let audio = match &mut self.gate {
+            Some(gate) => Cow::Owned(gate.feed(&audio, &self.name)),
+            None => audio,
+        };
+        let mut boundary = self.scanner.feed(&audio);
+        // Goes on at a whole frame, without the rest of one cut off
+        let audio = match (self.aligning, boundary) {
+            (Some(_), Some(at)) => {
+                self.aligning = None;
+                boundary = Some(0);
+                match audio {
+                    Cow::Borrowed(audio) => Cow::Borrowed(&audio[at..]),
+                    Cow::Owned(mut audio) => {
+                        audio.drain(..at);
+                        Cow::Owned(audio)
+                    }
+                }
+            }
+            // Not MP3, e.g. AAC; kept as it is
+            (Some(dropped), None) if dropped + audio.len() > MAX_FRAME_SEARCH => {
+                self.aligning = None;
+                audio
+            }
+            (Some(dropped), None) => {
+                self.aligning = Some(dropped + audio.len());
+                Cow::Borrowed(&[][..])
+            }
+            (None, _) => audio,
+        };
+        Chunk {
+            audio,
+            received: length,
+            title,
+            boundary,
+        }
+    }
+
+    /**
+     * Follows the metadata of a chunk that is discarded, e.g. while paused,
+     * to know the title when recording goes on.
+     */
+    pub fn skip(&mut self, received: &[u8]) {
+        if let Some(demux) = &mut self.demux {
+            demux.feed(received);
+        }
+    }
+
+    /**
+     * Starts looking for frames anew after audio was skipped, keeping the
+     * seconds inspected so far.
+     */
+    pub fn resync(&mut self) {
+        self.earlier += self.scanner.seconds();
+        self.scanner = FrameScanner::default();
+    }
+
+    /**
+     * Follows a new connection to the stream, whose metadata may come at
+     * another interval. Its audio is passed on from the first frame found,
+     * so that it can be appended to what came before.
+     */
+    pub fn reconnected(&mut self, response: &Response) {
+        self.demux = IcyDemuxer::from_response(response);
+        self.resync();
+        self.aligning = Some(0);
+    }
+
+    /**
+     * Seconds of audio in the frames inspected so far, across connections.
+     */
+    pub fn seconds(&self) -> f64 {
+        self.earlier + self.scanner.seconds()
+    }
+
+    /**
+     * The stream title playing, if the stream has metadata.
+     */
+    pub fn title(&self) -> Option<&str> {
+        self.demux.as_ref().and_then(|demux| demux.title())
+    }
+
+    /**
+     * What the speech filter kept so far, if audio is filtered.
+     */
+    pub fn gating(&self) -> Option<SpeechGating> {
+        self.gate.as_ref().map(|gate| gate.gating())
+    }
+
+    /**
+     * The audio the speech filter still holds back, to be written last, if
+     * audio is filtered.
+     */
+    pub fn finish(&mut self) -> Option<Vec<u8>> {
+        self.gate.as_mut().map(|gate| gate.finish())
+    }
+}
@@ -0,0 +1,110 @@
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserializer;
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::Place;
+
+/**
+ * The places kept from a place list, and the name of every country in it.
+ */
+#[derive(Debug, Default)]
+pub struct PlaceListing {
+    pub places: Vec<Place>,
+    pub countries: BTreeSet<String>,
+}
+
+/**
+ * Parses the response of the `places` API call, `{"data": {"list": [...]}}`,
+ * keeping only the places `keep` accepts. The list covers the whole world,
+ * so places are filtered one by one as they are parsed rather than after
+ * all of them have been.
+ */
+pub fn parse_places<F>(body: &[u8], keep: F) -> serde_json::Result<PlaceListing>
+where
+    F: FnMut(&Place) -> bool,
+{
+    let mut deserializer = serde_json::Deserializer::from_slice(body);
+    let filter = Filter {
+        keep,
+        listing: PlaceListing::default(),
+    };
+    let listing = Field("data", Field("list", filter)).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(listing)
+}
+
+/**
+ * Deserializes the value of the key `.0` of an object with `.1`, skipping
+ * every other key.
+ */
+struct Field<S>(&'static str, S);
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for Field<S> {
+    type Value = S::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, S: DeserializeSeed<'de>> Visitor<'de> for Field<S> {
+    type Value = S::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an object with {}", self.0)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let Field(name, seed) = self;
+        let mut seed = Some(seed);
+        let mut value = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match seed.take() {
+                Some(inner) if key == name => value = Some(map.next_value_seed(inner)?),
+                inner => {
+                    seed = inner;
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        value.ok_or_else(|| de::Error::missing_field(name))
+    }
+}
+
+/**
+ * Collects the places of a list that are to be kept.
+ */
+struct Filter<F> {
+    keep: F,
+    listing: PlaceListing,
+}
+
+impl<'de, F: FnMut(&Place) -> bool> DeserializeSeed<'de> for Filter<F> {
+    type Value = PlaceListing;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F: FnMut(&Place) -> bool> Visitor<'de> for Filter<F> {
+    type Value = PlaceListing;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of places")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<Self::Value, A::Error> {
+        while let Some(place) = seq.next_element::<Place>()? {
+            if !self.listing.countries.contains(&place.country) {
+                self.listing.countries.insert(place.country.clone());
+            }
+            if (self.keep)(&place) {
+                self.listing.places.push(place);
+            }
+        }
+        Ok(self.listing)
+    }
+}
@@ -0,0 +1,276 @@
+use futures::future::BoxFuture;
+use log::{error, info};
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::process::Command;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::analysis;
+use super::artwork::Artwork;
+use super::metadata::Metadata;
+use super::transcribe::Transcriber;
+use super::RecordingError;
+
+/**
+ * A stage run on every finished local recording, in the order the stages
+ * were added, before its sidecar is written. Stages may add to the metadata
+ * that ends up in the sidecar.
+ */
+pub trait PostProcessor: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn process<'a>(
+        &'a self,
+        client: &'a Client,
+        recording: &'a Path,
+        metadata: &'a mut Metadata,
+    ) -> BoxFuture<'a, Result<(), RecordingError>>;
+}
+
+/**
+ * Whether `metadata` belongs to a recording with MP3 audio that can be
+ * decoded.
+ */
+fn decodable(metadata: &Metadata) -> bool {
+    metadata.bytes > 0 && metadata.encryption.is_none() && metadata.codec.is_none()
+}
+
+/**
+ * Adds the speech/music/silence breakdown to the metadata.
+ */
+pub struct Classify;
+
+impl PostProcessor for Classify {
+    fn name(&self) -> &'static str {
+        "classify"
+    }
+
+    fn process<'a>(
+        &'a self,
+        _client: &'a Client,
+        recording: &'a Path,
+        metadata: &'a mut Metadata,
+    ) -> BoxFuture<'a, Result<(), RecordingError>> {
+        Box::pin(async move {
+            if decodable(metadata) {
+                // Decoding the whole recording would hold up a runtime thread
+                let path = recording.to_path_buf();
+                let classification =
+                    tokio::task::spawn_blocking(move || analysis::classify_file(&path))
+                        .await
+                        .map_err(|e| RecordingError::PostProcess(e.to_string()))??;
+                metadata.classification = Some(classification);
+            }
+            Ok(())
+        })
+    }
+}
+
+impl PostProcessor for Transcriber {
+    fn name(&self) -> &'static str {
+        "transcribe"
+    }
+
+    fn process<'a>(
+        &'a self,
+        client: &'a Client,
+        recording: &'a Path,
+        metadata: &'a mut Metadata,
+    ) -> BoxFuture<'a, Result<(), RecordingError>> {
+        Box::pin(async move {
+            let dead_air = metadata.levels.as_ref().is_some_and(|l| l.dead_air);
+            if decodable(metadata) && !dead_air {
+                self.transcribe(client, recording).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl PostProcessor for Artwork {
+    fn name(&self) -> &'static str {
+        "artwork"
+    }
+
+    fn process<'a>(
+        &'a self,
+        client: &'a Client,
+        recording: &'a Path,
+        metadata: &'a mut Metadata,
+    ) -> BoxFuture<'a, Result<(), RecordingError>> {
+        Box::pin(async move {
+            if decodable(metadata) {
+                let image = self.apply(client, &metadata.id, recording).await?;
+                metadata.artwork = image.map(|path| path.to_string_lossy().into_owned());
+            }
+            Ok(())
+        })
+    }
+}
+
+/**
+ * Runs a shell command on the recording, e.g. to tag or transcode it. The
+ * command gets the recording's path as `$RADAFI_RECORDING` and the station
+ * as `$RADAFI_STATION` and `$RADAFI_CHANNEL_ID`.
+ */
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunCommand {
+    command: String,
+}
+
+impl PostProcessor for RunCommand {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn process<'a>(
+        &'a self,
+        _client: &'a Client,
+        recording: &'a Path,
+        metadata: &'a mut Metadata,
+    ) -> BoxFuture<'a, Result<(), RecordingError>> {
+        Box::pin(async move {
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(&self.command)
+                .env("RADAFI_RECORDING", recording)
+                .env("RADAFI_STATION", &metadata.station)
+                .env("RADAFI_CHANNEL_ID", &metadata.id)
+                .status()
+                .await?;
+            if !status.success() {
+                return Err(RecordingError::PostProcess(format!(
+                    "`{}` exited with {}",
+                    self.command, status
+                )));
+            }
+            Ok(())
+        })
+    }
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Post-processors applied to each recording. A failing stage is logged and
+ * the remaining stages still run.
+ */
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    stages: Vec<Arc<dyn PostProcessor>>,
+}
+
+impl Pipeline {
+    pub fn push(&mut self, stage: Box<dyn PostProcessor>) {
+        self.stages.push(Arc::from(stage));
+    }
+
+    pub async fn run(&self, client: &Client, recording: &Path, metadata: &mut Metadata) {
+        for stage in &self.stages {
+            match stage.process(client, recording, metadata).await {
+                Ok(()) => info!("{} finished for {}", stage.name(), recording.display()),
+                Err(e) => error!("{} failed for {}: {}", stage.name(), recording.display(), e),
+            }
+        }
+    }
+}
+
+/**
+ * Builds a stage from its table in the configuration, without the `stage`
+ * key, or explains what is wrong with the table.
+ */
+pub type Factory = fn(toml::Table) -> Result<Box<dyn PostProcessor>, String>;
+
+/**
+ * ----------------------------------------------------------------------------
+ * Post-processors that can be enabled by name in the configuration:
+ *
+ *   [[post_process]]
+ *   stage = "command"
+ *   command = "id3v2 -a \"$RADAFI_STATION\" \"$RADAFI_RECORDING\""
+ */
+pub struct Registry {
+    factories: HashMap<&'static str, Factory>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Registry {
+            factories: HashMap::new(),
+        };
+        registry.register("classify", |_| Ok(Box::new(Classify)));
+        registry.register("transcribe", transcriber);
+        registry.register("artwork", |table| {
+            let options = options::<ArtworkOptions>(table)?;
+            Ok(Box::new(Artwork::new(options.embed)))
+        });
+        registry.register("command", |table| {
+            Ok(Box::new(options::<RunCommand>(table)?))
+        });
+        registry
+    }
+}
+
+impl Registry {
+    pub fn register(&mut self, name: &'static str, factory: Factory) {
+        self.factories.insert(name, factory);
+    }
+
+    /**
+     * Builds the stage described by a `[[post_process]]` table.
+     */
+    pub fn build(&self, mut table: toml::Table) -> Result<Box<dyn PostProcessor>, RecordingError> {
+        let name = match table.remove("stage") {
+            Some(toml::Value::String(name)) => name,
+            _ => {
+                return Err(RecordingError::Config(
+                    "post_process: every stage needs a `stage` name".to_string(),
+                ))
+            }
+        };
+        let factory = self.factories.get(name.as_str()).ok_or_else(|| {
+            RecordingError::Config(format!("post_process: unknown stage {}", name))
+        })?;
+        factory(table)
+            .map_err(|e| RecordingError::Config(format!("post_process stage {}: {}", name, e)))
+    }
+}
+
+/**
+ * Deserializes a stage's options from its table.
+ */
+fn options<T: for<'de> Deserialize<'de>>(table: toml::Table) -> Result<T, String> {
+    T::deserialize(table).map_err(|e| e.to_string())
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ArtworkOptions {
+    #[serde(default)]
+    embed: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TranscribeOptions {
+    command: Option<String>,
+    url: Option<String>,
+}
+
+fn transcriber(table: toml::Table) -> Result<Box<dyn PostProcessor>, String> {
+    let transcriber = match options::<TranscribeOptions>(table)? {
+        TranscribeOptions {
+            command: Some(command),
+            url: None,
+        } => Transcriber::Command(command),
+        TranscribeOptions {
+            command: None,
+            url: Some(url),
+        } => Transcriber::http(&url).map_err(|e| e.to_string())?,
+        _ => return Err("exactly one of `command` and `url` is required".to_string()),
+    };
+    Ok(Box::new(transcriber))
+}
@@ -0,0 +1,343 @@
+use bytes::Bytes;
+use log::info;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::estimate::Size;
+use super::timeshift::TimeShift;
+
+/**
+ * Chunks a preview listener may fall behind by before it skips ahead.
+ */
+const PREVIEW_BACKLOG: usize = 64;
+
+/**
+ * Which recordings are paused: every one, or those of the listed channel
+ * IDs.
+ */
+#[derive(Default)]
+struct Paused {
+    all: bool,
+    stations: HashSet<String>,
+}
+
+/**
+ * A recording in progress.
+ */
+struct Recording {
+    name: String,
+    started: Instant,
+    bytes: u64,               // Audio received
+    buffered: Arc<AtomicU64>, // Received but not yet written, see `WriteBuffer`
+}
+
+/**
+ * Live counters of a `record_streams` call, shared with whoever watches it,
+ * and controls to pause or cancel it.
+ */
+#[derive(Default)]
+pub struct Progress {
+    stations: AtomicUsize,
+    active: AtomicUsize,
+    recorded: AtomicUsize,
+    failed: AtomicUsize,
+    cancelled: AtomicBool,
+    paused: Mutex<Paused>,
+    previews: Mutex<HashMap<String, broadcast::Sender<Bytes>>>, // By channel ID, while recording
+    recordings: Mutex<BTreeMap<String, Recording>>,             // By channel ID
+    rotations: AtomicUsize, // Times the recordings were asked to start new files
+    time_shift: TimeShift,
+}
+
+/**
+ * Point-in-time copy of `Progress`.
+ */
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct ProgressSnapshot {
+    pub total: usize,  // Stations to record
+    pub active: usize, // Recordings in progress
+    pub recorded: usize,
+    pub failed: usize,
+}
+
+/**
+ * A recording in progress, as reported while it runs.
+ */
+#[derive(Serialize, Debug, Clone)]
+pub struct RecordingProgress {
+    pub id: String, // Channel ID
+    pub name: String,
+    pub bytes: u64, // Audio received
+    pub seconds: u64,
+    pub paused: bool,
+    pub buffered: u64, // Received but not yet written
+}
+
+impl Progress {
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        ProgressSnapshot {
+            total: self.stations.load(Ordering::Relaxed),
+            active: self.active.load(Ordering::Relaxed),
+            recorded: self.recorded.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+
+    /**
+     * Stops recordings at their next chunk and skips those not started yet.
+     * What was recorded so far is kept.
+     */
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /**
+     * Stops writing the recordings of the given channel IDs, or of every
+     * station if none are given, until they are resumed. Paused time does
+     * not count towards their duration.
+     */
+    pub fn pause(&self, stations: &[String]) {
+        let mut paused = self.paused.lock().unwrap();
+        if stations.is_empty() {
+            paused.all = true;
+        } else {
+            paused.stations.extend(stations.iter().cloned());
+        }
+    }
+
+    /**
+     * Resumes the recordings of the given channel IDs, or every recording
+     * if none are given. Stations stay paused while every station is.
+     */
+    pub fn resume(&self, stations: &[String]) {
+        let mut paused = self.paused.lock().unwrap();
+        if stations.is_empty() {
+            *paused = Paused::default();
+        } else {
+            for station in stations {
+                paused.stations.remove(station);
+            }
+        }
+    }
+
+    pub fn is_paused(&self, station: &str) -> bool {
+        let paused = self.paused.lock().unwrap();
+        paused.all || paused.stations.contains(station)
+    }
+
+    /**
+     * Starts a new file for every recording at its next MP3 frame, as when
+     * segmenting. Files after the first are numbered like segments.
+     */
+    pub fn rotate(&self) {
+        self.rotations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /**
+     * How often rotating was asked for so far, to compare with an earlier
+     * count.
+     */
+    pub fn rotations(&self) -> usize {
+        self.rotations.load(Ordering::Relaxed)
+    }
+
+    /**
+     * The recordings in progress, by channel ID.
+     */
+    pub fn recordings(&self) -> Vec<RecordingProgress> {
+        self.recordings
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, recording)| RecordingProgress {
+                id: id.clone(),
+                name: recording.name.clone(),
+                bytes: recording.bytes,
+                seconds: recording.started.elapsed().as_secs(),
+                paused: self.is_paused(id),
+                buffered: recording.buffered.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /**
+     * The counters and each recording in progress, one per line.
+     */
+    pub fn status(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut status = format!(
+            "{} of {} stations recording, {} recorded, {} failed",
+            snapshot.active, snapshot.total, snapshot.recorded, snapshot.failed
+        );
+        for recording in self.recordings() {
+            status.push_str(&format!(
+                "\n  {} {}: {} in {}s, {} buffered{}",
+                recording.id,
+                recording.name,
+                Size(recording.bytes),
+                recording.seconds,
+                Size(recording.buffered),
+                if recording.paused { " (paused)" } else { "" }
+            ));
+        }
+        status
+    }
+
+    /**
+     * Follows the signals of a long-running recorder until the runtime
+     * shuts down: SIGUSR1 prints the status of the recordings, SIGUSR2
+     * rotates their files, SIGTSTP pauses them and SIGCONT resumes them.
+     * SIGTSTP is only followed without a terminal on stdin, so that Ctrl+Z
+     * still suspends a recorder run from a shell.
+     */
+    #[cfg(unix)]
+    pub async fn follow_signals(self: Arc<Self>) -> std::io::Result<()> {
+        follow_signals(move || vec![Arc::clone(&self)]).await
+    }
+
+    /**
+     * Listens to the audio of the recording of a channel ID from now on, or
+     * None if it is not being recorded. The stream ends with the recording.
+     */
+    pub fn preview(&self, station: &str) -> Option<broadcast::Receiver<Bytes>> {
+        let previews = self.previews.lock().unwrap();
+        previews.get(station).map(|sender| sender.subscribe())
+    }
+
+    /**
+     * The recent audio of the recordings, kept if a time-shift window is
+     * set.
+     */
+    pub fn time_shift(&self) -> &TimeShift {
+        &self.time_shift
+    }
+
+    /**
+     * Passes audio written for `station` on to its preview listeners, if it
+     * has any, and to the time-shift.
+     */
+    pub fn tee(&self, station: &str, audio: &[u8]) {
+        self.time_shift.push(station, audio);
+        if let Some(recording) = self.recordings.lock().unwrap().get_mut(station) {
+            recording.bytes += audio.len() as u64;
+        }
+        let previews = self.previews.lock().unwrap();
+        if let Some(sender) = previews.get(station) {
+            if sender.receiver_count() > 0 {
+                let _ = sender.send(Bytes::copy_from_slice(audio));
+            }
+        }
+    }
+
+    /**
+     * Counter of the bytes `station` has waiting in its write buffer, shared
+     * by the buffers of its files. Recordings not followed get one of their
+     * own.
+     */
+    pub fn buffer_level(&self, station: &str) -> Arc<AtomicU64> {
+        let recordings = self.recordings.lock().unwrap();
+        recordings
+            .get(station)
+            .map_or_else(Arc::default, |recording| Arc::clone(&recording.buffered))
+    }
+
+    pub fn set_stations(&self, stations: usize) {
+        self.stations.store(stations, Ordering::Relaxed);
+    }
+
+    pub fn started(&self, station: &str, name: &str) {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        self.recordings.lock().unwrap().insert(
+            station.to_string(),
+            Recording {
+                name: name.to_string(),
+                started: Instant::now(),
+                bytes: 0,
+                buffered: Arc::default(),
+            },
+        );
+        let (sender, _) = broadcast::channel(PREVIEW_BACKLOG);
+        self.previews
+            .lock()
+            .unwrap()
+            .insert(station.to_string(), sender);
+    }
+
+    pub fn finished(&self, station: &str, ok: bool) {
+        // Ends the streams of its preview listeners
+        self.previews.lock().unwrap().remove(station);
+        self.recordings.lock().unwrap().remove(station);
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        let counter = if ok { &self.recorded } else { &self.failed };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/**
+ * Applies the signals described at `Progress::follow_signals` to each
+ * `Progress` that `watched` returns at the time, e.g. to those of every
+ * capture of the daemon.
+ */
+#[cfg(unix)]
+pub(crate) async fn follow_signals<F>(watched: F) -> std::io::Result<()>
+where
+    F: Fn() -> Vec<Arc<Progress>>,
+{
+    use std::io::IsTerminal;
+    use tokio::signal::unix::{signal, Signal, SignalKind};
+
+    /**
+     * Waits for `signal`, forever if it is not followed.
+     */
+    async fn next(signal: &mut Option<Signal>) {
+        match signal {
+            Some(signal) => {
+                signal.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    let mut status = signal(SignalKind::user_defined1())?;
+    let mut rotate = signal(SignalKind::user_defined2())?;
+    // Ctrl+Z in a terminal should still suspend the process as usual
+    let mut pause = match std::io::stdin().is_terminal() {
+        true => None,
+        false => Some(signal(SignalKind::from_raw(libc::SIGTSTP))?),
+    };
+    let mut resume = signal(SignalKind::from_raw(libc::SIGCONT))?;
+    loop {
+        tokio::select! {
+            _ = status.recv() => {
+                let watched = watched();
+                if watched.is_empty() {
+                    info!("Status: nothing is being recorded");
+                }
+                for progress in watched {
+                    info!("Status: {}", progress.status());
+                }
+            }
+            _ = rotate.recv() => {
+                info!("Rotating every recording");
+                watched().iter().for_each(|progress| progress.rotate());
+            }
+            _ = next(&mut pause) => {
+                info!("Pausing every recording");
+                watched().iter().for_each(|progress| progress.pause(&[]));
+            }
+            _ = resume.recv() => {
+                info!("Resuming every recording");
+                watched().iter().for_each(|progress| progress.resume(&[]));
+            }
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use minimp3::{Decoder, Error as Mp3Error};
+use serde::Serialize;
+
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::analysis::{mix_down, SILENCE_RMS};
+use super::metadata;
+use super::RecordingError;
+
+/**
+ * Waiting this long for the next chunk of a stream counts as a stall.
+ */
+pub const STALL_AFTER: Duration = Duration::from_secs(2);
+
+/**
+ * Recordings of which less than this share of bytes decodes are corrupt.
+ */
+const CORRUPT_BELOW_PERCENT: f64 = 50.0;
+
+/**
+ * Directory next to a corrupt recording that it is moved into.
+ */
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/**
+ * Most points each kind of problem takes off the score of 100.
+ */
+const STALL_POINTS: f64 = 5.0; // Per stall
+const MAX_STALL_POINTS: f64 = 20.0;
+const FAILED_POINTS: f64 = 20.0; // The recording ended with an error
+const MISSING_POINTS: f64 = 30.0; // No audio at all for the requested duration
+const UNDECODABLE_POINTS: f64 = 30.0; // Nothing in the file decodes
+const SILENCE_POINTS: f64 = 20.0; // Silent throughout
+
+/**
+ * What decoding a finished MP3 recording found.
+ */
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Probe {
+    pub audio_seconds: f64,     // Of audio that decoded
+    pub decode_errors: u64,     // Places where the decoder lost sync
+    pub decodable_percent: f64, // Of the file's bytes in frames that decoded
+    pub silence_percent: f64,   // Of the seconds decoded
+}
+
+impl Probe {
+    /**
+     * Why the recording is too corrupt to keep in the archive, if it is.
+     */
+    pub fn corruption(&self) -> Option<String> {
+        (self.decodable_percent < CORRUPT_BELOW_PERCENT).then(|| {
+            format!(
+                "only {:.0}% of the file decodes as MP3",
+                self.decodable_percent
+            )
+        })
+    }
+}
+
+/**
+ * Decodes the MP3 file at `path` from start to end. An ID3 tag or a first
+ * frame cut off by the start of the connection counts as one decode error.
+ */
+pub fn probe(path: &Path) -> Result<Probe, RecordingError> {
+    let size = fs::metadata(path)?.len();
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?));
+    let mut mono = Vec::new();
+    let mut probe = Probe::default();
+    let mut frame_bytes = 0.0;
+    let (mut energy, mut samples) = (0.0f64, 0u64); // Of the current second
+    let (mut seconds, mut silent_seconds) = (0u64, 0u64);
+    loop {
+        let frame = match decoder.next_frame() {
+            Ok(frame) => frame,
+            Err(Mp3Error::Eof) | Err(Mp3Error::InsufficientData) => break,
+            Err(Mp3Error::SkippedData) => {
+                probe.decode_errors += 1;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let rate = frame.sample_rate.max(1) as u64;
+        mix_down(&frame, &mut mono);
+        // The frame's length follows from its bitrate, padding aside
+        frame_bytes += mono.len() as f64 * frame.bitrate as f64 * 125.0 / rate as f64;
+        probe.audio_seconds += mono.len() as f64 / rate as f64;
+        for &sample in &mono {
+            energy += (sample * sample) as f64;
+            samples += 1;
+            if samples == rate {
+                seconds += 1;
+                if ((energy / samples as f64).sqrt() as f32) < SILENCE_RMS {
+                    silent_seconds += 1;
+                }
+                (energy, samples) = (0.0, 0);
+            }
+        }
+    }
+    if size > 0 {
+        probe.decodable_percent = (frame_bytes / size as f64 * 100.0).min(100.0);
+    }
+    if seconds > 0 {
+        probe.silence_percent = silent_seconds as f64 / seconds as f64 * 100.0;
+    }
+    Ok(probe)
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * How usable a recording is, from 0 (nothing to listen to) to 100, for its
+ * sidecar. Points are taken off for stalls of the stream, for ending with
+ * an error and, where the audio can be decoded (plain local MP3), for audio
+ * missing from the requested duration, for bytes that do not decode and for
+ * silence. Empty recordings score 0.
+ */
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Quality {
+    pub score: u8,
+    pub stalls: u32, // Waits of `STALL_AFTER` or more for the stream
+    pub stalled_seconds: f64,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub probe: Option<Probe>,
+}
+
+impl Quality {
+    pub fn new(
+        requested_seconds: u64,
+        bytes: u64,
+        failed: bool,
+        stalls: u32,
+        stalled: Duration,
+        probe: Option<Probe>,
+    ) -> Self {
+        let mut score = 100.0;
+        score -= (stalls as f64 * STALL_POINTS).min(MAX_STALL_POINTS);
+        if failed {
+            score -= FAILED_POINTS;
+        }
+        if let Some(probe) = &probe {
+            let complete = match requested_seconds {
+                0 => 1.0,
+                requested => (probe.audio_seconds / requested as f64).min(1.0),
+            };
+            score -= (1.0 - complete) * MISSING_POINTS;
+            score -= (100.0 - probe.decodable_percent) / 100.0 * UNDECODABLE_POINTS;
+            score -= probe.silence_percent / 100.0 * SILENCE_POINTS;
+        }
+        if bytes == 0 || probe.is_some_and(|probe| probe.audio_seconds == 0.0) {
+            score = 0.0;
+        }
+        Quality {
+            score: score.clamp(0.0, 100.0).round() as u8,
+            stalls,
+            stalled_seconds: stalled.as_secs_f64(),
+            probe,
+        }
+    }
+}
+
+/**
+ * Moves the corrupt recording at `recording` into the `quarantine`
+ * directory next to it, along with its sidecar and `companions` (such as
+ * an Opus copy). With `purge`, the recording and its companions are deleted
+ * instead and only the sidecar is moved, to keep the reason. Returns where
+ * the sidecar went.
+ */
+pub fn quarantine(recording: &Path, companions: &[PathBuf], purge: bool) -> io::Result<PathBuf> {
+    let directory = recording
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(QUARANTINE_DIR);
+    fs::create_dir_all(&directory)?;
+    let mut files = vec![recording.to_path_buf()];
+    files.extend(companions.iter().filter(|path| path.exists()).cloned());
+    for file in files {
+        if purge {
+            fs::remove_file(&file)?;
+        } else if let Some(name) = file.file_name() {
+            fs::rename(&file, directory.join(name))?;
+        }
+    }
+    let sidecar = metadata::sidecar_path(recording);
+    let moved = directory.join(sidecar.file_name().unwrap_or_default());
+    fs::rename(&sidecar, &moved)?;
+    Ok(moved)
+}
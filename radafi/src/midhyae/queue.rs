@@ -0,0 +1,268 @@
+use log::{error, info, warn};
+use redis::aio::MultiplexedConnection;
+use redis::streams::{StreamId, StreamReadOptions, StreamReadReply};
+use redis::AsyncCommands;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use std::collections::BTreeMap;
+
+use super::{ChannelId, Listener, RecordingError, Stream, StreamOutcome};
+
+const BLOCK_MILLIS: usize = 5000; // How long one read waits for new jobs
+
+fn default_stream() -> String {
+    "radafi:jobs".to_string()
+}
+
+fn default_group() -> String {
+    "radafi".to_string()
+}
+
+fn default_results() -> String {
+    "radafi:results".to_string()
+}
+
+/**
+ * Redis stream to take recording jobs from, read from a `[redis]` table:
+ *
+ *   [redis]
+ *   url = "redis://queue.local/"
+ *
+ * Jobs are stream entries with the fields `station` (channel ID),
+ * `duration` (seconds), `destination` (output directory, may contain date
+ * tokens such as `%Y`) and optionally `name`, e.g.
+ *
+ *   XADD radafi:jobs * station AbCd1234 duration 3600 destination archive
+ *
+ * Consumers share the work as members of `group`. A job is acknowledged
+ * once recorded and its outcome added to the `results` stream.
+ */
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RedisOptions {
+    pub url: String,
+    #[serde(default = "default_stream")]
+    pub stream: String,
+    #[serde(default = "default_group")]
+    pub group: String,
+    #[serde(default = "default_results")]
+    pub results: String,
+}
+
+/**
+ * A job entry read from the stream.
+ */
+struct QueuedJob {
+    entry: String, // Stream entry ID, used to acknowledge
+    station: ChannelId,
+    name: Option<String>,
+    duration: u64,
+    destination: String,
+}
+
+impl QueuedJob {
+    fn parse(entry: &StreamId) -> Result<Self, String> {
+        let field = |name: &str| {
+            entry
+                .get::<String>(name)
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| format!("missing field {}", name))
+        };
+        let station = field("station")?.parse()?;
+        let duration = field("duration")?;
+        Ok(QueuedJob {
+            entry: entry.id.clone(),
+            station,
+            name: entry.get("name"),
+            duration: duration
+                .parse()
+                .map_err(|_| format!("invalid duration {}", duration))?,
+            destination: field("destination")?,
+        })
+    }
+}
+
+impl Listener {
+    /**
+     * Records jobs from the Redis stream in `options` as consumer
+     * `consumer` until an error occurs, at most `concurrency` at a time.
+     * Jobs this consumer had read but not acknowledged, e.g. because it
+     * crashed, are recorded first, so consumer names should stay the same
+     * across restarts.
+     */
+    pub async fn consume(
+        &self,
+        options: &RedisOptions,
+        consumer: &str,
+    ) -> Result<(), RecordingError> {
+        let client = redis::Client::open(options.url.as_str())?;
+        let mut connection = client.get_multiplexed_tokio_connection().await?;
+        let created: Result<(), _> = connection
+            .xgroup_create_mkstream(&options.stream, &options.group, "0")
+            .await;
+        match created {
+            Ok(()) => info!(
+                "Created consumer group {} on {}",
+                options.group, options.stream
+            ),
+            Err(e) if e.code() == Some("BUSYGROUP") => {}
+            Err(e) => return Err(e.into()),
+        }
+        info!(
+            "Waiting for jobs on {} as {} in group {}",
+            options.stream, consumer, options.group
+        );
+
+        // Entry ID "0" rereads our pending entries, ">" asks for new ones
+        let mut backlog = true;
+        loop {
+            let mut read = StreamReadOptions::default()
+                .group(&options.group, consumer)
+                .count(self.concurrency);
+            if !backlog {
+                read = read.block(BLOCK_MILLIS);
+            }
+            let start = if backlog { "0" } else { ">" };
+            let reply: Option<StreamReadReply> = connection
+                .xread_options(&[&options.stream], &[start], &read)
+                .await?;
+            let entries: Vec<StreamId> = reply
+                .into_iter()
+                .flat_map(|reply| reply.keys)
+                .flat_map(|key| key.ids)
+                .collect();
+            if entries.is_empty() {
+                backlog = false;
+                continue;
+            }
+            self.run_queued(&mut connection, options, entries).await?;
+        }
+    }
+
+    /**
+     * Records a batch of entries, one `record_streams` call per destination,
+     * publishing and acknowledging each one as its recording finishes.
+     */
+    async fn run_queued(
+        &self,
+        connection: &mut MultiplexedConnection,
+        options: &RedisOptions,
+        entries: Vec<StreamId>,
+    ) -> Result<(), RecordingError> {
+        let mut by_destination: BTreeMap<String, Vec<QueuedJob>> = BTreeMap::new();
+        for entry in &entries {
+            match QueuedJob::parse(entry) {
+                Ok(job) => by_destination
+                    .entry(job.destination.clone())
+                    .or_default()
+                    .push(job),
+                Err(e) => {
+                    warn!("Dropping job {}: {}", entry.id, e);
+                    let fields = [("job", entry.id.clone()), ("error", e)];
+                    finish_job(connection, options, &entry.id, &fields).await?;
+                }
+            }
+        }
+
+        for (destination, jobs) in by_destination {
+            let streams: Vec<Stream> = jobs
+                .iter()
+                .map(|job| Stream {
+                    duration: Some(job.duration),
+                    ..self.channel_stream(
+                        &job.station,
+                        job.name.as_deref().unwrap_or(job.station.as_str()),
+                    )
+                })
+                .collect();
+            info!("Recording {} queued jobs into {}", jobs.len(), destination);
+            // Each job is acknowledged as soon as its recording finishes
+            let (done, mut finished) = mpsc::unbounded_channel();
+            let recording = self.record_streams_each(
+                &streams,
+                jobs[0].duration,
+                &destination,
+                move |outcome: &StreamOutcome| {
+                    let _ = done.send(outcome.clone());
+                },
+            );
+            let acknowledging = async {
+                let mut pending = jobs;
+                while let Some(outcome) = finished.recv().await {
+                    let (covered, rest): (Vec<_>, Vec<_>) = pending
+                        .into_iter()
+                        .partition(|job| outcome.covers(&job.station));
+                    pending = rest;
+                    for job in covered {
+                        let fields = result_fields(&job, Ok(&outcome));
+                        finish_job(connection, options, &job.entry, &fields).await?;
+                    }
+                }
+                Ok::<_, RecordingError>(pending)
+            };
+            let (recorded, pending) = tokio::join!(recording, acknowledging);
+            let failure = match recorded {
+                Ok(_) => "not recorded".to_string(),
+                Err(e) => {
+                    error!("Failed to record into {}: {}", destination, e);
+                    e.to_string()
+                }
+            };
+            for job in pending? {
+                let fields = result_fields(&job, Err(&failure));
+                finish_job(connection, options, &job.entry, &fields).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Adds a result entry for the job and acknowledges it.
+ */
+async fn finish_job(
+    connection: &mut MultiplexedConnection,
+    options: &RedisOptions,
+    entry: &str,
+    fields: &[(&str, String)],
+) -> Result<(), RecordingError> {
+    let _: String = connection.xadd(&options.results, "*", fields).await?;
+    let _: usize = connection
+        .xack(&options.stream, &options.group, &[entry])
+        .await?;
+    Ok(())
+}
+
+/**
+ * Fields of the result entry for `job`, or why its destination could not be
+ * recorded into at all.
+ */
+fn result_fields(
+    job: &QueuedJob,
+    outcome: Result<&StreamOutcome, &str>,
+) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("job", job.entry.clone()),
+        ("station", job.station.to_string()),
+        ("destination", job.destination.clone()),
+    ];
+    match outcome {
+        Ok(outcome) => {
+            fields.push(("ok", outcome.ok().to_string()));
+            fields.push(("bytes", outcome.bytes.to_string()));
+            if let Some(category) = outcome.failure_category() {
+                fields.push(("category", category.name().to_string()));
+                fields.push(("code", category.code().name().to_string()));
+            }
+            if let Some(error) = &outcome.error {
+                fields.push(("error", error.clone()));
+            }
+        }
+        Err(failure) => {
+            fields.push(("ok", "false".to_string()));
+            fields.push(("error", failure.to_string()));
+        }
+    }
+    fields
+}
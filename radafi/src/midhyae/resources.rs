@@ -0,0 +1,82 @@
+use super::RecordingError;
+
+/**
+ * File descriptors a recording may hold at once: its connection and one
+ * being opened on retry, the file it writes, its Opus copy and sidecar,
+ * the pipes of a post-processing command and the descriptors of its own
+ * runtime.
+ */
+#[cfg(unix)]
+const FDS_PER_RECORDING: u64 = 16;
+
+/**
+ * File descriptors kept for everything else: standard streams, the
+ * journal, the lock file, API connections and idle pooled connections.
+ */
+#[cfg(unix)]
+const FDS_RESERVED: u64 = 64;
+
+/**
+ * How many of `recordings` can run at once within the limit on open files,
+ * raising the soft limit up to the hard one if needed. Returns fewer than
+ * `recordings` if even the hard limit is too low, so the rest wait for a
+ * slot rather than failing with "too many open files" midway. Fails if
+ * not a single recording fits.
+ */
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // rlim_t is not 64 bits everywhere
+pub fn fit_recordings(recordings: usize) -> Result<usize, RecordingError> {
+    let needed = FDS_RESERVED + recordings as u64 * FDS_PER_RECORDING;
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid rlimit for getrlimit and setrlimit to use
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        log::warn!(
+            "Could not read the open file limit: {}",
+            std::io::Error::last_os_error()
+        );
+        return Ok(recordings);
+    }
+    let mut available = limit.rlim_cur as u64;
+    if available < needed {
+        let raised = libc::rlimit {
+            rlim_cur: needed.min(limit.rlim_max as u64) as libc::rlim_t,
+            rlim_max: limit.rlim_max,
+        };
+        // SAFETY: as above
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } == 0 {
+            log::info!(
+                "Raised the open file limit from {} to {}",
+                available,
+                raised.rlim_cur
+            );
+            available = raised.rlim_cur as u64;
+        }
+    }
+    let fit = (available.saturating_sub(FDS_RESERVED) / FDS_PER_RECORDING) as usize;
+    if fit == 0 {
+        return Err(RecordingError::Resources(format!(
+            "the limit of {} open files leaves no room for a recording; raise it with ulimit -n {}",
+            available,
+            FDS_RESERVED + FDS_PER_RECORDING
+        )));
+    }
+    if fit < recordings {
+        log::warn!(
+            "The limit of {} open files allows {} recordings at a time instead of {}; raise it with ulimit -n {} to run them all",
+            available,
+            fit,
+            recordings,
+            needed
+        );
+        return Ok(fit);
+    }
+    Ok(recordings)
+}
+
+#[cfg(not(unix))]
+pub fn fit_recordings(recordings: usize) -> Result<usize, RecordingError> {
+    Ok(recordings)
+}
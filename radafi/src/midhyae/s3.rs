@@ -0,0 +1,386 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use reqwest::{Client, Method};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use std::env;
+
+use super::RecordingError;
+
+/**
+ * Parts are uploaded once this much audio has been buffered. S3 requires
+ * every part except the last to be at least 5 MiB.
+ */
+pub const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/**
+ * ----------------------------------------------------------------------------
+ * An S3 (or S3-compatible) bucket that recordings are streamed into,
+ * configured from an `s3://bucket/prefix` URL plus the usual AWS environment
+ * variables: AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, AWS_SESSION_TOKEN,
+ * AWS_REGION and, for other providers, AWS_ENDPOINT_URL.
+ */
+pub struct S3Target {
+    url: String, // The s3:// URL the target was configured from
+    bucket: String,
+    prefix: String,
+    region: String,
+    endpoint: Option<String>, // Custom endpoint, addressed path-style
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl S3Target {
+    pub fn from_url(target: &str) -> Result<Self, RecordingError> {
+        let invalid = |reason: &str| RecordingError::Upload(format!("{}: {}", reason, target));
+
+        let url = Url::parse(target).map_err(|_| invalid("invalid upload URL"))?;
+        if url.scheme() != "s3" {
+            return Err(invalid("upload URL must start with s3://"));
+        }
+        let bucket = url
+            .host_str()
+            .ok_or_else(|| invalid("upload URL needs a bucket"))?
+            .to_string();
+        let prefix = url.path().trim_start_matches('/').to_string();
+
+        let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+        let access_key = var("AWS_ACCESS_KEY_ID")
+            .ok_or_else(|| RecordingError::Upload("AWS_ACCESS_KEY_ID is not set".to_string()))?;
+        let secret_key = var("AWS_SECRET_ACCESS_KEY").ok_or_else(|| {
+            RecordingError::Upload("AWS_SECRET_ACCESS_KEY is not set".to_string())
+        })?;
+
+        Ok(S3Target {
+            url: target.to_string(),
+            bucket,
+            prefix,
+            region: var("AWS_REGION")
+                .or_else(|| var("AWS_DEFAULT_REGION"))
+                .unwrap_or_else(|| "us-east-1".to_string()),
+            endpoint: var("AWS_ENDPOINT_URL").map(|e| e.trim_end_matches('/').to_string()),
+            access_key,
+            secret_key,
+            session_token: var("AWS_SESSION_TOKEN"),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /**
+     * Object key for a recording at `relative` within the upload prefix.
+     */
+    pub fn key(&self, relative: &str) -> String {
+        let relative = relative.trim_start_matches('/');
+        if self.prefix.is_empty() {
+            relative.to_string()
+        } else {
+            format!("{}/{}", self.prefix.trim_end_matches('/'), relative)
+        }
+    }
+
+    /**
+     * Returns the host and canonical path of an object, plus its full URL.
+     */
+    fn locate(&self, key: &str) -> (String, String, String) {
+        let path = format!("/{}", uri_encode(key, false));
+        match &self.endpoint {
+            Some(endpoint) => {
+                let host = endpoint.split("://").nth(1).unwrap_or(endpoint);
+                let path = format!("/{}{}", self.bucket, path);
+                let url = format!("{}{}", endpoint, path);
+                (host.to_string(), path, url)
+            }
+            None => {
+                let host = format!("{}.s3.{}.amazonaws.com", self.bucket, self.region);
+                let url = format!("https://{}{}", host, path);
+                (host, path, url)
+            }
+        }
+    }
+
+    /**
+     * Sends a request signed with AWS Signature Version 4. `query` must
+     * already be in canonical (sorted, encoded) form.
+     */
+    async fn send(
+        &self,
+        client: &Client,
+        method: Method,
+        key: &str,
+        query: &str,
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response, RecordingError> {
+        let (host, path, url) = self.locate(key);
+        let url = if query.is_empty() {
+            url
+        } else {
+            format!("{}?{}", url, query)
+        };
+
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex(&Sha256::digest(&body));
+
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method, path, query, canonical_headers, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut signing_key = hmac(format!("AWS4{}", self.secret_key).as_bytes(), &date);
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            signing_key = hmac(&signing_key, part);
+        }
+        let signature = hex(&hmac(&signing_key, &string_to_sign));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature
+        );
+
+        let mut request = client
+            .request(method, url)
+            .header("authorization", authorization)
+            .body(body);
+        for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+            request = request.header(name, value);
+        }
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(RecordingError::Upload(format!(
+                "S3 returned {}: {}",
+                status, text
+            )));
+        }
+        Ok(response)
+    }
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * An in-progress multipart upload of one recording. Parts are sent as they
+ * fill up, so only `PART_SIZE` bytes of audio are ever held locally.
+ */
+pub struct MultipartUpload {
+    key: String,
+    upload_id: String,
+    etags: Vec<String>, // ETag of each uploaded part, in order
+    buffer: Vec<u8>,
+}
+
+impl MultipartUpload {
+    pub async fn start(
+        client: &Client,
+        target: &S3Target,
+        key: String,
+    ) -> Result<Self, RecordingError> {
+        let response = target
+            .send(client, Method::POST, &key, "uploads=", Vec::new())
+            .await?;
+        let body = response.text().await?;
+        let upload_id = xml_value(&body, "UploadId")
+            .ok_or_else(|| RecordingError::Upload("missing UploadId in response".to_string()))?;
+        info!("Started upload of s3://{}/{}", target.bucket, key);
+
+        Ok(MultipartUpload {
+            key,
+            upload_id,
+            etags: Vec::new(),
+            buffer: Vec::with_capacity(PART_SIZE),
+        })
+    }
+
+    /**
+     * Continues an upload started earlier, e.g. by an interrupted run, whose
+     * parts so far have the given ETags.
+     */
+    pub fn resume(key: String, upload_id: String, etags: Vec<String>) -> Self {
+        MultipartUpload {
+            key,
+            upload_id,
+            etags,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn upload_id(&self) -> &str {
+        &self.upload_id
+    }
+
+    /**
+     * ETags of the parts uploaded so far, in order.
+     */
+    pub fn etags(&self) -> &[String] {
+        &self.etags
+    }
+
+    /**
+     * Buffers `chunk`, uploading a part whenever enough data has accumulated.
+     */
+    pub async fn write(
+        &mut self,
+        client: &Client,
+        target: &S3Target,
+        chunk: &[u8],
+    ) -> Result<(), RecordingError> {
+        self.buffer.extend_from_slice(chunk);
+        if self.buffer.len() >= PART_SIZE {
+            self.flush_part(client, target).await?;
+        }
+        Ok(())
+    }
+
+    async fn flush_part(
+        &mut self,
+        client: &Client,
+        target: &S3Target,
+    ) -> Result<(), RecordingError> {
+        let part = std::mem::replace(&mut self.buffer, Vec::with_capacity(PART_SIZE));
+        let number = self.etags.len() + 1;
+        let query = format!(
+            "partNumber={}&uploadId={}",
+            number,
+            uri_encode(&self.upload_id, true)
+        );
+        let response = target
+            .send(client, Method::PUT, &self.key, &query, part)
+            .await?;
+        let etag = response
+            .headers()
+            .get("etag")
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| RecordingError::Upload("missing ETag for uploaded part".to_string()))?
+            .to_string();
+        self.etags.push(etag);
+        Ok(())
+    }
+
+    /**
+     * Uploads any remaining data and assembles the object. An upload that
+     * received no data is aborted instead, since S3 rejects empty uploads.
+     */
+    pub async fn finish(
+        mut self,
+        client: &Client,
+        target: &S3Target,
+    ) -> Result<(), RecordingError> {
+        if !self.buffer.is_empty() {
+            self.flush_part(client, target).await?;
+        }
+        if self.etags.is_empty() {
+            return self.abort(client, target).await;
+        }
+
+        let parts: String = self
+            .etags
+            .iter()
+            .enumerate()
+            .map(|(i, etag)| {
+                format!(
+                    "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                    i + 1,
+                    etag
+                )
+            })
+            .collect();
+        let body = format!(
+            "<CompleteMultipartUpload>{}</CompleteMultipartUpload>",
+            parts
+        );
+        let query = format!("uploadId={}", uri_encode(&self.upload_id, true));
+        target
+            .send(client, Method::POST, &self.key, &query, body.into_bytes())
+            .await?;
+        info!(
+            "Completed upload of s3://{}/{} in {} parts",
+            target.bucket,
+            self.key,
+            self.etags.len()
+        );
+        Ok(())
+    }
+
+    /**
+     * Discards the upload and any parts already stored.
+     */
+    pub async fn abort(self, client: &Client, target: &S3Target) -> Result<(), RecordingError> {
+        warn!("Aborting upload of s3://{}/{}", target.bucket, self.key);
+        let query = format!("uploadId={}", uri_encode(&self.upload_id, true));
+        target
+            .send(client, Method::DELETE, &self.key, &query, Vec::new())
+            .await?;
+        Ok(())
+    }
+}
+
+/**
+ * Percent-encodes everything but unreserved characters, as SigV4 requires.
+ * Slashes are kept when encoding object keys.
+ */
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char)
+            }
+            b'/' if !encode_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/**
+ * Extracts the text of the first `<tag>` element from an XML response.
+ */
+fn xml_value(body: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = body.find(&open)? + open.len();
+    let end = body[start..].find(&close)? + start;
+    Some(body[start..end].to_string())
+}
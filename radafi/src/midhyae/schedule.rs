@@ -0,0 +1,394 @@
+use chrono::{DateTime, Datelike, Duration as Span, Local, NaiveTime, Weekday};
+use log::{error, info};
+use serde::Deserialize;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::config::{Config, LiveConfig};
+use super::manager::SessionManager;
+use super::metadata;
+use super::progress::Progress;
+use super::session::create_session_dir;
+use super::store::{Run, Store};
+use super::{ChannelId, Listener, RecordingError};
+
+/**
+ * Longest the scheduler sleeps before looking at the clock again, so
+ * clock changes and daylight saving time are noticed.
+ */
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/**
+ * Country that scheduled captures are counted under in the store, as a
+ * station's country is not known without discovering it.
+ */
+const SCHEDULED_COUNTRY: &str = "scheduled";
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/**
+ * A weekly show in a station's program guide, such as `Mon-Fri 07:00-08:00`,
+ * `Sat,Sun 10:00-11:30` or `daily 23:30-00:30`. Shows ending at or before
+ * their start time end on the next day.
+ */
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Show {
+    days: [bool; 7], // Monday first
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl FromStr for Show {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| format!("invalid show {}: {}", s, reason);
+        let (days, times) = s
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| invalid("expected e.g. Mon-Fri 07:00-08:00"))?;
+        let (start, end) = times
+            .trim()
+            .split_once('-')
+            .ok_or_else(|| invalid("expected a time range such as 07:00-08:00"))?;
+        let time = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|_| invalid(&format!("invalid time {}", time.trim())))
+        };
+        let show = Show {
+            days: parse_days(days).map_err(|reason| invalid(&reason))?,
+            start: time(start)?,
+            end: time(end)?,
+        };
+        Ok(show)
+    }
+}
+
+impl TryFrom<String> for Show {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Show {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.days == [true; 7] {
+            return write!(
+                f,
+                "daily {}-{}",
+                self.start.format("%H:%M"),
+                self.end.format("%H:%M")
+            );
+        }
+        let days: Vec<String> = WEEKDAYS
+            .iter()
+            .zip(self.days)
+            .filter(|(_, on)| *on)
+            .map(|(day, _)| day.to_string())
+            .collect();
+        write!(
+            f,
+            "{} {}-{}",
+            days.join(","),
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+/**
+ * Days such as `Mon-Fri`, `Sat,Sun`, `Fri-Mon` or `daily`.
+ */
+fn parse_days(days: &str) -> Result<[bool; 7], String> {
+    let mut set = [false; 7];
+    if days.eq_ignore_ascii_case("daily") {
+        return Ok([true; 7]);
+    }
+    let day = |name: &str| {
+        let name = name.trim();
+        WEEKDAYS
+            .iter()
+            .position(|day| {
+                name.get(..3)
+                    .is_some_and(|short| day.to_string().eq_ignore_ascii_case(short))
+            })
+            .ok_or_else(|| format!("unknown day {}", name))
+    };
+    for item in days.split(',') {
+        let (first, last) = match item.split_once('-') {
+            Some((first, last)) => (day(first)?, day(last)?),
+            None => (day(item)?, day(item)?),
+        };
+        // Ranges may wrap around the end of the week
+        let mut index = first;
+        loop {
+            set[index] = true;
+            if index == last {
+                break;
+            }
+            index = (index + 1) % 7;
+        }
+    }
+    Ok(set)
+}
+
+impl Show {
+    /**
+     * Length of each airing.
+     */
+    pub fn length(&self) -> Span {
+        let length = self.end - self.start;
+        if length <= Span::zero() {
+            length + Span::days(1)
+        } else {
+            length
+        }
+    }
+
+    /**
+     * The next airing that has not ended by `after`, as its start and end.
+     * An airing in progress starts at `after`.
+     */
+    pub fn next(&self, after: DateTime<Local>) -> Option<(DateTime<Local>, DateTime<Local>)> {
+        // Airings that started the day before may still be on
+        (-1..=7)
+            .filter_map(|offset| {
+                let date = after.date_naive() + Span::days(offset);
+                if !self.days[date.weekday().num_days_from_monday() as usize] {
+                    return None;
+                }
+                // Start times skipped by daylight saving time do not air
+                let start = date
+                    .and_time(self.start)
+                    .and_local_timezone(Local)
+                    .earliest()?;
+                let end = start + self.length();
+                (end > after).then_some((start.max(after), end))
+            })
+            .min()
+    }
+}
+
+/**
+ * A show of a station, with when it was last recorded until.
+ */
+type Scheduled = (ChannelId, Show, DateTime<Local>);
+
+/**
+ * The shows in the schedules of `config`, keeping how far each was
+ * recorded from `previous`.
+ */
+fn scheduled(config: &Config, previous: &[Scheduled]) -> Vec<Scheduled> {
+    let mut shows: Vec<Scheduled> = config
+        .stations
+        .iter()
+        .flat_map(|(id, station)| {
+            station.schedule.iter().map(move |show| {
+                let after = previous
+                    .iter()
+                    .find(|(other, other_show, _)| other == id && other_show == show)
+                    .map_or_else(Local::now, |(.., after)| *after);
+                (id.clone(), *show, after)
+            })
+        })
+        .collect();
+    shows.sort_by(|a, b| a.0.cmp(&b.0));
+    shows
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Records the shows listed in the `schedule` of each station in `config`,
+ * forever: each airing is recorded into a session directory of its own
+ * below `root`, as it airs. Captures that overlap run side by side, as far
+ * as the limits of `config` allow; the others wait for a slot. Captures are
+ * counted in the store under the country `scheduled`. Signals apply to
+ * every capture in progress, see `Progress::follow_signals`, and SIGHUP
+ * reloads `config`, schedules included, for the captures started
+ * afterwards.
+ */
+pub async fn record_schedule(root: &Path, config: LiveConfig) -> Result<(), RecordingError> {
+    let mut current = config.get();
+    let mut shows = scheduled(&current, &[]);
+    if shows.is_empty() {
+        return Err(RecordingError::Config(
+            "no station has a schedule to record".to_string(),
+        ));
+    }
+    let manager = Arc::new(SessionManager::new(
+        "http://radio.garden/api/ara/content/",
+        &current.http,
+        config.limits().budget(),
+    )?);
+    let config = Arc::new(config);
+    let mut changes = config.subscribe();
+    let store = Arc::new(Mutex::new(()));
+    let active: Arc<Mutex<Vec<Arc<Progress>>>> = Arc::default();
+    let mut announced = None;
+
+    #[cfg(unix)]
+    {
+        let active = Arc::clone(&active);
+        tokio::spawn(async move {
+            let watched = move || active.lock().unwrap().clone();
+            if let Err(e) = super::progress::follow_signals(watched).await {
+                log::warn!("Following signals is unavailable: {}", e);
+            }
+        });
+        let config = Arc::clone(&config);
+        let manager = Arc::clone(&manager);
+        tokio::spawn(async move {
+            if let Err(e) = config.reload_on_signal(manager.budget()).await {
+                log::warn!("Reloading on signals is unavailable: {}", e);
+            }
+        });
+    }
+
+    loop {
+        let latest = config.get();
+        if !Arc::ptr_eq(&latest, &current) {
+            shows = scheduled(&latest, &shows);
+            current = latest;
+            announced = None;
+        }
+        // `after` is when the show was last recorded until
+        let now = Local::now();
+        let next = shows
+            .iter_mut()
+            .filter_map(|(id, show, after)| {
+                let (start, end) = show.next(now.max(*after))?;
+                Some((start, end, &*id, *show, after))
+            })
+            .min_by_key(|(start, ..)| *start);
+        let (start, end, id, show, after) = match next {
+            Some(next) => next,
+            None => {
+                info!("Nothing to record; waiting for the configuration to change");
+                let _ = changes.changed().await;
+                continue;
+            }
+        };
+        if start > now {
+            if announced != Some(start) {
+                info!(
+                    "Next capture: {} ({}) at {}",
+                    id,
+                    show,
+                    start.format("%a %Y-%m-%d %H:%M")
+                );
+                announced = Some(start);
+            }
+            let wait = (start - now).to_std().unwrap_or_default();
+            tokio::select! {
+                _ = tokio::time::sleep(wait.min(MAX_SLEEP)) => {}
+                _ = changes.changed() => {}
+            }
+            continue;
+        }
+
+        *after = end;
+        let seconds = (end - now).num_seconds().max(1) as u64;
+        info!("Recording {} ({}) until {}", id, show, end.format("%H:%M"));
+        let capture = Capture {
+            id: id.clone(),
+            seconds,
+            root: root.to_path_buf(),
+            config: Arc::clone(&current),
+            store: Arc::clone(&store),
+        };
+        let listener = manager.listener();
+        let progress = listener.progress();
+        active.lock().unwrap().push(Arc::clone(&progress));
+        let active = Arc::clone(&active);
+        thread::spawn(move || {
+            capture.run(listener);
+            active
+                .lock()
+                .unwrap()
+                .retain(|other| !Arc::ptr_eq(other, &progress));
+        });
+    }
+}
+
+/**
+ * One airing to record.
+ */
+struct Capture {
+    id: ChannelId,
+    seconds: u64,
+    root: PathBuf,
+    config: Arc<Config>,
+    store: Arc<Mutex<()>>, // Held while the store is updated
+}
+
+impl Capture {
+    /**
+     * Records the airing on the current thread.
+     */
+    fn run(self, mut listener: Listener) {
+        let directory = match create_session_dir(&self.root) {
+            Ok(directory) => directory,
+            Err(e) => {
+                error!("Failed to create a directory for {}: {}", self.id, e);
+                return;
+            }
+        };
+        if let Some(session) = directory.file_name() {
+            listener.set_session(&session.to_string_lossy());
+        }
+        listener.set_drop_when_paused(self.config.drop_connection_when_paused);
+        listener.set_staging_dir(self.config.staging_dir.clone());
+        if let Some(size) = self.config.write_buffer {
+            listener.set_write_buffer(size.0);
+        }
+        listener.set_tags(self.config.tags.clone());
+        listener.add_stream(listener.channel_stream(&self.id, self.id.as_str()));
+        listener.apply_overrides(&self.config);
+        // The show decides how long to record, not the station's duration
+        if let Some(mut stream) = listener.remove_stream(&self.id) {
+            stream.duration = None;
+            listener.add_stream(stream);
+        }
+
+        let rt = tokio::runtime::Runtime::new().expect("Failed to create a runtime");
+        let mut run = Run::new(SCHEDULED_COUNTRY, self.seconds, SystemTime::now());
+        match rt.block_on(listener.record_streams(
+            &listener.streams(),
+            self.seconds,
+            &directory.to_string_lossy(),
+        )) {
+            Ok(outcomes) => run.outcomes = outcomes,
+            Err(e) => error!("Failed to record {}: {}", self.id, e),
+        }
+        info!(
+            "Finished capture of {} into {}",
+            self.id,
+            directory.display()
+        );
+        if let Err(e) = metadata::write_summary(&directory, &run) {
+            error!("Failed to write summary: {}", e);
+        }
+
+        let _guard = self.store.lock().unwrap();
+        let store_path = Store::default_path();
+        if let Err(e) = Store::append_run(&store_path, run) {
+            error!("Failed to save store {}: {}", store_path.display(), e);
+        }
+    }
+}
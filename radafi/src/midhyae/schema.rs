@@ -0,0 +1,111 @@
+use serde::de::Error as _;
+use serde::Deserialize;
+
+use std::fmt;
+
+/**
+ * Layouts of the channel list of a place that the Radio Garden API has
+ * been seen to use. Each is adapted to the same list of links.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelLayout {
+    Pages, // data.content[].items[].page, the current layout
+    Items, // data.content[].items[], with the link on the item itself
+    List,  // data.list[], without sections
+}
+
+impl fmt::Display for ChannelLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ChannelLayout::Pages => "data.content[].items[].page",
+            ChannelLayout::Items => "data.content[].items[]",
+            ChannelLayout::List => "data.list[]",
+        })
+    }
+}
+
+/**
+ * A channel listed for a place: its page URL, such as
+ * "/listen/radio-name/AbCd1234", and its title.
+ */
+#[derive(Deserialize, Debug, Clone)]
+pub struct ChannelLink {
+    #[serde(alias = "href")]
+    pub url: String,
+    pub title: String,
+}
+
+/**
+ * The channels of a place and the layout they were found in.
+ */
+#[derive(Debug)]
+pub struct ChannelPage {
+    pub layout: ChannelLayout,
+    pub links: Vec<ChannelLink>,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    data: Data,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Data {
+    Sections { content: Vec<Section> },
+    List { list: Vec<ChannelLink> },
+}
+
+#[derive(Deserialize)]
+struct Section {
+    #[serde(default)]
+    items: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    Page { page: ChannelLink },
+    Link(ChannelLink),
+}
+
+/**
+ * Parses the response of the `page/<place>/channels` API call in whichever
+ * of the known layouts it comes. Sections mixing both kinds of item count
+ * as `Items`.
+ */
+pub fn parse_channels(body: &[u8]) -> serde_json::Result<ChannelPage> {
+    let response: Response = serde_json::from_slice(body).map_err(|e| {
+        if e.is_data() {
+            serde_json::Error::custom(format!(
+                "channels in an unknown layout, expected {}, {} or {}",
+                ChannelLayout::Pages,
+                ChannelLayout::Items,
+                ChannelLayout::List
+            ))
+        } else {
+            e
+        }
+    })?;
+    let sections = match response.data {
+        Data::List { list } => {
+            return Ok(ChannelPage {
+                layout: ChannelLayout::List,
+                links: list,
+            })
+        }
+        Data::Sections { content } => content,
+    };
+    let mut layout = ChannelLayout::Pages;
+    let mut links = Vec::new();
+    for entry in sections.into_iter().flat_map(|section| section.items) {
+        match entry {
+            Entry::Page { page } => links.push(page),
+            Entry::Link(link) => {
+                layout = ChannelLayout::Items;
+                links.push(link);
+            }
+        }
+    }
+    Ok(ChannelPage { layout, links })
+}
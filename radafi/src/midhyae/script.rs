@@ -0,0 +1,157 @@
+use log::info;
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+
+use std::path::Path;
+
+use super::{template, Listener, RecordingError, Stream};
+
+/**
+ * Name of the function a filter script must define.
+ */
+const FILTER_FN: &str = "filter";
+
+/**
+ * What a filter script decided about one station.
+ */
+struct Decision {
+    keep: bool,
+    duration: Option<u64>,
+    filename: Option<String>,
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * A Rhai script deciding which discovered stations to record. It defines
+ *
+ *   fn filter(station) { ... }
+ *
+ * which is called with a map of the station's `id`, `name`, `url`,
+ * `favorite`, `places` (an array of maps with `id` and `title`) and any
+ * `duration` and `filename` overrides already applied, and returns either
+ * `true`/`false` or a map with `keep` and optional `duration` (seconds) and
+ * `filename` (template) overrides. Returning nothing keeps the station.
+ */
+pub struct FilterScript {
+    engine: Engine,
+    ast: AST,
+}
+
+impl FilterScript {
+    pub fn load(path: &Path) -> Result<Self, RecordingError> {
+        let engine = Engine::new();
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| RecordingError::Script(format!("{}: {}", path.display(), e)))?;
+        if !ast.iter_functions().any(|f| f.name == FILTER_FN) {
+            return Err(RecordingError::Script(format!(
+                "{}: no `{}` function defined",
+                path.display(),
+                FILTER_FN
+            )));
+        }
+        Ok(FilterScript { engine, ast })
+    }
+
+    fn decide(&self, stream: &Stream) -> Result<Decision, String> {
+        let result: Dynamic = self
+            .engine
+            .call_fn(&mut Scope::new(), &self.ast, FILTER_FN, (station(stream),))
+            .map_err(|e| e.to_string())?;
+
+        let mut decision = Decision {
+            keep: true,
+            duration: None,
+            filename: None,
+        };
+        if result.is_unit() {
+            return Ok(decision);
+        }
+        if let Ok(keep) = result.as_bool() {
+            decision.keep = keep;
+            return Ok(decision);
+        }
+        let map = result
+            .try_cast::<Map>()
+            .ok_or("`filter` must return a bool, a map or nothing")?;
+        for (key, value) in map {
+            match key.as_str() {
+                "keep" => {
+                    decision.keep = value.as_bool().map_err(|_| "`keep` must be a bool")?;
+                }
+                "duration" => {
+                    let duration = value
+                        .as_int()
+                        .ok()
+                        .and_then(|d| u64::try_from(d).ok())
+                        .ok_or("`duration` must be a non-negative integer")?;
+                    decision.duration = Some(duration);
+                }
+                "filename" => {
+                    let filename = value
+                        .into_string()
+                        .map_err(|_| "`filename` must be a string")?;
+                    template::validate(&filename)?;
+                    decision.filename = Some(filename);
+                }
+                other => return Err(format!("unknown key `{}` in result", other)),
+            }
+        }
+        Ok(decision)
+    }
+}
+
+/**
+ * The map describing `stream` to the script.
+ */
+fn station(stream: &Stream) -> Map {
+    let places: Array = stream
+        .places
+        .iter()
+        .map(|place| {
+            let mut map = Map::new();
+            map.insert("id".into(), place.id.to_string().into());
+            map.insert("title".into(), place.title.clone().into());
+            Dynamic::from_map(map)
+        })
+        .collect();
+
+    let mut map = Map::new();
+    map.insert("id".into(), stream.id.to_string().into());
+    map.insert("name".into(), stream.name.clone().into());
+    map.insert("url".into(), stream.url.clone().into());
+    map.insert("favorite".into(), stream.favorite.into());
+    map.insert("places".into(), places.into());
+    if let Some(duration) = stream.duration {
+        map.insert("duration".into(), (duration as i64).into());
+    }
+    if let Some(filename) = &stream.filename {
+        map.insert("filename".into(), filename.clone().into());
+    }
+    map
+}
+
+impl Listener {
+    /**
+     * Runs `script` on every stored stream, dropping those it skips and
+     * applying its overrides. Returns the number of streams skipped.
+     */
+    pub fn apply_filter_script(&self, script: &FilterScript) -> Result<usize, RecordingError> {
+        let mut streams = self.stored();
+        let before = streams.len();
+        let mut kept = Vec::with_capacity(before);
+        for mut stream in streams.drain(..) {
+            let decision = script.decide(&stream).map_err(|e| {
+                RecordingError::Script(format!("{} ({}): {}", stream.name, stream.id, e))
+            })?;
+            if !decision.keep {
+                info!("Filter script skipped {}", stream.name);
+                continue;
+            }
+            stream.duration = decision.duration.or(stream.duration);
+            stream.filename = decision.filename.or(stream.filename);
+            kept.push(stream);
+        }
+        *streams = kept;
+        Ok(before - streams.len())
+    }
+}
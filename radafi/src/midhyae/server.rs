@@ -0,0 +1,517 @@
+use axum::body::Body;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::Local;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::io::ReaderStream;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::SystemTime;
+
+use super::browse::content_type;
+use super::config::LiveConfig;
+use super::manager::SessionManager;
+use super::metadata;
+use super::progress::{Progress, ProgressSnapshot};
+use super::session::create_session_dir;
+use super::store::{Run, Store};
+use super::{Config, Listener, RecordingError};
+
+/**
+ * Body of `POST /sessions`.
+ */
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SessionRequest {
+    country: String,
+    duration: u64, // Seconds
+    #[serde(default)]
+    stations: Vec<String>, // Channel IDs or titles; all stations if empty
+    #[serde(default)]
+    time_shift: Option<u64>, // Minutes of audio to keep of each station
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum SessionState {
+    Discovering,
+    Recording,
+    Finished,
+    Cancelled,
+    Failed,
+}
+
+/**
+ * A recording session started through the API. Its ID is the name of its
+ * directory under the server's root.
+ */
+struct Session {
+    id: String,
+    country: String,
+    duration: u64,
+    stations: Vec<String>,
+    started: String, // RFC 3339
+    directory: PathBuf,
+    state: Mutex<(SessionState, Option<String>)>, // State and why it failed
+    progress: Arc<Progress>,
+}
+
+/**
+ * Body of `POST /sessions/<id>/pause`, `/resume` and `/timeshift`, which is
+ * optional.
+ */
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PauseRequest {
+    #[serde(default)]
+    stations: Vec<String>, // Channel IDs; every station if empty
+}
+
+/**
+ * JSON representation of a session.
+ */
+#[derive(Serialize)]
+struct SessionView<'a> {
+    id: &'a str,
+    country: &'a str,
+    duration: u64,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stations: &'a [String],
+    started: &'a str,
+    state: SessionState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(flatten)]
+    progress: ProgressSnapshot,
+}
+
+impl Session {
+    fn view(&self) -> SessionView<'_> {
+        let (state, error) = self.state.lock().unwrap().clone();
+        SessionView {
+            id: &self.id,
+            country: &self.country,
+            duration: self.duration,
+            stations: &self.stations,
+            started: &self.started,
+            state,
+            error,
+            progress: self.progress.snapshot(),
+        }
+    }
+
+    fn set_state(&self, state: SessionState, error: Option<String>) {
+        *self.state.lock().unwrap() = (state, error);
+    }
+
+    /**
+     * Files recorded so far, as paths relative to the session directory.
+     */
+    fn files(&self) -> io::Result<Vec<String>> {
+        let mut files = Vec::new();
+        let mut pending = vec![self.directory.clone()];
+        while let Some(dir) = pending.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                } else if let Ok(relative) = path.strip_prefix(&self.directory) {
+                    let parts: Vec<_> = relative
+                        .components()
+                        .map(|c| c.as_os_str().to_string_lossy())
+                        .collect();
+                    files.push(parts.join("/"));
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * State shared by the request handlers.
+ */
+struct Server {
+    root: PathBuf, // Session directories are created here
+    config: LiveConfig,
+    manager: SessionManager, // Shares the HTTP client and budget between sessions
+    sessions: Mutex<HashMap<String, Arc<Session>>>,
+    store: Mutex<()>, // Serializes updates of the store by finished sessions
+}
+
+type Shared = Arc<Server>;
+
+fn error_response(status: StatusCode, message: impl ToString) -> Response {
+    (status, Json(json!({ "error": message.to_string() }))).into_response()
+}
+
+/**
+ * Serves the REST API on `address`, recording sessions into directories
+ * under `root`:
+ *
+ *   POST   /sessions                    start recording, e.g.
+ *                                       {"country": "Brazil", "duration": 3600}
+ *   GET    /sessions                    list sessions
+ *   GET    /sessions/<id>               state and progress of a session
+ *   DELETE /sessions/<id>               cancel a session
+ *   POST   /sessions/<id>/pause         pause recordings, e.g.
+ *                                       {"stations": ["AbCd1234"]}; all if
+ *                                       there is no body
+ *   POST   /sessions/<id>/resume        resume recordings, likewise
+ *   POST   /sessions/<id>/timeshift     save the last minutes of recordings
+ *                                       into timeshift/, likewise; needs
+ *                                       "time_shift" (minutes) at the start
+ *   GET    /sessions/<id>/files         list recorded files
+ *   GET    /sessions/<id>/files/<path>  download a file
+ *   GET    /sessions/<id>/preview/<channel ID>
+ *                                       listen to a recording in progress
+ *
+ * Sessions run concurrently within the limits of `config`. They are
+ * forgotten when the server stops; their files remain. Access is limited by
+ * the `[server]` table of `config`, see `ServerOptions`. SIGHUP reloads
+ * `config` for the sessions started afterwards.
+ */
+pub async fn serve(
+    address: SocketAddr,
+    root: &Path,
+    config: LiveConfig,
+) -> Result<(), RecordingError> {
+    fs::create_dir_all(root)?;
+    let initial = config.get();
+    let options = initial.server.clone();
+    let manager = SessionManager::new(
+        "http://radio.garden/api/ara/content/",
+        &initial.http,
+        config.limits().budget(),
+    )?;
+    let server = Arc::new(Server {
+        root: root.to_path_buf(),
+        config,
+        manager,
+        sessions: Mutex::new(HashMap::new()),
+        store: Mutex::new(()),
+    });
+    #[cfg(unix)]
+    {
+        let server = Arc::clone(&server);
+        tokio::spawn(async move {
+            let budget = server.manager.budget();
+            if let Err(e) = server.config.reload_on_signal(budget).await {
+                log::warn!("Reloading on signals is unavailable: {}", e);
+            }
+        });
+    }
+    let app = Router::new()
+        .route("/sessions", post(create_session).get(list_sessions))
+        .route("/sessions/:id", get(show_session).delete(cancel_session))
+        .route("/sessions/:id/pause", post(pause_session))
+        .route("/sessions/:id/resume", post(resume_session))
+        .route("/sessions/:id/timeshift", post(save_time_shift))
+        .route("/sessions/:id/files", get(list_files))
+        .route("/sessions/:id/files/*path", get(download_file))
+        .route("/sessions/:id/preview/:station", get(preview))
+        .with_state(server);
+    options.serve(address, app, "the API").await
+}
+
+fn find_session(server: &Server, id: &str) -> Option<Arc<Session>> {
+    server.sessions.lock().unwrap().get(id).cloned()
+}
+
+fn no_such_session() -> Response {
+    error_response(StatusCode::NOT_FOUND, "no such session")
+}
+
+async fn create_session(
+    State(server): State<Shared>,
+    Json(request): Json<SessionRequest>,
+) -> Response {
+    if request.country.trim().is_empty() || request.duration == 0 {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "country and a positive duration are required",
+        );
+    }
+
+    let config = server.config.get();
+    let mut listener = server.manager.listener();
+    listener.add_country_aliases(&config.country_aliases);
+    listener.set_drop_when_paused(config.drop_connection_when_paused);
+    listener.set_staging_dir(config.staging_dir.clone());
+    if let Some(size) = config.write_buffer {
+        listener.set_write_buffer(size.0);
+    }
+    listener.set_time_shift(request.time_shift);
+    let directory = match create_session_dir(&server.root) {
+        Ok(directory) => directory,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    };
+    let id = directory
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    listener.set_session(&id);
+
+    let session = Arc::new(Session {
+        id: id.clone(),
+        country: request.country,
+        duration: request.duration,
+        stations: request.stations,
+        started: Local::now().to_rfc3339(),
+        directory,
+        state: Mutex::new((SessionState::Discovering, None)),
+        progress: listener.progress(),
+    });
+    server
+        .sessions
+        .lock()
+        .unwrap()
+        .insert(id, Arc::clone(&session));
+
+    let response = (StatusCode::CREATED, Json(session.view())).into_response();
+    let server = Arc::clone(&server);
+    thread::spawn(move || record_session(listener, &session, &server, &config));
+    response
+}
+
+/**
+ * Runs a session to completion on the current thread.
+ */
+fn record_session(mut listener: Listener, session: &Session, server: &Server, config: &Config) {
+    info!(
+        "Session {}: recording {} for {} seconds",
+        session.id, session.country, session.duration
+    );
+    let rt = tokio::runtime::Runtime::new().expect("Failed to create a runtime");
+    let mut run = Run::new(&session.country, session.duration, SystemTime::now());
+    let result = rt.block_on(async {
+        listener
+            .store_streams(&session.country)
+            .await
+            .map_err(|e| e.to_string())?;
+        if !session.stations.is_empty() {
+            let stations: Vec<&str> = session.stations.iter().map(String::as_str).collect();
+            listener.filter_stations(&stations);
+        }
+        listener.set_tags(config.tags.clone());
+        listener.apply_overrides(config);
+        if session.progress.is_cancelled() {
+            return Ok(());
+        }
+
+        session.set_state(SessionState::Recording, None);
+        let directory = session.directory.to_string_lossy();
+        run.outcomes = listener
+            .record_streams(&listener.streams(), session.duration, &directory)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    });
+
+    if let Err(e) = metadata::write_summary(&session.directory, &run) {
+        error!("Session {}: failed to write summary: {}", session.id, e);
+    }
+    let _guard = server.store.lock().unwrap();
+    let store_path = Store::default_path();
+    if let Err(e) = Store::append_run(&store_path, run) {
+        error!("Failed to save store {}: {}", store_path.display(), e);
+    }
+
+    match result {
+        Err(e) => {
+            error!("Session {} failed: {}", session.id, e);
+            session.set_state(SessionState::Failed, Some(e));
+        }
+        Ok(()) if session.progress.is_cancelled() => {
+            info!("Session {} cancelled", session.id);
+            session.set_state(SessionState::Cancelled, None);
+        }
+        Ok(()) => {
+            info!("Session {} finished", session.id);
+            session.set_state(SessionState::Finished, None);
+        }
+    }
+}
+
+async fn list_sessions(State(server): State<Shared>) -> Response {
+    let sessions: Vec<Arc<Session>> = server.sessions.lock().unwrap().values().cloned().collect();
+    let mut views: Vec<SessionView> = sessions.iter().map(|s| s.view()).collect();
+    views.sort_by(|a, b| a.id.cmp(b.id));
+    Json(views).into_response()
+}
+
+async fn show_session(State(server): State<Shared>, UrlPath(id): UrlPath<String>) -> Response {
+    match find_session(&server, &id) {
+        Some(session) => Json(session.view()).into_response(),
+        None => no_such_session(),
+    }
+}
+
+async fn cancel_session(State(server): State<Shared>, UrlPath(id): UrlPath<String>) -> Response {
+    let session = match find_session(&server, &id) {
+        Some(session) => session,
+        None => return no_such_session(),
+    };
+    let (state, _) = *session.state.lock().unwrap();
+    if !matches!(state, SessionState::Discovering | SessionState::Recording) {
+        return error_response(StatusCode::CONFLICT, "session is not running");
+    }
+    session.progress.cancel();
+    (StatusCode::ACCEPTED, Json(session.view())).into_response()
+}
+
+async fn pause_session(
+    State(server): State<Shared>,
+    UrlPath(id): UrlPath<String>,
+    request: Option<Json<PauseRequest>>,
+) -> Response {
+    set_paused(&server, &id, request, true)
+}
+
+async fn resume_session(
+    State(server): State<Shared>,
+    UrlPath(id): UrlPath<String>,
+    request: Option<Json<PauseRequest>>,
+) -> Response {
+    set_paused(&server, &id, request, false)
+}
+
+fn set_paused(
+    server: &Server,
+    id: &str,
+    request: Option<Json<PauseRequest>>,
+    pause: bool,
+) -> Response {
+    let session = match find_session(server, id) {
+        Some(session) => session,
+        None => return no_such_session(),
+    };
+    let (state, _) = *session.state.lock().unwrap();
+    if !matches!(state, SessionState::Discovering | SessionState::Recording) {
+        return error_response(StatusCode::CONFLICT, "session is not running");
+    }
+    let stations = request.map(|Json(r)| r.stations).unwrap_or_default();
+    if pause {
+        session.progress.pause(&stations);
+    } else {
+        session.progress.resume(&stations);
+    }
+    Json(session.view()).into_response()
+}
+
+/**
+ * Saves the time-shift of the session and answers the paths of the clips
+ * within it.
+ */
+async fn save_time_shift(
+    State(server): State<Shared>,
+    UrlPath(id): UrlPath<String>,
+    request: Option<Json<PauseRequest>>,
+) -> Response {
+    let session = match find_session(&server, &id) {
+        Some(session) => session,
+        None => return no_such_session(),
+    };
+    let time_shift = session.progress.time_shift();
+    if !time_shift.is_enabled() {
+        return error_response(StatusCode::CONFLICT, "session has no time-shift");
+    }
+    let stations = request.map(|Json(r)| r.stations).unwrap_or_default();
+    match time_shift.save(&session.directory.join("timeshift"), &stations) {
+        Ok(saved) => {
+            let paths: Vec<String> = saved
+                .iter()
+                .filter_map(|path| path.strip_prefix(&session.directory).ok())
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect();
+            Json(paths).into_response()
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+async fn list_files(State(server): State<Shared>, UrlPath(id): UrlPath<String>) -> Response {
+    let session = match find_session(&server, &id) {
+        Some(session) => session,
+        None => return no_such_session(),
+    };
+    match session.files() {
+        Ok(files) => Json(files).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+async fn download_file(
+    State(server): State<Shared>,
+    UrlPath((id, path)): UrlPath<(String, String)>,
+) -> Response {
+    let session = match find_session(&server, &id) {
+        Some(session) => session,
+        None => return no_such_session(),
+    };
+    // Only serve files listed for the session, so paths cannot escape it
+    match session.files() {
+        Ok(files) if files.contains(&path) => {}
+        Ok(_) => return error_response(StatusCode::NOT_FOUND, "no such file"),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+    let file = match tokio::fs::File::open(session.directory.join(&path)).await {
+        Ok(file) => file,
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    };
+    (
+        [(header::CONTENT_TYPE, content_type(Path::new(&path)))],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response()
+}
+
+/**
+ * Streams the audio of a station as it is recorded, from the moment the
+ * request arrives until the recording ends. Listeners that fall behind skip
+ * ahead rather than slow down the recording.
+ */
+async fn preview(
+    State(server): State<Shared>,
+    UrlPath((id, station)): UrlPath<(String, String)>,
+) -> Response {
+    let session = match find_session(&server, &id) {
+        Some(session) => session,
+        None => return no_such_session(),
+    };
+    let receiver = match session.progress.preview(&station) {
+        Some(receiver) => receiver,
+        None => return error_response(StatusCode::NOT_FOUND, "station is not recording"),
+    };
+    let audio = futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(chunk) => return Some((Ok::<_, io::Error>(chunk), receiver)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    (
+        [
+            (header::CONTENT_TYPE, "audio/mpeg"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        Body::from_stream(audio),
+    )
+        .into_response()
+}
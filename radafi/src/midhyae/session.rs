@@ -0,0 +1,137 @@
+use chrono::Local;
+use log::{info, warn};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::template;
+
+/**
+ * Creates `directory` if needed and checks that files can be written to it,
+ * by writing and removing a probe file.
+ */
+pub fn ensure_writable(directory: &Path) -> io::Result<()> {
+    let probe = directory.join(".radafi-probe");
+    fs::create_dir_all(directory)?;
+    fs::write(&probe, b"radafi")?;
+    fs::remove_file(&probe)
+}
+
+/**
+ * Creates a `YYYY-MM-DD_HHMMSS` directory for this invocation under `root`
+ * and points the `root/latest` symlink at it, so repeated runs never
+ * overwrite each other's recordings. Returns the new directory.
+ */
+pub fn create_session_dir(root: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(root)?;
+
+    let stamp = Local::now().format("%Y-%m-%d_%H%M%S").to_string();
+    let mut name = stamp.clone();
+    let mut attempt = 1;
+    let session = loop {
+        let candidate = root.join(&name);
+        match fs::create_dir(&candidate) {
+            Ok(()) => break candidate,
+            // Two invocations within the same second get distinct suffixes
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                name = format!("{}-{}", stamp, attempt);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    info!("Recording into session directory {}", session.display());
+
+    if let Err(e) = update_latest(root, &name) {
+        warn!(
+            "Failed to update latest symlink in {}: {}",
+            root.display(),
+            e
+        );
+    }
+    Ok(session)
+}
+
+/**
+ * Points `root/latest` at `target` (relative to `root`), keeping the
+ * archive relocatable.
+ */
+fn update_latest(root: &Path, target: &str) -> io::Result<()> {
+    replace_symlink(&root.join("latest"), Path::new(target), true)
+}
+
+/**
+ * Points `<root>/latest/<name>.mp3` (or `.mp3.age` for encrypted recordings)
+ * at `recording`, which lies below `root`, so scripts can always find the
+ * newest recording of a station.
+ */
+pub fn update_latest_recording(root: &Path, name: &str, recording: &Path) -> io::Result<()> {
+    let relative = recording.strip_prefix(root).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is outside {}", recording.display(), root.display()),
+        )
+    })?;
+    let extension = match recording.extension().and_then(|e| e.to_str()) {
+        Some("age") => "mp3.age",
+        Some("opus") => "opus",
+        _ => "mp3",
+    };
+    let dir = root.join("latest");
+    fs::create_dir_all(&dir)?;
+    let target = Path::new("..").join(relative);
+    let link = dir.join(format!("{}.{}", template::safe_name(name), extension));
+    replace_symlink(&link, &target, false)
+}
+
+/**
+ * Atomically replaces the symlink `link` with one to `target`. An existing
+ * file or directory at `link` is left alone.
+ */
+fn replace_symlink(link: &Path, target: &Path, is_dir: bool) -> io::Result<()> {
+    match fs::symlink_metadata(link) {
+        Ok(meta) if !meta.file_type().is_symlink() => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a symlink", link.display()),
+            ))
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    // Create the new link beside the old one and rename it over it, so
+    // readers never find the link missing
+    let mut temporary = link.as_os_str().to_owned();
+    temporary.push(format!(".{}.tmp", std::process::id()));
+    let temporary = PathBuf::from(temporary);
+    let _ = fs::remove_file(&temporary);
+    symlink(target, &temporary, is_dir)?;
+    fs::rename(&temporary, link).inspect_err(|_| {
+        let _ = fs::remove_file(&temporary);
+    })
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path, _is_dir: bool) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path, is_dir: bool) -> io::Result<()> {
+    if is_dir {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_target: &Path, _link: &Path, _is_dir: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symlinks are not supported on this platform",
+    ))
+}
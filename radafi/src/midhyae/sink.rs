@@ -0,0 +1,157 @@
+use age::stream::StreamWriter;
+use log::warn;
+use reqwest::Client;
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+use super::buffer::WriteBuffer;
+use super::encrypt::Encryption;
+use super::journal::{Entry, Journal};
+use super::opus::OpusEncoder;
+use super::s3::{MultipartUpload, S3Target};
+use super::RecordingError;
+
+/**
+ * Destination of the audio of one recording: a local file (optionally
+ * encrypted or encoded to Opus), or an S3 multipart upload that is fed
+ * while the recording is in progress. A sink can also feed an Opus copy
+ * besides; should encoding fail, the copy is given up and the original is
+ * still written. Any sink can be written through a `WriteBuffer`.
+ */
+pub enum Sink {
+    File(File),
+    Encrypted(StreamWriter<File>),
+    Upload {
+        upload: MultipartUpload,
+        target: Arc<S3Target>,
+        client: Client,
+        journal: Option<Arc<Journal>>, // Records the progress of the upload
+    },
+    Encoded(OpusEncoder),                // Only an Opus file
+    Tee(Box<Sink>, Option<OpusEncoder>), // None once the copy has failed
+    Buffered(WriteBuffer),
+}
+
+impl Sink {
+    pub fn create_file(
+        path: &Path,
+        encryption: Option<&Encryption>,
+    ) -> Result<Self, RecordingError> {
+        let file = File::create(path)?;
+        match encryption {
+            Some(encryption) => Ok(Sink::Encrypted(encryption.wrap(file)?)),
+            None => Ok(Sink::File(file)),
+        }
+    }
+
+    pub async fn start_upload(
+        client: &Client,
+        target: Arc<S3Target>,
+        key: String,
+        journal: Option<Arc<Journal>>,
+    ) -> Result<Self, RecordingError> {
+        let upload = MultipartUpload::start(client, &target, key.clone()).await?;
+        if let Some(journal) = &journal {
+            journal.record(&Entry::UploadStarted {
+                target: target.url().to_string(),
+                key,
+                upload_id: upload.upload_id().to_string(),
+            });
+        }
+        Ok(Sink::Upload {
+            upload,
+            target,
+            client: client.clone(),
+            journal,
+        })
+    }
+
+    /**
+     * Also encodes the audio into an Opus copy.
+     */
+    pub fn with_copy(self, encoder: OpusEncoder) -> Self {
+        Sink::Tee(Box::new(self), Some(encoder))
+    }
+
+    /**
+     * Writes through a buffer of `capacity` bytes in a task of its own, see
+     * `WriteBuffer`, whose fill is kept in `level`. Must be called within a
+     * runtime.
+     */
+    pub fn buffered(self, capacity: u64, level: Arc<AtomicU64>) -> Self {
+        Sink::Buffered(WriteBuffer::spawn(self, capacity, level))
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), RecordingError> {
+        match self {
+            Sink::File(file) => Ok(file.write_all(chunk)?),
+            Sink::Encrypted(writer) => Ok(writer.write_all(chunk)?),
+            Sink::Upload {
+                upload,
+                target,
+                client,
+                journal,
+            } => {
+                let parts = upload.etags().len();
+                upload.write(client, target, chunk).await?;
+                if let (Some(journal), Some(etag)) = (journal, upload.etags().get(parts)) {
+                    journal.record(&Entry::PartUploaded {
+                        upload_id: upload.upload_id().to_string(),
+                        part: parts + 1,
+                        etag: etag.clone(),
+                    });
+                }
+                Ok(())
+            }
+            Sink::Encoded(encoder) => encoder.write(chunk).await,
+            Sink::Tee(original, copy) => {
+                Box::pin(original.write(chunk)).await?;
+                if let Some(encoder) = copy {
+                    if let Err(e) = encoder.write(chunk).await {
+                        warn!("Giving up the Opus copy: {}", e);
+                        *copy = None;
+                    }
+                }
+                Ok(())
+            }
+            Sink::Buffered(buffer) => buffer.write(chunk).await,
+        }
+    }
+
+    /**
+     * Flushes the remaining data. For uploads this assembles the object.
+     */
+    pub async fn finish(self) -> Result<(), RecordingError> {
+        match self {
+            Sink::File(mut file) => Ok(file.flush()?),
+            Sink::Encrypted(writer) => Ok(writer.finish()?.flush()?),
+            Sink::Upload {
+                upload,
+                target,
+                client,
+                journal,
+            } => {
+                let upload_id = upload.upload_id().to_string();
+                upload.finish(&client, &target).await?;
+                if let Some(journal) = journal {
+                    journal.record(&Entry::UploadFinished { upload_id });
+                }
+                Ok(())
+            }
+            Sink::Encoded(encoder) => encoder.finish().await,
+            Sink::Tee(original, copy) => {
+                if let Some(encoder) = copy {
+                    if let Err(e) = encoder.finish().await {
+                        warn!("Opus copy failed: {}", e);
+                    }
+                }
+                Box::pin(original.finish()).await
+            }
+            Sink::Buffered(buffer) => Box::pin(buffer.finish()).await,
+        }
+    }
+}
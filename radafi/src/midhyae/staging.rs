@@ -0,0 +1,63 @@
+use log::warn;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::template;
+
+/**
+ * Working directory of one stream's files in progress below the staging
+ * directory `root`, kept apart from those of every other stream and
+ * session.
+ */
+pub fn stream_dir(root: &Path, session: &str, id: &str) -> PathBuf {
+    root.join(template::safe_name(session))
+        .join(template::safe_name(id))
+}
+
+/**
+ * Moves the finished file `staged` to `destination`, along with the files
+ * written next to it under the same stem (its sidecar, cue sheet, Opus
+ * copy and post-processing output). Moving falls back to copying when the
+ * staging directory is on another file system. The stream's working
+ * directory is removed once it is empty.
+ */
+pub fn unstage(staged: &Path, destination: &Path) -> io::Result<()> {
+    let (Some(directory), Some(stem)) = (staged.parent(), staged.file_stem()) else {
+        return move_file(staged, destination);
+    };
+    let target = destination.parent().unwrap_or(Path::new(""));
+    fs::create_dir_all(target)?;
+    let prefix = format!("{}.", stem.to_string_lossy());
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name.to_string_lossy().starts_with(&prefix) && entry.file_type()?.is_file() {
+            move_file(&entry.path(), &target.join(&name))?;
+        }
+    }
+    // Other segments of the stream may still be in progress
+    if fs::remove_dir(directory).is_ok() {
+        if let Some(session) = directory.parent() {
+            let _ = fs::remove_dir(session);
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Renames `from` to `to`, or copies and removes it if they are on
+ * different file systems.
+ */
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::File::open(to)?.sync_all()?;
+    if let Err(e) = fs::remove_file(from) {
+        warn!("Failed to remove staged {}: {}", from.display(), e);
+    }
+    Ok(())
+}
@@ -0,0 +1,569 @@
+use fs2::FileExt;
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{ChannelId, ErrorCategory, ErrorCode, StreamOutcome};
+
+/**
+ * Runs in a row a station must fail hard in to count as dead.
+ */
+const DEAD_AFTER_RUNS: usize = 3;
+
+/**
+ * Distinguishes the temporary files of saves running at once.
+ */
+static SAVES: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * A station the user has marked as a favorite. Favorites are recorded ahead
+ * of other stations and may carry their own recording duration.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Favorite {
+    pub id: ChannelId,         // Radio Garden channel ID
+    pub name: Option<String>,  // Optional human-readable label
+    pub duration: Option<u64>, // Seconds to record instead of the default
+}
+
+/**
+ * One invocation of `record_streams` and the outcome for every station it
+ * attempted.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Run {
+    pub started: u64, // Unix timestamp (seconds) of the start of the run
+    pub country: String,
+    pub duration: u64, // Requested recording duration in seconds
+    pub outcomes: Vec<StreamOutcome>,
+}
+
+impl Run {
+    pub fn new(country: &str, duration: u64, started: SystemTime) -> Self {
+        Run {
+            started: started
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            country: country.to_string(),
+            duration,
+            outcomes: Vec::new(),
+        }
+    }
+
+    /**
+     * Number of failed recordings in this run for each cause.
+     */
+    pub fn failures_by_category(&self) -> BTreeMap<ErrorCategory, usize> {
+        count_failures(&self.outcomes)
+    }
+
+    /**
+     * Number of failed recordings in this run for each error code.
+     */
+    pub fn failures_by_code(&self) -> BTreeMap<ErrorCode, usize> {
+        let mut counts = BTreeMap::new();
+        for code in self.outcomes.iter().filter_map(|o| o.failure_code()) {
+            *counts.entry(code).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /**
+     * Why the run as a whole failed: when stations were attempted and none
+     * was recorded, the most common code among them. Runs with some
+     * recordings succeed even if other stations failed.
+     */
+    pub fn failure_code(&self) -> Option<ErrorCode> {
+        if self.outcomes.is_empty() || self.outcomes.iter().any(|o| o.ok()) {
+            return None;
+        }
+        self.failures_by_code()
+            .into_iter()
+            .max_by_key(|&(code, count)| (count, Reverse(code)))
+            .map(|(code, _)| code)
+    }
+}
+
+fn count_failures<'a, I>(outcomes: I) -> BTreeMap<ErrorCategory, usize>
+where
+    I: IntoIterator<Item = &'a StreamOutcome>,
+{
+    let mut counts = BTreeMap::new();
+    for category in outcomes.into_iter().filter_map(|o| o.failure_category()) {
+        *counts.entry(category).or_insert(0) += 1;
+    }
+    counts
+}
+
+/**
+ * A station that failed hard (see `ErrorCategory::is_hard`) in each of its
+ * last runs, and is skipped by future runs until it is recorded again.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeadStation {
+    pub id: ChannelId,
+    pub name: String,
+    pub since: u64,              // Unix timestamp of the run it was found dead in
+    pub category: ErrorCategory, // Of its last failure
+}
+
+/**
+ * How often a station failed across a window of runs.
+ */
+pub struct Reliability {
+    pub id: ChannelId,
+    pub name: String,
+    pub attempts: usize,
+    pub failures: usize,
+}
+
+impl Reliability {
+    pub fn failure_rate(&self) -> f64 {
+        if self.attempts == 0 {
+            return 0.0;
+        }
+        self.failures as f64 / self.attempts as f64
+    }
+}
+
+/**
+ * What recording totals are grouped by.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageGrouping {
+    Country, // Country of the runs
+    Place,   // First place each station is listed under, with the country
+}
+
+impl FromStr for UsageGrouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "country" => Ok(UsageGrouping::Country),
+            "place" => Ok(UsageGrouping::Place),
+            _ => Err(format!("invalid grouping {}, expected country or place", s)),
+        }
+    }
+}
+
+/**
+ * How much was recorded in one country or place across a window of runs.
+ */
+pub struct Usage {
+    pub name: String,
+    pub recordings: usize, // Attempted, including failed ones
+    pub bytes: u64,
+    pub seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct StoreData {
+    #[serde(default, deserialize_with = "skip_invalid")]
+    favorites: Vec<Favorite>,
+    // Run history of stores written before it moved to its own file
+    #[serde(default, deserialize_with = "skip_invalid", skip_serializing)]
+    runs: Vec<Run>,
+    #[serde(default, deserialize_with = "skip_invalid")]
+    dead: Vec<DeadStation>,
+    // Hard failures in a row of each station, in runs that recorded something
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    strikes: BTreeMap<ChannelId, usize>,
+}
+
+impl StoreData {
+    fn read(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(StoreData::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /**
+     * Revives stations recorded in `run` and marks those that have now
+     * failed hard in `DEAD_AFTER_RUNS` runs in a row. Runs in which nothing
+     * was recorded do not count, as they point at the local network rather
+     * than at the stations.
+     */
+    fn update_dead(&mut self, run: &Run) {
+        if !run.outcomes.iter().any(|o| o.ok()) {
+            return;
+        }
+        for outcome in &run.outcomes {
+            match outcome.failure_category() {
+                None => {
+                    self.strikes.remove(&outcome.id);
+                    self.dead.retain(|dead| dead.id != outcome.id);
+                }
+                Some(category) if category.is_hard() => {
+                    let strikes = self.strikes.entry(outcome.id.clone()).or_insert(0);
+                    *strikes += 1;
+                    if *strikes >= DEAD_AFTER_RUNS
+                        && !self.dead.iter().any(|dead| dead.id == outcome.id)
+                    {
+                        self.dead.push(DeadStation {
+                            id: outcome.id.clone(),
+                            name: outcome.name.clone(),
+                            since: run.started,
+                            category,
+                        });
+                    }
+                }
+                Some(_) => {
+                    self.strikes.remove(&outcome.id);
+                }
+            }
+        }
+    }
+
+    /**
+     * Moves runs kept in the store by earlier versions to the end of the
+     * history, which must be locked.
+     */
+    fn migrate_runs(&mut self, history: &mut File) -> io::Result<()> {
+        if self.runs.is_empty() {
+            return Ok(());
+        }
+        append_runs(history, &self.runs)?;
+        self.strikes.clear();
+        for run in std::mem::take(&mut self.runs) {
+            self.update_dead(&run);
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Reads a list of the store, skipping entries that cannot be read, e.g. a
+ * favorite whose channel ID was edited by hand into an invalid one, so that
+ * one bad entry does not make the whole store unreadable.
+ */
+fn skip_invalid<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let entries = Vec::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(entries
+        .into_iter()
+        .filter_map(|entry| match serde_json::from_value(entry) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!("Skipping invalid entry in the store: {}", e);
+                None
+            }
+        })
+        .collect())
+}
+
+/**
+ * The run history next to the store at `store_path`.
+ */
+fn history_path(store_path: &Path) -> PathBuf {
+    store_path.with_file_name("runs.jsonl")
+}
+
+/**
+ * Opens the run history of the store at `store_path` for appending, holding
+ * an exclusive lock on it until the file is closed. The lock also guards the
+ * store, which is updated along with the history.
+ */
+fn lock_history(store_path: &Path) -> io::Result<File> {
+    if let Some(parent) = store_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(history_path(store_path))?;
+    file.lock_exclusive()?;
+    Ok(file)
+}
+
+/**
+ * Appends one line per run to `history` and waits for them to reach the
+ * disk. A line torn by a crash is ended first, so that it does not swallow
+ * the next one.
+ */
+fn append_runs(history: &mut File, runs: &[Run]) -> io::Result<()> {
+    let mut lines = Vec::new();
+    let len = history.metadata()?.len();
+    if len > 0 {
+        let mut last = [0; 1];
+        history.seek(SeekFrom::Start(len - 1))?;
+        history.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            lines.push(b'\n');
+        }
+    }
+    for run in runs {
+        serde_json::to_writer(&mut lines, run)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        lines.push(b'\n');
+    }
+    history.write_all(&lines)?;
+    history.sync_data()
+}
+
+/**
+ * Reads the run history at `path`, oldest run first, skipping lines that
+ * cannot be parsed. A missing file holds no runs.
+ */
+fn read_runs(path: &Path) -> io::Result<Vec<Run>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut runs = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(run) => runs.push(run),
+            Err(e) => warn!("Skipping unreadable run in {}: {}", path.display(), e),
+        }
+    }
+    Ok(runs)
+}
+
+/**
+ * Writes the store to `path`, replacing the previous contents atomically.
+ */
+fn write_data(path: &Path, data: &StoreData) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let contents = serde_json::to_string_pretty(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let save = SAVES.fetch_add(1, Ordering::Relaxed);
+    let tmp = path.with_extension(format!("json.{}-{}.tmp", process::id(), save));
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Persistent state shared between runs. Favorites and dead stations are kept
+ * as a single JSON document; the history of runs, which only grows, is
+ * appended to `runs.jsonl` next to it, one run per line.
+ */
+pub struct Store {
+    path: PathBuf,
+    data: StoreData,
+    runs: Vec<Run>, // Oldest first
+}
+
+impl Store {
+    /**
+     * Location of the store when none is given explicitly: `$RADAFI_HOME`,
+     * falling back to `~/.radafi`.
+     */
+    pub fn default_path() -> PathBuf {
+        let home = env::var_os("RADAFI_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".radafi")))
+            .unwrap_or_else(|| PathBuf::from(".radafi"));
+        home.join("store.json")
+    }
+
+    /**
+     * Loads the store at `path` along with its run history. A missing file
+     * yields an empty store. Runs kept in the store by earlier versions are
+     * moved to the history first.
+     */
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut data = StoreData::read(path)?;
+        if !data.runs.is_empty() {
+            let mut history = lock_history(path)?;
+            // Another process may have moved them meanwhile
+            data = StoreData::read(path)?;
+            if !data.runs.is_empty() {
+                data.migrate_runs(&mut history)?;
+                write_data(path, &data)?;
+            }
+        }
+        Ok(Store {
+            path: path.to_path_buf(),
+            data,
+            runs: read_runs(&history_path(path))?,
+        })
+    }
+
+    /**
+     * Writes the store back to disk, replacing the previous contents
+     * atomically.
+     */
+    pub fn save(&self) -> io::Result<()> {
+        write_data(&self.path, &self.data)
+    }
+
+    pub fn favorites(&self) -> &[Favorite] {
+        &self.data.favorites
+    }
+
+    /**
+     * Adds a favorite, replacing any existing entry with the same channel ID.
+     * Returns true if an entry was replaced.
+     */
+    pub fn add_favorite(&mut self, favorite: Favorite) -> bool {
+        let replaced = self.remove_favorite(&favorite.id);
+        self.data.favorites.push(favorite);
+        replaced
+    }
+
+    /**
+     * Removes the favorite with the given channel ID. Returns true if it was
+     * present.
+     */
+    pub fn remove_favorite(&mut self, id: &ChannelId) -> bool {
+        let before = self.data.favorites.len();
+        self.data.favorites.retain(|f| &f.id != id);
+        self.data.favorites.len() != before
+    }
+
+    /**
+     * Appends `run` to the history of the store at `path` and updates the
+     * dead stations, re-reading the store rather than saving a copy read
+     * long before, which would undo the favorites saved meanwhile.
+     */
+    pub fn append_run(path: &Path, run: Run) -> io::Result<()> {
+        let mut history = lock_history(path)?;
+        let mut data = StoreData::read(path)?;
+        data.migrate_runs(&mut history)?;
+        data.update_dead(&run);
+        append_runs(&mut history, std::slice::from_ref(&run))?;
+        write_data(path, &data)
+    }
+
+    /**
+     * Stations found dead, in the order they were found.
+     */
+    pub fn dead_stations(&self) -> &[DeadStation] {
+        &self.data.dead
+    }
+
+    /**
+     * Summarizes per-station outcomes over the most recent `last_runs` runs,
+     * optionally restricted to one country. Stations are ordered from least
+     * to most reliable.
+     */
+    pub fn reliability(&self, country: Option<&str>, last_runs: usize) -> Vec<Reliability> {
+        let runs = self.recent_runs(country, last_runs);
+        let mut stations: Vec<Reliability> = Vec::new();
+        let mut indices: HashMap<&ChannelId, usize> = HashMap::new();
+        for outcome in runs.iter().flat_map(|r| r.outcomes.iter()) {
+            let index = *indices.entry(&outcome.id).or_insert_with(|| {
+                stations.push(Reliability {
+                    id: outcome.id.clone(),
+                    name: outcome.name.clone(),
+                    attempts: 0,
+                    failures: 0,
+                });
+                stations.len() - 1
+            });
+            stations[index].attempts += 1;
+            if !outcome.ok() {
+                stations[index].failures += 1;
+            }
+        }
+
+        stations.sort_by(|a, b| b.failure_rate().total_cmp(&a.failure_rate()));
+        stations
+    }
+
+    /**
+     * Number of failed recordings for each cause over the same window as
+     * `reliability`.
+     */
+    pub fn failures_by_category(
+        &self,
+        country: Option<&str>,
+        last_runs: usize,
+    ) -> BTreeMap<ErrorCategory, usize> {
+        let runs = self.recent_runs(country, last_runs);
+        count_failures(runs.iter().flat_map(|r| r.outcomes.iter()))
+    }
+
+    /**
+     * Totals the bytes and time recorded over the most recent `last_runs`
+     * runs, optionally restricted to one country, grouped by `grouping`.
+     * Groups are ordered from most to least bytes. Outcomes recorded before
+     * time was tracked count no time.
+     */
+    pub fn usage(
+        &self,
+        grouping: UsageGrouping,
+        country: Option<&str>,
+        last_runs: usize,
+    ) -> Vec<Usage> {
+        let mut groups: BTreeMap<String, Usage> = BTreeMap::new();
+        for run in self.recent_runs(country, last_runs) {
+            for outcome in &run.outcomes {
+                let name = match (grouping, &outcome.place) {
+                    (UsageGrouping::Country, _) => run.country.clone(),
+                    (UsageGrouping::Place, Some(place)) => format!("{}, {}", place, run.country),
+                    (UsageGrouping::Place, None) => format!("(unknown), {}", run.country),
+                };
+                // Countries are named as given on the command line
+                let usage = groups.entry(name.to_lowercase()).or_insert(Usage {
+                    name,
+                    recordings: 0,
+                    bytes: 0,
+                    seconds: 0,
+                });
+                usage.recordings += 1;
+                usage.bytes += outcome.bytes;
+                usage.seconds += outcome.seconds;
+            }
+        }
+        let mut usage: Vec<Usage> = groups.into_values().collect();
+        usage.sort_by_key(|usage| Reverse(usage.bytes));
+        usage
+    }
+
+    /**
+     * Average bytes per second received from each station, by channel ID,
+     * over every run that recorded it for some time.
+     */
+    pub fn byte_rates(&self) -> HashMap<ChannelId, f64> {
+        let mut totals: HashMap<&ChannelId, (u64, u64)> = HashMap::new();
+        for outcome in self.runs.iter().flat_map(|r| r.outcomes.iter()) {
+            if outcome.seconds > 0 && outcome.bytes > 0 {
+                let total = totals.entry(&outcome.id).or_default();
+                total.0 += outcome.bytes;
+                total.1 += outcome.seconds;
+            }
+        }
+        totals
+            .into_iter()
+            .map(|(id, (bytes, seconds))| (id.clone(), bytes as f64 / seconds as f64))
+            .collect()
+    }
+
+    fn recent_runs(&self, country: Option<&str>, last_runs: usize) -> Vec<&Run> {
+        self.runs
+            .iter()
+            .rev()
+            .filter(|r| country.is_none_or(|c| r.country.eq_ignore_ascii_case(c)))
+            .take(last_runs)
+            .collect()
+    }
+}
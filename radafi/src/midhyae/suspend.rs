@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+/**
+ * Gaps between `Instant` and the reference clock shorter than this are
+ * scheduling noise rather than a suspend.
+ */
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(10);
+
+/**
+ * Notices the system being suspended, e.g. a laptop put to sleep, which
+ * `Instant` does not count on Linux and Apple systems: the time it misses
+ * is compared with a clock that keeps running through a suspend.
+ */
+pub struct SuspendWatch {
+    monotonic: Instant,
+    reference: Duration,
+}
+
+impl Default for SuspendWatch {
+    fn default() -> Self {
+        SuspendWatch {
+            monotonic: Instant::now(),
+            reference: reference(),
+        }
+    }
+}
+
+impl SuspendWatch {
+    /**
+     * How long the system was suspended since the watch was created or
+     * last checked, if it was.
+     */
+    pub fn check(&mut self) -> Option<Duration> {
+        let (monotonic, reference) = (Instant::now(), reference());
+        let slept = reference
+            .saturating_sub(self.reference)
+            .saturating_sub(monotonic - self.monotonic);
+        self.monotonic = monotonic;
+        self.reference = reference;
+        (slept >= SUSPEND_THRESHOLD).then_some(slept)
+    }
+}
+
+/**
+ * A clock that runs while the system is suspended: the boot time clock on
+ * Linux and Android, and the monotonic one on Apple systems, which unlike
+ * `Instant` includes sleep there. Neither changes with the time of day.
+ */
+#[cfg(any(target_os = "linux", target_os = "android", target_vendor = "apple"))]
+#[allow(clippy::unnecessary_cast)] // time_t is not 64 bits everywhere
+fn reference() -> Duration {
+    #[cfg(target_vendor = "apple")]
+    const CLOCK: libc::clockid_t = libc::CLOCK_MONOTONIC;
+    #[cfg(not(target_vendor = "apple"))]
+    const CLOCK: libc::clockid_t = libc::CLOCK_BOOTTIME;
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `time` is a valid timespec for clock_gettime to fill in
+    if unsafe { libc::clock_gettime(CLOCK, &mut time) } != 0 {
+        return Duration::ZERO;
+    }
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+/**
+ * Elsewhere the wall clock, so setting the clock forward also counts as a
+ * suspend.
+ */
+#[cfg(not(any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+fn reference() -> Duration {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+}
@@ -0,0 +1,187 @@
+use chrono::{DateTime, Local};
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/**
+ * Output path templates. A template may contain strftime tokens such as
+ * `%Y/%m/%d`, expanded from the time a recording starts, and the
+ * placeholders `{name}` (station title) and `{id}` (channel ID).
+ */
+pub const DEFAULT_FILENAME: &str = "stream_{name}.mp3";
+
+/**
+ * Characters that Windows does not allow in file names, besides control
+ * characters.
+ */
+const RESERVED_CHARACTERS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/**
+ * Device names that Windows reserves in every directory, even with an
+ * extension such as `CON.mp3`.
+ */
+const RESERVED_NAMES: [&str; 28] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "COM¹", "COM²", "COM³", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8",
+    "LPT9", "LPT¹", "LPT²", "LPT³",
+];
+
+/**
+ * Longest station title put into a path, in bytes, leaving room for the
+ * rest of a file name within the 255 bytes most file systems allow.
+ */
+const MAX_NAME_BYTES: usize = 200;
+
+/**
+ * Length of Windows paths from which the `\\?\` prefix is needed: 260
+ * (MAX_PATH) for files, but 248 for directories, which must leave room for
+ * an 8.3 file name.
+ */
+const MAX_PATH: usize = 248;
+
+/**
+ * Checks that every strftime token in `template` is recognized.
+ */
+pub fn validate(template: &str) -> Result<(), String> {
+    let mut out = String::new();
+    write!(out, "{}", Local::now().format(template))
+        .map_err(|_| format!("invalid date token in template: {}", template))
+}
+
+/**
+ * Expands `template` for a recording of `name`/`id` starting at `now`.
+ * The template must have passed `validate`. Station values are made safe
+ * to use as file names, see `safe_name`.
+ */
+pub fn expand(template: &str, now: &DateTime<Local>, name: &str, id: &str) -> String {
+    let mut out = String::new();
+    // Station values are substituted afterwards so they are never parsed as
+    // date tokens themselves
+    if write!(out, "{}", now.format(template)).is_err() {
+        out = template.to_string();
+    }
+    out.replace("{name}", &safe_name(name))
+        .replace("{id}", &safe_name(id))
+}
+
+/**
+ * `name`, e.g. a station title, made usable as a file or directory name on
+ * every platform: path separators, characters Windows reserves and control
+ * characters become `_`, trailing dots and spaces (which Windows drops) are
+ * removed, device names such as `CON` or `LPT1` get a `_` appended, and
+ * overly long names are shortened. Empty names become `_`.
+ */
+pub fn safe_name(name: &str) -> String {
+    let mut safe: String = name
+        .chars()
+        .map(
+            |c| match c.is_control() || RESERVED_CHARACTERS.contains(&c) {
+                true => '_',
+                false => c,
+            },
+        )
+        .collect();
+    if safe.len() > MAX_NAME_BYTES {
+        let mut end = MAX_NAME_BYTES;
+        while !safe.is_char_boundary(end) {
+            end -= 1;
+        }
+        safe.truncate(end);
+    }
+    let kept = safe.trim_end_matches(['.', ' ']).len();
+    safe.truncate(kept);
+    if safe.is_empty() {
+        return "_".to_string();
+    }
+    // `CON.mp3` and `CON .mp3` are the device as well
+    let stem = safe.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        safe.insert(stem.len(), '_');
+    }
+    safe
+}
+
+/**
+ * `path` in the `\\?\` form that lifts the MAX_PATH limit, if it is an
+ * absolute Windows path that long: `\\?\C:\...` for drive paths and
+ * `\\?\UNC\server\share\...` for network shares, with forward slashes
+ * turned into backslashes since the prefix turns off that translation. None
+ * if the path is short enough, has the prefix already, or is relative or
+ * contains `.` or `..`, which the prefix leaves unresolved.
+ */
+pub fn windows_long_path(path: &str) -> Option<String> {
+    if path.encode_utf16().count() < MAX_PATH || path.starts_with(r"\\?\") {
+        return None;
+    }
+    let path = path.replace('/', "\\");
+    if path
+        .split('\\')
+        .any(|component| component == "." || component == "..")
+    {
+        return None;
+    }
+    if let Some(share) = path.strip_prefix(r"\\") {
+        return Some(format!(r"\\?\UNC\{}", share));
+    }
+    let bytes = path.as_bytes();
+    match bytes.len() > 3 && bytes[0].is_ascii_alphabetic() && &bytes[1..3] == b":\\" {
+        true => Some(format!(r"\\?\{}", path)),
+        false => None,
+    }
+}
+
+/**
+ * `path` as it has to be opened on this platform: on Windows, in the long
+ * form when it exceeds MAX_PATH.
+ */
+pub fn native_path(path: PathBuf) -> PathBuf {
+    #[cfg(windows)]
+    if let Some(long) = windows_long_path(&path.to_string_lossy()) {
+        return PathBuf::from(long);
+    }
+    path
+}
+
+/**
+ * Expands the date tokens of an output directory template as of `at`.
+ */
+pub fn expand_directory(directory: &str, at: &DateTime<Local>) -> PathBuf {
+    PathBuf::from(expand(directory, at, "", ""))
+}
+
+/**
+ * The leading directories of `directory` that contain no tokens or
+ * placeholders, i.e. the part shared by every recording of a run.
+ */
+pub fn static_prefix(directory: &str) -> PathBuf {
+    Path::new(directory)
+        .components()
+        .take_while(|c| {
+            let part = c.as_os_str().to_string_lossy();
+            !part.contains('%') && !part.contains('{')
+        })
+        .collect()
+}
+
+/**
+ * Name of segment `part` (counting from 1) of a recording to `path`, with
+ * the segment number before the extension, e.g. `stream_x_002.mp3`. An
+ * `.age` suffix stays last.
+ */
+pub fn segment_name(path: &str, part: usize) -> String {
+    let (path, encrypted) = match path.strip_suffix(".age") {
+        Some(path) => (path, ".age"),
+        None => (path, ""),
+    };
+    let file_start = path
+        .rfind(std::path::is_separator)
+        .map_or(0, |separator| separator + 1);
+    let (stem, extension) = match path[file_start..].rfind('.') {
+        Some(dot) if dot > 0 => path.split_at(file_start + dot),
+        _ => (path, ""),
+    };
+    format!("{}_{:03}{}{}", stem, part, extension, encrypted)
+}
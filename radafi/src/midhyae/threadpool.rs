@@ -72,20 +72,24 @@ struct Worker {
 
 impl Worker {
     fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
-
-            match message {
-                Message::NewTask(task) => {
-                    debug!("Worker {} got a task; executing.", id);
-                    task();
-                }
-                Message::Terminate => {
-                    debug!("Worker {} was told to terminate.", id);
-                    break;
+        // The name identifies the worker in the log context of its recordings
+        let builder = thread::Builder::new().name(format!("worker-{}", id));
+        let thread = builder
+            .spawn(move || loop {
+                let message = receiver.lock().unwrap().recv().unwrap();
+
+                match message {
+                    Message::NewTask(task) => {
+                        debug!("Worker {} got a task; executing.", id);
+                        task();
+                    }
+                    Message::Terminate => {
+                        debug!("Worker {} was told to terminate.", id);
+                        break;
+                    }
                 }
-            }
-        });
+            })
+            .expect("Failed to spawn a worker thread");
 
         Worker {
             id,
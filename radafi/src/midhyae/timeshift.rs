@@ -0,0 +1,156 @@
+use bytes::Bytes;
+use chrono::Local;
+use log::{error, info};
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::mp3::FrameScanner;
+
+/**
+ * The recent audio of one station.
+ */
+#[derive(Default)]
+struct Ring {
+    chunks: VecDeque<(Instant, Bytes)>, // Oldest first, with when they arrived
+}
+
+impl Ring {
+    fn push(&mut self, audio: &[u8], window: Duration) {
+        let now = Instant::now();
+        self.chunks.push_back((now, Bytes::copy_from_slice(audio)));
+        while let Some((arrived, _)) = self.chunks.front() {
+            if now.duration_since(*arrived) <= window {
+                break;
+            }
+            self.chunks.pop_front();
+        }
+    }
+
+    /**
+     * The audio held, from its first MP3 frame on so the clip plays from
+     * the start. Streams without frames are returned whole.
+     */
+    fn audio(&self) -> Vec<u8> {
+        let mut audio = Vec::new();
+        for (_, chunk) in &self.chunks {
+            audio.extend_from_slice(chunk);
+        }
+        let start = FrameScanner::default().feed(&audio).unwrap_or(0);
+        audio.split_off(start)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    window: Option<Duration>,
+    rings: HashMap<String, Ring>, // By channel ID
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Keeps the last minutes of audio of every station being recorded in memory,
+ * so something just heard can be saved into a clip of its own after the fact,
+ * e.g. from the middle of a long recording. A station at 128 kbit/s takes
+ * about 1 MB per minute kept. Nothing is kept until a window is set.
+ */
+#[derive(Default)]
+pub struct TimeShift {
+    state: Mutex<State>,
+}
+
+impl TimeShift {
+    /**
+     * Keeps `window` of audio of each station, or nothing if None.
+     */
+    pub fn set_window(&self, window: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        state.window = window.filter(|window| !window.is_zero());
+        if state.window.is_none() {
+            state.rings.clear();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.lock().unwrap().window.is_some()
+    }
+
+    /**
+     * Adds audio received for `station`, dropping what has fallen out of the
+     * window.
+     */
+    pub fn push(&self, station: &str, audio: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        let window = match state.window {
+            Some(window) => window,
+            None => return,
+        };
+        state
+            .rings
+            .entry(station.to_string())
+            .or_default()
+            .push(audio, window);
+    }
+
+    /**
+     * Writes the audio kept of the given channel IDs, or of every station if
+     * none are given, into `directory` as `<id>_timeshift_<time>.mp3`, and
+     * returns the files written. Stations that finished keep the end of
+     * their recording.
+     */
+    pub fn save(&self, directory: &Path, stations: &[String]) -> io::Result<Vec<PathBuf>> {
+        let clips: Vec<(String, Vec<u8>)> = {
+            let state = self.state.lock().unwrap();
+            state
+                .rings
+                .iter()
+                .filter(|(id, _)| stations.is_empty() || stations.contains(id))
+                .map(|(id, ring)| (id.clone(), ring.audio()))
+                .filter(|(_, audio)| !audio.is_empty())
+                .collect()
+        };
+        if clips.is_empty() {
+            return Ok(Vec::new());
+        }
+        fs::create_dir_all(directory)?;
+        let time = Local::now().format("%Y%m%d-%H%M%S");
+        let mut saved = Vec::new();
+        for (id, audio) in clips {
+            let path = directory.join(format!("{}_timeshift_{}.mp3", id, time));
+            fs::write(&path, audio)?;
+            info!("Saved the last minutes of {} to {}", id, path.display());
+            saved.push(path);
+        }
+        saved.sort();
+        Ok(saved)
+    }
+
+    /**
+     * Saves the audio kept of every station into `directory` on each
+     * SIGQUIT (Ctrl+\ in a terminal), until the runtime shuts down.
+     */
+    #[cfg(unix)]
+    pub async fn save_on_signal(&self, directory: &Path) -> io::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut quit = signal(SignalKind::quit())?;
+        while quit.recv().await.is_some() {
+            match self.save(directory, &[]) {
+                Ok(saved) if saved.is_empty() => {
+                    info!("Nothing to save from the time-shift yet")
+                }
+                Ok(saved) => {
+                    for path in saved {
+                        info!("Saved {}", path.display());
+                    }
+                }
+                Err(e) => error!("Failed to save the time-shift: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
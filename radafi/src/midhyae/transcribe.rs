@@ -0,0 +1,229 @@
+use log::info;
+use reqwest::multipart::{Form, Part};
+use reqwest::Client;
+use url::Url;
+
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use super::analysis::decode_mono;
+use super::RecordingError;
+
+/**
+ * Sample rate of the PCM handed to speech recognizers.
+ */
+pub const ASR_SAMPLE_RATE: u32 = 16_000;
+
+/**
+ * An external speech recognizer that finished recordings are passed to. The
+ * transcript is saved as `<recording>.txt`.
+ *
+ * - `Command` runs a shell command and writes 16 kHz mono signed 16-bit
+ *   little-endian PCM to its stdin; whatever it prints is the transcript.
+ *   The recording's path is available to it as `$RADAFI_RECORDING`.
+ * - `Http` posts the audio as a 16 kHz WAV file in the `file` field of a
+ *   multipart form (as whisper.cpp's server and OpenAI-compatible endpoints
+ *   expect) and saves the response body.
+ */
+pub enum Transcriber {
+    Command(String),
+    Http(Url),
+}
+
+impl Transcriber {
+    pub fn http(endpoint: &str) -> Result<Self, RecordingError> {
+        Url::parse(endpoint)
+            .map(Transcriber::Http)
+            .map_err(|e| RecordingError::Transcription(format!("{}: {}", endpoint, e)))
+    }
+
+    /**
+     * Transcribes the MP3 file at `recording`, returning the path of the
+     * saved transcript.
+     */
+    pub async fn transcribe(
+        &self,
+        client: &Client,
+        recording: &Path,
+    ) -> Result<PathBuf, RecordingError> {
+        let transcript = match self {
+            Transcriber::Command(command) => {
+                let (command, recording) = (command.clone(), recording.to_path_buf());
+                blocking(move || run_command(&command, &recording)).await?
+            }
+            Transcriber::Http(url) => post_wav(client, url, recording).await?,
+        };
+
+        let mut path = recording.as_os_str().to_owned();
+        path.push(".txt");
+        let path = PathBuf::from(path);
+        fs::write(&path, transcript)?;
+        info!("Saved transcript {}", path.display());
+        Ok(path)
+    }
+}
+
+/**
+ * Streams the decoded audio into `command` and collects its output.
+ */
+fn run_command(command: &str, recording: &Path) -> Result<String, RecordingError> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("RADAFI_RECORDING", recording)
+        .env("RADAFI_SAMPLE_RATE", ASR_SAMPLE_RATE.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Read the output concurrently so a chatty recognizer cannot block us
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let mut write_error = None;
+    pcm16k(recording, |samples| {
+        if write_error.is_none() {
+            let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+            if let Err(e) = stdin.write_all(&bytes) {
+                write_error = Some(e);
+            }
+        }
+    })?;
+    drop(stdin);
+
+    let status = child.wait()?;
+    let output = reader
+        .join()
+        .map_err(|_| RecordingError::Transcription("output reader panicked".to_string()))??;
+    if !status.success() {
+        return Err(RecordingError::Transcription(format!(
+            "command exited with {}",
+            status
+        )));
+    }
+    // A recognizer may stop reading early; that is only an error if it failed
+    if let Some(e) = write_error {
+        if e.kind() != std::io::ErrorKind::BrokenPipe {
+            return Err(e.into());
+        }
+    }
+    Ok(output)
+}
+
+async fn post_wav(client: &Client, url: &Url, recording: &Path) -> Result<String, RecordingError> {
+    let path = recording.to_path_buf();
+    let samples = blocking(move || {
+        let mut samples = Vec::new();
+        pcm16k(&path, |chunk| samples.extend_from_slice(chunk))?;
+        Ok(samples)
+    })
+    .await?;
+
+    let file_name = recording
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let part = Part::bytes(wav(&samples))
+        .file_name(format!("{}.wav", file_name))
+        .mime_str("audio/wav")?;
+    let form = Form::new()
+        .part("file", part)
+        .text("response_format", "text");
+
+    let response = client.post(url.clone()).multipart(form).send().await?;
+    if !response.status().is_success() {
+        return Err(RecordingError::Transcription(format!(
+            "{} returned {}",
+            url,
+            response.status()
+        )));
+    }
+    Ok(response.text().await?)
+}
+
+/**
+ * Runs `f`, which decodes audio or waits for a process, off the runtime's
+ * worker threads.
+ */
+async fn blocking<T, F>(f: F) -> Result<T, RecordingError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, RecordingError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| RecordingError::Transcription(e.to_string()))?
+}
+
+/**
+ * Decodes `recording` and resamples it to 16 kHz signed 16-bit PCM,
+ * handing the samples to `sink` frame by frame.
+ */
+fn pcm16k<F: FnMut(&[i16])>(recording: &Path, mut sink: F) -> Result<(), RecordingError> {
+    let mut resampler = Resampler::default();
+    let mut out = Vec::new();
+    decode_mono(BufReader::new(File::open(recording)?), |samples, rate| {
+        out.clear();
+        resampler.push(samples, rate, &mut out);
+        sink(&out);
+    })?;
+    Ok(())
+}
+
+/**
+ * Linear-interpolation resampler to `ASR_SAMPLE_RATE`, adequate for speech.
+ */
+#[derive(Default)]
+struct Resampler {
+    next: f64,  // Input position of the next output sample
+    index: u64, // Input position of the next input sample
+    previous: f32,
+}
+
+impl Resampler {
+    fn push(&mut self, input: &[f32], rate: u32, out: &mut Vec<i16>) {
+        let step = rate as f64 / ASR_SAMPLE_RATE as f64;
+        for &sample in input {
+            let position = self.index as f64;
+            while self.next <= position {
+                let fraction = (self.next - (position - 1.0)) as f32;
+                let value = self.previous + (sample - self.previous) * fraction;
+                out.push((value.clamp(-1.0, 1.0) * i16::MAX as f32) as i16);
+                self.next += step;
+            }
+            self.previous = sample;
+            self.index += 1;
+        }
+    }
+}
+
+/**
+ * Wraps mono 16 kHz samples in a WAV container.
+ */
+fn wav(samples: &[i16]) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes()); // fmt chunk size
+    out.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    out.extend_from_slice(&1u16.to_le_bytes()); // mono
+    out.extend_from_slice(&ASR_SAMPLE_RATE.to_le_bytes());
+    out.extend_from_slice(&(ASR_SAMPLE_RATE * 2).to_le_bytes()); // byte rate
+    out.extend_from_slice(&2u16.to_le_bytes()); // block align
+    out.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        out.extend_from_slice(&sample.to_le_bytes());
+    }
+    out
+}
@@ -0,0 +1,191 @@
+use log::warn;
+use minimp3::{Decoder, Error as Mp3Error};
+use serde::Serialize;
+
+use std::collections::VecDeque;
+use std::mem;
+
+use super::analysis::{mix_down, Classifier, SegmentKind};
+use super::mp3::FrameHeader;
+
+/**
+ * Seconds kept after the last second of speech, so pauses between sentences
+ * do not cut them apart.
+ */
+const HANGOVER_SECONDS: u32 = 2;
+
+/**
+ * How much of a stream to search for MP3 frames before giving up on it
+ * (e.g. for AAC streams) and keeping all of it.
+ */
+const MAX_FRAME_SEARCH: usize = 64 * 1024;
+
+/**
+ * How much of a recording was kept as speech, for its sidecar.
+ */
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct SpeechGating {
+    pub listened_seconds: f64, // Audio received from the stream
+    pub kept_seconds: f64,     // Audio written to the recording
+}
+
+impl SpeechGating {
+    /**
+     * What was gated since `earlier`.
+     */
+    pub fn since(self, earlier: SpeechGating) -> SpeechGating {
+        SpeechGating {
+            listened_seconds: self.listened_seconds - earlier.listened_seconds,
+            kept_seconds: self.kept_seconds - earlier.kept_seconds,
+        }
+    }
+}
+
+/**
+ * ----------------------------------------------------------------------------
+ * Passes on only the parts of an MP3 stream that contain speech. The stream
+ * is decoded as it arrives and each second is classified like `Classify`
+ * does; seconds of speech are kept along with the second before them and
+ * a few after them, the rest is dropped. Audio is cut between frames, so
+ * what is kept plays as a regular MP3 file.
+ */
+pub struct SpeechGate {
+    pending: Vec<u8>,               // Received bytes not yet split into frames
+    window: Vec<u8>,                // Frames of the second being classified
+    window_seconds: f64,            // Audio in `window`
+    previous: Vec<u8>,              // The second before, dropped unless speech follows
+    previous_seconds: f64,          // Audio in `previous`
+    decoder: Decoder<VecDeque<u8>>, // Fed the frames of `window`
+    mono: Vec<f32>,                 // Samples of the frame last decoded
+    classifier: Classifier,
+    classified: u64,   // Seconds of the classifier kept or dropped
+    hangover: u32,     // Seconds still to keep after speech
+    passthrough: bool, // No MP3 frames found; everything is kept
+    gating: SpeechGating,
+}
+
+impl Default for SpeechGate {
+    fn default() -> Self {
+        SpeechGate {
+            pending: Vec::new(),
+            window: Vec::new(),
+            window_seconds: 0.0,
+            previous: Vec::new(),
+            previous_seconds: 0.0,
+            decoder: Decoder::new(VecDeque::new()),
+            mono: Vec::new(),
+            classifier: Classifier::default(),
+            classified: 0,
+            hangover: 0,
+            passthrough: false,
+            gating: SpeechGating::default(),
+        }
+    }
+}
+
+impl SpeechGate {
+    /**
+     * Consumes the next chunk of the stream and returns the audio to keep,
+     * which lags behind the stream by about a second.
+     */
+    pub fn feed(&mut self, chunk: &[u8], station: &str) -> Vec<u8> {
+        if self.passthrough {
+            return chunk.to_vec();
+        }
+        self.pending.extend_from_slice(chunk);
+        let mut kept = Vec::new();
+        let mut at = 0;
+        while at + 4 <= self.pending.len() {
+            let header = match FrameHeader::parse(&self.pending[at..]) {
+                Some(header) => header,
+                None => {
+                    // Lost sync, e.g. on a gap in the stream
+                    at += 1;
+                    continue;
+                }
+            };
+            if at + header.length > self.pending.len() {
+                break;
+            }
+            let frame = &self.pending[at..at + header.length];
+            self.window.extend_from_slice(frame);
+            self.window_seconds += header.seconds();
+            self.decoder.reader_mut().extend(frame);
+            at += header.length;
+            self.decode();
+            if self.classifier.seconds() > self.classified {
+                self.classified += 1;
+                self.finish_second(&mut kept);
+            }
+        }
+        self.pending.drain(..at);
+
+        let any_frames = self.classified > 0 || !self.window.is_empty();
+        if !any_frames && self.pending.len() > MAX_FRAME_SEARCH {
+            warn!(
+                "No MP3 frames found in {}; keeping all of it instead of only speech",
+                station
+            );
+            self.passthrough = true;
+            kept.append(&mut self.pending);
+        }
+        kept
+    }
+
+    /**
+     * The audio still held back that belongs to the recording: the second
+     * being classified, if speech was heard just before it.
+     */
+    pub fn finish(&mut self) -> Vec<u8> {
+        self.gating.listened_seconds += self.window_seconds;
+        if self.hangover == 0 || self.passthrough {
+            return Vec::new();
+        }
+        self.gating.kept_seconds += self.window_seconds;
+        mem::take(&mut self.window)
+    }
+
+    pub fn gating(&self) -> SpeechGating {
+        self.gating
+    }
+
+    /**
+     * Decodes the frames fed so far into the classifier.
+     */
+    fn decode(&mut self) {
+        loop {
+            let frame = match self.decoder.next_frame() {
+                Ok(frame) => frame,
+                Err(Mp3Error::SkippedData) => continue,
+                // Wait for the next frame
+                Err(_) => return,
+            };
+            mix_down(&frame, &mut self.mono);
+            self.classifier
+                .push(&self.mono, frame.sample_rate.max(1) as u32);
+        }
+    }
+
+    /**
+     * Keeps or holds back the second just classified.
+     */
+    fn finish_second(&mut self, kept: &mut Vec<u8>) {
+        let window = mem::take(&mut self.window);
+        let seconds = mem::take(&mut self.window_seconds);
+        self.gating.listened_seconds += seconds;
+        if self.classifier.last_kind() == Some(SegmentKind::Speech) {
+            // Lead in with the second before, unless it was kept already
+            kept.append(&mut self.previous);
+            self.gating.kept_seconds += mem::take(&mut self.previous_seconds) + seconds;
+            kept.extend(window);
+            self.hangover = HANGOVER_SECONDS;
+        } else if self.hangover > 0 {
+            self.hangover -= 1;
+            self.gating.kept_seconds += seconds;
+            kept.extend(window);
+        } else {
+            self.previous = window;
+            self.previous_seconds = seconds;
+        }
+    }
+}
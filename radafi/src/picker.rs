@@ -0,0 +1,150 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+/**
+ * Interactive multi-select over a list of station titles. The list is shown
+ * on stderr and commands are read line by line from stdin:
+ *
+ *   /text     fuzzy-filter the list (a bare `/` clears the filter)
+ *   1 3-5     toggle the numbered entries currently shown
+ *   a         toggle every entry currently shown
+ *   q         abort without selecting anything
+ *   <enter>   finish and return the selection
+ *
+ * Returns the indices (into `titles`) of the selected entries, or `None`
+ * if the user aborted.
+ */
+pub fn pick(titles: &[&str]) -> io::Result<Option<Vec<usize>>> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut stderr = io::stderr();
+
+    let mut selected = BTreeSet::new();
+    let mut query = String::new();
+
+    loop {
+        let shown = filter(titles, &query);
+        writeln!(stderr)?;
+        for (n, &i) in shown.iter().enumerate() {
+            let mark = if selected.contains(&i) { 'x' } else { ' ' };
+            writeln!(stderr, "{:>4} [{}] {}", n + 1, mark, titles[i])?;
+        }
+        writeln!(
+            stderr,
+            "{} shown, {} selected. /filter, numbers to toggle, a = all, q = quit, enter = done",
+            shown.len(),
+            selected.len()
+        )?;
+        write!(stderr, "{}> ", query)?;
+        stderr.flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(Some(selected.into_iter().collect())),
+        };
+        let line = line.trim();
+
+        match line {
+            "" => return Ok(Some(selected.into_iter().collect())),
+            "q" => return Ok(None),
+            "a" => {
+                for &i in &shown {
+                    if !selected.remove(&i) {
+                        selected.insert(i);
+                    }
+                }
+            }
+            _ if line.starts_with('/') => query = line[1..].to_string(),
+            _ => match parse_numbers(line, shown.len()) {
+                Some(numbers) => {
+                    for n in numbers {
+                        let i = shown[n - 1];
+                        if !selected.remove(&i) {
+                            selected.insert(i);
+                        }
+                    }
+                }
+                None => writeln!(stderr, "Unrecognized input: {}", line)?,
+            },
+        }
+    }
+}
+
+/**
+ * Returns the indices of titles that fuzzily match `query`, best matches
+ * first. An empty query matches everything in the original order.
+ */
+fn filter(titles: &[&str], query: &str) -> Vec<usize> {
+    let mut matches: Vec<(usize, usize)> = titles
+        .iter()
+        .enumerate()
+        .filter_map(|(i, title)| score(title, query).map(|s| (s, i)))
+        .collect();
+    matches.sort();
+    matches.into_iter().map(|(_, i)| i).collect()
+}
+
+/**
+ * Scores `title` against `query` as a case-insensitive subsequence match.
+ * Lower scores are better: the score is the length of the shortest span of
+ * the title that contains the query characters in order.
+ */
+fn score(title: &str, query: &str) -> Option<usize> {
+    let title: Vec<char> = title.to_lowercase().chars().collect();
+    let query: Vec<char> = query
+        .to_lowercase()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let mut best = None;
+    for start in 0..title.len() {
+        if title[start] != query[0] {
+            continue;
+        }
+        let mut q = 1;
+        let mut end = start;
+        for (offset, c) in title[start + 1..].iter().enumerate() {
+            if q == query.len() {
+                break;
+            }
+            if *c == query[q] {
+                q += 1;
+                end = start + 1 + offset;
+            }
+        }
+        if q == query.len() {
+            let span = end - start + 1;
+            best = Some(best.map_or(span, |b: usize| b.min(span)));
+        }
+    }
+    best
+}
+
+/**
+ * Parses a list like `1 3-5,7` into 1-based entry numbers, rejecting
+ * anything outside `1..=max`.
+ */
+fn parse_numbers(line: &str, max: usize) -> Option<Vec<usize>> {
+    let mut numbers = Vec::new();
+    for part in line.split(|c: char| c == ',' || c.is_whitespace()) {
+        if part.is_empty() {
+            continue;
+        }
+        let (from, to): (usize, usize) = match part.split_once('-') {
+            Some((from, to)) => (from.parse().ok()?, to.parse().ok()?),
+            None => {
+                let n = part.parse().ok()?;
+                (n, n)
+            }
+        };
+        if from == 0 || to > max || from > to {
+            return None;
+        }
+        numbers.extend(from..=to);
+    }
+    Some(numbers)
+}
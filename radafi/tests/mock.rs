@@ -0,0 +1,43 @@
+//! Discovery and recording end to end against the mock Radio Garden API.
+
+#![cfg(feature = "mock-server")]
+
+use radafi::midhyae::{Listener, MockRadioGarden, MockStation, MockStream};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stations_are_discovered_and_recorded() {
+    let mock = MockRadioGarden::start(vec![
+        MockStation::new("Ch01", "Radio One", "Recife", "Brazil"),
+        MockStation::new("Ch02", "Radio Two", "Recife", "Brazil")
+            .with_stream(MockStream::Status(503)),
+        MockStation::new("Ch03", "Radio Three", "Lisbon", "Portugal"),
+    ])
+    .unwrap();
+    let directory = std::env::temp_dir().join(format!("radafi-mock-{}", std::process::id()));
+    let mut listener = Listener::new(&mock.base_url());
+    listener.set_allow_insecure(true);
+    // Journals stay out of the real store directory
+    listener.set_journal_dir(&directory.join("journals"));
+
+    let discovery = listener.store_streams("Brazil").await.unwrap();
+    let mut found: Vec<&str> = discovery.streams.iter().map(|s| s.id.as_str()).collect();
+    found.sort();
+    assert_eq!(found, ["Ch01", "Ch02"]);
+
+    let outcomes = listener
+        .record_streams(&discovery.streams, 1, &directory.to_string_lossy())
+        .await
+        .unwrap();
+    let outcome = |id: &str| outcomes.iter().find(|o| o.id == id).unwrap();
+    assert!(outcome("Ch01").ok());
+    assert!(outcome("Ch01").bytes > 0);
+    assert!(!outcome("Ch02").ok());
+    assert!(directory.join("stream_RadioOne.mp3").is_file());
+    assert!(!directory.join("stream_RadioTwo.mp3").exists());
+
+    // Only the places of the country asked for are looked into
+    let requests = mock.requests();
+    assert!(requests.iter().any(|path| path.ends_with("/places")));
+    assert!(!requests.iter().any(|path| path.contains("Ch03")));
+    std::fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,153 @@
+//! Output paths for station titles that are awkward as file names,
+//! especially on Windows.
+
+use radafi::midhyae::{expand_directory, safe_name, segment_name, windows_long_path};
+
+use chrono::{Local, TimeZone};
+
+#[test]
+fn path_separators_and_reserved_characters_are_replaced() {
+    assert_eq!(safe_name("AC/DC Radio"), "AC_DC Radio");
+    assert_eq!(safe_name(r"Rock\Pop"), "Rock_Pop");
+    assert_eq!(safe_name("News: 24/7?"), "News_ 24_7_");
+    assert_eq!(
+        safe_name("<Best> \"Hits\" | *Now*"),
+        "_Best_ _Hits_ _ _Now_"
+    );
+    assert_eq!(safe_name("Radio\tOne\n"), "Radio_One_");
+}
+
+#[test]
+fn titles_that_are_fine_stay_as_they_are() {
+    assert_eq!(safe_name("Rádio Gaúcha"), "Rádio Gaúcha");
+    assert_eq!(safe_name("ラジオ日本"), "ラジオ日本");
+    assert_eq!(safe_name("Radio 1.FM"), "Radio 1.FM");
+    assert_eq!(safe_name(".hidden"), ".hidden");
+}
+
+#[test]
+fn trailing_dots_and_spaces_are_removed() {
+    assert_eq!(safe_name("Radio FM."), "Radio FM");
+    assert_eq!(safe_name("Radio FM . . "), "Radio FM");
+    assert_eq!(safe_name("..."), "_");
+    assert_eq!(safe_name("   "), "_");
+    assert_eq!(safe_name(""), "_");
+}
+
+#[test]
+fn device_names_are_not_used_as_is() {
+    assert_eq!(safe_name("CON"), "CON_");
+    assert_eq!(safe_name("con"), "con_");
+    assert_eq!(safe_name("Nul.fm"), "Nul_.fm");
+    assert_eq!(safe_name("COM1 .radio"), "COM1_ .radio");
+    assert_eq!(safe_name("LPT²"), "LPT²_");
+    assert_eq!(safe_name("CONTACT"), "CONTACT");
+    assert_eq!(safe_name("COM10"), "COM10");
+    assert_eq!(safe_name("Radio AUX"), "Radio AUX");
+}
+
+#[test]
+fn long_titles_are_shortened_on_a_character_boundary() {
+    let long = "é".repeat(150);
+    let safe = safe_name(&long);
+    assert!(safe.len() <= 200);
+    assert!(safe.chars().all(|c| c == 'é'));
+    assert_eq!(safe_name(&format!("{}.", "a".repeat(199))), "a".repeat(199));
+}
+
+#[test]
+fn segment_numbers_go_before_the_extension() {
+    assert_eq!(segment_name("out/stream_x.mp3", 2), "out/stream_x_002.mp3");
+    assert_eq!(
+        segment_name("out/stream_x.mp3.age", 3),
+        "out/stream_x_003.mp3.age"
+    );
+    assert_eq!(segment_name("out.d/stream", 1), "out.d/stream_001");
+    #[cfg(windows)]
+    assert_eq!(
+        segment_name(r"C:\out.d\stream_x.mp3", 2),
+        r"C:\out.d\stream_x_002.mp3"
+    );
+}
+
+#[test]
+fn directories_expand_their_date_tokens() {
+    let at = Local.with_ymd_and_hms(2024, 3, 9, 7, 5, 0).unwrap();
+    assert_eq!(
+        expand_directory("archive/%Y/%m-%d", &at),
+        std::path::PathBuf::from("archive/2024/03-09")
+    );
+}
+
+#[test]
+fn short_windows_paths_need_no_prefix() {
+    assert_eq!(windows_long_path(r"C:\archive\stream_x.mp3"), None);
+}
+
+#[test]
+fn long_drive_paths_get_the_long_path_prefix() {
+    let path = format!(r"C:\archive\{}\stream_x.mp3", "d".repeat(250));
+    assert_eq!(windows_long_path(&path), Some(format!(r"\\?\{}", path)));
+
+    let slashes = format!("D:/archive/{}/stream_x.mp3", "d".repeat(250));
+    assert_eq!(
+        windows_long_path(&slashes),
+        Some(format!(r"\\?\D:\archive\{}\stream_x.mp3", "d".repeat(250)))
+    );
+}
+
+#[test]
+fn long_share_paths_get_the_unc_prefix() {
+    let path = format!(r"\\nas\radio\{}\stream_x.mp3", "d".repeat(250));
+    assert_eq!(
+        windows_long_path(&path),
+        Some(format!(
+            r"\\?\UNC\nas\radio\{}\stream_x.mp3",
+            "d".repeat(250)
+        ))
+    );
+}
+
+#[test]
+fn long_paths_that_cannot_take_the_prefix_are_left_alone() {
+    let long = "d".repeat(250);
+    // Already prefixed
+    assert_eq!(windows_long_path(&format!(r"\\?\C:\{}\x.mp3", long)), None);
+    // Relative
+    assert_eq!(windows_long_path(&format!(r"archive\{}\x.mp3", long)), None);
+    // Not resolved under the prefix
+    assert_eq!(
+        windows_long_path(&format!(r"C:\archive\..\{}\x.mp3", long)),
+        None
+    );
+}
+
+#[cfg(feature = "mock-server")]
+#[tokio::test(flavor = "multi_thread")]
+async fn recordings_of_awkward_titles_get_usable_file_names() {
+    use radafi::midhyae::{Listener, MockRadioGarden, MockStation};
+
+    let mock = MockRadioGarden::start(vec![
+        MockStation::new("Ch01", "AC/DC: Live?", "Recife", "Brazil"),
+        MockStation::new("Ch02", "CON", "Recife", "Brazil"),
+        MockStation::new("Ch03", "¡¿!", "Recife", "Brazil"),
+    ])
+    .unwrap();
+    let directory = std::env::temp_dir().join(format!("radafi-paths-{}", std::process::id()));
+    let mut listener = Listener::new(&mock.base_url());
+    listener.set_allow_insecure(true);
+    // Journals stay out of the real store directory
+    listener.set_journal_dir(&directory.join("journals"));
+    let discovery = listener.store_streams("Brazil").await.unwrap();
+    let outcomes = listener
+        .record_streams(&discovery.streams, 1, &directory.to_string_lossy())
+        .await
+        .unwrap();
+
+    assert!(outcomes.iter().all(|outcome| outcome.ok()));
+    // Titles are reduced to alphanumerics before they become names
+    assert!(directory.join("stream_ACDCLive.mp3").is_file());
+    assert!(directory.join("stream_CON_.mp3").is_file());
+    assert!(directory.join("stream__.mp3").is_file());
+    std::fs::remove_dir_all(&directory).unwrap();
+}